@@ -14,7 +14,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use config::utils::file::set_permission;
-use infra::file_list as infra_file_list;
+use infra::{file_list as infra_file_list, short_url::migration::ShortUrlMigration};
 
 use crate::{
     cli::data::{
@@ -22,7 +22,7 @@ use crate::{
         export, import, Context,
     },
     common::{infra::config::USERS, meta, migration},
-    service::{compact, db, file_list, users},
+    service::{compact, db, file_list, short_url, users},
 };
 
 pub async fn cli() -> Result<bool, anyhow::Error> {
@@ -109,6 +109,35 @@ pub async fn cli() -> Result<bool, anyhow::Error> {
                         .help("the parquet file name"),
                 ),
             clap::Command::new("migrate-schemas").about("migrate from single row to row per schema version"),
+            clap::Command::new("export-short-urls")
+                .about("export short urls to a ndjson file")
+                .arg(
+                    clap::Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .value_name("file")
+                        .required(true)
+                        .help("the ndjson file to write"),
+                ),
+            clap::Command::new("import-short-urls")
+                .about("import short urls from a ndjson file")
+                .arg(
+                    clap::Arg::new("file")
+                        .short('f')
+                        .long("file")
+                        .value_name("file")
+                        .required(true)
+                        .help("the ndjson file to read"),
+                ),
+            clap::Command::new("short-url")
+                .about("back up and restore short urls in the object store")
+                .subcommand_required(true)
+                .subcommands([
+                    clap::Command::new("backup")
+                        .about("upload all short urls to short_url/backup/{timestamp}.ndjson.gz"),
+                    clap::Command::new("restore")
+                        .about("add the short urls of the latest backup, existing short_ids are skipped"),
+                ]),
         ])
         .get_matches();
 
@@ -267,6 +296,43 @@ pub async fn cli() -> Result<bool, anyhow::Error> {
             println!("Running schema migration to row per schema version");
             migration::schema::run().await?
         }
+        "export-short-urls" => {
+            let file = command.get_one::<String>("file").unwrap();
+            let mut writer = tokio::fs::File::create(file).await?;
+            let count = ShortUrlMigration::default()
+                .export_ndjson(&mut writer)
+                .await?;
+            println!("exported {count} short urls to {file}");
+        }
+        "import-short-urls" => {
+            let file = command.get_one::<String>("file").unwrap();
+            let mut reader = tokio::fs::File::open(file).await?;
+            let report = ShortUrlMigration::default()
+                .import_ndjson(&mut reader)
+                .await?;
+            println!(
+                "imported short urls from {file}, inserted: {}, skipped: {}, errors: {}",
+                report.inserted, report.skipped, report.errors
+            );
+        }
+        "short-url" => match command.subcommand_name() {
+            Some("backup") => {
+                let (key, count) = short_url::backup().await?;
+                println!("backed up {count} short urls to {key}");
+            }
+            Some("restore") => {
+                let (key, report) = short_url::restore().await?;
+                println!(
+                    "restored short urls from {key}, inserted: {}, skipped: {}, errors: {}",
+                    report.inserted, report.skipped, report.errors
+                );
+            }
+            sub => {
+                return Err(anyhow::anyhow!(
+                    "unsupported short-url sub command: {sub:?}"
+                ));
+            }
+        },
         _ => {
             return Err(anyhow::anyhow!("unsupported sub command: {name}"));
         }
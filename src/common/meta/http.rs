@@ -109,6 +109,15 @@ impl HttpResponse {
             .json(Self::error(StatusCode::CONFLICT.into(), error.to_string()))
     }
 
+    /// Send a UnprocessableEntity response in json format and associate the
+    /// provided error as `error` field.
+    pub fn unprocessable_entity(error: impl ToString) -> ActixHttpResponse {
+        ActixHttpResponse::UnprocessableEntity().json(Self::error(
+            StatusCode::UNPROCESSABLE_ENTITY.into(),
+            error.to_string(),
+        ))
+    }
+
     /// Send a NotFound response in json format and associate the
     /// provided error as `error` field.
     pub fn not_found(error: impl ToString) -> ActixHttpResponse {
@@ -57,6 +57,7 @@ impl UserRequest {
                 token,
                 rum_token: Some(rum_token),
                 role: self.role.clone(),
+                short_url_api_key: None,
             }],
             is_external,
             password_ext: Some(password_ext),
@@ -162,6 +163,10 @@ pub struct UserOrg {
     pub rum_token: Option<String>,
     #[serde(default)]
     pub role: UserRole,
+    /// SHA-256 of the key accepted as `?api_key=` when creating short urls, the key
+    /// itself is only returned once when it is generated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_url_api_key: Option<String>,
 }
 
 impl PartialEq for UserOrg {
@@ -16,4 +16,5 @@
 pub mod infra;
 pub mod meta;
 pub mod migration;
+pub mod short_url;
 pub mod utils;
@@ -0,0 +1,163 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Roles of the short url endpoints: a `viewer` reads short urls, an `editor` also creates
+//! them and changes the ones it created, an `admin` may call every endpoint
+
+use std::fmt;
+
+use crate::{
+    common::{meta::user::UserRole, utils::auth::is_root_user},
+    service::users,
+};
+
+pub const ROLE_VIEWER: &str = "viewer";
+pub const ROLE_EDITOR: &str = "editor";
+pub const ROLE_ADMIN: &str = "admin";
+
+/// Ordered so a role allows everything the roles below it do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShortUrlRole {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+impl ShortUrlRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShortUrlRole::Viewer => ROLE_VIEWER,
+            ShortUrlRole::Editor => ROLE_EDITOR,
+            ShortUrlRole::Admin => ROLE_ADMIN,
+        }
+    }
+
+    /// Short url role of an org role, `None` for users without access to short urls
+    pub fn from_user_role(role: &UserRole) -> Option<Self> {
+        match role {
+            UserRole::Root | UserRole::Admin => Some(ShortUrlRole::Admin),
+            UserRole::Member => Some(ShortUrlRole::Editor),
+            #[cfg(feature = "enterprise")]
+            UserRole::Editor | UserRole::ServiceAccount => Some(ShortUrlRole::Editor),
+            #[cfg(feature = "enterprise")]
+            UserRole::Viewer => Some(ShortUrlRole::Viewer),
+            #[cfg(feature = "enterprise")]
+            UserRole::User => None,
+        }
+    }
+
+    /// An admin may change any short url, an editor only those created by `user_id`
+    pub fn may_modify(self, user_id: &str, created_by: Option<&str>) -> bool {
+        match self {
+            ShortUrlRole::Admin => true,
+            ShortUrlRole::Editor => created_by == Some(user_id),
+            ShortUrlRole::Viewer => false,
+        }
+    }
+}
+
+impl fmt::Display for ShortUrlRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Role of `user_id` in `org_id`, `None` if the user is not a member of the org
+pub async fn user_role(org_id: &str, user_id: &str) -> Option<ShortUrlRole> {
+    if is_root_user(user_id) {
+        return Some(ShortUrlRole::Admin);
+    }
+    let user = users::get_user(Some(org_id), user_id).await?;
+    ShortUrlRole::from_user_role(&user.role)
+}
+
+/// The org_id and the role an API request needs, `path` is relative to `/api/`. `None` for
+/// requests to other endpoints than the short url ones. Unknown short url requests need the
+/// admin role
+pub fn required_role<'a>(method: &str, path: &'a str) -> Option<(&'a str, ShortUrlRole)> {
+    let segments = path.trim_end_matches('/').split('/').collect::<Vec<_>>();
+    let [org_id, "short", rest @ ..] = segments.as_slice() else {
+        return None;
+    };
+    let role = match (method, rest) {
+        ("GET" | "HEAD", ["_export" | "_health"]) => ShortUrlRole::Admin,
+        ("GET" | "HEAD", _) => ShortUrlRole::Viewer,
+        ("POST", [] | ["_batch" | "_api_key"]) => ShortUrlRole::Editor,
+        // endpoints of the whole org start with '_', e.g. a clear
+        ("PATCH" | "PUT" | "DELETE", [short_id]) if !short_id.starts_with('_') => {
+            ShortUrlRole::Editor
+        }
+        _ => ShortUrlRole::Admin,
+    };
+    Some((org_id, role))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_role() {
+        let role = |method, path| required_role(method, path).map(|(_, role)| role);
+        assert_eq!(role("GET", "default/short"), Some(ShortUrlRole::Viewer));
+        assert_eq!(role("GET", "default/short/"), Some(ShortUrlRole::Viewer));
+        assert_eq!(
+            role("GET", "default/short/abc/qr"),
+            Some(ShortUrlRole::Viewer)
+        );
+        assert_eq!(
+            role("GET", "default/short/_export"),
+            Some(ShortUrlRole::Admin)
+        );
+        assert_eq!(role("POST", "default/short"), Some(ShortUrlRole::Editor));
+        assert_eq!(
+            role("POST", "default/short/_batch"),
+            Some(ShortUrlRole::Editor)
+        );
+        assert_eq!(
+            role("PATCH", "default/short/abc"),
+            Some(ShortUrlRole::Editor)
+        );
+        assert_eq!(
+            role("DELETE", "default/short/abc"),
+            Some(ShortUrlRole::Editor)
+        );
+        assert_eq!(
+            role("DELETE", "default/short/_clear"),
+            Some(ShortUrlRole::Admin)
+        );
+        assert_eq!(
+            role("POST", "default/short/_batch_remove"),
+            Some(ShortUrlRole::Admin)
+        );
+        assert_eq!(role("GET", "default/streams"), None);
+        assert_eq!(role("GET", "default"), None);
+        assert_eq!(
+            required_role("GET", "default/short/abc"),
+            Some(("default", ShortUrlRole::Viewer))
+        );
+    }
+
+    #[test]
+    fn test_may_modify() {
+        assert!(ShortUrlRole::Admin.may_modify("a@example.com", None));
+        assert!(ShortUrlRole::Editor.may_modify("a@example.com", Some("a@example.com")));
+        assert!(!ShortUrlRole::Editor.may_modify("a@example.com", Some("b@example.com")));
+        assert!(!ShortUrlRole::Editor.may_modify("a@example.com", None));
+        assert!(!ShortUrlRole::Viewer.may_modify("a@example.com", Some("a@example.com")));
+        assert!(ShortUrlRole::Viewer < ShortUrlRole::Editor);
+        assert!(ShortUrlRole::Editor < ShortUrlRole::Admin);
+    }
+}
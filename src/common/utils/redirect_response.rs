@@ -23,6 +23,7 @@ const DEFAULT_REDIRECT_RELATIVE_URI: &str = "/web/";
 pub struct RedirectResponse {
     redirect_relative_uri: String,
     query_params: HashMap<String, String>,
+    permanent: bool,
 }
 
 impl RedirectResponse {
@@ -51,9 +52,12 @@ impl RedirectResponse {
     fn build_redirect_response(&self) -> HttpResponse {
         let redirect_uri = self.build_full_redirect_uri();
 
-        HttpResponse::Found()
-            .append_header(("Location", redirect_uri))
-            .finish()
+        let mut response = if self.permanent {
+            HttpResponse::MovedPermanently()
+        } else {
+            HttpResponse::Found()
+        };
+        response.append_header(("Location", redirect_uri)).finish()
     }
 }
 
@@ -74,6 +78,7 @@ impl ResponseError for RedirectResponse {
 pub struct RedirectResponseBuilder {
     redirect_relative_uri: String,
     query_params: HashMap<String, String>,
+    permanent: bool,
 }
 
 impl RedirectResponseBuilder {
@@ -84,6 +89,7 @@ impl RedirectResponseBuilder {
         RedirectResponseBuilder {
             redirect_relative_uri: redirect_relative_uri.to_string(),
             query_params: HashMap::new(),
+            permanent: false,
         }
     }
 
@@ -92,10 +98,17 @@ impl RedirectResponseBuilder {
         self
     }
 
+    /// Respond with 301 Moved Permanently instead of 302 Found
+    pub fn with_permanent(mut self, permanent: bool) -> Self {
+        self.permanent = permanent;
+        self
+    }
+
     pub fn build(self) -> RedirectResponse {
         RedirectResponse {
             redirect_relative_uri: self.redirect_relative_uri,
             query_params: self.query_params,
+            permanent: self.permanent,
         }
     }
 }
@@ -105,6 +118,7 @@ impl Default for RedirectResponseBuilder {
         RedirectResponseBuilder {
             redirect_relative_uri: DEFAULT_REDIRECT_RELATIVE_URI.to_string(),
             query_params: HashMap::new(),
+            permanent: false,
         }
     }
 }
@@ -171,4 +185,18 @@ mod tests {
         assert_eq!(http_response.status(), actix_web::http::StatusCode::FOUND);
         assert_eq!(http_response.headers().get(LOCATION).unwrap(), expected_uri);
     }
+
+    #[test]
+    fn test_redirect_permanent() {
+        let redirect_response = RedirectResponseBuilder::new("/web")
+            .with_permanent(true)
+            .build();
+
+        let http_response: HttpResponse = redirect_response.redirect_http();
+        assert_eq!(
+            http_response.status(),
+            actix_web::http::StatusCode::MOVED_PERMANENTLY
+        );
+        assert_eq!(http_response.headers().get(LOCATION).unwrap(), "/web");
+    }
 }
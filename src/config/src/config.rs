@@ -32,7 +32,7 @@ use once_cell::sync::Lazy;
 use sysinfo::{DiskExt, SystemExt};
 
 use crate::{
-    meta::cluster,
+    meta::{cluster, meta_store::MetaStore},
     utils::{cgroup, file::get_file_meta},
 };
 
@@ -69,6 +69,9 @@ pub const QUERY_WITH_NO_LIMIT: i32 = -999;
 
 pub const REQUIRED_DB_CONNECTIONS: u32 = 4;
 
+pub const SHORT_URL_ID_MIN_LENGTH: usize = 4;
+pub const SHORT_URL_ID_MAX_LENGTH: usize = 64; // short_id column is VARCHAR(64)
+
 // Columns added to ingested records for _INTERNAL_ use only.
 // Used for storing and querying unflattened original data
 pub const ORIGINAL_DATA_COL_NAME: &str = "_original";
@@ -488,6 +491,12 @@ pub struct Common {
     pub app_name: String,
     #[env_config(name = "ZO_LOCAL_MODE", default = true)]
     pub local_mode: bool,
+    #[env_config(
+        name = "ZO_CONFIG_FILE",
+        default = "",
+        help = "path of a TOML config file with a [short_url] section, env vars take precedence over the file"
+    )]
+    pub config_file: String,
     // ZO_LOCAL_MODE_STORAGE is ignored when ZO_LOCAL_MODE is set to false
     #[env_config(name = "ZO_LOCAL_MODE_STORAGE", default = "disk")]
     pub local_mode_storage: String,
@@ -1006,6 +1015,270 @@ pub struct Limit {
     pub upper_bound_for_max_ts: i64,
     #[env_config(name = "ZO_SHORT_URL_RETENTION_DAYS", default = 30)] // days
     pub short_url_retention_days: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_ID_LENGTH",
+        default = 16,
+        help = "length of generated short url ids, between 4 and 64"
+    )]
+    pub short_url_id_length: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_ID_CHARSET",
+        default = "0123456789abcdef",
+        help = "characters used to generate short url ids"
+    )]
+    pub short_url_id_charset: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_RATE_LIMIT_PER_MIN",
+        default = 60,
+        help = "max short urls created per minute for each org and client ip, 0 disables the limit"
+    )]
+    pub short_url_rate_limit_per_min: u32,
+    #[env_config(
+        name = "ZO_SHORT_URL_TRUSTED_PROXIES",
+        default = "",
+        help = "comma separated ips of the reverse proxies whose forwarded header gives the client ip for the short url rate limit and click log, empty trusts none"
+    )]
+    pub short_url_trusted_proxies: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_QUOTA_PER_ORG",
+        default = 0,
+        help = "max short urls each org can have, creating more fails with 429, 0 is unlimited"
+    )]
+    pub short_url_quota_per_org: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_PURGE_INTERVAL",
+        default = 86400,
+        help = "interval in seconds to purge expired short urls"
+    )]
+    pub short_url_purge_interval: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_PURGE_BATCH_SIZE",
+        default = 1000,
+        help = "max expired short urls removed per batch"
+    )]
+    pub short_url_purge_batch_size: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_PURGE_DRY_RUN",
+        default = false,
+        help = "log the expired short urls the purge task would remove without removing them"
+    )]
+    pub short_url_purge_dry_run: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_PURGE_RANDOM_SAMPLE",
+        default = false,
+        help = "pick each purge batch as a random sample of the expired short urls instead of the oldest first"
+    )]
+    pub short_url_purge_random_sample: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_ARCHIVE_EXPIRED",
+        default = false,
+        help = "move expired short urls to the short_url_archive table instead of deleting them"
+    )]
+    pub short_url_archive_expired: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_INTEGRITY_CHECK_INTERVAL",
+        default = 86400,
+        help = "interval in seconds to verify the checksums of all short urls, 0 disables the check"
+    )]
+    pub short_url_integrity_check_interval: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_INTEGRITY_CHECK_BATCH_SIZE",
+        default = 1000,
+        help = "short urls verified per batch by the integrity check"
+    )]
+    pub short_url_integrity_check_batch_size: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_LINK_ROT_CHECK_INTERVAL_HOURS",
+        default = 24,
+        help = "hours between two runs of the link rot detector checking the original urls of short urls, 0 disables it"
+    )]
+    pub short_url_link_rot_check_interval_hours: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_LINK_ROT_SAMPLE_SIZE",
+        default = 100,
+        help = "short urls whose original url is checked per run of the link rot detector"
+    )]
+    pub short_url_link_rot_sample_size: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_TABLE_NAME",
+        default = "short_urls",
+        help = "table name of short urls, allows multiple instances to share one db schema"
+    )]
+    pub short_url_table_name: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_BACKEND",
+        default = "",
+        help = "db of short urls: mysql, postgres or sqlite, defaults to ZO_META_STORE"
+    )]
+    pub short_url_backend: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_SHADOW_BACKEND",
+        default = "",
+        help = "db every short url write is also sent to in the background to compare it with the main backend before a migration: mysql, postgres or sqlite, empty disables it"
+    )]
+    pub short_url_shadow_backend: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_MERGE_STRATEGY",
+        default = "error_on_conflict",
+        help = "what merging a short url whose short_id is taken does: error_on_conflict fails, last_write_wins keeps the one with the higher created_ts"
+    )]
+    pub short_url_merge_strategy: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_SLOW_QUERY_THRESHOLD_MS",
+        default = 100,
+        help = "short url db calls slower than this are logged, 0 disables the log"
+    )]
+    pub short_url_slow_query_threshold_ms: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_NOT_FOUND_REDIRECT",
+        default = "",
+        help = "url to redirect to with 302 when a short url is not found, empty responds 404"
+    )]
+    pub short_url_not_found_redirect: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_CACHE_SIZE",
+        default = 10000,
+        help = "max short urls kept in the db read cache, 0 disables the cache"
+    )]
+    pub short_url_cache_size: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_CACHE_TTL_SECS",
+        default = 60,
+        help = "seconds a short url stays in the db read cache"
+    )]
+    pub short_url_cache_ttl_secs: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_REDIS_URL",
+        default = "",
+        help = "redis url of the short url read cache shared by the nodes, needs the short_url_redis feature, empty disables it"
+    )]
+    pub short_url_redis_url: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_REDIS_CACHE_TTL_SECS",
+        default = 300,
+        help = "seconds a short url stays in the shared redis read cache"
+    )]
+    pub short_url_redis_cache_ttl_secs: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_ALLOWED_DOMAINS",
+        default = "",
+        help = "comma separated glob patterns of the hosts short urls may point to, e.g. *.corp.example.com, empty allows all"
+    )]
+    pub short_url_allowed_domains: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_RESERVED_WORDS",
+        default = "",
+        help = "comma separated short ids callers may not choose, on top of admin, api, health, login, logout and static, matched ignoring case"
+    )]
+    pub short_url_reserved_words: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_MEMORY_FALLBACK",
+        default = false,
+        help = "serve short url reads from an in-memory copy while the db can not be reached, meant for single node setups"
+    )]
+    pub short_url_memory_fallback: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_MAX_BODY_SIZE",
+        default = 8192,
+        help = "max bytes of a create short url request body, larger ones get 413"
+    )]
+    pub short_url_max_body_size: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_MAX_URL_LENGTH",
+        default = 2048,
+        help = "max bytes of the original url of a short url, longer ones get 422"
+    )]
+    pub short_url_max_url_length: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_MAX_REDIRECT_DEPTH",
+        default = 3,
+        help = "a redirect whose Short-URL-Redirect-Depth request header reached this value gets 400 instead, 0 disables the check"
+    )]
+    pub short_url_max_redirect_depth: u32,
+    #[env_config(
+        name = "ZO_SHORT_URL_MAX_CONCURRENT_REDIRECTS",
+        default = 1000,
+        help = "max short url redirects in flight per node, more get 503 with Retry-After: 1, 0 disables the limit"
+    )]
+    pub short_url_max_concurrent_redirects: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_DB_CONNECT_TIMEOUT_SECS",
+        default = 30,
+        help = "seconds the startup waits for the short url db to answer before failing, 0 waits forever"
+    )]
+    pub short_url_db_connect_timeout_secs: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_DB_QUERY_TIMEOUT_SECS",
+        default = 30,
+        help = "seconds a short url db call may take before it fails, schema changes are not limited, 0 disables"
+    )]
+    pub short_url_db_query_timeout_secs: u64,
+    #[env_config(
+        name = "ZO_SHORT_URL_MAX_OFFSET",
+        default = 100000,
+        help = "max offset when listing short urls by page, the db scans every skipped row so deeper pages must use after_ts"
+    )]
+    pub short_url_max_offset: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_CLICK_WEBHOOK_URL",
+        default = "",
+        help = "if set, a JSON POST is sent to this url for every short url redirect served"
+    )]
+    pub short_url_click_webhook_url: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_CLICK_WEBHOOK_QUEUE_SIZE",
+        default = 1024,
+        help = "max pending click webhook events, new events are dropped when the queue is full"
+    )]
+    pub short_url_click_webhook_queue_size: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_ACCESS_LOG_ENABLED",
+        default = true,
+        help = "record every short url redirect served in the short_url_access_log table"
+    )]
+    pub short_url_access_log_enabled: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_ACCESS_LOG_QUEUE_SIZE",
+        default = 4096,
+        help = "max pending access log entries, new entries are dropped when the queue is full"
+    )]
+    pub short_url_access_log_queue_size: usize,
+    #[env_config(
+        name = "ZO_SHORT_URL_ACCESS_LOG_RETENTION_DAYS",
+        default = 90,
+        help = "days the short url access log is kept, 0 keeps it forever"
+    )]
+    pub short_url_access_log_retention_days: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_AUDIT_LOG_ENABLED",
+        default = true,
+        help = "emit a JSON audit log line with the short_url_audit tracing target for every short url mutation"
+    )]
+    pub short_url_audit_log_enabled: bool,
+    #[env_config(
+        name = "ZO_SHORT_URL_EXPIRY_NOTIFY_DAYS",
+        default = 0,
+        help = "warn the owner of a short url this many days before it expires, 0 disables expiry notifications"
+    )]
+    pub short_url_expiry_notify_days: i64,
+    #[env_config(
+        name = "ZO_SHORT_URL_EXPIRY_WEBHOOK_URL",
+        default = "",
+        help = "if set, expiry notifications are sent as a JSON POST to this url, otherwise they are only logged"
+    )]
+    pub short_url_expiry_webhook_url: String,
+    #[env_config(
+        name = "ZO_SHORT_URL_COLLISION_WARN_RATE",
+        default = 0.0001,
+        help = "warn when this share of short url adds over the last 5 minutes hit an existing short_id, 0.0001 is 0.01%"
+    )]
+    pub short_url_collision_warn_rate: f64,
+    #[env_config(
+        name = "ZO_SHORT_URL_DIGEST_ENABLED",
+        default = false,
+        help = "email the admins of each org a daily summary of its short url activity, needs ZO_SMTP_ENABLED"
+    )]
+    pub short_url_digest_enabled: bool,
 }
 
 #[derive(EnvConfig)]
@@ -1294,6 +1567,14 @@ pub fn init() -> Config {
     dotenv_override().ok();
     let mut cfg = Config::init().unwrap();
 
+    // the config file fills in the settings no env var is set for
+    if !cfg.common.config_file.is_empty() {
+        let path = cfg.common.config_file.clone();
+        if let Err(e) = crate::config_file::load(&path, &mut cfg) {
+            panic!("config file error: {e}");
+        }
+    }
+
     // set local mode
     if cfg.common.local_mode {
         cfg.common.node_role = "all".to_string();
@@ -1406,6 +1687,11 @@ pub fn init() -> Config {
         panic!("s3 config error: {e}");
     }
 
+    // check short url config
+    if let Err(e) = check_short_url_config(&cfg) {
+        panic!("short url config error: {e}");
+    }
+
     cfg
 }
 
@@ -1795,6 +2081,107 @@ fn check_disk_cache_config(cfg: &mut Config) -> Result<(), anyhow::Error> {
     Ok(())
 }
 
+fn check_short_url_config(cfg: &Config) -> Result<(), anyhow::Error> {
+    if !(SHORT_URL_ID_MIN_LENGTH..=SHORT_URL_ID_MAX_LENGTH).contains(&cfg.limit.short_url_id_length)
+    {
+        return Err(anyhow::anyhow!(
+            "ZO_SHORT_URL_ID_LENGTH (short_url.id_length in the config file) must be between {SHORT_URL_ID_MIN_LENGTH} and {SHORT_URL_ID_MAX_LENGTH}, got {}.",
+            cfg.limit.short_url_id_length
+        ));
+    }
+    let charset = &cfg.limit.short_url_id_charset;
+    // short ids are used as a url path segment, only allow unreserved characters
+    if charset.len() < 2
+        || !charset
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+    {
+        return Err(anyhow::anyhow!(
+            "ZO_SHORT_URL_ID_CHARSET (short_url.id_charset in the config file) must contain at least 2 characters of [a-zA-Z0-9-._~], got {charset:?}."
+        ));
+    }
+    // the table name is substituted into sql, it must match ^[a-z][a-z0-9_]{0,62}$
+    let table_name = &cfg.limit.short_url_table_name;
+    if table_name.is_empty()
+        || table_name.len() > 63
+        || !table_name.starts_with(|c: char| c.is_ascii_lowercase())
+        || !table_name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+    {
+        return Err(anyhow::anyhow!(
+            "ZO_SHORT_URL_TABLE_NAME (short_url.table_name in the config file) must match ^[a-z][a-z0-9_]{{0,62}}$, got {table_name:?}."
+        ));
+    }
+    match cfg.limit.short_url_backend.as_str() {
+        "" | "sqlite" => {}
+        "mysql" if cfg.common.meta_mysql_dsn.is_empty() => {
+            return Err(anyhow::anyhow!(
+                "Short url backend is MySQL, you must set ZO_META_MYSQL_DSN"
+            ));
+        }
+        "postgres" | "postgresql" if cfg.common.meta_postgres_dsn.is_empty() => {
+            return Err(anyhow::anyhow!(
+                "Short url backend is PostgreSQL, you must set ZO_META_POSTGRES_DSN"
+            ));
+        }
+        "mysql" | "postgres" | "postgresql" => {}
+        _ => {
+            return Err(anyhow::anyhow!(
+                "ZO_SHORT_URL_BACKEND must be one of mysql, postgres or sqlite."
+            ));
+        }
+    }
+    if !matches!(
+        cfg.limit.short_url_merge_strategy.as_str(),
+        "error_on_conflict" | "last_write_wins"
+    ) {
+        return Err(anyhow::anyhow!(
+            "ZO_SHORT_URL_MERGE_STRATEGY must be error_on_conflict or last_write_wins."
+        ));
+    }
+    match cfg.limit.short_url_shadow_backend.as_str() {
+        "" => {}
+        "mysql" if cfg.common.meta_mysql_dsn.is_empty() => {
+            return Err(anyhow::anyhow!(
+                "Short url shadow backend is MySQL, you must set ZO_META_MYSQL_DSN"
+            ));
+        }
+        "postgres" | "postgresql" if cfg.common.meta_postgres_dsn.is_empty() => {
+            return Err(anyhow::anyhow!(
+                "Short url shadow backend is PostgreSQL, you must set ZO_META_POSTGRES_DSN"
+            ));
+        }
+        "sqlite" | "mysql" | "postgres" | "postgresql" => {
+            // the same db would get every write twice
+            let primary = if cfg.limit.short_url_backend.is_empty() {
+                cfg.common.meta_store.as_str()
+            } else {
+                cfg.limit.short_url_backend.as_str()
+            };
+            if short_url_db(primary) == short_url_db(&cfg.limit.short_url_shadow_backend) {
+                return Err(anyhow::anyhow!(
+                    "ZO_SHORT_URL_SHADOW_BACKEND must not be the short url backend."
+                ));
+            }
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "ZO_SHORT_URL_SHADOW_BACKEND must be one of mysql, postgres or sqlite."
+            ));
+        }
+    }
+    Ok(())
+}
+
+// the db a short url backend name ends up in, any other meta store keeps short urls in sqlite
+fn short_url_db(name: &str) -> MetaStore {
+    match MetaStore::from(name) {
+        store @ (MetaStore::MySQL | MetaStore::PostgreSQL) => store,
+        _ => MetaStore::Sqlite,
+    }
+}
+
 fn check_s3_config(cfg: &mut Config) -> Result<(), anyhow::Error> {
     if !cfg.s3.bucket_prefix.is_empty() && !cfg.s3.bucket_prefix.ends_with('/') {
         cfg.s3.bucket_prefix = format!("{}/", cfg.s3.bucket_prefix);
@@ -1898,4 +2285,37 @@ mod tests {
         assert_eq!(cfg.common.data_dir, "/abc/".to_string());
         assert_eq!(cfg.common.base_uri, "/abc".to_string());
     }
+
+    #[test]
+    fn test_check_short_url_config() {
+        let mut cfg = Config::init().unwrap();
+        assert!(check_short_url_config(&cfg).is_ok());
+
+        cfg.limit.short_url_id_length = 3;
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_id_length = 65;
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_id_length = 6;
+        assert!(check_short_url_config(&cfg).is_ok());
+
+        cfg.limit.short_url_id_charset = "abc/def".to_string();
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_id_charset =
+            "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_".to_string();
+        assert!(check_short_url_config(&cfg).is_ok());
+
+        cfg.limit.short_url_table_name = "short_urls; DROP TABLE users".to_string();
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_table_name = "1short_urls".to_string();
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_table_name = "a".repeat(64);
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_table_name = "tenant_a_short_urls".to_string();
+        assert!(check_short_url_config(&cfg).is_ok());
+
+        cfg.limit.short_url_backend = "redis".to_string();
+        assert!(check_short_url_config(&cfg).is_err());
+        cfg.limit.short_url_backend = "sqlite".to_string();
+        assert!(check_short_url_config(&cfg).is_ok());
+    }
 }
@@ -0,0 +1,160 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Settings read from the TOML file at `ZO_CONFIG_FILE`. Every key has an env var and the env
+//! var wins when both are set, so a file can hold the defaults of a deployment.
+
+use serde::Deserialize;
+
+use crate::config::{Config, Limit};
+
+/// Keys of `[short_url]` are the `ZO_SHORT_URL_*` env vars without the prefix, in lower case
+macro_rules! short_url_file_config {
+    ($($key:ident => $field:ident: $ty:ty, $env:literal;)*) => {
+        /// `[short_url]` section of the config file
+        #[derive(Debug, Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct ShortUrlFileConfig {
+            $(pub $key: Option<$ty>,)*
+        }
+
+        impl ShortUrlFileConfig {
+            // `env_is_set` tells whether the env var of a key is set, those keys are skipped
+            fn apply(self, limit: &mut Limit, env_is_set: impl Fn(&str) -> bool) {
+                $(
+                    if let Some(value) = self.$key {
+                        if !env_is_set($env) {
+                            limit.$field = value;
+                        }
+                    }
+                )*
+            }
+        }
+    };
+}
+
+short_url_file_config! {
+    retention_days => short_url_retention_days: i64, "ZO_SHORT_URL_RETENTION_DAYS";
+    id_length => short_url_id_length: usize, "ZO_SHORT_URL_ID_LENGTH";
+    id_charset => short_url_id_charset: String, "ZO_SHORT_URL_ID_CHARSET";
+    rate_limit_per_min => short_url_rate_limit_per_min: u32, "ZO_SHORT_URL_RATE_LIMIT_PER_MIN";
+    trusted_proxies => short_url_trusted_proxies: String, "ZO_SHORT_URL_TRUSTED_PROXIES";
+    quota_per_org => short_url_quota_per_org: i64, "ZO_SHORT_URL_QUOTA_PER_ORG";
+    purge_interval => short_url_purge_interval: u64, "ZO_SHORT_URL_PURGE_INTERVAL";
+    purge_batch_size => short_url_purge_batch_size: i64, "ZO_SHORT_URL_PURGE_BATCH_SIZE";
+    purge_dry_run => short_url_purge_dry_run: bool, "ZO_SHORT_URL_PURGE_DRY_RUN";
+    purge_random_sample => short_url_purge_random_sample: bool, "ZO_SHORT_URL_PURGE_RANDOM_SAMPLE";
+    archive_expired => short_url_archive_expired: bool, "ZO_SHORT_URL_ARCHIVE_EXPIRED";
+    integrity_check_interval => short_url_integrity_check_interval: u64, "ZO_SHORT_URL_INTEGRITY_CHECK_INTERVAL";
+    integrity_check_batch_size => short_url_integrity_check_batch_size: usize, "ZO_SHORT_URL_INTEGRITY_CHECK_BATCH_SIZE";
+    link_rot_check_interval_hours => short_url_link_rot_check_interval_hours: u64, "ZO_SHORT_URL_LINK_ROT_CHECK_INTERVAL_HOURS";
+    link_rot_sample_size => short_url_link_rot_sample_size: i64, "ZO_SHORT_URL_LINK_ROT_SAMPLE_SIZE";
+    table_name => short_url_table_name: String, "ZO_SHORT_URL_TABLE_NAME";
+    backend => short_url_backend: String, "ZO_SHORT_URL_BACKEND";
+    shadow_backend => short_url_shadow_backend: String, "ZO_SHORT_URL_SHADOW_BACKEND";
+    merge_strategy => short_url_merge_strategy: String, "ZO_SHORT_URL_MERGE_STRATEGY";
+    slow_query_threshold_ms => short_url_slow_query_threshold_ms: u64, "ZO_SHORT_URL_SLOW_QUERY_THRESHOLD_MS";
+    not_found_redirect => short_url_not_found_redirect: String, "ZO_SHORT_URL_NOT_FOUND_REDIRECT";
+    cache_size => short_url_cache_size: usize, "ZO_SHORT_URL_CACHE_SIZE";
+    cache_ttl_secs => short_url_cache_ttl_secs: u64, "ZO_SHORT_URL_CACHE_TTL_SECS";
+    redis_url => short_url_redis_url: String, "ZO_SHORT_URL_REDIS_URL";
+    redis_cache_ttl_secs => short_url_redis_cache_ttl_secs: u64, "ZO_SHORT_URL_REDIS_CACHE_TTL_SECS";
+    allowed_domains => short_url_allowed_domains: String, "ZO_SHORT_URL_ALLOWED_DOMAINS";
+    reserved_words => short_url_reserved_words: String, "ZO_SHORT_URL_RESERVED_WORDS";
+    memory_fallback => short_url_memory_fallback: bool, "ZO_SHORT_URL_MEMORY_FALLBACK";
+    max_body_size => short_url_max_body_size: usize, "ZO_SHORT_URL_MAX_BODY_SIZE";
+    max_url_length => short_url_max_url_length: usize, "ZO_SHORT_URL_MAX_URL_LENGTH";
+    max_redirect_depth => short_url_max_redirect_depth: u32, "ZO_SHORT_URL_MAX_REDIRECT_DEPTH";
+    max_concurrent_redirects => short_url_max_concurrent_redirects: usize, "ZO_SHORT_URL_MAX_CONCURRENT_REDIRECTS";
+    db_connect_timeout_secs => short_url_db_connect_timeout_secs: u64, "ZO_SHORT_URL_DB_CONNECT_TIMEOUT_SECS";
+    db_query_timeout_secs => short_url_db_query_timeout_secs: u64, "ZO_SHORT_URL_DB_QUERY_TIMEOUT_SECS";
+    max_offset => short_url_max_offset: i64, "ZO_SHORT_URL_MAX_OFFSET";
+    click_webhook_url => short_url_click_webhook_url: String, "ZO_SHORT_URL_CLICK_WEBHOOK_URL";
+    click_webhook_queue_size => short_url_click_webhook_queue_size: usize, "ZO_SHORT_URL_CLICK_WEBHOOK_QUEUE_SIZE";
+    access_log_enabled => short_url_access_log_enabled: bool, "ZO_SHORT_URL_ACCESS_LOG_ENABLED";
+    access_log_queue_size => short_url_access_log_queue_size: usize, "ZO_SHORT_URL_ACCESS_LOG_QUEUE_SIZE";
+    access_log_retention_days => short_url_access_log_retention_days: i64, "ZO_SHORT_URL_ACCESS_LOG_RETENTION_DAYS";
+    audit_log_enabled => short_url_audit_log_enabled: bool, "ZO_SHORT_URL_AUDIT_LOG_ENABLED";
+    expiry_notify_days => short_url_expiry_notify_days: i64, "ZO_SHORT_URL_EXPIRY_NOTIFY_DAYS";
+    expiry_webhook_url => short_url_expiry_webhook_url: String, "ZO_SHORT_URL_EXPIRY_WEBHOOK_URL";
+    collision_warn_rate => short_url_collision_warn_rate: f64, "ZO_SHORT_URL_COLLISION_WARN_RATE";
+    digest_enabled => short_url_digest_enabled: bool, "ZO_SHORT_URL_DIGEST_ENABLED";
+}
+
+/// The config file, unknown sections and keys are rejected so a typo is not silently ignored
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub short_url: ShortUrlFileConfig,
+}
+
+impl ConfigFile {
+    pub fn parse(content: &str) -> Result<Self, anyhow::Error> {
+        toml::from_str(content).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
+
+/// Apply the config file at `path` to `cfg`, settings whose env var is set are left alone
+pub fn load(path: &str, cfg: &mut Config) -> Result<(), anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("can not read config file {path}: {e}"))?;
+    let file = ConfigFile::parse(&content)
+        .map_err(|e| anyhow::anyhow!("invalid config file {path}: {e}"))?;
+    file.short_url
+        .apply(&mut cfg.limit, |env| std::env::var_os(env).is_some());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use dotenv_config::EnvConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_apply_short_url_section() {
+        let file = ConfigFile::parse(
+            r#"
+            [short_url]
+            id_length = 8
+            backend = "postgres"
+            purge_dry_run = true
+            "#,
+        )
+        .unwrap();
+        let mut cfg = Config::init().unwrap();
+        let cache_size = cfg.limit.short_url_cache_size;
+        file.short_url
+            .apply(&mut cfg.limit, |env| env == "ZO_SHORT_URL_BACKEND");
+        assert_eq!(cfg.limit.short_url_id_length, 8);
+        assert!(cfg.limit.short_url_purge_dry_run);
+        // the env var wins
+        assert_ne!(cfg.limit.short_url_backend, "postgres");
+        // keys missing from the file keep their value
+        assert_eq!(cfg.limit.short_url_cache_size, cache_size);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(ConfigFile::parse("").is_ok());
+        let err = ConfigFile::parse("[short_url]\nid_lenght = 8\n").unwrap_err();
+        assert!(err.to_string().contains("id_lenght"), "{err}");
+        let err = ConfigFile::parse("[short_url]\nid_length = \"eight\"\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+        assert!(ConfigFile::parse("[shorturl]\n").is_err());
+        assert!(ConfigFile::parse("[short_url]\nid_length = -1\n").is_err());
+    }
+}
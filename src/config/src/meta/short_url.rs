@@ -13,15 +13,165 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
 #[derive(Clone, Debug, Default, Deserialize, ToSchema)]
 pub struct ShortenUrlRequest {
     pub original_url: String,
+    /// Custom short_id matching `^[a-zA-Z0-9_-]{3,32}$`, generated by the server when absent
+    #[serde(default)]
+    pub short_id: Option<String>,
+    /// Expiry timestamp in microseconds, defaults to the global short url retention
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    /// Redirect with 301 Moved Permanently instead of 302 Found
+    #[serde(default)]
+    pub permanent: bool,
+    /// Kind of the resource the URL links to, e.g. `dashboard`
+    #[serde(default)]
+    pub resource_type: Option<String>,
+    /// Id of the resource the URL links to, e.g. a dashboard_id
+    #[serde(default)]
+    pub resource_id: Option<String>,
+    /// Key-value metadata for filtering, e.g. `{"campaign": "q4-2024"}`
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// Namespace of a vanity short_id, served at `/{org_id}/short/{namespace}/{short_id}`.
+    /// Requires `short_id`, namespaces are scoped to the org
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Orgs whose users may resolve the short URL, anyone who can reach it when absent. Only
+    /// members of the org owning the short URL reach it, so the acl narrows access within that
+    /// org to the members also in one of the listed orgs
+    #[serde(default)]
+    pub acl: Option<Vec<String>>,
+    /// Rewrites of `original_url` applied in order before each redirect
+    #[serde(default)]
+    pub pipeline: Vec<UrlTransform>,
+    /// Email of the authenticated user, set by the handler and never read from the body
+    #[serde(skip)]
+    pub created_by: Option<String>,
+}
+
+impl ShortenUrlRequest {
+    pub fn new(original_url: &str) -> Self {
+        Self {
+            original_url: original_url.to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Fields of a short URL that can be changed, absent fields are left as they are
+#[derive(Clone, Debug, Default, Deserialize, ToSchema)]
+pub struct ShortUrlPatchRequest {
+    /// Pinned short URLs never expire
+    #[serde(default)]
+    pub pinned: Option<bool>,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
 pub struct ShortenUrlResponse {
     pub short_url: String,
 }
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlItem {
+    pub short_id: String,
+    pub short_url: String,
+    pub original_url: String,
+    pub created_ts: i64,
+    pub click_count: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+}
+
+/// Metadata of a short URL, returned without following the redirect
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlPreviewResponse {
+    pub short_id: String,
+    pub original_url: String,
+    pub created_ts: i64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ListShortUrlResponse {
+    pub list: Vec<ShortUrlItem>,
+    /// Number of short URLs in the organization, set when paging with `offset`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+/// Click summary of the short URLs of an organization
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlStatsResponse {
+    pub total_urls: i64,
+    pub total_clicks: i64,
+    /// The most clicked short URLs, most clicked first
+    pub top_urls: Vec<ShortUrlItem>,
+}
+
+/// A newly generated short url api key, it is not stored and can not be retrieved again
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlApiKeyResponse {
+    pub api_key: String,
+}
+
+/// Outcome of a CSV batch create
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlBatchResponse {
+    /// short_ids of the created short URLs
+    pub inserted: Vec<String>,
+    /// short_ids skipped because they are already in use in the organization
+    pub duplicates: Vec<String>,
+    /// Rows that were not created because they are invalid
+    pub errors: Vec<ShortUrlBatchRowError>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlBatchRowError {
+    /// Line of the row in the CSV file, the header is line 1
+    pub line: u64,
+    pub error: String,
+}
+
+/// Round trip of a sentinel short URL through the short url db
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlHealthReport {
+    pub add_latency_ms: f64,
+    pub get_latency_ms: f64,
+    pub remove_latency_ms: f64,
+    /// Version reported by the db, e.g. `8.0.36` for MySQL
+    pub db_version: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct ShortUrlNotFoundResponse {
+    pub error: String,
+    pub short_id: String,
+}
+
+impl ShortUrlNotFoundResponse {
+    pub fn new(short_id: &str) -> Self {
+        Self {
+            error: "short_url not found".to_string(),
+            short_id: short_id.to_string(),
+        }
+    }
+}
+
+/// A rewrite of the original URL of a short URL before redirecting
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UrlTransform {
+    /// Add `key=value` to the query string, existing pairs are kept
+    AppendQueryParam { key: String, value: String },
+    /// Swap the host, scheme, port, path and query are kept
+    ReplaceHost { new_host: String },
+    /// Replace the path by its base64url decoding, for URLs that carry their target path
+    /// encoded
+    Base64DecodePath,
+}
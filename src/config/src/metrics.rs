@@ -18,7 +18,7 @@ use std::collections::HashMap;
 use actix_web_prometheus::{PrometheusMetrics, PrometheusMetricsBuilder};
 use once_cell::sync::Lazy;
 use prometheus::{
-    CounterVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    CounterVec, GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
 };
 
 pub const NAMESPACE: &str = "zo";
@@ -709,6 +709,125 @@ pub static FILE_LIST_CACHE_HIT_COUNT: Lazy<IntGaugeVec> = Lazy::new(|| {
     .expect("Metric created")
 });
 
+// short url
+pub static SHORT_URL_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new("short_url_total", "total number of short urls added")
+            .namespace(NAMESPACE)
+            .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_EXPIRED_REMOVED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_expired_removed",
+            "number of expired short urls removed",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_PURGE_DRY_RUN: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_purge_dry_run_total",
+            "number of expired short urls a dry run purge would have removed",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_ADD_CONFLICT: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_add_conflict",
+            "number of short url adds rejected by an existing short_id",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_INTEGRITY_FAILURE: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_integrity_failure_total",
+            "number of short urls found with a checksum not matching their original_url",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_LINK_ROT_DETECTED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_link_rot_detected_total",
+            "number of link checks that got a 4xx or 5xx from the original url of a short url",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_ACCESS_LOG_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_access_log_dropped_total",
+            "number of short url access log entries dropped because the queue was full",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_CLICK_WEBHOOK_DROPPED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_click_webhook_dropped_total",
+            "number of short url click webhook events dropped because the queue was full",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_SHED: Lazy<IntCounterVec> = Lazy::new(|| {
+    IntCounterVec::new(
+        Opts::new(
+            "short_url_shed_total",
+            "number of short url redirects answered with 503 because ZO_SHORT_URL_MAX_CONCURRENT_REDIRECTS were in flight",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+pub static SHORT_URL_ID_COLLISION_RATE: Lazy<GaugeVec> = Lazy::new(|| {
+    GaugeVec::new(
+        Opts::new(
+            "short_url_id_collision_rate",
+            "share of short url adds over the last 5 minutes rejected by an existing short_id",
+        )
+        .namespace(NAMESPACE)
+        .const_labels(create_const_labels()),
+        &[],
+    )
+    .expect("Metric created")
+});
+
 fn register_metrics(registry: &Registry) {
     // http latency
     registry
@@ -896,6 +1015,38 @@ fn register_metrics(registry: &Registry) {
     registry
         .register(Box::new(FILE_LIST_CACHE_HIT_COUNT.clone()))
         .expect("Metric registered");
+
+    // short url
+    registry
+        .register(Box::new(SHORT_URL_TOTAL.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_EXPIRED_REMOVED.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_PURGE_DRY_RUN.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_ADD_CONFLICT.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_CLICK_WEBHOOK_DROPPED.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_ACCESS_LOG_DROPPED.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_INTEGRITY_FAILURE.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_LINK_ROT_DETECTED.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_SHED.clone()))
+        .expect("Metric registered");
+    registry
+        .register(Box::new(SHORT_URL_ID_COLLISION_RATE.clone()))
+        .expect("Metric registered");
 }
 
 fn create_const_labels() -> HashMap<String, String> {
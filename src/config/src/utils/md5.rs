@@ -13,6 +13,8 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+const HEX_CHARSET: &str = "0123456789abcdef";
+
 // Get the md5 hash of a string
 fn hash(input: &str) -> String {
     let digest = md5::compute(input.as_bytes());
@@ -31,6 +33,29 @@ pub fn short_hash(input: &str) -> String {
     short_id.to_string()
 }
 
+// Get a short hash of a string with the given length, using only characters from `charset`.
+// For the hex charset the middle of the hex digest is used, same as `short_hash`.
+pub fn short_hash_with_charset(input: &str, charset: &str, len: usize) -> String {
+    if charset == HEX_CHARSET && len <= 32 {
+        let hash = hash(input);
+        let start = (hash.len() - len) / 2;
+        return hash[start..start + len].to_string();
+    }
+
+    let charset = charset.as_bytes();
+    let mut short_id = String::with_capacity(len);
+    let mut round = 0;
+    while short_id.len() < len {
+        // every digest gives 16 characters, rehash with a round suffix for more
+        let digest = md5::compute(format!("{input}{round}").as_bytes());
+        for b in digest.iter().take(len - short_id.len()) {
+            short_id.push(charset[*b as usize % charset.len()] as char);
+        }
+        round += 1;
+    }
+    short_id
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -50,4 +75,20 @@ mod tests {
         let expected = "e01eeed093cb22bb";
         assert_eq!(short_hash(input), expected);
     }
+
+    #[test]
+    fn test_short_hash_with_charset() {
+        let input = "hello world";
+        assert_eq!(
+            short_hash_with_charset(input, HEX_CHARSET, 16),
+            short_hash(input)
+        );
+        assert_eq!(short_hash_with_charset(input, HEX_CHARSET, 6), "ed093c");
+
+        let charset = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+        let res = short_hash_with_charset(input, charset, 40);
+        assert_eq!(res.len(), 40);
+        assert!(res.chars().all(|c| charset.contains(c)));
+        assert_eq!(res, short_hash_with_charset(input, charset, 40));
+    }
 }
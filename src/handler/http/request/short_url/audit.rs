@@ -0,0 +1,56 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use config::get_config;
+use serde::Serialize;
+
+/// Target of the audit events, a subscriber filter on it routes them to a separate sink
+pub const AUDIT_TARGET: &str = "short_url_audit";
+
+/// Mutation recorded in the audit log
+#[derive(Clone, Copy, Debug, Serialize)]
+pub enum AuditEvent {
+    #[serde(rename = "short_url.created")]
+    Created,
+    #[serde(rename = "short_url.updated")]
+    Updated,
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    event: AuditEvent,
+    short_id: &'a str,
+    org_id: &'a str,
+    created_by: Option<&'a str>,
+    ts: i64,
+}
+
+/// Emit one JSON line for a successful mutation, unless `ZO_SHORT_URL_AUDIT_LOG_ENABLED` is off
+pub fn log(event: AuditEvent, org_id: &str, short_id: &str, created_by: Option<&str>) {
+    if !get_config().limit.short_url_audit_log_enabled {
+        return;
+    }
+    let record = AuditRecord {
+        event,
+        short_id,
+        org_id,
+        created_by,
+        ts: chrono::Utc::now().timestamp_micros(),
+    };
+    match serde_json::to_string(&record) {
+        Ok(line) => tracing::info!(target: AUDIT_TARGET, "{line}"),
+        Err(e) => log::error!("[SHORT_URL] audit log serialize error: {e}"),
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Caps the redirects in flight, a burst beyond the cap is answered with 503 at once instead of
+//! queueing on the short url db pool
+
+use config::{get_config, metrics::SHORT_URL_SHED};
+use once_cell::sync::Lazy;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+pub static REDIRECT_LIMITER: Lazy<RedirectLimiter> =
+    Lazy::new(|| RedirectLimiter::new(get_config().limit.short_url_max_concurrent_redirects));
+
+pub struct RedirectLimiter {
+    permits: Semaphore,
+}
+
+impl RedirectLimiter {
+    /// `max` redirects at once, 0 disables the limit
+    pub fn new(max: usize) -> Self {
+        let max = if max == 0 {
+            Semaphore::MAX_PERMITS
+        } else {
+            max
+        };
+        Self {
+            permits: Semaphore::new(max),
+        }
+    }
+
+    /// A permit to hold for the whole redirect, `None` when all of them are taken and the
+    /// request is shed
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let permit = self.permits.try_acquire().ok();
+        if permit.is_none() {
+            SHORT_URL_SHED.with_label_values(&[]).inc();
+        }
+        permit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redirect_limiter() {
+        let limiter = RedirectLimiter::new(1);
+        let permit = limiter.try_acquire();
+        assert!(permit.is_some());
+        assert!(limiter.try_acquire().is_none());
+        drop(permit);
+        assert!(limiter.try_acquire().is_some());
+
+        let unlimited = RedirectLimiter::new(0);
+        let permits: Vec<_> = (0..10_000).map(|_| unlimited.try_acquire()).collect();
+        assert!(permits.iter().all(Option::is_some));
+    }
+}
@@ -13,24 +13,108 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::io::Error;
+use std::{collections::HashMap, io::Error};
 
 use actix_http::StatusCode;
-use actix_web::{get, post, web, HttpRequest, HttpResponse};
-use config::meta::short_url::ShortenUrlResponse;
+use actix_multipart::Multipart;
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::header,
+    patch, post, web, HttpMessage, HttpRequest, HttpResponse,
+};
+use actix_web_lab::middleware::Next;
+use config::{
+    get_config,
+    meta::short_url::{
+        ListShortUrlResponse, ShortUrlApiKeyResponse, ShortUrlBatchResponse, ShortUrlHealthReport,
+        ShortUrlNotFoundResponse, ShortUrlPatchRequest, ShortUrlPreviewResponse,
+        ShortUrlStatsResponse, ShortenUrlResponse,
+    },
+};
+use futures::{StreamExt, TryStreamExt};
+use infra::short_url::{error::ShortUrlError, SortBy, SortDir};
+use tracing::{Instrument, Span};
 
 use crate::{
     common::{
         meta::{self, http::HttpResponse as MetaHttpResponse},
-        utils::redirect_response::RedirectResponseBuilder,
+        short_url::rbac::{self, ShortUrlRole},
+        utils::{http::get_or_create_trace_id, redirect_response::RedirectResponseBuilder},
     },
-    service::short_url,
+    service::short_url::{self, AclDenied, IdempotencyKeyState},
 };
 
+mod audit;
+mod load_shed;
+mod rate_limiter;
+
+const QR_DEFAULT_SIZE: u32 = 10;
+const QR_MAX_SIZE: u32 = 50;
+const QR_DEFAULT_MARGIN: u32 = 4;
+const QR_MAX_MARGIN: u32 = 16;
+
+const TRACE_ID_HEADER: &str = "x-trace-id";
+const REDIRECT_CACHE_CONTROL: &str = "max-age=3600";
+const SHORT_PATH: &str = "/short/";
+// header names are case insensitive, `HeaderName::from_static` needs the lowercase form
+const REDIRECT_DEPTH_HEADER: &str = "short-url-redirect-depth";
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Trace id of the request from `X-Trace-Id` or `traceparent`, a new one if neither is set
+fn get_trace_id(req: &HttpRequest) -> String {
+    req.headers()
+        .get(TRACE_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .unwrap_or_else(|| get_or_create_trace_id(req.headers(), &Span::none()))
+}
+
+/// Ip of the client, the forwarded header is only honoured when the peer is one of the
+/// comma separated `trusted_proxies`, anyone else could set it to dodge the rate limit
+fn client_ip(req: &HttpRequest, trusted_proxies: &str) -> String {
+    let peer_ip = req
+        .peer_addr()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default();
+    let trusted = !peer_ip.is_empty()
+        && trusted_proxies
+            .split(',')
+            .map(str::trim)
+            .any(|proxy| proxy == peer_ip);
+    if trusted {
+        let conn_info = req.connection_info();
+        // without a forwarded header this falls back to the peer address itself
+        if let Some(ip) = conn_info.realip_remote_addr() {
+            return ip.to_string();
+        }
+    }
+    peer_ip
+}
+
+// the db calls of a request run in this span so their logs can be correlated with it
+fn trace_span(trace_id: &str) -> Span {
+    tracing::info_span!("short_url", trace_id = trace_id)
+}
+
+fn internal_error(trace_id: String, e: impl ToString) -> HttpResponse {
+    let mut body =
+        meta::http::HttpResponse::error(StatusCode::INTERNAL_SERVER_ERROR.into(), e.to_string());
+    body.trace_id = Some(trace_id);
+    HttpResponse::InternalServerError().json(body)
+}
+
 /// Shorten a URL
 #[utoipa::path(
     post,
     context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("api_key" = Option<String>, Query, description = "Short url api key of the user, used instead of the Authorization header"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Up to 128 printable ASCII characters, a retried request with the same key and body within 24 hours gets the short URL of the first one back"),
+    ),
     request_body(
         content = ShortenUrlRequest,
         description = "The original URL to shorten",
@@ -46,36 +130,725 @@ use crate::{
             body = ShortenUrlResponse,
             content_type = "application/json",
             example = json!({
-                "short_url": "http://localhost:5080/short/ddbffcea3ad44292"
+                "short_url": "http://localhost:5080/api/default/short/ddbffcea3ad44292"
             })
         ),
-        (status = 400, description = "Invalid request", content_type = "application/json")
+        (status = 400, description = "Invalid request or short_id", content_type = "application/json"),
+        (status = 409, description = "The custom short_id is already in use, or a request with the same Idempotency-Key is still in progress", content_type = "application/json"),
+        (status = 413, description = "The request body is larger than ZO_SHORT_URL_MAX_BODY_SIZE", content_type = "application/json", example = json!({
+            "error": "request body too large"
+        })),
+        (status = 422, description = "The original URL is not an absolute http(s) URL, is longer than ZO_SHORT_URL_MAX_URL_LENGTH or points to a domain not in ZO_SHORT_URL_ALLOWED_DOMAINS, the custom short_id is reserved, or the Idempotency-Key header is invalid or was used with a different body", content_type = "application/json", example = json!({
+            "error": "domain not allowed"
+        })),
+        (status = 429, description = "Too many requests or the org quota is exceeded", content_type = "application/json")
     ),
     tag = "Short Url"
 )]
 #[post("/{org_id}/short")]
-pub async fn shorten(org_id: web::Path<String>, body: web::Bytes) -> Result<HttpResponse, Error> {
-    let req: config::meta::short_url::ShortenUrlRequest = match serde_json::from_slice(&body) {
+pub async fn shorten(
+    org_id: web::Path<String>,
+    body: web::Payload,
+    in_req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let client_ip = client_ip(&in_req, &get_config().limit.short_url_trusted_proxies);
+    if let Err(retry_after) = rate_limiter::RATE_LIMITER.check(&org_id, &client_ip) {
+        let retry_after = retry_after.as_secs().max(1);
+        return Ok(HttpResponse::TooManyRequests()
+            .insert_header((
+                actix_web::http::header::RETRY_AFTER,
+                retry_after.to_string(),
+            ))
+            .json(meta::http::HttpResponse::error(
+                StatusCode::TOO_MANY_REQUESTS.into(),
+                format!("too many short url requests, retry after {retry_after}s"),
+            )));
+    }
+
+    let trace_id = get_trace_id(&in_req);
+    // read no more than the limit instead of buffering the whole body first
+    let body = match body
+        .to_bytes_limited(get_config().limit.short_url_max_body_size)
+        .await
+    {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return Ok(MetaHttpResponse::bad_request(e)),
+        Err(_) => {
+            return Ok(HttpResponse::PayloadTooLarge()
+                .json(serde_json::json!({"error": "request body too large"})));
+        }
+    };
+    let mut req: config::meta::short_url::ShortenUrlRequest = match serde_json::from_slice(&body) {
         Ok(v) => v,
         Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
     };
+    if let Some(short_id) = req.short_id.as_deref() {
+        if let Err(e) = short_url::validate_short_id(short_id) {
+            return Ok(MetaHttpResponse::bad_request(e));
+        }
+        if short_url::is_reserved_short_id(short_id) {
+            return Ok(HttpResponse::UnprocessableEntity()
+                .json(serde_json::json!({"error": "short_id is reserved"})));
+        }
+    }
+    if let Some(namespace) = req.namespace.as_deref() {
+        if let Err(e) = short_url::validate_namespace(namespace, req.short_id.as_deref()) {
+            return Ok(MetaHttpResponse::bad_request(e));
+        }
+    }
+    if req.acl.as_ref().is_some_and(|acl| acl.is_empty()) {
+        return Ok(MetaHttpResponse::bad_request(
+            "acl must list at least one org_id",
+        ));
+    }
+    let max_url_length = get_config().limit.short_url_max_url_length;
+    if req.original_url.len() > max_url_length {
+        return Ok(MetaHttpResponse::unprocessable_entity(format!(
+            "original_url is longer than {max_url_length} bytes"
+        )));
+    }
+    req.original_url = match short_url::normalize_url(&req.original_url) {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::unprocessable_entity(e)),
+    };
+    if !short_url::is_domain_allowed(&req.original_url) {
+        return Ok(HttpResponse::UnprocessableEntity()
+            .json(serde_json::json!({"error": "domain not allowed"})));
+    }
+    if let Err(e) = short_url::validate_pipeline(&req.pipeline, &req.original_url) {
+        return Ok(MetaHttpResponse::unprocessable_entity(e));
+    }
+    req.created_by = in_req
+        .headers()
+        .get("user_id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
 
-    match short_url::shorten(&org_id, &req.original_url).await {
+    let idempotency_key = match in_req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(v) => match v.to_str() {
+            Ok(v) => Some(v.to_string()),
+            Err(_) => {
+                return Ok(MetaHttpResponse::unprocessable_entity(
+                    "Idempotency-Key must be printable ASCII without spaces",
+                ));
+            }
+        },
+        None => None,
+    };
+    if let Some(idempotency_key) = idempotency_key.as_deref() {
+        if let Err(e) = short_url::validate_idempotency_key(idempotency_key) {
+            return Ok(MetaHttpResponse::unprocessable_entity(e));
+        }
+        // a retry must not count against the quota a second time
+        match short_url::reserve_idempotency_key(&org_id, idempotency_key, &body).await {
+            Ok(IdempotencyKeyState::Reserved) => {}
+            Ok(IdempotencyKeyState::Created(short_url)) => {
+                return Ok(HttpResponse::Ok().json(ShortenUrlResponse { short_url }));
+            }
+            Ok(IdempotencyKeyState::InProgress) => {
+                return Ok(MetaHttpResponse::conflict(
+                    "a request with this Idempotency-Key is still in progress",
+                ));
+            }
+            Ok(IdempotencyKeyState::BodyMismatch) => {
+                return Ok(MetaHttpResponse::unprocessable_entity(
+                    "Idempotency-Key was already used with a different request body",
+                ));
+            }
+            Err(e) => {
+                log::error!(
+                    "[trace_id {trace_id}] Failed to reserve short URL idempotency key: {:?}",
+                    e
+                );
+                return Ok(internal_error(trace_id, e));
+            }
+        }
+    }
+
+    match short_url::quota_exceeded(&org_id).await {
+        Ok(None) => {}
+        Ok(Some((limit, current))) => {
+            release_idempotency_key(&org_id, idempotency_key.as_deref(), &trace_id).await;
+            return Ok(HttpResponse::TooManyRequests().json(serde_json::json!({
+                "error": "org quota exceeded",
+                "limit": limit,
+                "current": current,
+            })));
+        }
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to check short URL quota: {:?}",
+                e
+            );
+            release_idempotency_key(&org_id, idempotency_key.as_deref(), &trace_id).await;
+            return Ok(internal_error(trace_id, e));
+        }
+    }
+
+    match short_url::shorten(&org_id, &req)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
         Ok(short_url) => {
+            if let Some((_, short_id)) = short_url.rsplit_once(SHORT_PATH) {
+                audit::log(
+                    audit::AuditEvent::Created,
+                    &org_id,
+                    short_id,
+                    req.created_by.as_deref(),
+                );
+                if let Some(idempotency_key) = idempotency_key.as_deref() {
+                    // the short URL is created, a retry then waits for the key until it expires
+                    if let Err(e) =
+                        short_url::complete_idempotency_key(&org_id, idempotency_key, short_id)
+                            .await
+                    {
+                        log::error!(
+                            "[trace_id {trace_id}] Failed to complete short URL idempotency key: {:?}",
+                            e
+                        );
+                    }
+                }
+            }
             let response = ShortenUrlResponse {
                 short_url: short_url.clone(),
             };
 
             Ok(HttpResponse::Ok().json(response))
         }
+        Err(e) if matches!(e.downcast_ref(), Some(ShortUrlError::Conflict(_))) => {
+            release_idempotency_key(&org_id, idempotency_key.as_deref(), &trace_id).await;
+            Ok(MetaHttpResponse::conflict(format!(
+                "short_id {} is already in use",
+                req.short_id.unwrap_or_default()
+            )))
+        }
         Err(e) => {
-            log::error!("Failed to shorten URL: {:?}", e);
-            Ok(
-                HttpResponse::InternalServerError().json(meta::http::HttpResponse::error(
-                    StatusCode::INTERNAL_SERVER_ERROR.into(),
-                    e.to_string(),
-                )),
-            )
+            log::error!("[trace_id {trace_id}] Failed to shorten URL: {:?}", e);
+            release_idempotency_key(&org_id, idempotency_key.as_deref(), &trace_id).await;
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+// a request that did not create its short URL gives up its Idempotency-Key for the retries
+async fn release_idempotency_key(org_id: &str, idempotency_key: Option<&str>, trace_id: &str) {
+    let Some(idempotency_key) = idempotency_key else {
+        return;
+    };
+    if let Err(e) = short_url::release_idempotency_key(org_id, idempotency_key).await {
+        log::error!(
+            "[trace_id {trace_id}] Failed to release short URL idempotency key: {:?}",
+            e
+        );
+    }
+}
+
+/// List short URLs
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of short URLs to return"),
+        ("after_ts" = Option<i64>, Query, description = "Only return short URLs created before this timestamp, use the created_ts of the last item to get the next page"),
+        ("user" = Option<String>, Query, description = "Only return short URLs created by this user email, admins only, other users always get their own short URLs"),
+        ("offset" = Option<i64>, Query, description = "Number of short URLs to skip, at most ZO_SHORT_URL_MAX_OFFSET, the response then includes the total count, cannot be combined with after_ts"),
+        ("page" = Option<i64>, Query, description = "Page number starting at 1, sets offset to (page - 1) * limit, requires limit"),
+        ("sort_by" = Option<String>, Query, description = "created_ts (default), click_count, original_url or short_id"),
+        ("sort_dir" = Option<String>, Query, description = "asc or desc (default)"),
+    ),
+    responses(
+        (status = 200, description = "Short URLs, newest first unless sort_by is set", body = ListShortUrlResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid request", content_type = "application/json"),
+        (status = 403, description = "Listing the short URLs of another user requires the admin role", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short")]
+pub async fn list(org_id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let limit = match query.get("limit").map(|v| v.parse::<i64>()).transpose() {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    let after_ts = match query.get("after_ts").map(|v| v.parse::<i64>()).transpose() {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+
+    let offset = match query.get("offset").map(|v| v.parse::<i64>()).transpose() {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    let page = match query.get("page").map(|v| v.parse::<i64>()).transpose() {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    let offset = match (offset, page) {
+        (Some(_), Some(_)) => {
+            return Ok(MetaHttpResponse::bad_request(
+                "offset cannot be combined with page",
+            ));
+        }
+        (None, Some(page)) if page < 1 => {
+            return Ok(MetaHttpResponse::bad_request("page starts at 1"));
+        }
+        (None, Some(page)) => {
+            let Some(limit) = limit else {
+                return Ok(MetaHttpResponse::bad_request("page requires limit"));
+            };
+            Some((page - 1).saturating_mul(limit))
+        }
+        (offset, None) => offset,
+    };
+    // the db still reads and drops every skipped row, deep pages should use after_ts
+    let max_offset = get_config().limit.short_url_max_offset;
+    if let Some(offset) = offset {
+        if !(0..=max_offset).contains(&offset) {
+            return Ok(MetaHttpResponse::bad_request(format!(
+                "offset must be between 0 and {max_offset}, use after_ts to page further"
+            )));
+        }
+    }
+
+    if offset.is_some() && after_ts.is_some() {
+        return Ok(MetaHttpResponse::bad_request(
+            "offset cannot be combined with after_ts",
+        ));
+    }
+
+    let sort_by = match query
+        .get("sort_by")
+        .map(|v| v.parse::<SortBy>())
+        .transpose()
+    {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    let sort_dir = match query
+        .get("sort_dir")
+        .map(|v| v.parse::<SortDir>())
+        .transpose()
+    {
+        Ok(v) => v.unwrap_or_default(),
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    // the cursor is a created_ts, it only pages through the newest first order
+    if after_ts.is_some() && (sort_by != SortBy::CreatedTs || sort_dir != SortDir::Desc) {
+        return Ok(MetaHttpResponse::bad_request(
+            "after_ts only works with the default sort, use offset or page instead",
+        ));
+    }
+
+    // `created_by` is the name of the filter before `user`
+    let user = query
+        .get("user")
+        .or_else(|| query.get("created_by"))
+        .map(String::as_str);
+    let created_by = match req.headers().get("user_id").and_then(|v| v.to_str().ok()) {
+        Some(user_id) if is_org_admin(&org_id, user_id).await => user,
+        Some(user_id) if user.is_some_and(|user| user != user_id) => {
+            return Ok(MetaHttpResponse::forbidden(
+                "only admins can list the short URLs of other users",
+            ));
+        }
+        Some(user_id) => Some(user_id),
+        None => user,
+    };
+
+    let ret = match offset {
+        Some(offset) => {
+            short_url::list_page(&org_id, created_by, limit, Some(offset), sort_by, sort_dir)
+                .instrument(trace_span(&trace_id))
+                .await
+        }
+        None => {
+            short_url::list(&org_id, created_by, limit, after_ts, sort_by, sort_dir)
+                .instrument(trace_span(&trace_id))
+                .await
+        }
+    };
+    match ret {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!("[trace_id {trace_id}] Failed to list short URLs: {:?}", e);
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+async fn is_org_admin(org_id: &str, user_id: &str) -> bool {
+    rbac::user_role(org_id, user_id).await == Some(ShortUrlRole::Admin)
+}
+
+/// Answers 403 to the short url requests the role of the caller in the org does not allow,
+/// see `rbac::required_role`. Users outside the org have no role there and are turned away
+/// before any acl is read, see `short_url::AclDenied`. The role is left in the request
+/// extensions for the handlers
+pub async fn rbac_middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let prefix = format!("{}/api/", get_config().common.base_uri);
+    let Some((org_id, required)) = req
+        .path()
+        .strip_prefix(&prefix)
+        .and_then(|path| rbac::required_role(req.method().as_str(), path))
+        .map(|(org_id, required)| (org_id.to_string(), required))
+    else {
+        return Ok(next.call(req).await?.map_into_left_body());
+    };
+    let role = match req.headers().get("user_id").and_then(|v| v.to_str().ok()) {
+        Some(user_id) => rbac::user_role(&org_id, user_id).await,
+        None => None,
+    };
+    match role {
+        Some(role) if role >= required => {
+            req.extensions_mut().insert(role);
+            Ok(next.call(req).await?.map_into_left_body())
+        }
+        _ => Ok(req
+            .into_response(MetaHttpResponse::forbidden(format!(
+                "this short url endpoint requires the {required} role"
+            )))
+            .map_into_right_body()),
+    }
+}
+
+/// Search short URLs by original URL
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("q" = String, Query, description = "Text the original URL must contain, e.g. a domain or path prefix"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of short URLs to return"),
+    ),
+    responses(
+        (status = 200, description = "Matching short URLs, newest first", body = ListShortUrlResponse, content_type = "application/json"),
+        (status = 400, description = "Invalid request", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/_search")]
+pub async fn search(org_id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let Some(q) = query.get("q").filter(|v| !v.is_empty()) else {
+        return Ok(MetaHttpResponse::bad_request("q is required"));
+    };
+    let limit = match query.get("limit").map(|v| v.parse::<i64>()).transpose() {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+
+    match short_url::search(&org_id, q, limit)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!("[trace_id {trace_id}] Failed to search short URLs: {:?}", e);
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Click summary of the short URLs of an organization
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    responses(
+        (status = 200, description = "Totals and the ten most clicked short URLs", body = ShortUrlStatsResponse, content_type = "application/json", example = json!({
+            "total_urls": 2,
+            "total_clicks": 17,
+            "top_urls": []
+        }))
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/_stats")]
+pub async fn stats(org_id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    match short_url::stats(&org_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to get short URL stats: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Short URLs of an organization whose original URL looks broken
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    responses(
+        (status = 200, description = "Short URLs whose original URL answered the last link check with a 4xx or 5xx, newest first", body = ListShortUrlResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/_link_rot")]
+pub async fn link_rot(org_id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    match short_url::link_rot(&org_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(response) => Ok(HttpResponse::Ok().json(response)),
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to list short URLs with link rot: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Export the short URLs of an organization as CSV, requires the admin role
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("format" = Option<String>, Query, description = "Export format, only `csv` is supported"),
+    ),
+    responses(
+        (status = 200, description = "CSV with the columns short_id, original_url, created_ts, created_by, org_id, click_count, pinned", content_type = "text/csv"),
+        (status = 400, description = "Unsupported format", content_type = "application/json"),
+        (status = 403, description = "Exporting short URLs requires the admin role", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/_export")]
+pub async fn export(org_id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    if let Some(format) = query.get("format").filter(|format| *format != "csv") {
+        return Ok(MetaHttpResponse::bad_request(format!(
+            "unsupported export format {format:?}, only csv is supported"
+        )));
+    }
+    match short_url::export_csv(&org_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(rows) => {
+            let filename = format!(
+                "short_urls_{org_id}_{}.csv",
+                chrono::Utc::now().format("%Y-%m-%d")
+            );
+            Ok(HttpResponse::Ok()
+                .content_type("text/csv")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{filename}\""),
+                ))
+                .streaming(rows))
+        }
+        Err(e) => {
+            log::error!("[trace_id {trace_id}] Failed to export short URLs: {:?}", e);
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Time a round trip of a sentinel short URL through the short url db, requires the admin role
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    responses(
+        (status = 200, description = "Latency of add, get and remove in milliseconds and the db version", body = ShortUrlHealthReport, content_type = "application/json"),
+        (status = 403, description = "The health report requires the admin role", content_type = "application/json"),
+        (status = 503, description = "The round trip failed", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/_health")]
+pub async fn health(req: HttpRequest) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+
+    match infra::short_url::health_report()
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(report) => Ok(HttpResponse::Ok().json(report)),
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Short URL health report failed: {:?}",
+                e
+            );
+            let mut body = meta::http::HttpResponse::error(
+                StatusCode::SERVICE_UNAVAILABLE.into(),
+                e.to_string(),
+            );
+            body.trace_id = Some(trace_id);
+            Ok(HttpResponse::ServiceUnavailable().json(body))
+        }
+    }
+}
+
+/// Create short URLs from a CSV file
+///
+/// The file has a header and the columns `short_id`, `original_url`, `expires_at` (microseconds),
+/// `pinned` and `tags` (a JSON object), only the first two are required. Valid rows are created,
+/// rows whose short_id is in use are skipped and invalid rows are reported by line.
+#[utoipa::path(
+    post,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    request_body(content = String, description = "multipart/form-data with the CSV file, at most 10 MB and 10000 rows", content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "Created, skipped and invalid rows", body = ShortUrlBatchResponse, content_type = "application/json"),
+        (status = 400, description = "No CSV file, no short_id and original_url columns or more than 10000 rows", content_type = "application/json"),
+        (status = 413, description = "The file is larger than 10 MB", content_type = "application/json"),
+        (status = 429, description = "The rows would exceed the org quota", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[post("/{org_id}/short/_batch")]
+pub async fn batch_create(
+    org_id: web::Path<String>,
+    mut payload: Multipart,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    let mut data = Vec::new();
+    loop {
+        let mut field = match payload.try_next().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+        };
+        if field.content_disposition().get_filename().is_none() {
+            continue;
+        }
+        while let Some(chunk) = field.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+            };
+            if data.len() + chunk.len() > short_url::BATCH_MAX_FILE_SIZE {
+                return Ok(HttpResponse::PayloadTooLarge()
+                    .json(serde_json::json!({"error": "file too large"})));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        break;
+    }
+    if data.is_empty() {
+        return Ok(MetaHttpResponse::bad_request("no CSV file uploaded"));
+    }
+
+    let created_by = req.headers().get("user_id").and_then(|v| v.to_str().ok());
+    let (records, errors) = match short_url::parse_batch_csv(&org_id, created_by, &data) {
+        Ok(v) => v,
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    match short_url::quota_exceeded_by(&org_id, records.len() as i64).await {
+        Ok(None) => {}
+        Ok(Some((limit, current))) => {
+            return Ok(HttpResponse::TooManyRequests().json(serde_json::json!({
+                "error": "org quota exceeded",
+                "limit": limit,
+                "current": current,
+            })));
+        }
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to check short URL quota: {:?}",
+                e
+            );
+            return Ok(internal_error(trace_id, e));
+        }
+    }
+
+    match short_url::batch_create(&org_id, records)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok((inserted, duplicates)) => {
+            for short_id in inserted.iter() {
+                audit::log(audit::AuditEvent::Created, &org_id, short_id, created_by);
+            }
+            Ok(HttpResponse::Ok().json(ShortUrlBatchResponse {
+                inserted,
+                duplicates,
+                errors,
+            }))
+        }
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to batch create short URLs: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Generate or rotate the caller's short url api key
+///
+/// The key can be passed as `?api_key=` instead of the Authorization header when creating
+/// short URLs, e.g. from a bookmarklet, and is only returned by this call.
+#[utoipa::path(
+    post,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+    ),
+    responses(
+        (status = 200, description = "The new api key, the previous one stops working", body = ShortUrlApiKeyResponse, content_type = "application/json"),
+        (status = 403, description = "Not a member of the organization", content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[post("/{org_id}/short/_api_key")]
+pub async fn rotate_api_key(
+    org_id: web::Path<String>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let trace_id = get_trace_id(&req);
+    let Some(user_id) = req.headers().get("user_id").and_then(|v| v.to_str().ok()) else {
+        return Ok(MetaHttpResponse::forbidden("unknown user"));
+    };
+    if rbac::user_role(&org_id, user_id).await.is_none() {
+        return Ok(MetaHttpResponse::forbidden(
+            "not a member of this organization",
+        ));
+    }
+
+    match short_url::rotate_api_key(&org_id, user_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(api_key) => Ok(HttpResponse::Ok().json(ShortUrlApiKeyResponse { api_key })),
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to rotate short URL api key: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
         }
     }
 }
@@ -83,15 +856,26 @@ pub async fn shorten(org_id: web::Path<String>, body: web::Bytes) -> Result<Http
 /// Retrieve the original URL from a short_id
 #[utoipa::path(
     get,
-    context_path = "/short",
+    context_path = "/api",
     params(
+        ("org_id" = String, Path, description = "Organization name"),
         ("short_id" = String, Path, description = "The short ID to retrieve the original URL", example = "ddbffcea3ad44292")
     ),
     responses(
+        (status = 301, description = "Permanent redirect to the original URL", headers(
+            ("Location" = String, description = "The original URL to which the client is redirected")
+        )),
         (status = 302, description = "Redirect to the original URL", headers(
             ("Location" = String, description = "The original URL to which the client is redirected")
         )),
-        (status = 404, description = "Short URL not found", content_type = "text/plain")
+        (status = 302, description = "Redirect to ZO_SHORT_URL_NOT_FOUND_REDIRECT when the short URL is not found"),
+        (status = 400, description = "The Short-URL-Redirect-Depth request header reached ZO_SHORT_URL_MAX_REDIRECT_DEPTH", content_type = "application/json"),
+        (status = 403, description = "The user is in none of the orgs of the short URL's acl. Only members of org_id reach this route, unauthenticated callers are redirected to the login page, so the acl narrows access within org_id", content_type = "application/json"),
+        (status = 503, description = "Too many redirects in flight, retry after the Retry-After header", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json", example = json!({
+            "error": "short_url not found",
+            "short_id": "ddbffcea3ad44292"
+        }))
     ),
     tag = "Short Url"
 )]
@@ -100,19 +884,475 @@ pub async fn retrieve(
     req: HttpRequest,
     path: web::Path<(String, String)>,
 ) -> Result<HttpResponse, Error> {
+    let (org_id, short_id) = path.into_inner();
+    redirect(&req, &org_id, &short_id).await
+}
+
+/// Redirect to the original URL of the short_id given as `?id=`, for clients that drop the
+/// last path segment of a link but keep its query string
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("id" = String, Query, description = "The short ID to retrieve the original URL", example = "ddbffcea3ad44292")
+    ),
+    responses(
+        (status = 301, description = "Permanent redirect to the original URL", headers(
+            ("Location" = String, description = "The original URL to which the client is redirected")
+        )),
+        (status = 302, description = "Redirect to the original URL", headers(
+            ("Location" = String, description = "The original URL to which the client is redirected")
+        )),
+        (status = 400, description = "No short ID in the path or the query", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/")]
+pub async fn retrieve_by_query(
+    req: HttpRequest,
+    org_id: web::Path<String>,
+) -> Result<HttpResponse, Error> {
+    let Some(short_id) = requested_short_id(req.uri()) else {
+        return Ok(MetaHttpResponse::bad_request(
+            "short_id is required in the path or as the id query parameter",
+        ));
+    };
+    redirect(&req, &org_id, &short_id).await
+}
+
+/// The short_id after `/short/` in the path, or the `id` query parameter when the path has none
+fn requested_short_id(uri: &actix_web::http::Uri) -> Option<String> {
+    let from_path = uri
+        .path()
+        .rsplit_once(SHORT_PATH)
+        .map(|(_, short_id)| short_id)
+        .filter(|short_id| !short_id.is_empty() && !short_id.contains('/'))
+        .map(str::to_string);
+    from_path.or_else(|| {
+        url::form_urlencoded::parse(uri.query()?.as_bytes())
+            .find(|(key, _)| key == "id")
+            .map(|(_, short_id)| short_id.trim().to_string())
+            .filter(|short_id| !short_id.is_empty())
+    })
+}
+
+async fn redirect(req: &HttpRequest, org_id: &str, short_id: &str) -> Result<HttpResponse, Error> {
     log::info!(
         "short_url::retrieve handler called for path: {}",
         req.path()
     );
-    let (_org_id, short_id) = path.into_inner();
-    let original_url = short_url::retrieve(&short_id).await;
+    let Some(_permit) = load_shed::REDIRECT_LIMITER.try_acquire() else {
+        return Ok(HttpResponse::ServiceUnavailable()
+            .insert_header((header::RETRY_AFTER, "1"))
+            .json(meta::http::HttpResponse::error(
+                StatusCode::SERVICE_UNAVAILABLE.into(),
+                "too many short url redirects in flight, retry after 1s".to_string(),
+            )));
+    };
+    let trace_id = get_trace_id(req);
+    let depth = redirect_depth(req.headers());
+    let max_depth = get_config().limit.short_url_max_redirect_depth;
+    if max_depth > 0 && depth >= max_depth {
+        log::warn!(
+            "[trace_id {trace_id}] Short URL {short_id} reached the max redirect depth {max_depth}"
+        );
+        return Ok(MetaHttpResponse::bad_request(format!(
+            "redirect chain reached the max depth {max_depth}"
+        )));
+    }
+    if let Some(if_none_match) = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+    {
+        // a cached short URL is answered without a db call, no click is counted
+        if let Some(etag) = short_url::current_etag(org_id, short_id)
+            .instrument(trace_span(&trace_id))
+            .await
+        {
+            if etag_matches(if_none_match, &etag) {
+                return Ok(HttpResponse::NotModified()
+                    .insert_header((header::ETAG, etag))
+                    .insert_header((header::CACHE_CONTROL, REDIRECT_CACHE_CONTROL))
+                    .finish());
+            }
+        }
+    }
+    let user_id = req.headers().get("user_id").and_then(|v| v.to_str().ok());
+    let original_url = match short_url::retrieve(org_id, short_id, user_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(record) => record,
+        Err(AclDenied) => {
+            log::warn!("[trace_id {trace_id}] Short URL {short_id} denied by its acl");
+            return Ok(MetaHttpResponse::forbidden(
+                "short URL is restricted to other organizations",
+            ));
+        }
+    };
 
-    if let Some(url) = original_url {
-        let redirect_http = RedirectResponseBuilder::new(&url).build().redirect_http();
+    if let Some(record) = original_url {
+        let user_agent = req
+            .headers()
+            .get(actix_web::http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        let client_ip = client_ip(&req, &get_config().limit.short_url_trusted_proxies);
+        short_url::notify_click(&record, user_agent, &client_ip);
+        short_url::log_access(&record, user_agent, &client_ip);
+
+        let target = match short_url::redirect_target(&record) {
+            Ok(target) => target,
+            Err(e) => {
+                log::error!(
+                    "[trace_id {trace_id}] Failed to apply the pipeline of {short_id}: {e}"
+                );
+                return Ok(internal_error(trace_id, e));
+            }
+        };
+        let mut redirect_http = RedirectResponseBuilder::new(&target)
+            .with_permanent(record.permanent)
+            .build()
+            .redirect_http();
+        let headers = redirect_http.headers_mut();
+        if let Ok(etag) = header::HeaderValue::from_str(&short_url::redirect_etag(&record)) {
+            headers.insert(header::ETAG, etag);
+        }
+        headers.insert(
+            header::CACHE_CONTROL,
+            header::HeaderValue::from_static(REDIRECT_CACHE_CONTROL),
+        );
+        headers.insert(
+            header::HeaderName::from_static(REDIRECT_DEPTH_HEADER),
+            header::HeaderValue::from(depth.saturating_add(1)),
+        );
         Ok(redirect_http)
     } else {
-        let redirect = RedirectResponseBuilder::default().build();
-        log::error!("Short URL not found, {}", &redirect);
-        Ok(redirect.redirect_http())
+        Ok(not_found(&trace_id, short_id))
+    }
+}
+
+/// Redirects already taken by the client according to the `Short-URL-Redirect-Depth` header,
+/// 0 when it is absent or not a number
+fn redirect_depth(headers: &header::HeaderMap) -> u32 {
+    headers
+        .get(REDIRECT_DEPTH_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or_default()
+}
+
+/// Weak comparison of an `If-None-Match` list against the current ETag
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = opaque(etag);
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// Update a short URL, only `pinned` can be changed for now
+#[utoipa::path(
+    patch,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("short_id" = String, Path, description = "The short ID to update", example = "ddbffcea3ad44292")
+    ),
+    request_body(content = ShortUrlPatchRequest, description = "Fields to change", content_type = "application/json", example = json!({
+        "pinned": true
+    })),
+    responses(
+        (status = 200, description = "Short URL updated", content_type = "application/json"),
+        (status = 400, description = "Invalid request", content_type = "application/json"),
+        (status = 403, description = "Editors can only change the short URLs they created", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[patch("/{org_id}/short/{short_id}")]
+pub async fn update(
+    path: web::Path<(String, String)>,
+    body: web::Json<ShortUrlPatchRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (org_id, short_id) = path.into_inner();
+    let trace_id = get_trace_id(&req);
+    let Some(pinned) = body.pinned else {
+        return Ok(MetaHttpResponse::bad_request("nothing to update"));
+    };
+    // the role is set by `rbac_middleware`, an editor only changes its own short URLs
+    let user_id = req
+        .headers()
+        .get("user_id")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let role = req.extensions().get::<ShortUrlRole>().copied();
+    let allowed = match role {
+        Some(role) => short_url::may_modify(&org_id, &short_id, user_id, role).await,
+        None => false,
+    };
+    if !allowed {
+        return Ok(MetaHttpResponse::forbidden(
+            "only admins can change the short URLs of other users",
+        ));
+    }
+
+    match short_url::set_pinned(&org_id, &short_id, pinned)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(()) => {
+            audit::log(
+                audit::AuditEvent::Updated,
+                &org_id,
+                &short_id,
+                req.headers().get("user_id").and_then(|v| v.to_str().ok()),
+            );
+            Ok(MetaHttpResponse::ok(format!(
+                "short url {short_id} {}",
+                if pinned { "pinned" } else { "unpinned" }
+            )))
+        }
+        Err(e) if matches!(e.downcast_ref(), Some(ShortUrlError::NotFound(_))) => {
+            Ok(HttpResponse::NotFound().json(ShortUrlNotFoundResponse::new(&short_id)))
+        }
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to update short URL {short_id}: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Retrieve the metadata of a short_id without redirecting
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("short_id" = String, Path, description = "The short ID to preview", example = "ddbffcea3ad44292")
+    ),
+    responses(
+        (status = 200, description = "Metadata of the short URL", body = ShortUrlPreviewResponse, content_type = "application/json", example = json!({
+            "short_id": "ddbffcea3ad44292",
+            "original_url": "https://example.com/web/logs?stream=default",
+            "created_ts": 1724930507759294_i64
+        })),
+        (status = 403, description = "The user is in none of the orgs of the short URL's acl", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/{short_id}/preview")]
+pub async fn preview(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (org_id, short_id) = path.into_inner();
+    let trace_id = get_trace_id(&req);
+    let user_id = req.headers().get("user_id").and_then(|v| v.to_str().ok());
+    match short_url::preview(&org_id, &short_id, user_id)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(Some(response)) => Ok(HttpResponse::Ok().json(response)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ShortUrlNotFoundResponse::new(&short_id))),
+        Err(AclDenied) => Ok(MetaHttpResponse::forbidden(
+            "short URL is restricted to other organizations",
+        )),
+    }
+}
+
+fn not_found(trace_id: &str, short_id: &str) -> HttpResponse {
+    let redirect = &get_config().limit.short_url_not_found_redirect;
+    if redirect.is_empty() {
+        log::warn!("[trace_id {trace_id}] Short URL not found: {short_id}");
+        return HttpResponse::NotFound().json(ShortUrlNotFoundResponse::new(short_id));
+    }
+    let redirect = RedirectResponseBuilder::new(redirect).build();
+    log::warn!("[trace_id {trace_id}] Short URL not found: {short_id}, {redirect}");
+    redirect.redirect_http()
+}
+
+/// Render the short URL of a short_id as a PNG QR code
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("short_id" = String, Path, description = "The short ID to render", example = "ddbffcea3ad44292"),
+        ("size" = Option<u32>, Query, description = "Pixels per module, default 10, max 50"),
+        ("margin" = Option<u32>, Query, description = "Quiet zone width in modules, default 4, max 16"),
+    ),
+    responses(
+        (status = 200, description = "QR code of the short URL", content_type = "image/png"),
+        (status = 400, description = "Invalid request", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/{short_id}/qr")]
+pub async fn qr_code(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let (org_id, short_id) = path.into_inner();
+    let trace_id = get_trace_id(&req);
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).unwrap();
+    let size = match query.get("size").map(|v| v.parse::<u32>()).transpose() {
+        Ok(v) => v.unwrap_or(QR_DEFAULT_SIZE),
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    if !(1..=QR_MAX_SIZE).contains(&size) {
+        return Ok(MetaHttpResponse::bad_request(format!(
+            "size must be between 1 and {QR_MAX_SIZE}"
+        )));
+    }
+    let margin = match query.get("margin").map(|v| v.parse::<u32>()).transpose() {
+        Ok(v) => v.unwrap_or(QR_DEFAULT_MARGIN),
+        Err(e) => return Ok(MetaHttpResponse::bad_request(e)),
+    };
+    if margin > QR_MAX_MARGIN {
+        return Ok(MetaHttpResponse::bad_request(format!(
+            "margin must be at most {QR_MAX_MARGIN}"
+        )));
+    }
+
+    match short_url::qr_code(&org_id, &short_id, size, margin)
+        .instrument(trace_span(&trace_id))
+        .await
+    {
+        Ok(Some(png)) => Ok(HttpResponse::Ok().content_type("image/png").body(png)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ShortUrlNotFoundResponse::new(&short_id))),
+        Err(e) => {
+            log::error!(
+                "[trace_id {trace_id}] Failed to render QR code for {short_id}: {:?}",
+                e
+            );
+            Ok(internal_error(trace_id, e))
+        }
+    }
+}
+
+/// Retrieve the original URL from a short_id in a namespace of the org
+#[utoipa::path(
+    get,
+    context_path = "/api",
+    params(
+        ("org_id" = String, Path, description = "Organization name"),
+        ("namespace" = String, Path, description = "Namespace of the short ID", example = "eng"),
+        ("short_id" = String, Path, description = "The short ID in the namespace", example = "onboarding")
+    ),
+    responses(
+        (status = 301, description = "Permanent redirect to the original URL", headers(
+            ("Location" = String, description = "The original URL to which the client is redirected")
+        )),
+        (status = 302, description = "Redirect to the original URL", headers(
+            ("Location" = String, description = "The original URL to which the client is redirected")
+        )),
+        (status = 302, description = "Redirect to ZO_SHORT_URL_NOT_FOUND_REDIRECT when the short URL is not found"),
+        (status = 400, description = "The Short-URL-Redirect-Depth request header reached ZO_SHORT_URL_MAX_REDIRECT_DEPTH", content_type = "application/json"),
+        (status = 403, description = "The user is in none of the orgs of the short URL's acl. Only members of org_id reach this route, unauthenticated callers are redirected to the login page, so the acl narrows access within org_id", content_type = "application/json"),
+        (status = 503, description = "Too many redirects in flight, retry after the Retry-After header", content_type = "application/json"),
+        (status = 404, description = "Short URL not found", body = ShortUrlNotFoundResponse, content_type = "application/json")
+    ),
+    tag = "Short Url"
+)]
+#[get("/{org_id}/short/{namespace}/{short_id}")]
+pub async fn retrieve_namespaced(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+) -> Result<HttpResponse, Error> {
+    let (org_id, namespace, short_id) = path.into_inner();
+    let short_id = infra::short_url::id::namespaced_short_id(&namespace, &short_id);
+    redirect(&req, &org_id, &short_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::Uri;
+
+    use super::*;
+
+    #[test]
+    fn test_redirect_depth() {
+        let mut headers = header::HeaderMap::new();
+        assert_eq!(redirect_depth(&headers), 0);
+        headers.insert(
+            header::HeaderName::from_static(REDIRECT_DEPTH_HEADER),
+            header::HeaderValue::from_static("2"),
+        );
+        assert_eq!(redirect_depth(&headers), 2);
+        headers.insert(
+            header::HeaderName::from_static(REDIRECT_DEPTH_HEADER),
+            header::HeaderValue::from_static("many"),
+        );
+        assert_eq!(redirect_depth(&headers), 0);
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let req = |peer: &str| {
+            actix_web::test::TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("x-forwarded-for", "203.0.113.7"))
+                .to_http_request()
+        };
+        // an untrusted peer can't pick its own ip
+        assert_eq!(client_ip(&req("198.51.100.1:4000"), ""), "198.51.100.1");
+        assert_eq!(
+            client_ip(&req("198.51.100.1:4000"), "10.0.0.1"),
+            "198.51.100.1"
+        );
+        assert_eq!(
+            client_ip(&req("10.0.0.1:4000"), "10.0.0.2, 10.0.0.1"),
+            "203.0.113.7"
+        );
+        let direct = actix_web::test::TestRequest::default()
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_http_request();
+        assert_eq!(client_ip(&direct, "10.0.0.1"), "10.0.0.1");
+    }
+
+    #[test]
+    fn test_requested_short_id() {
+        let short_id = |uri: &str| requested_short_id(&uri.parse::<Uri>().unwrap());
+        assert_eq!(
+            short_id("/api/default/short/abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            short_id("/api/default/short/?id=abc123"),
+            Some("abc123".to_string())
+        );
+        // the path wins over the query
+        assert_eq!(
+            short_id("/api/default/short/abc123?id=other"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(
+            short_id("/api/default/short/?utm=x&id=abc%20123"),
+            Some("abc 123".to_string())
+        );
+        assert_eq!(short_id("/api/default/short/"), None);
+        assert_eq!(short_id("/api/default/short/?id="), None);
+        assert_eq!(short_id("/api/default/short/?other=abc123"), None);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        let etag = r#"W/"18d1-ab""#;
+        assert!(etag_matches(r#"W/"18d1-ab""#, etag));
+        assert!(etag_matches(r#""18d1-ab""#, etag));
+        assert!(etag_matches(r#"W/"other", W/"18d1-ab""#, etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches(r#"W/"18d1-ac""#, etag));
+        assert!(!etag_matches("", etag));
     }
 }
@@ -0,0 +1,124 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use config::get_config;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+
+// drop idle buckets once the map grows beyond this many keys
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+pub static RATE_LIMITER: Lazy<ShortUrlRateLimiter> =
+    Lazy::new(|| ShortUrlRateLimiter::new(get_config().limit.short_url_rate_limit_per_min));
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// In-memory token bucket rate limiter for short URL creation, keyed by org_id and client ip
+pub struct ShortUrlRateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: DashMap<String, TokenBucket>,
+}
+
+impl ShortUrlRateLimiter {
+    /// `per_min` requests are allowed per minute and key, 0 disables the limit
+    pub fn new(per_min: u32) -> Self {
+        Self {
+            capacity: per_min as f64,
+            refill_per_sec: per_min as f64 / 60.0,
+            buckets: DashMap::new(),
+        }
+    }
+
+    /// Takes a token for the key, returns how long to wait when the bucket is exhausted
+    pub fn check(&self, org_id: &str, ip: &str) -> Result<(), Duration> {
+        self.check_at(org_id, ip, Instant::now())
+    }
+
+    fn check_at(&self, org_id: &str, ip: &str, now: Instant) -> Result<(), Duration> {
+        if self.capacity == 0.0 {
+            return Ok(());
+        }
+        if self.buckets.len() > MAX_IDLE_BUCKETS {
+            self.evict_idle(now);
+        }
+
+        let mut bucket = self
+            .buckets
+            .entry(format!("{org_id}/{ip}"))
+            .or_insert_with(|| TokenBucket {
+                tokens: self.capacity,
+                last_refill: now,
+            });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / self.refill_per_sec;
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+
+    // a bucket idle for a full refill period is back at capacity and can be dropped
+    fn evict_idle(&self, now: Instant) {
+        let full_refill = Duration::from_secs_f64(self.capacity / self.refill_per_sec);
+        self.buckets
+            .retain(|_, bucket| now.saturating_duration_since(bucket.last_refill) < full_refill);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter_exhausts_and_refills() {
+        let limiter = ShortUrlRateLimiter::new(2);
+        let now = Instant::now();
+        assert!(limiter.check_at("default", "127.0.0.1", now).is_ok());
+        assert!(limiter.check_at("default", "127.0.0.1", now).is_ok());
+        let wait = limiter
+            .check_at("default", "127.0.0.1", now)
+            .expect_err("bucket should be exhausted");
+        assert_eq!(wait.as_secs(), 30);
+
+        // other keys have their own bucket
+        assert!(limiter.check_at("default", "10.0.0.1", now).is_ok());
+        assert!(limiter.check_at("other", "127.0.0.1", now).is_ok());
+
+        let later = now + Duration::from_secs(30);
+        assert!(limiter.check_at("default", "127.0.0.1", later).is_ok());
+        assert!(limiter.check_at("default", "127.0.0.1", later).is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter_disabled() {
+        let limiter = ShortUrlRateLimiter::new(0);
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert!(limiter.check_at("default", "127.0.0.1", now).is_ok());
+        }
+    }
+}
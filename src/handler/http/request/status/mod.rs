@@ -179,6 +179,28 @@ pub async fn schedulez() -> Result<HttpResponse, Error> {
     })
 }
 
+/// Readiness of the node, not ready while the short_urls store is unreachable
+#[utoipa::path(
+    path = "/readyz",
+    tag = "Meta",
+    responses(
+        (status = 200, description="Status OK", content_type = "application/json", body = HealthzResponse, example = json!({"status": "ok"})),
+        (status = 503, description="Status Not OK", content_type = "application/json", body = HealthzResponse, example = json!({"status": "not ok"})),
+    )
+)]
+#[get("/readyz")]
+pub async fn readyz() -> Result<HttpResponse, Error> {
+    if let Err(e) = infra::short_url::ping().await {
+        log::error!("[READYZ] short_urls store is unreachable: {}", e);
+        return Ok(HttpResponse::ServiceUnavailable().json(HealthzResponse {
+            status: "not ok".to_string(),
+        }));
+    }
+    Ok(HttpResponse::Ok().json(HealthzResponse {
+        status: "ok".to_string(),
+    }))
+}
+
 #[get("")]
 pub async fn zo_config() -> Result<HttpResponse, Error> {
     #[cfg(feature = "enterprise")]
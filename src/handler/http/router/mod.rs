@@ -57,6 +57,7 @@ fn get_cors() -> Rc<Cors> {
             header::ACCEPT,
             header::CONTENT_TYPE,
             header::HeaderName::from_lowercase(b"traceparent").unwrap(),
+            header::HeaderName::from_lowercase(b"x-trace-id").unwrap(),
         ])
         .allow_any_origin()
         .supports_credentials()
@@ -210,7 +211,8 @@ pub fn get_basic_routes(cfg: &mut web::ServiceConfig) {
     let cors = get_cors();
     cfg.service(status::healthz)
         .service(status::healthz_head)
-        .service(status::schedulez);
+        .service(status::schedulez)
+        .service(status::readyz);
     cfg.service(
         web::scope("/auth")
             .wrap(cors.clone())
@@ -325,6 +327,7 @@ pub fn get_service_routes(cfg: &mut web::ServiceConfig) {
 
     cfg.service(
         web::scope("/api")
+            .wrap(from_fn(short_url::rbac_middleware))
             .wrap(from_fn(audit_middleware))
             .wrap(HttpAuthentication::with_fn(
                 super::auth::validator::oo_validator,
@@ -491,7 +494,20 @@ pub fn get_service_routes(cfg: &mut web::ServiceConfig) {
             .service(search::multi_streams::around_multi)
             .service(stream::delete_stream_cache)
             .service(short_url::shorten)
-            .service(short_url::retrieve),
+            .service(short_url::list)
+            .service(short_url::search)
+            .service(short_url::stats)
+            .service(short_url::link_rot)
+            .service(short_url::export)
+            .service(short_url::batch_create)
+            .service(short_url::health)
+            .service(short_url::rotate_api_key)
+            .service(short_url::retrieve)
+            .service(short_url::retrieve_by_query)
+            .service(short_url::update)
+            .service(short_url::preview)
+            .service(short_url::qr_code)
+            .service(short_url::retrieve_namespaced),
     );
 }
 
@@ -116,7 +116,20 @@ use crate::{common::meta, handler::http::request};
         request::syslog::delete_route,
         request::clusters::list_clusters,
         request::short_url::shorten,
+        request::short_url::list,
+        request::short_url::search,
+        request::short_url::stats,
+        request::short_url::link_rot,
+        request::short_url::export,
+        request::short_url::batch_create,
+        request::short_url::health,
+        request::short_url::rotate_api_key,
         request::short_url::retrieve,
+        request::short_url::retrieve_by_query,
+        request::short_url::update,
+        request::short_url::preview,
+        request::short_url::qr_code,
+        request::short_url::retrieve_namespaced,
     ),
     components(
         schemas(
@@ -218,6 +231,19 @@ use crate::{common::meta, handler::http::request};
             meta::syslog::SyslogRoutes,
             meta::prom::Metadata,
             meta::prom::MetricType,
+            config::meta::short_url::ShortenUrlRequest,
+            config::meta::short_url::ShortenUrlResponse,
+            config::meta::short_url::ShortUrlItem,
+            config::meta::short_url::ListShortUrlResponse,
+            config::meta::short_url::ShortUrlNotFoundResponse,
+            config::meta::short_url::ShortUrlPreviewResponse,
+            config::meta::short_url::ShortUrlPatchRequest,
+            config::meta::short_url::ShortUrlStatsResponse,
+            config::meta::short_url::ShortUrlApiKeyResponse,
+            config::meta::short_url::ShortUrlBatchResponse,
+            config::meta::short_url::ShortUrlBatchRowError,
+            config::meta::short_url::ShortUrlHealthReport,
+            config::meta::short_url::UrlTransform,
          ),
     ),
     modifiers(&SecurityAddon),
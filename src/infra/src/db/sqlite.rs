@@ -60,6 +60,9 @@ fn connect_rw() -> Pool<Sqlite> {
     let idle_timeout = zero_or(cfg.limit.sql_db_connections_idle_timeout, 600);
     let max_lifetime = zero_or(cfg.limit.sql_db_connections_max_lifetime, 1800);
 
+    // WAL lets readers on CLIENT_RO run while the single writer commits, and NORMAL
+    // synchronous is durable in WAL mode without an fsync per transaction. The busy timeout
+    // covers the short window where a WAL checkpoint holds the lock.
     let db_opts = SqliteConnectOptions::from_str(&url)
         .expect("sqlite connect options create failed")
         .journal_mode(SqliteJournalMode::Wal)
@@ -69,6 +72,8 @@ fn connect_rw() -> Pool<Sqlite> {
         // .disable_statement_logging()
         .create_if_missing(true);
 
+    // SQLite only allows one writer at a time, a single connection behind the CLIENT_RW mutex
+    // queues writers in-process instead of failing them with SQLITE_BUSY
     SqlitePoolOptions::new()
         .min_connections(1)
         .max_connections(1)
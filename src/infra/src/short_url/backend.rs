@@ -0,0 +1,522 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use config::meta::{meta_store::MetaStore, short_url::ShortUrlHealthReport};
+use futures::{future::BoxFuture, stream::BoxStream};
+use hashbrown::HashMap;
+use tracing::{Instrument, Span};
+
+use crate::short_url::{
+    error::{Result, ShortUrlError},
+    mysql::MysqlShortUrl,
+    postgres::PostgresShortUrl,
+    sqlite::SqliteShortUrl,
+    tx::ShortUrlTx,
+    AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+    ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir, TABLE_NAME,
+};
+
+const SLOW_QUERY_MAX_PARAMS_LEN: usize = 1024;
+
+/// The short url backend, dispatched statically to the selected db
+pub enum ShortUrlBackend {
+    Mysql(MysqlShortUrl),
+    Postgres(PostgresShortUrl),
+    Sqlite(SqliteShortUrl),
+}
+
+impl ShortUrlBackend {
+    /// Select the backend from `ZO_SHORT_URL_BACKEND`, falls back to `ZO_META_STORE`
+    pub fn from_config() -> Self {
+        let cfg = config::get_config();
+        let backend = if cfg.limit.short_url_backend.is_empty() {
+            cfg.common.meta_store.as_str()
+        } else {
+            cfg.limit.short_url_backend.as_str()
+        };
+        Self::from_name(backend)
+    }
+
+    /// The backend for a store name as in `ZO_META_STORE`, anything unknown is sqlite
+    pub fn from_name(name: &str) -> Self {
+        match name.into() {
+            MetaStore::MySQL => Self::Mysql(MysqlShortUrl::default()),
+            MetaStore::PostgreSQL => Self::Postgres(PostgresShortUrl::default()),
+            _ => Self::Sqlite(SqliteShortUrl::default()),
+        }
+    }
+
+    /// The `db.system` of the backend, named as in the OpenTelemetry conventions
+    pub fn system(&self) -> &'static str {
+        match self {
+            Self::Mysql(_) => "mysql",
+            Self::Postgres(_) => "postgresql",
+            Self::Sqlite(_) => "sqlite",
+        }
+    }
+
+    fn span(&self, operation: &'static str) -> Span {
+        tracing::info_span!(
+            "short_url:db",
+            db.system = self.system(),
+            db.operation = operation,
+            db.sql.table = TABLE_NAME.as_str(),
+            elapsed_ms = tracing::field::Empty,
+        )
+    }
+
+    fn log_if_slow(&self, operation: &str, elapsed: Duration, params: impl FnOnce() -> String) {
+        let threshold = config::get_config().limit.short_url_slow_query_threshold_ms;
+        if threshold == 0 || elapsed < Duration::from_millis(threshold) {
+            return;
+        }
+        let params: String = params().chars().take(SLOW_QUERY_MAX_PARAMS_LEN).collect();
+        tracing::warn!(
+            db.system = self.system(),
+            db.operation = operation,
+            db.sql.table = TABLE_NAME.as_str(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            params = %redact_urls(&params),
+            "[SHORT_URL] slow query"
+        );
+    }
+}
+
+/// Urls can carry tokens or personal data in their path and query, only their scheme and host
+/// are kept
+fn redact_urls(params: &str) -> String {
+    let mut redacted = String::with_capacity(params.len());
+    let mut rest = params;
+    while let Some(pos) = rest.find("http") {
+        let (before, candidate) = rest.split_at(pos);
+        redacted.push_str(before);
+        let Some(scheme) = ["https://", "http://"]
+            .into_iter()
+            .find(|scheme| candidate.starts_with(scheme))
+        else {
+            redacted.push_str("http");
+            rest = &candidate[4..];
+            continue;
+        };
+        let end = candidate
+            .find(|c: char| c == '"' || c.is_whitespace())
+            .unwrap_or(candidate.len());
+        let url = &candidate[..end];
+        let host_end = url[scheme.len()..]
+            .find(|c: char| matches!(c, '/' | '?' | '#'))
+            .map_or(url.len(), |i| scheme.len() + i);
+        redacted.push_str(&url[..host_end]);
+        if host_end < url.len() {
+            redacted.push_str("/<redacted>");
+        }
+        rest = &candidate[end..];
+    }
+    redacted.push_str(rest);
+    redacted
+}
+
+// every call runs in a `short_url:db` span, is logged when slower than
+// `ZO_SHORT_URL_SLOW_QUERY_THRESHOLD_MS` and fails once it takes longer than
+// `ZO_SHORT_URL_DB_QUERY_TIMEOUT_SECS`
+macro_rules! dispatch {
+    (@call $self:ident.$method:ident($($arg:ident),*)) => {
+        match $self {
+            Self::Mysql(backend) => backend.$method($($arg),*).await,
+            Self::Postgres(backend) => backend.$method($($arg),*).await,
+            Self::Sqlite(backend) => backend.$method($($arg),*).await,
+        }
+    };
+    (@timed $self:ident.$method:ident($($arg:ident),*), $params:expr, $timeout:ident) => {{
+        let operation = stringify!($method);
+        let span = $self.span(operation);
+        let start = Instant::now();
+        let ret = $timeout(operation, async { dispatch!(@call $self.$method($($arg),*)) })
+            .instrument(span.clone())
+            .await;
+        let elapsed = start.elapsed();
+        span.record("elapsed_ms", elapsed.as_millis() as u64);
+        $self.log_if_slow(operation, elapsed, $params);
+        ret
+    }};
+    // arguments moved into the call are not logged
+    ($self:ident.$method:ident($($arg:ident),*) moved) => {
+        dispatch!(@timed $self.$method($($arg),*), String::new, with_query_timeout)
+    };
+    // schema changes may run long on a big table, and `len` and `is_empty` can not fail
+    ($self:ident.$method:ident($($arg:ident),*) no_timeout) => {
+        dispatch!(@timed $self.$method($($arg),*), || format!("{:?}", ($(&$arg,)*)), without_timeout)
+    };
+    ($self:ident.$method:ident($($arg:ident),*)) => {
+        dispatch!(@timed $self.$method($($arg),*), || format!("{:?}", ($(&$arg,)*)), with_query_timeout)
+    };
+}
+
+async fn with_query_timeout<T>(operation: &str, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    let secs = config::get_config().limit.short_url_db_query_timeout_secs;
+    if secs == 0 {
+        return fut.await;
+    }
+    match tokio::time::timeout(Duration::from_secs(secs), fut).await {
+        Ok(ret) => ret,
+        Err(_) => Err(ShortUrlError::Timeout(format!("{operation} after {secs}s"))),
+    }
+}
+
+async fn without_timeout<T>(_operation: &str, fut: impl Future<Output = T>) -> T {
+    fut.await
+}
+
+#[async_trait]
+impl ShortUrl for ShortUrlBackend {
+    async fn create_table(&self) -> Result<()> {
+        dispatch!(self.create_table() no_timeout)
+    }
+
+    async fn create_table_index(&self) -> Result<()> {
+        dispatch!(self.create_table_index() no_timeout)
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        dispatch!(self.migrate() no_timeout)
+    }
+
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        dispatch!(self.add(record))
+    }
+
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        dispatch!(self.add_if_absent(record))
+    }
+
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        dispatch!(self.batch_add(records))
+    }
+
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        dispatch!(self.remove(org_id, short_id))
+    }
+
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        dispatch!(self.update(org_id, short_id, new_url))
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        dispatch!(self.rename(org_id, old_short_id, new_short_id))
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        dispatch!(self.set_pinned(org_id, short_id, pinned))
+    }
+
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        dispatch!(self.get(org_id, short_id))
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        dispatch!(self.get_many(org_id, short_ids))
+    }
+
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        dispatch!(self.increment_click_count(org_id, short_id))
+    }
+
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        dispatch!(self.get_by_original_url(org_id, original_url))
+    }
+
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.list(org_id, created_by, limit, after_ts, sort_by, sort_dir))
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        dispatch!(self.stream())
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        dispatch!(self.stream_by_org(org_id))
+    }
+
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        dispatch!(self.list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir))
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.list_by_resource(resource_type, resource_id))
+    }
+
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.list_by_tag(key, value, limit))
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.search(org_id, url_pattern, limit))
+    }
+
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        dispatch!(self.contains(org_id, short_id))
+    }
+
+    async fn len(&self) -> usize {
+        dispatch!(self.len() no_timeout)
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        dispatch!(self.clear())
+    }
+
+    async fn is_empty(&self) -> bool {
+        dispatch!(self.is_empty() no_timeout)
+    }
+
+    async fn ping(&self) -> Result<()> {
+        dispatch!(self.ping())
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        dispatch!(self.db_version())
+    }
+
+    async fn health_report(&self) -> Result<ShortUrlHealthReport> {
+        dispatch!(self.health_report())
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        dispatch!(self.get_expired(org_id, expired_before, limit, strategy))
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        dispatch!(self.batch_remove(short_ids) moved)
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        dispatch!(self.restore(org_id, short_id))
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        dispatch!(self.hard_delete_expired_soft_deleted(older_than))
+    }
+
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        dispatch!(self.archive_expired(expired_before, limit))
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
+        dispatch!(self.archive(short_ids))
+    }
+
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        dispatch!(self.with_transaction(f) moved)
+    }
+
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        dispatch!(self.count_by_date_range(org_id, from_ts, to_ts, granularity))
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.top_by_clicks(org_id, limit))
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        dispatch!(self.click_totals(org_id))
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        dispatch!(self.count_by_org(org_id))
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        dispatch!(self.list_namespaces(org_id))
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.get_due_for_notification(now))
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        dispatch!(self.mark_notified(org_id, short_id))
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.sample_for_link_check(limit))
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        dispatch!(self.set_link_check_status(org_id, short_id, status))
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        dispatch!(self.list_link_rot_candidates(org_id))
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        dispatch!(self.replay_from_events(from_ts))
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        dispatch!(self.add_access_log(entries))
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        dispatch!(self.get_access_log(org_id, short_id, from_ts, to_ts))
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        dispatch!(self.purge_access_log(older_than))
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        dispatch!(self.get_idempotency_key(org_id, idempotency_key, created_after))
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        dispatch!(self.reserve_idempotency_key(org_id, idempotency_key, body_hash, expired_before))
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        dispatch!(self.complete_idempotency_key(org_id, idempotency_key, short_id))
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        dispatch!(self.release_idempotency_key(org_id, idempotency_key))
+    }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        dispatch!(self.purge_idempotency_keys(older_than))
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        dispatch!(self.generate_digest(org_id, period_start, period_end))
+    }
+
+    async fn add_or_get(&self, record: &ShortUrlRecord) -> Result<(String, bool)> {
+        dispatch!(self.add_or_get(record))
+    }
+
+    async fn add_alias(&self, org_id: &str, alias: &str, target: &str) -> Result<()> {
+        dispatch!(self.add_alias(org_id, alias, target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_urls() {
+        assert_eq!(
+            redact_urls(r#"("default", "https://example.com/reset?token=secret")"#),
+            r#"("default", "https://example.com/<redacted>")"#
+        );
+        assert_eq!(
+            redact_urls(r#"original_url: "http://example.com", org_id: "httpd""#),
+            r#"original_url: "http://example.com", org_id: "httpd""#
+        );
+        assert_eq!(
+            redact_urls("https://example.com?q=1 https://a.io/b"),
+            "https://example.com/<redacted> https://a.io/<redacted>"
+        );
+    }
+}
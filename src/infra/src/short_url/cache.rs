@@ -0,0 +1,632 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use config::meta::short_url::ShortUrlHealthReport;
+use futures::{future::BoxFuture, stream::BoxStream};
+use hashbrown::HashMap;
+use hashlink::lru_cache::LruCache;
+use parking_lot::Mutex;
+
+use crate::short_url::{
+    error::Result, tx::ShortUrlTx, AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity,
+    IdempotencyKey, ShortUrl, ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir,
+};
+
+type CacheKey = (String, String);
+
+/// Read-through LRU cache of `get` in front of a `ShortUrl` store.
+///
+/// Writes through the wrapper invalidate their entries, writes made by other nodes are seen
+/// once the entry outlives `ttl` or is `invalidate`d. Cached click counts lag the store.
+pub struct CachedShortUrl<S: ShortUrl> {
+    inner: S,
+    cache: Option<Mutex<LruCache<CacheKey, (Instant, ShortUrlRecord)>>>,
+    ttl: Duration,
+}
+
+impl<S: ShortUrl> CachedShortUrl<S> {
+    /// A zero `capacity` disables the cache
+    pub fn new(inner: S, capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner,
+            cache: (capacity > 0).then(|| Mutex::new(LruCache::new(capacity))),
+            ttl,
+        }
+    }
+
+    /// Build the cache from `ZO_SHORT_URL_CACHE_SIZE` and `ZO_SHORT_URL_CACHE_TTL_SECS`
+    pub fn from_config(inner: S) -> Self {
+        let cfg = config::get_config();
+        Self::new(
+            inner,
+            cfg.limit.short_url_cache_size,
+            Duration::from_secs(cfg.limit.short_url_cache_ttl_secs),
+        )
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Drop the cached record of a short_id, the next `get` reads it from the store
+    pub fn invalidate(&self, org_id: &str, short_id: &str) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache
+                .lock()
+                .remove(&(org_id.to_string(), short_id.to_string()));
+        }
+    }
+
+    fn invalidate_all(&self) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.lock().clear();
+        }
+    }
+
+    fn cached(&self, org_id: &str, short_id: &str) -> Option<ShortUrlRecord> {
+        let mut cache = self.cache.as_ref()?.lock();
+        let key = (org_id.to_string(), short_id.to_string());
+        let fresh = cache
+            .get(&key)
+            .map(|(cached_at, record)| (cached_at.elapsed() < self.ttl).then(|| record.clone()));
+        match fresh {
+            Some(Some(record)) => Some(record),
+            Some(None) => {
+                cache.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, org_id: &str, short_id: &str, record: &ShortUrlRecord) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.lock().insert(
+                (org_id.to_string(), short_id.to_string()),
+                (Instant::now(), record.clone()),
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ShortUrl> ShortUrl for CachedShortUrl<S> {
+    async fn create_table(&self) -> Result<()> {
+        self.inner.create_table().await
+    }
+
+    async fn create_table_index(&self) -> Result<()> {
+        self.inner.create_table_index().await
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        self.inner.migrate().await
+    }
+
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        self.inner.add(record).await
+    }
+
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        self.inner.add_if_absent(record).await
+    }
+
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        self.inner.batch_add(records).await
+    }
+
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let ret = self.inner.remove(org_id, short_id).await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        let ret = self.inner.update(org_id, short_id, new_url).await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        let ret = self.inner.rename(org_id, old_short_id, new_short_id).await;
+        self.invalidate(org_id, old_short_id);
+        self.invalidate(org_id, new_short_id);
+        ret
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        let ret = self.inner.set_pinned(org_id, short_id, pinned).await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        if let Some(record) = self.cached(org_id, short_id) {
+            return Ok(record);
+        }
+        let record = self.inner.get(org_id, short_id).await?;
+        self.insert(org_id, short_id, &record);
+        Ok(record)
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        let mut records = HashMap::with_capacity(short_ids.len());
+        let mut missing = Vec::new();
+        for short_id in short_ids {
+            match self.cached(org_id, short_id) {
+                Some(record) => {
+                    records.insert(short_id.to_string(), record);
+                }
+                None => missing.push(*short_id),
+            }
+        }
+        if !missing.is_empty() {
+            for (short_id, record) in self.inner.get_many(org_id, &missing).await? {
+                self.insert(org_id, &short_id, &record);
+                records.insert(short_id, record);
+            }
+        }
+        Ok(records)
+    }
+
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.inner.increment_click_count(org_id, short_id).await
+    }
+
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        self.inner.get_by_original_url(org_id, original_url).await
+    }
+
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.inner
+            .list(org_id, created_by, limit, after_ts, sort_by, sort_dir)
+            .await
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.inner.stream().await
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.inner.stream_by_org(org_id).await
+    }
+
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        self.inner
+            .list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+            .await
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.inner
+            .list_by_resource(resource_type, resource_id)
+            .await
+    }
+
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.list_by_tag(key, value, limit).await
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.search(org_id, url_pattern, limit).await
+    }
+
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        self.inner.contains(org_id, short_id).await
+    }
+
+    async fn len(&self) -> usize {
+        self.inner.len().await
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        let ret = self.inner.clear().await;
+        self.invalidate_all();
+        ret
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.inner.is_empty().await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.inner.ping().await
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        self.inner.db_version().await
+    }
+
+    async fn health_report(&self) -> Result<ShortUrlHealthReport> {
+        self.inner.health_report().await
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        self.inner
+            .get_expired(org_id, expired_before, limit, strategy)
+            .await
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let keys = short_ids.clone();
+        let ret = self.inner.batch_remove(short_ids).await;
+        for (org_id, short_id) in keys.iter() {
+            self.invalidate(org_id, short_id);
+        }
+        ret
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let ret = self.inner.restore(org_id, short_id).await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        self.inner
+            .hard_delete_expired_soft_deleted(older_than)
+            .await
+    }
+
+    /// The archived short_ids are not known here, so all of the cache is dropped
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        let ret = self.inner.archive_expired(expired_before, limit).await;
+        self.invalidate_all();
+        ret
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
+        let ret = self.inner.archive(short_ids).await;
+        for (org_id, short_id) in short_ids {
+            self.invalidate(org_id, short_id);
+        }
+        ret
+    }
+
+    /// Writes made in the transaction bypass the cache, so all of it is dropped on commit
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        let ret = self.inner.with_transaction(f).await;
+        if ret.is_ok() {
+            self.invalidate_all();
+        }
+        ret
+    }
+
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        self.inner
+            .count_by_date_range(org_id, from_ts, to_ts, granularity)
+            .await
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.top_by_clicks(org_id, limit).await
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        self.inner.click_totals(org_id).await
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        self.inner.count_by_org(org_id).await
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        self.inner.list_namespaces(org_id).await
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.get_due_for_notification(now).await
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let ret = self.inner.mark_notified(org_id, short_id).await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.sample_for_link_check(limit).await
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        let ret = self
+            .inner
+            .set_link_check_status(org_id, short_id, status)
+            .await;
+        self.invalidate(org_id, short_id);
+        ret
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        self.inner.list_link_rot_candidates(org_id).await
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        let ret = self.inner.replay_from_events(from_ts).await;
+        self.invalidate_all();
+        ret
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        self.inner.add_access_log(entries).await
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        self.inner
+            .get_access_log(org_id, short_id, from_ts, to_ts)
+            .await
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        self.inner.purge_access_log(older_than).await
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        self.inner
+            .get_idempotency_key(org_id, idempotency_key, created_after)
+            .await
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        self.inner
+            .reserve_idempotency_key(org_id, idempotency_key, body_hash, expired_before)
+            .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        self.inner
+            .complete_idempotency_key(org_id, idempotency_key, short_id)
+            .await
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        self.inner
+            .release_idempotency_key(org_id, idempotency_key)
+            .await
+    }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        self.inner.purge_idempotency_keys(older_than).await
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        self.inner
+            .generate_digest(org_id, period_start, period_end)
+            .await
+    }
+
+    async fn add_or_get(&self, record: &ShortUrlRecord) -> Result<(String, bool)> {
+        self.inner.add_or_get(record).await
+    }
+
+    async fn add_alias(&self, org_id: &str, alias: &str, target: &str) -> Result<()> {
+        self.inner.add_alias(org_id, alias, target).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::short_url::sqlite::SqliteShortUrl;
+
+    #[tokio::test]
+    async fn test_cached_get() {
+        let short_url = CachedShortUrl::new(SqliteShortUrl::new(), 16, Duration::from_secs(3600));
+        short_url.create_table().await.unwrap();
+        short_url
+            .batch_remove(vec![("default".to_string(), "cached_get".to_string())])
+            .await
+            .unwrap();
+        let record = ShortUrlRecord::new("default", "cached_get", "https://example.com/old");
+        short_url.add(&record).await.unwrap();
+        assert_eq!(
+            short_url
+                .get("default", "cached_get")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/old"
+        );
+
+        // writes bypassing the wrapper are not seen until the entry is invalidated
+        short_url
+            .inner()
+            .update("default", "cached_get", "https://example.com/new")
+            .await
+            .unwrap();
+        assert_eq!(
+            short_url
+                .get("default", "cached_get")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/old"
+        );
+        short_url.invalidate("default", "cached_get");
+        assert_eq!(
+            short_url
+                .get("default", "cached_get")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/new"
+        );
+
+        short_url
+            .update("default", "cached_get", "https://example.com/newer")
+            .await
+            .unwrap();
+        assert_eq!(
+            short_url
+                .get("default", "cached_get")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/newer"
+        );
+
+        short_url.remove("default", "cached_get").await.unwrap();
+        assert!(short_url.get("default", "cached_get").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cache_invalidated_on_notify_and_restore() {
+        let short_url = CachedShortUrl::new(SqliteShortUrl::new(), 16, Duration::from_secs(3600));
+        short_url.create_table().await.unwrap();
+        short_url
+            .batch_remove(vec![("default".to_string(), "cached_notify".to_string())])
+            .await
+            .unwrap();
+        let record = ShortUrlRecord::new("default", "cached_notify", "https://example.com/notify");
+        short_url.add(&record).await.unwrap();
+
+        short_url.get("default", "cached_notify").await.unwrap();
+        assert!(short_url.cached("default", "cached_notify").is_some());
+        short_url
+            .mark_notified("default", "cached_notify")
+            .await
+            .unwrap();
+        assert!(short_url.cached("default", "cached_notify").is_none());
+
+        short_url.remove("default", "cached_notify").await.unwrap();
+        // an entry cached before the soft delete, e.g. by another task
+        short_url.insert("default", "cached_notify", &record);
+        short_url.restore("default", "cached_notify").await.unwrap();
+        assert!(short_url.cached("default", "cached_notify").is_none());
+        short_url.get("default", "cached_notify").await.unwrap();
+
+        short_url
+            .batch_remove(vec![("default".to_string(), "cached_notify".to_string())])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cached_get_ttl() {
+        let short_url = CachedShortUrl::new(SqliteShortUrl::new(), 16, Duration::ZERO);
+        short_url.create_table().await.unwrap();
+        short_url
+            .batch_remove(vec![("default".to_string(), "cached_ttl".to_string())])
+            .await
+            .unwrap();
+        let record = ShortUrlRecord::new("default", "cached_ttl", "https://example.com/old");
+        short_url.add(&record).await.unwrap();
+        short_url.get("default", "cached_ttl").await.unwrap();
+
+        short_url
+            .inner()
+            .update("default", "cached_ttl", "https://example.com/new")
+            .await
+            .unwrap();
+        assert_eq!(
+            short_url
+                .get("default", "cached_ttl")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/new"
+        );
+
+        short_url.remove("default", "cached_ttl").await.unwrap();
+    }
+}
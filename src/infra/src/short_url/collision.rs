@@ -0,0 +1,124 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Share of generated short_ids that were already taken over the last 5 minutes. It grows
+//! as the id space fills up, the fix is a longer `ZO_SHORT_URL_ID_LENGTH`
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use config::{get_config, metrics::SHORT_URL_ID_COLLISION_RATE};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+const BUCKET_SECS: u64 = 30;
+// 10 buckets of 30s make the 5 minute window
+const BUCKETS: usize = 10;
+
+static TRACKER: Lazy<CollisionTracker> = Lazy::new(CollisionTracker::default);
+
+#[derive(Clone, Copy, Default)]
+struct Bucket {
+    slot: u64,
+    attempts: u64,
+    conflicts: u64,
+}
+
+#[derive(Default)]
+struct Window {
+    buckets: [Bucket; BUCKETS],
+    // slot of the last warning, warn at most once per window
+    warned_slot: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct CollisionTracker {
+    window: Mutex<Window>,
+}
+
+impl CollisionTracker {
+    /// Count an insert of a generated short_id at `now` (unix seconds), returns the collision
+    /// rate over the window and whether it is time to warn about a rate above `warn_rate`
+    pub fn record_at(&self, now: u64, conflict: bool, warn_rate: f64) -> (f64, bool) {
+        let slot = now / BUCKET_SECS;
+        let mut window = self.window.lock();
+        let bucket = &mut window.buckets[slot as usize % BUCKETS];
+        if bucket.slot != slot {
+            *bucket = Bucket {
+                slot,
+                ..Default::default()
+            };
+        }
+        bucket.attempts += 1;
+        bucket.conflicts += conflict as u64;
+
+        let (attempts, conflicts) = window
+            .buckets
+            .iter()
+            .filter(|b| b.slot + BUCKETS as u64 > slot)
+            .fold((0, 0), |(a, c), b| (a + b.attempts, c + b.conflicts));
+        let rate = conflicts as f64 / attempts as f64;
+        let warn = warn_rate > 0.0
+            && rate > warn_rate
+            && window
+                .warned_slot
+                .map_or(true, |warned| warned + BUCKETS as u64 <= slot);
+        if warn {
+            window.warned_slot = Some(slot);
+        }
+        (rate, warn)
+    }
+}
+
+/// Count an insert of a generated short_id, `conflict` if the short_id was already taken
+pub fn record(conflict: bool) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let warn_rate = get_config().limit.short_url_collision_warn_rate;
+    let (rate, warn) = TRACKER.record_at(now, conflict, warn_rate);
+    SHORT_URL_ID_COLLISION_RATE.with_label_values(&[]).set(rate);
+    if warn {
+        tracing::warn!(
+            "[SHORT_URL] {:.4}% of generated short_ids collided in the last 5 minutes, above ZO_SHORT_URL_COLLISION_WARN_RATE {:.4}%, consider increasing ZO_SHORT_URL_ID_LENGTH",
+            rate * 100.0,
+            warn_rate * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_at() {
+        let tracker = CollisionTracker::default();
+        let start = 1_000_000;
+        for i in 0..9 {
+            assert_eq!(tracker.record_at(start + i, false, 0.01), (0.0, false));
+        }
+        // 1 of 10 collided, above the threshold
+        assert_eq!(tracker.record_at(start + 10, true, 0.01), (0.1, true));
+        // warned once per window
+        assert_eq!(tracker.record_at(start + 11, true, 0.01).1, false);
+        assert_eq!(tracker.record_at(start + 12, false, 0.0).1, false);
+
+        // the old buckets leave the window
+        let later = start + BUCKET_SECS * BUCKETS as u64 * 2;
+        assert_eq!(tracker.record_at(later, false, 0.01), (0.0, false));
+        assert_eq!(tracker.record_at(later + 1, true, 0.01), (0.5, true));
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use thiserror::Error as ThisError;
+
+use crate::errors::{DbError, Error};
+
+pub type Result<T> = std::result::Result<T, ShortUrlError>;
+
+#[derive(ThisError, Debug)]
+pub enum ShortUrlError {
+    #[error("short url {0} not found")]
+    NotFound(String),
+    #[error("short url {0} already exists")]
+    Conflict(String),
+    #[error("DatabaseError# {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("invalid short id: {0}")]
+    InvalidShortId(String),
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("not supported: {0}")]
+    Unsupported(String),
+    #[error("short url db call timed out: {0}")]
+    Timeout(String),
+}
+
+impl ShortUrlError {
+    /// The store could not be reached, as opposed to a query it rejected
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            ShortUrlError::Timeout(_)
+                | ShortUrlError::DatabaseError(
+                    sqlx::Error::Io(_)
+                        | sqlx::Error::Tls(_)
+                        | sqlx::Error::PoolTimedOut
+                        | sqlx::Error::PoolClosed
+                        | sqlx::Error::WorkerCrashed
+                )
+        )
+    }
+}
+
+/// Maps onto the `DbError`s short urls used to return, existing matches on them keep working
+impl From<ShortUrlError> for Error {
+    fn from(e: ShortUrlError) -> Self {
+        match e {
+            ShortUrlError::NotFound(short_id) => Error::DbError(DbError::KeyNotExists(short_id)),
+            ShortUrlError::Conflict(_) => Error::DbError(DbError::UniqueViolation),
+            ShortUrlError::DatabaseError(e) => Error::SqlxError(e),
+            e => Error::Message(e.to_string()),
+        }
+    }
+}
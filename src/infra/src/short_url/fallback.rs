@@ -0,0 +1,575 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use config::meta::short_url::ShortUrlHealthReport;
+use futures::{future::BoxFuture, stream::BoxStream};
+use hashbrown::HashMap;
+
+use crate::short_url::{
+    error::{Result, ShortUrlError},
+    memory::MemoryShortUrl,
+    tx::ShortUrlTx,
+    AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+    ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir,
+};
+
+/// Serves short url reads from `fallback` while `primary` can not be reached, so redirects keep
+/// working through a db outage.
+///
+/// Everything goes to `primary`. The records it returns or accepts are copied into
+/// `fallback`, and only `get`, `get_many`, `get_by_original_url` and `contains` read from
+/// `fallback` when `primary` fails with a connection error. Writes are never redirected to
+/// `fallback`, they fail with the primary error so nothing is kept only in memory.
+pub struct FallbackShortUrl<P: ShortUrl> {
+    primary: P,
+    fallback: Option<MemoryShortUrl>,
+}
+
+impl<P: ShortUrl> FallbackShortUrl<P> {
+    pub fn new(primary: P, fallback: MemoryShortUrl) -> Self {
+        Self {
+            primary,
+            fallback: Some(fallback),
+        }
+    }
+
+    /// Only use `primary`
+    pub fn disabled(primary: P) -> Self {
+        Self {
+            primary,
+            fallback: None,
+        }
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    // the fallback to read from after `e`, if any
+    fn fallback_for(&self, operation: &str, e: &ShortUrlError) -> Option<&MemoryShortUrl> {
+        let fallback = self.fallback.as_ref().filter(|_| e.is_connection_error())?;
+        log::warn!("[SHORT_URL] {operation} falling back to the in-memory store: {e}");
+        Some(fallback)
+    }
+
+    fn remember(&self, records: &[ShortUrlRecord]) {
+        if let Some(fallback) = self.fallback.as_ref() {
+            // replace what the fallback has, primary is the source of truth
+            fallback.upsert(records);
+        }
+    }
+
+    async fn forget(&self, org_id: &str, short_id: &str) {
+        if let Some(fallback) = self.fallback.as_ref() {
+            let _ = fallback
+                .batch_remove(vec![(org_id.to_string(), short_id.to_string())])
+                .await;
+        }
+    }
+
+    // for the writes that don't say which records they changed
+    async fn forget_all(&self) {
+        if let Some(fallback) = self.fallback.as_ref() {
+            let _ = fallback.clear().await;
+        }
+    }
+
+    /// Fall back to an in-memory store when `ZO_SHORT_URL_MEMORY_FALLBACK` is set
+    pub fn from_config(primary: P) -> Self {
+        if config::get_config().limit.short_url_memory_fallback {
+            Self::new(primary, MemoryShortUrl::new())
+        } else {
+            Self::disabled(primary)
+        }
+    }
+}
+
+#[async_trait]
+impl<P: ShortUrl> ShortUrl for FallbackShortUrl<P> {
+    async fn create_table(&self) -> Result<()> {
+        self.primary.create_table().await
+    }
+
+    async fn create_table_index(&self) -> Result<()> {
+        self.primary.create_table_index().await
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        self.primary.migrate().await
+    }
+
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        let record = self.primary.add(record).await?;
+        self.remember(std::slice::from_ref(&record));
+        Ok(record)
+    }
+
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        self.primary.add_if_absent(record).await
+    }
+
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        self.primary.batch_add(records).await
+    }
+
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.remove(org_id, short_id).await?;
+        self.forget(org_id, short_id).await;
+        Ok(())
+    }
+
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        self.primary.update(org_id, short_id, new_url).await?;
+        self.forget(org_id, short_id).await;
+        Ok(())
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        self.primary
+            .rename(org_id, old_short_id, new_short_id)
+            .await?;
+        self.forget(org_id, old_short_id).await;
+        Ok(())
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        self.primary.set_pinned(org_id, short_id, pinned).await?;
+        self.forget(org_id, short_id).await;
+        Ok(())
+    }
+
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        match self.primary.get(org_id, short_id).await {
+            Ok(record) => {
+                self.remember(std::slice::from_ref(&record));
+                Ok(record)
+            }
+            Err(e) => match self.fallback_for("get", &e) {
+                Some(fallback) => fallback.get(org_id, short_id).await,
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        match self.primary.get_many(org_id, short_ids).await {
+            Ok(records) => {
+                let found = records.values().cloned().collect::<Vec<_>>();
+                self.remember(&found);
+                Ok(records)
+            }
+            Err(e) => match self.fallback_for("get_many", &e) {
+                Some(fallback) => fallback.get_many(org_id, short_ids).await,
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.increment_click_count(org_id, short_id).await
+    }
+
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        match self.primary.get_by_original_url(org_id, original_url).await {
+            Ok(record) => Ok(record),
+            Err(e) => match self.fallback_for("get_by_original_url", &e) {
+                Some(fallback) => fallback.get_by_original_url(org_id, original_url).await,
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary
+            .list(org_id, created_by, limit, after_ts, sort_by, sort_dir)
+            .await
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.primary.stream().await
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.primary.stream_by_org(org_id).await
+    }
+
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        self.primary
+            .list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+            .await
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary
+            .list_by_resource(resource_type, resource_id)
+            .await
+    }
+
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.list_by_tag(key, value, limit).await
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.search(org_id, url_pattern, limit).await
+    }
+
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        match self.primary.contains(org_id, short_id).await {
+            Ok(found) => Ok(found),
+            Err(e) => match self.fallback_for("contains", &e) {
+                Some(fallback) => fallback.contains(org_id, short_id).await,
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn len(&self) -> usize {
+        self.primary.len().await
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        let deleted = self.primary.clear().await?;
+        if let Some(fallback) = self.fallback.as_ref() {
+            fallback.clear().await?;
+        }
+        Ok(deleted)
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.primary.is_empty().await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.primary.ping().await
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        self.primary.db_version().await
+    }
+
+    async fn health_report(&self) -> Result<ShortUrlHealthReport> {
+        self.primary.health_report().await
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        self.primary
+            .get_expired(org_id, expired_before, limit, strategy)
+            .await
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let ret = self.primary.batch_remove(short_ids.clone()).await?;
+        if let Some(fallback) = self.fallback.as_ref() {
+            let _ = fallback.batch_remove(short_ids).await;
+        }
+        Ok(ret)
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.restore(org_id, short_id).await
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        let deleted = self
+            .primary
+            .hard_delete_expired_soft_deleted(older_than)
+            .await?;
+        self.forget_all().await;
+        Ok(deleted)
+    }
+
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        let archived = self.primary.archive_expired(expired_before, limit).await?;
+        self.forget_all().await;
+        Ok(archived)
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
+        let archived = self.primary.archive(short_ids).await?;
+        if let Some(fallback) = self.fallback.as_ref() {
+            let _ = fallback.batch_remove(short_ids.to_vec()).await;
+        }
+        Ok(archived)
+    }
+
+    async fn with_transaction<Func, T>(&self, f: Func) -> Result<T>
+    where
+        Self: Sized,
+        Func: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        let ret = self.primary.with_transaction(f).await;
+        // the transaction may have changed anything
+        if ret.is_ok() {
+            self.forget_all().await;
+        }
+        ret
+    }
+
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        self.primary
+            .count_by_date_range(org_id, from_ts, to_ts, granularity)
+            .await
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.top_by_clicks(org_id, limit).await
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        self.primary.click_totals(org_id).await
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        self.primary.count_by_org(org_id).await
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        self.primary.list_namespaces(org_id).await
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.get_due_for_notification(now).await
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.mark_notified(org_id, short_id).await
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.sample_for_link_check(limit).await
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        self.primary
+            .set_link_check_status(org_id, short_id, status)
+            .await
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.list_link_rot_candidates(org_id).await
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        let replayed = self.primary.replay_from_events(from_ts).await?;
+        self.forget_all().await;
+        Ok(replayed)
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        self.primary.add_access_log(entries).await
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        self.primary
+            .get_access_log(org_id, short_id, from_ts, to_ts)
+            .await
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        self.primary.purge_access_log(older_than).await
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        self.primary
+            .get_idempotency_key(org_id, idempotency_key, created_after)
+            .await
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        self.primary
+            .reserve_idempotency_key(org_id, idempotency_key, body_hash, expired_before)
+            .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        self.primary
+            .complete_idempotency_key(org_id, idempotency_key, short_id)
+            .await
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        self.primary
+            .release_idempotency_key(org_id, idempotency_key)
+            .await
+    }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        self.primary.purge_idempotency_keys(older_than).await
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        self.primary
+            .generate_digest(org_id, period_start, period_end)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::short_url::sqlite::SqliteShortUrl;
+
+    #[test]
+    fn test_is_connection_error() {
+        assert!(ShortUrlError::DatabaseError(sqlx::Error::PoolTimedOut).is_connection_error());
+        assert!(!ShortUrlError::DatabaseError(sqlx::Error::RowNotFound).is_connection_error());
+        assert!(!ShortUrlError::NotFound("abc".to_string()).is_connection_error());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_not_used_without_connection_error() {
+        let short_url = FallbackShortUrl::new(SqliteShortUrl::new(), MemoryShortUrl::new());
+        short_url.create_table().await.unwrap();
+        short_url
+            .batch_remove(vec![("default".to_string(), "fallback".to_string())])
+            .await
+            .unwrap();
+        let record = ShortUrlRecord::new("default", "fallback", "https://example.com/fallback");
+        short_url.add(&record).await.unwrap();
+        assert!(
+            short_url
+                .fallback
+                .as_ref()
+                .unwrap()
+                .contains("default", "fallback")
+                .await
+                .unwrap()
+        );
+
+        // removed from the primary, a not found is not a reason to read the copy
+        short_url
+            .primary()
+            .batch_remove(vec![("default".to_string(), "fallback".to_string())])
+            .await
+            .unwrap();
+        assert!(matches!(
+            short_url.get("default", "fallback").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_fallback_forgets_archived() {
+        let short_url = FallbackShortUrl::new(SqliteShortUrl::new(), MemoryShortUrl::new());
+        short_url.create_table().await.unwrap();
+        let key = ("default".to_string(), "fallback_archived".to_string());
+        short_url.batch_remove(vec![key.clone()]).await.unwrap();
+        let record = ShortUrlRecord::new(
+            "default",
+            "fallback_archived",
+            "https://example.com/archived",
+        );
+        short_url.add(&record).await.unwrap();
+        short_url
+            .increment_click_count("default", "fallback_archived")
+            .await
+            .unwrap();
+        // the copy is the record as the primary has it
+        short_url.get("default", "fallback_archived").await.unwrap();
+        let fallback = short_url.fallback.as_ref().unwrap();
+        let copy = fallback.get("default", "fallback_archived").await.unwrap();
+        assert_eq!(copy.click_count, 1);
+
+        short_url.archive(&[key]).await.unwrap();
+        assert!(
+            !fallback
+                .contains("default", "fallback_archived")
+                .await
+                .unwrap()
+        );
+    }
+}
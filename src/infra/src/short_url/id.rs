@@ -0,0 +1,153 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use config::{get_config, ider::SnowflakeIdGenerator};
+use hashbrown::HashSet;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use regex::Regex;
+
+const BASE62_CHARSET: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+static RE_CUSTOM_SHORT_ID: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_-]{3,32}$").unwrap());
+
+/// A short id chosen by a caller is 3 to 32 letters, digits, '_' or '-'
+pub fn is_valid_custom_short_id(short_id: &str) -> bool {
+    RE_CUSTOM_SHORT_ID.is_match(short_id)
+}
+
+/// Short ids a caller may never choose, they read like routes of the server
+pub const RESERVED_SHORT_IDS: [&str; 6] = ["admin", "api", "health", "login", "logout", "static"];
+
+/// Checks caller chosen short ids against `RESERVED_SHORT_IDS` and extra reserved words,
+/// ignoring case
+#[derive(Debug, Clone)]
+pub struct ReservedShortIdChecker {
+    words: HashSet<String>,
+}
+
+impl ReservedShortIdChecker {
+    /// `extra_words` is a comma separated list reserved on top of `RESERVED_SHORT_IDS`
+    pub fn new(extra_words: &str) -> Self {
+        let words = RESERVED_SHORT_IDS
+            .into_iter()
+            .chain(extra_words.split(','))
+            .map(|word| word.trim().to_ascii_lowercase())
+            .filter(|word| !word.is_empty())
+            .collect();
+        Self { words }
+    }
+
+    /// The words of `ZO_SHORT_URL_RESERVED_WORDS` on top of `RESERVED_SHORT_IDS`
+    pub fn from_config() -> Self {
+        Self::new(&get_config().limit.short_url_reserved_words)
+    }
+
+    pub fn is_reserved(&self, short_id: &str) -> bool {
+        self.words.contains(&short_id.to_ascii_lowercase())
+    }
+}
+
+static RESERVED_SHORT_ID_CHECKER: Lazy<ReservedShortIdChecker> =
+    Lazy::new(ReservedShortIdChecker::from_config);
+
+/// Whether a caller may not choose `short_id`, see `ReservedShortIdChecker::from_config`
+pub fn is_reserved_short_id(short_id: &str) -> bool {
+    RESERVED_SHORT_ID_CHECKER.is_reserved(short_id)
+}
+
+static RE_NAMESPACE: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z0-9_-]{1,31}$").unwrap());
+
+/// A namespace is 1 to 31 letters, digits, '_' or '-', so `{namespace}/{short_id}` fits the
+/// 64 characters of the short_id column
+pub fn is_valid_namespace(namespace: &str) -> bool {
+    RE_NAMESPACE.is_match(namespace)
+}
+
+/// The stored short_id of `short_id` in `namespace`
+pub fn namespaced_short_id(namespace: &str, short_id: &str) -> String {
+    format!("{namespace}/{short_id}")
+}
+
+/// Time ordered short id unique across the cluster, the node bits of the snowflake id come from
+/// the generator's machine_id
+pub fn snowflake_short_id(generator: &mut SnowflakeIdGenerator) -> String {
+    encode_base62(generator.real_time_generate() as u64)
+}
+
+// node ids are assigned when the node joins the cluster, so it is read on first use
+static SNOWFLAKE_GENERATOR: Lazy<Mutex<SnowflakeIdGenerator>> = Lazy::new(|| {
+    let machine_id = unsafe { config::cluster::LOCAL_NODE_ID };
+    Mutex::new(SnowflakeIdGenerator::new(machine_id))
+});
+
+/// `snowflake_short_id` from the generator of this node, for short urls that are never
+/// deduped on their url
+pub fn next_snowflake_short_id() -> String {
+    snowflake_short_id(&mut SNOWFLAKE_GENERATOR.lock())
+}
+
+/// Encode in base62, a snowflake id takes at most 11 characters
+pub fn encode_base62(mut id: u64) -> String {
+    if id == 0 {
+        return "0".to_string();
+    }
+    let mut buf = Vec::with_capacity(11);
+    while id > 0 {
+        buf.push(BASE62_CHARSET[(id % 62) as usize]);
+        id /= 62;
+    }
+    buf.reverse();
+    String::from_utf8(buf).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserved_short_ids() {
+        let checker = ReservedShortIdChecker::new(" Docs ,,status");
+        for short_id in [
+            "admin", "ADMIN", "Login", "static", "docs", "DOCS", "status",
+        ] {
+            assert!(checker.is_reserved(short_id), "{short_id}");
+        }
+        for short_id in ["admins", "my-api", "", "doc"] {
+            assert!(!checker.is_reserved(short_id), "{short_id}");
+        }
+        assert!(ReservedShortIdChecker::new("").is_reserved("health"));
+    }
+
+    #[test]
+    fn test_encode_base62() {
+        assert_eq!(encode_base62(0), "0");
+        assert_eq!(encode_base62(61), "z");
+        assert_eq!(encode_base62(62), "10");
+        assert_eq!(encode_base62(i64::MAX as u64).len(), 11);
+    }
+
+    #[test]
+    fn test_snowflake_short_id_unique_across_nodes() {
+        let mut generators: Vec<_> = (0..4).map(SnowflakeIdGenerator::new).collect();
+        let mut short_ids = HashSet::with_capacity(100_000);
+        for i in 0..100_000 {
+            let generator = &mut generators[i % 4];
+            assert!(short_ids.insert(snowflake_short_id(generator)));
+        }
+        assert_eq!(short_ids.len(), 100_000);
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{future::Future, time::Duration};
+
+use config::{get_config, metrics::SHORT_URL_LINK_ROT_DETECTED};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::short_url::{self, error::Result};
+
+/// Background task checking the original_url of a random sample of short urls and flagging
+/// those answering with a 4xx or 5xx, see `ShortUrl::list_link_rot_candidates`
+pub struct LinkRotDetector {
+    interval: Duration,
+    sample_size: i64,
+    token: CancellationToken,
+}
+
+impl LinkRotDetector {
+    pub fn new(interval: Duration, sample_size: i64, token: CancellationToken) -> Self {
+        Self {
+            interval,
+            sample_size: sample_size.max(1),
+            token,
+        }
+    }
+
+    /// Build the task from `ZO_SHORT_URL_LINK_ROT_*`, `None` if the detector is disabled
+    pub fn from_config(token: CancellationToken) -> Option<Self> {
+        let cfg = get_config();
+        if cfg.limit.short_url_link_rot_check_interval_hours == 0 {
+            return None;
+        }
+        Some(Self::new(
+            Duration::from_secs(cfg.limit.short_url_link_rot_check_interval_hours * 3600),
+            cfg.limit.short_url_link_rot_sample_size,
+            token,
+        ))
+    }
+
+    /// Run the check loop until the token is cancelled, `check` requests an original_url and
+    /// returns the HTTP status it answered with, `None` if no answer came
+    pub fn spawn<C, Fut>(self, check: C) -> JoinHandle<()>
+    where
+        C: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Option<u16>> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            interval.tick().await; // trigger the first run
+            loop {
+                tokio::select! {
+                    _ = self.token.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                match self.check_once(&check).await {
+                    Ok(0) => {}
+                    Ok(n) => log::info!("[SHORT_URL] link rot detected in {n} short urls"),
+                    Err(e) => log::error!("[SHORT_URL] link rot check error: {}", e),
+                }
+            }
+            log::info!("[SHORT_URL] link rot detector stopped");
+        })
+    }
+
+    /// Check one sample of short urls and record the status each answered with, short urls
+    /// without an answer keep their last status. Returns the number found broken
+    pub async fn check_once<C, Fut>(&self, check: &C) -> Result<usize>
+    where
+        C: Fn(String) -> Fut,
+        Fut: Future<Output = Option<u16>>,
+    {
+        let sample = short_url::sample_for_link_check(self.sample_size).await?;
+        let mut broken = 0;
+        for record in sample {
+            if self.token.is_cancelled() {
+                break;
+            }
+            let Some(status) = check(record.original_url.clone()).await else {
+                continue;
+            };
+            // a status outside the SMALLINT range is not a valid HTTP status
+            let Ok(status) = i16::try_from(status) else {
+                continue;
+            };
+            short_url::set_link_check_status(&record.org_id, &record.short_id, status).await?;
+            if status >= 400 {
+                broken += 1;
+            }
+        }
+        SHORT_URL_LINK_ROT_DETECTED
+            .with_label_values(&[])
+            .inc_by(broken as u64);
+        Ok(broken)
+    }
+}
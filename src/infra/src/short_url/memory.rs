@@ -0,0 +1,944 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use hashbrown::HashMap;
+use parking_lot::RwLock;
+
+use crate::short_url::{
+    error::{Result, ShortUrlError},
+    tx::ShortUrlTx,
+    AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+    ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir, DIGEST_TOP_URLS,
+};
+
+const MICROS_PER_HOUR: i64 = 3_600_000_000;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+struct Entry {
+    record: ShortUrlRecord,
+    deleted_at: Option<i64>,
+    notified: bool,
+}
+
+/// Short url store kept in a process local map, nothing survives a restart. Used as the
+/// fallback of `FallbackShortUrl` and as a stand in for the db in unit tests, it follows the
+/// sql backends except that transactions are not supported
+#[derive(Default)]
+pub struct MemoryShortUrl {
+    entries: RwLock<HashMap<(String, String), Entry>>,
+    access_log: RwLock<Vec<AccessLogEntry>>,
+    /// `(key, created_at)` keyed by `(org_id, idempotency_key)`
+    idempotency_keys: RwLock<HashMap<(String, String), (IdempotencyKey, i64)>>,
+}
+
+impl MemoryShortUrl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `records` as they are, replacing whatever is kept under their keys, under a
+    /// single write lock
+    pub fn upsert(&self, records: &[ShortUrlRecord]) {
+        let mut entries = self.entries.write();
+        for record in records {
+            entries.insert(
+                key(&record.org_id, &record.short_id),
+                Entry {
+                    record: record.clone(),
+                    deleted_at: None,
+                    notified: false,
+                },
+            );
+        }
+    }
+
+    // records that are not soft deleted, in no particular order
+    fn live<P>(&self, predicate: P) -> Vec<ShortUrlRecord>
+    where
+        P: Fn(&ShortUrlRecord) -> bool,
+    {
+        self.entries
+            .read()
+            .values()
+            .filter(|e| e.deleted_at.is_none() && predicate(&e.record))
+            .map(|e| e.record.clone())
+            .collect()
+    }
+}
+
+fn key(org_id: &str, short_id: &str) -> (String, String) {
+    (org_id.to_string(), short_id.to_string())
+}
+
+// ties are broken by short_id like `push_order_by` does
+fn sorted(
+    mut records: Vec<ShortUrlRecord>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+    limit: Option<i64>,
+) -> Vec<ShortUrlRecord> {
+    records.sort_by(|a, b| {
+        let ord = match sort_by {
+            SortBy::CreatedTs => a.created_ts.cmp(&b.created_ts),
+            SortBy::ClickCount => a.click_count.cmp(&b.click_count),
+            SortBy::OriginalUrl => a.original_url.cmp(&b.original_url),
+            SortBy::ShortId => a.short_id.cmp(&b.short_id),
+        }
+        .then_with(|| a.short_id.cmp(&b.short_id));
+        match sort_dir {
+            SortDir::Asc => ord,
+            SortDir::Desc => ord.reverse(),
+        }
+    });
+    if let Some(limit) = limit {
+        records.truncate(limit.max(0) as usize);
+    }
+    records
+}
+
+fn newest_first(mut records: Vec<ShortUrlRecord>, limit: Option<i64>) -> Vec<ShortUrlRecord> {
+    records.sort_by(|a, b| b.created_ts.cmp(&a.created_ts));
+    if let Some(limit) = limit {
+        records.truncate(limit.max(0) as usize);
+    }
+    records
+}
+
+// 1970-01-01 was a Thursday, weeks start on Monday like the sql backends
+fn date_bucket(ts: i64, granularity: Granularity) -> i64 {
+    match granularity {
+        Granularity::Hour => ts - ts.rem_euclid(MICROS_PER_HOUR),
+        Granularity::Day => ts - ts.rem_euclid(MICROS_PER_DAY),
+        Granularity::Week => {
+            let day = ts.div_euclid(MICROS_PER_DAY);
+            (day - (day + 3).rem_euclid(7)) * MICROS_PER_DAY
+        }
+    }
+}
+
+#[async_trait]
+impl ShortUrl for MemoryShortUrl {
+    async fn create_table(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn create_table_index(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        let mut entries = self.entries.write();
+        let key = key(&record.org_id, &record.short_id);
+        if entries.contains_key(&key) {
+            return Err(ShortUrlError::Conflict(record.short_id.clone()));
+        }
+        let record = ShortUrlRecord {
+            created_ts: Utc::now().timestamp_micros(),
+            click_count: 0,
+            checksum: Some(record.compute_checksum()),
+            ..record.clone()
+        };
+        entries.insert(
+            key,
+            Entry {
+                record: record.clone(),
+                deleted_at: None,
+                notified: false,
+            },
+        );
+        Ok(record)
+    }
+
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        match self.add(record).await {
+            Ok(_) => Ok(true),
+            Err(ShortUrlError::Conflict(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        let now = Utc::now().timestamp_micros();
+        let mut entries = self.entries.write();
+        let mut ret = BatchAddResult::default();
+        for record in records {
+            let key = key(&record.org_id, &record.short_id);
+            if entries.contains_key(&key) {
+                ret.skipped += 1;
+                continue;
+            }
+            let mut record = record.clone();
+            if record.created_ts == 0 {
+                record.created_ts = now;
+            }
+            record.checksum = Some(record.compute_checksum());
+            entries.insert(
+                key,
+                Entry {
+                    record,
+                    deleted_at: None,
+                    notified: false,
+                },
+            );
+            ret.inserted += 1;
+        }
+        Ok(ret)
+    }
+
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        if let Some(entry) = self.entries.write().get_mut(&key(org_id, short_id)) {
+            entry
+                .deleted_at
+                .get_or_insert_with(|| Utc::now().timestamp_micros());
+        }
+        Ok(())
+    }
+
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        match self.entries.write().get_mut(&key(org_id, short_id)) {
+            Some(entry) if entry.deleted_at.is_none() => {
+                entry.record.original_url = new_url.to_string();
+                entry.record.checksum = Some(entry.record.compute_checksum());
+                Ok(())
+            }
+            _ => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        let mut entries = self.entries.write();
+        let old_key = key(org_id, old_short_id);
+        if !entries
+            .get(&old_key)
+            .is_some_and(|e| e.deleted_at.is_none())
+        {
+            return Err(ShortUrlError::NotFound(old_short_id.to_string()));
+        }
+        let new_key = key(org_id, new_short_id);
+        if entries.contains_key(&new_key) {
+            return Err(ShortUrlError::Conflict(new_short_id.to_string()));
+        }
+        let mut entry = entries.remove(&old_key).unwrap();
+        entry.record.short_id = new_short_id.to_string();
+        entry.record.checksum = Some(entry.record.compute_checksum());
+        entries.insert(new_key, entry);
+        Ok(())
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        match self.entries.write().get_mut(&key(org_id, short_id)) {
+            Some(entry) if entry.deleted_at.is_none() => {
+                entry.record.pinned = pinned;
+                Ok(())
+            }
+            _ => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        match self.entries.read().get(&key(org_id, short_id)) {
+            Some(entry) if entry.deleted_at.is_none() => Ok(entry.record.clone()),
+            _ => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        let entries = self.entries.read();
+        Ok(short_ids
+            .iter()
+            .filter_map(|short_id| entries.get(&key(org_id, short_id)))
+            .filter(|e| e.deleted_at.is_none())
+            .map(|e| (e.record.short_id.clone(), e.record.clone()))
+            .collect())
+    }
+
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        if let Some(entry) = self.entries.write().get_mut(&key(org_id, short_id)) {
+            if entry.deleted_at.is_none() {
+                entry.record.click_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        Ok(self
+            .live(|r| r.org_id == org_id && r.original_url == original_url)
+            .into_iter()
+            .next())
+    }
+
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let records = self.live(|r| {
+            org_id.map_or(true, |org_id| r.org_id == org_id)
+                && created_by.map_or(true, |created_by| {
+                    r.created_by.as_deref() == Some(created_by)
+                })
+                && after_ts.map_or(true, |after_ts| r.created_ts < after_ts)
+        });
+        Ok(sorted(records, sort_by, sort_dir, limit))
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        let records = newest_first(self.live(|_| true), None);
+        Ok(futures::stream::iter(records.into_iter().map(Ok)).boxed())
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        let records = newest_first(self.live(|r| r.org_id == org_id), None);
+        Ok(futures::stream::iter(records.into_iter().map(Ok)).boxed())
+    }
+
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        let records = self
+            .list(Some(org_id), created_by, None, None, sort_by, sort_dir)
+            .await?;
+        let total = records.len() as i64;
+        let page = records
+            .into_iter()
+            .skip(offset.unwrap_or_default().max(0) as usize)
+            .take(limit.map_or(usize::MAX, |limit| limit.max(0) as usize))
+            .collect();
+        Ok((page, total))
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let records = self.live(|r| {
+            r.resource_type.as_deref() == Some(resource_type)
+                && r.resource_id.as_deref() == Some(resource_id)
+        });
+        Ok(newest_first(records, None))
+    }
+
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let records = self.live(|r| r.tags.get(key).is_some_and(|v| v == value));
+        Ok(newest_first(records, limit))
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let records = self.live(|r| r.org_id == org_id && r.original_url.contains(url_pattern));
+        Ok(newest_first(records, limit))
+    }
+
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        Ok(self.get(org_id, short_id).await.is_ok())
+    }
+
+    async fn len(&self) -> usize {
+        self.live(|_| true).len()
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        let mut entries = self.entries.write();
+        let deleted = entries.len() as u64;
+        entries.clear();
+        Ok(deleted)
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        Ok("memory".to_string())
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        let now = Utc::now().timestamp_micros();
+        let mut expired = self
+            .entries
+            .read()
+            .values()
+            .map(|e| &e.record)
+            .filter(|r| {
+                org_id.map_or(true, |org_id| r.org_id == org_id)
+                    && !r.pinned
+                    && (r.created_ts < expired_before || r.expires_at.is_some_and(|ts| ts < now))
+            })
+            .map(|r| (r.created_ts, r.org_id.clone(), r.short_id.clone()))
+            .collect::<Vec<_>>();
+        // a random sample keeps the arbitrary order of the map
+        if strategy == EvictionStrategy::OldestFirst {
+            expired.sort();
+        }
+        if let Some(limit) = limit {
+            expired.truncate(limit.max(0) as usize);
+        }
+        Ok(expired
+            .into_iter()
+            .map(|(_, org_id, short_id)| (org_id, short_id))
+            .collect())
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let mut entries = self.entries.write();
+        Ok(short_ids
+            .iter()
+            .filter(|key| entries.remove(*key).is_some())
+            .count() as u64)
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        match self.entries.write().get_mut(&key(org_id, short_id)) {
+            Some(entry) if entry.deleted_at.is_some() => {
+                entry.deleted_at = None;
+                Ok(())
+            }
+            _ => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        let mut entries = self.entries.write();
+        let before = entries.len();
+        entries.retain(|_, e| e.deleted_at.map_or(true, |ts| ts >= older_than));
+        Ok((before - entries.len()) as u64)
+    }
+
+    async fn archive_expired(&self, _expired_before: i64, _limit: Option<i64>) -> Result<u64> {
+        Err(ShortUrlError::Unsupported(
+            "the in-memory short url store keeps no archive".to_string(),
+        ))
+    }
+
+    async fn archive(&self, _short_ids: &[(String, String)]) -> Result<u64> {
+        Err(ShortUrlError::Unsupported(
+            "the in-memory short url store keeps no archive".to_string(),
+        ))
+    }
+
+    async fn with_transaction<F, T>(&self, _f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        Err(ShortUrlError::Unsupported(
+            "transactions on the in-memory short url store".to_string(),
+        ))
+    }
+
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        let mut buckets = std::collections::BTreeMap::new();
+        for record in self.live(|r| r.org_id == org_id && (from_ts..to_ts).contains(&r.created_ts))
+        {
+            *buckets
+                .entry(date_bucket(record.created_ts, granularity))
+                .or_insert(0) += 1;
+        }
+        Ok(buckets.into_iter().collect())
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        let mut records = self.live(|r| r.org_id == org_id);
+        records.sort_by(|a, b| b.click_count.cmp(&a.click_count));
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        let records = self.live(|r| r.org_id == org_id);
+        let clicks = records.iter().map(|r| r.click_count).sum();
+        Ok((records.len() as i64, clicks))
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        Ok(self.live(|r| r.org_id == org_id).len() as i64)
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        let mut namespaces = self
+            .live(|r| r.org_id == org_id)
+            .into_iter()
+            .filter_map(|r| r.namespace)
+            .collect::<Vec<_>>();
+        namespaces.sort();
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        let mut due = self
+            .entries
+            .read()
+            .values()
+            .filter(|e| {
+                e.deleted_at.is_none()
+                    && !e.notified
+                    && !e.record.pinned
+                    && e.record.expiry_notify_at.is_some_and(|ts| ts <= now)
+            })
+            .map(|e| e.record.clone())
+            .collect::<Vec<_>>();
+        due.sort_by_key(|r| r.expiry_notify_at);
+        Ok(due)
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        match self.entries.write().get_mut(&key(org_id, short_id)) {
+            Some(entry) => {
+                entry.notified = true;
+                Ok(())
+            }
+            None => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        // the arbitrary order of the map stands in for a random sample
+        let mut records = self.live(|_| true);
+        records.truncate(limit.max(0) as usize);
+        Ok(records)
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        match self.entries.write().get_mut(&key(org_id, short_id)) {
+            Some(entry) if entry.deleted_at.is_none() => {
+                entry.record.last_check_status = Some(status);
+                entry.record.link_rot_detected = status >= 400;
+                Ok(())
+            }
+            _ => Err(ShortUrlError::NotFound(short_id.to_string())),
+        }
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        let mut records = self.live(|r| r.org_id == org_id && r.link_rot_detected);
+        records.sort_by(|a, b| b.created_ts.cmp(&a.created_ts));
+        Ok(records)
+    }
+
+    async fn replay_from_events(&self, _from_ts: i64) -> Result<usize> {
+        Err(ShortUrlError::Unsupported(
+            "the in-memory short url store keeps no event log".to_string(),
+        ))
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        let mut access_log = self.access_log.write();
+        let mut id = access_log.last().map_or(0, |entry| entry.id);
+        for entry in entries {
+            id += 1;
+            access_log.push(AccessLogEntry {
+                id,
+                ..entry.clone()
+            });
+        }
+        Ok(())
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        let mut entries: Vec<_> = self
+            .access_log
+            .read()
+            .iter()
+            .filter(|e| {
+                e.org_id == org_id
+                    && e.short_id == short_id
+                    && e.accessed_at >= from_ts
+                    && e.accessed_at < to_ts
+            })
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| (e.accessed_at, e.id));
+        Ok(entries)
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        let mut access_log = self.access_log.write();
+        let before = access_log.len();
+        access_log.retain(|e| e.accessed_at >= older_than);
+        Ok((before - access_log.len()) as u64)
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        Ok(self
+            .idempotency_keys
+            .read()
+            .get(&key(org_id, idempotency_key))
+            .filter(|(_, created_at)| *created_at >= created_after)
+            .map(|(idempotency_key, _)| idempotency_key.clone()))
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        let mut idempotency_keys = self.idempotency_keys.write();
+        let k = key(org_id, idempotency_key);
+        if idempotency_keys
+            .get(&k)
+            .is_some_and(|(_, created_at)| *created_at >= expired_before)
+        {
+            return Ok(false);
+        }
+        let reserved = IdempotencyKey {
+            short_id: None,
+            body_hash: body_hash.to_string(),
+        };
+        idempotency_keys.insert(k, (reserved, Utc::now().timestamp_micros()));
+        Ok(true)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        if let Some((idempotency_key, _)) = self
+            .idempotency_keys
+            .write()
+            .get_mut(&key(org_id, idempotency_key))
+        {
+            idempotency_key.short_id = Some(short_id.to_string());
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        let mut idempotency_keys = self.idempotency_keys.write();
+        let k = key(org_id, idempotency_key);
+        if idempotency_keys
+            .get(&k)
+            .is_some_and(|(idempotency_key, _)| idempotency_key.short_id.is_none())
+        {
+            idempotency_keys.remove(&k);
+        }
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        let mut idempotency_keys = self.idempotency_keys.write();
+        let before = idempotency_keys.len();
+        idempotency_keys.retain(|_, (_, created_at)| *created_at >= older_than);
+        Ok((before - idempotency_keys.len()) as u64)
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        let new_urls = self
+            .entries
+            .read()
+            .values()
+            .filter(|e| {
+                e.record.org_id == org_id
+                    && e.record.created_ts >= period_start
+                    && e.record.created_ts < period_end
+            })
+            .count() as u64;
+        let mut clicks: HashMap<String, u64> = HashMap::new();
+        for entry in self.access_log.read().iter().filter(|e| {
+            e.org_id == org_id && e.accessed_at >= period_start && e.accessed_at < period_end
+        }) {
+            *clicks.entry(entry.short_id.clone()).or_default() += 1;
+        }
+        let total_clicks = clicks.values().sum();
+        let mut top_5_by_clicks: Vec<_> = clicks.into_iter().collect();
+        top_5_by_clicks.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_5_by_clicks.truncate(DIGEST_TOP_URLS as usize);
+        // removes leave no trace in the in-memory store
+        Ok(ShortUrlDigestReport {
+            new_urls,
+            total_clicks,
+            expired_removed: 0,
+            top_5_by_clicks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_date_bucket() {
+        // 2024-01-03 13:45 UTC, a Wednesday
+        let ts = 1704289500 * 1_000_000;
+        assert_eq!(date_bucket(ts, Granularity::Hour), 1704286800 * 1_000_000);
+        assert_eq!(date_bucket(ts, Granularity::Day), 1704240000 * 1_000_000);
+        // Monday 2024-01-01
+        assert_eq!(date_bucket(ts, Granularity::Week), 1704067200 * 1_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_memory_short_url() {
+        let short_url = MemoryShortUrl::new();
+        let record = ShortUrlRecord::new("default", "memory", "https://example.com/memory");
+        let added = short_url.add(&record).await.unwrap();
+        assert!(added.created_ts > 0);
+        assert!(matches!(
+            short_url.add(&record).await,
+            Err(ShortUrlError::Conflict(_))
+        ));
+        assert_eq!(
+            short_url
+                .get("default", "memory")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/memory"
+        );
+        assert!(short_url.get("other", "memory").await.is_err());
+
+        short_url.remove("default", "memory").await.unwrap();
+        assert!(!short_url.contains("default", "memory").await.unwrap());
+        assert!(short_url.is_empty().await);
+        short_url.restore("default", "memory").await.unwrap();
+        assert_eq!(short_url.len().await, 1);
+        assert_eq!(
+            short_url
+                .batch_remove(vec![("default".to_string(), "memory".to_string())])
+                .await
+                .unwrap(),
+            1
+        );
+
+        // clear counts soft deleted rows as well
+        short_url.add(&record).await.unwrap();
+        short_url
+            .add(&ShortUrlRecord::new(
+                "default",
+                "memory_2",
+                "https://example.com/2",
+            ))
+            .await
+            .unwrap();
+        short_url.remove("default", "memory_2").await.unwrap();
+        assert_eq!(short_url.clear().await.unwrap(), 2);
+        assert_eq!(short_url.clear().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_get_expired() {
+        let short_url = MemoryShortUrl::new();
+        let records = [("old", 1, None), ("due", 10, Some(1)), ("fresh", 10, None)]
+            .into_iter()
+            .map(|(short_id, created_ts, expires_at)| {
+                let mut record = ShortUrlRecord::new("default", short_id, "https://example.com");
+                record.created_ts = created_ts;
+                record.expires_at = expires_at;
+                record
+            })
+            .collect::<Vec<_>>();
+        short_url.batch_add(&records).await.unwrap();
+        let mut other = ShortUrlRecord::new("other", "old", "https://example.com");
+        other.created_ts = 2;
+        short_url.batch_add(&[other]).await.unwrap();
+
+        assert_eq!(
+            short_url
+                .get_expired(None, 5, None, EvictionStrategy::OldestFirst)
+                .await
+                .unwrap(),
+            vec![
+                ("default".to_string(), "old".to_string()),
+                ("other".to_string(), "old".to_string()),
+                ("default".to_string(), "due".to_string()),
+            ]
+        );
+        assert_eq!(
+            short_url
+                .get_expired(Some("other"), 5, None, EvictionStrategy::OldestFirst)
+                .await
+                .unwrap(),
+            vec![("other".to_string(), "old".to_string())]
+        );
+        assert_eq!(
+            short_url
+                .get_expired(None, 5, Some(1), EvictionStrategy::OldestFirst)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        let mut sample = short_url
+            .get_expired(None, 5, None, EvictionStrategy::Random)
+            .await
+            .unwrap();
+        sample.sort();
+        assert_eq!(
+            sample,
+            vec![
+                ("default".to_string(), "due".to_string()),
+                ("default".to_string(), "old".to_string()),
+                ("other".to_string(), "old".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_idempotency_keys() {
+        let short_url = MemoryShortUrl::new();
+        assert!(
+            short_url
+                .reserve_idempotency_key("default", "k1", "h1", 0)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !short_url
+                .reserve_idempotency_key("default", "k1", "h2", 0)
+                .await
+                .unwrap()
+        );
+        short_url
+            .complete_idempotency_key("default", "k1", "first")
+            .await
+            .unwrap();
+        short_url
+            .release_idempotency_key("default", "k1")
+            .await
+            .unwrap();
+        assert_eq!(
+            short_url
+                .get_idempotency_key("default", "k1", 0)
+                .await
+                .unwrap(),
+            Some(IdempotencyKey {
+                short_id: Some("first".to_string()),
+                body_hash: "h1".to_string(),
+            })
+        );
+        // an expired key is taken over
+        assert!(
+            short_url
+                .reserve_idempotency_key("default", "k1", "h2", i64::MAX)
+                .await
+                .unwrap()
+        );
+        short_url
+            .release_idempotency_key("default", "k1")
+            .await
+            .unwrap();
+        assert_eq!(
+            short_url
+                .get_idempotency_key("default", "k1", 0)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_batch_remove() {
+        let short_url = MemoryShortUrl::new();
+        for short_id in ["batch_1", "batch_2"] {
+            short_url
+                .add(&ShortUrlRecord::new(
+                    "default",
+                    short_id,
+                    "https://example.com",
+                ))
+                .await
+                .unwrap();
+        }
+        short_url.remove("default", "batch_2").await.unwrap();
+        // soft deleted records are removed for good as well
+        let removed = short_url
+            .batch_remove(vec![
+                ("default".to_string(), "batch_1".to_string()),
+                ("default".to_string(), "batch_2".to_string()),
+                ("default".to_string(), "missing".to_string()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert!(short_url.restore("default", "batch_2").await.is_err());
+        assert!(short_url.entries.read().is_empty());
+    }
+}
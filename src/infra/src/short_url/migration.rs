@@ -0,0 +1,259 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use config::utils::json;
+use futures::TryStreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{
+    errors::{Error, Result},
+    short_url::{backend::ShortUrlBackend, ShortUrl, ShortUrlRecord},
+};
+
+const IMPORT_BATCH_SIZE: usize = 1000;
+const EXPORT_BATCH_SIZE: usize = 1000;
+
+/// Outcome of an `import_ndjson`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportReport {
+    pub inserted: usize,
+    /// records whose short_id already exists
+    pub skipped: usize,
+    /// lines that could not be parsed or inserted
+    pub errors: usize,
+}
+
+/// Copies the short_urls table of a backend to and from NDJSON, one `ShortUrlRecord` per line
+pub struct ShortUrlMigration {
+    client: Box<dyn ShortUrl>,
+    export_batch_size: usize,
+}
+
+impl ShortUrlMigration {
+    pub fn new(client: Box<dyn ShortUrl>) -> Self {
+        Self {
+            client,
+            export_batch_size: EXPORT_BATCH_SIZE,
+        }
+    }
+
+    /// Number of records an export buffers before writing them out
+    pub fn with_export_batch_size(mut self, export_batch_size: usize) -> Self {
+        self.export_batch_size = export_batch_size.max(1);
+        self
+    }
+
+    /// Write all records to `writer` as they are read from the store, at most
+    /// `export_batch_size` of them are buffered. Returns the number of records written
+    pub async fn export_ndjson(
+        &self,
+        writer: &mut (impl AsyncWrite + Unpin + Send),
+    ) -> Result<usize> {
+        let mut records = self.client.stream().await?;
+        let mut count = 0;
+        let mut buf = Vec::new();
+        let mut buffered = 0;
+        while let Some(record) = records.try_next().await? {
+            buf.extend(json::to_vec(&record)?);
+            buf.push(b'\n');
+            buffered += 1;
+            if buffered >= self.export_batch_size {
+                writer.write_all(&buf).await?;
+                buf.clear();
+                count += buffered;
+                buffered = 0;
+                log::info!("[SHORT_URL] exported {count} short urls");
+            }
+        }
+        writer.write_all(&buf).await?;
+        writer.flush().await?;
+        Ok(count + buffered)
+    }
+
+    /// Read records from `reader` and insert them, existing short_ids are skipped
+    pub async fn import_ndjson(
+        &self,
+        reader: &mut (impl AsyncRead + Unpin + Send),
+    ) -> Result<ImportReport> {
+        let mut report = ImportReport::default();
+        let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+        let mut lines = BufReader::new(reader).lines();
+        let mut line_no = 0;
+        while let Some(line) = lines.next_line().await? {
+            line_no += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match json::from_str::<ShortUrlRecord>(&line) {
+                Ok(record) => batch.push(record),
+                Err(e) => {
+                    log::error!("[SHORT_URL] import line {line_no} parse error: {}", e);
+                    report.errors += 1;
+                }
+            }
+            if batch.len() >= IMPORT_BATCH_SIZE {
+                self.import_batch(&batch, &mut report).await;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            self.import_batch(&batch, &mut report).await;
+        }
+        Ok(report)
+    }
+
+    async fn import_batch(&self, batch: &[ShortUrlRecord], report: &mut ImportReport) {
+        match self.client.batch_add(batch).await {
+            Ok(ret) => {
+                report.inserted += ret.inserted;
+                report.skipped += ret.skipped;
+                log::info!(
+                    "[SHORT_URL] imported {} short urls, skipped {}",
+                    report.inserted,
+                    report.skipped
+                );
+            }
+            Err(e) => {
+                log::error!("[SHORT_URL] import batch error: {}", e);
+                report.errors += batch.len();
+            }
+        }
+    }
+}
+
+impl Default for ShortUrlMigration {
+    fn default() -> Self {
+        Self::new(Box::new(ShortUrlBackend::from_config()))
+    }
+}
+
+/// Outcome of a `migrate_live`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationStats {
+    pub copied: usize,
+    /// records whose short_id already exists in the target
+    pub skipped: usize,
+    /// records of batches the target rejected
+    pub errors: usize,
+}
+
+/// Copies the short urls of one backend to another while the server keeps running on the
+/// source. Writes made during the copy only reach the target when it is also configured as
+/// `ZO_SHORT_URL_SHADOW_BACKEND`
+pub struct ShortUrlMigrator;
+
+impl ShortUrlMigrator {
+    /// Stream every record of `source` into `target` in batches of `batch_size`, existing
+    /// short_ids are skipped so an interrupted migration can be run again. Fails when the two
+    /// backends do not hold the same number of short urls afterwards
+    pub async fn migrate_live(
+        source: &dyn ShortUrl,
+        target: &dyn ShortUrl,
+        batch_size: usize,
+    ) -> Result<MigrationStats> {
+        let batch_size = batch_size.max(1);
+        let mut stats = MigrationStats::default();
+        let mut records = source.stream().await?;
+        let mut batch = Vec::with_capacity(batch_size);
+        while let Some(record) = records.try_next().await? {
+            batch.push(record);
+            if batch.len() >= batch_size {
+                Self::migrate_batch(target, &batch, &mut stats).await;
+                batch.clear();
+            }
+        }
+        if !batch.is_empty() {
+            Self::migrate_batch(target, &batch, &mut stats).await;
+        }
+
+        let (source_len, target_len) = (source.len().await, target.len().await);
+        if source_len != target_len {
+            return Err(Error::Message(format!(
+                "short url migration copied {}, skipped {}, failed {} but the source has {source_len} short urls and the target {target_len}",
+                stats.copied, stats.skipped, stats.errors
+            )));
+        }
+        Ok(stats)
+    }
+
+    async fn migrate_batch(
+        target: &dyn ShortUrl,
+        batch: &[ShortUrlRecord],
+        stats: &mut MigrationStats,
+    ) {
+        match target.batch_add(batch).await {
+            Ok(ret) => {
+                stats.copied += ret.inserted;
+                stats.skipped += ret.skipped;
+                log::info!(
+                    "[SHORT_URL] migrated {} short urls, skipped {}",
+                    stats.copied,
+                    stats.skipped
+                );
+            }
+            Err(e) => {
+                log::error!("[SHORT_URL] migrate batch error: {}", e);
+                stats.errors += batch.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::short_url::memory::MemoryShortUrl;
+
+    #[tokio::test]
+    async fn test_migrate_live() {
+        let source = MemoryShortUrl::new();
+        let target = MemoryShortUrl::new();
+        for short_id in ["a", "b", "c"] {
+            let record = ShortUrlRecord::new("default", short_id, "https://example.com/");
+            source.add(&record).await.unwrap();
+        }
+        // copied by an earlier run
+        let record = source.get("default", "a").await.unwrap();
+        target.batch_add(&[record]).await.unwrap();
+
+        let stats = ShortUrlMigrator::migrate_live(&source, &target, 2)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            MigrationStats {
+                copied: 2,
+                skipped: 1,
+                errors: 0
+            }
+        );
+        // the created_ts of the source is kept
+        assert_eq!(
+            target.get("default", "b").await.unwrap().created_ts,
+            source.get("default", "b").await.unwrap().created_ts
+        );
+
+        // a short url only in the target fails the reconciliation
+        target
+            .add(&ShortUrlRecord::new("default", "d", "https://example.com/"))
+            .await
+            .unwrap();
+        assert!(
+            ShortUrlMigrator::migrate_live(&source, &target, 2)
+                .await
+                .is_err()
+        );
+    }
+}
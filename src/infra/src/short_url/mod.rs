@@ -13,48 +13,562 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::{str::FromStr, time::Instant};
+
 use async_trait::async_trait;
-use config::meta::meta_store::MetaStore;
+use chrono::Utc;
+use config::{
+    meta::short_url::{ShortUrlHealthReport, UrlTransform},
+    metrics::{SHORT_URL_ADD_CONFLICT, SHORT_URL_TOTAL},
+    utils::md5,
+};
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt};
+use hashbrown::HashMap;
 use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
-use crate::errors::Result;
+use crate::short_url::{
+    error::{Result, ShortUrlError},
+    tx::ShortUrlTx,
+};
 
+pub mod backend;
+pub mod cache;
+pub mod collision;
+pub mod error;
+pub mod fallback;
+pub mod id;
+pub mod link_rot;
+pub mod memory;
+pub mod migration;
 pub mod mysql;
 pub mod postgres;
+pub mod purge;
+pub mod retry;
+pub mod shadow;
 pub mod sqlite;
+#[cfg(feature = "short_url_redis")]
+pub mod tiered;
+pub mod transform;
+pub mod tx;
 
-static CLIENT: Lazy<Box<dyn ShortUrl>> = Lazy::new(connect_default);
+type Store = fallback::FallbackShortUrl<
+    shadow::ShadowShortUrl<backend::ShortUrlBackend, backend::ShortUrlBackend>,
+>;
 
-pub fn connect_default() -> Box<dyn ShortUrl> {
-    match config::get_config().common.meta_store.as_str().into() {
-        MetaStore::MySQL => Box::<mysql::MysqlShortUrl>::default(),
-        MetaStore::PostgreSQL => Box::<postgres::PostgresShortUrl>::default(),
-        _ => Box::<sqlite::SqliteShortUrl>::default(),
-    }
-}
+#[cfg(not(feature = "short_url_redis"))]
+type Client = cache::CachedShortUrl<Store>;
+#[cfg(feature = "short_url_redis")]
+type Client = tiered::TieredShortUrlCache<Store, redis::aio::ConnectionManager>;
+
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    let store = fallback::FallbackShortUrl::from_config(shadow::ShadowShortUrl::from_config(
+        backend::ShortUrlBackend::from_config(),
+    ));
+    #[cfg(feature = "short_url_redis")]
+    let store = tiered::RedisShortUrlCache::from_config(store);
+    cache::CachedShortUrl::from_config(store)
+});
+
+const ADD_OR_GET_MAX_ATTEMPTS: u32 = 5;
+
+// the sentinel of `health_report`
+const HEALTH_ORG_ID: &str = "__health__";
+const HEALTH_SHORT_ID_PREFIX: &str = "__health__";
+const HEALTH_URL: &str = "https://openobserve.ai/";
+
+pub const DEFAULT_TABLE_NAME: &str = "short_urls";
+
+/// Name of the short urls table, read once from `ZO_SHORT_URL_TABLE_NAME`
+pub static TABLE_NAME: Lazy<String> =
+    Lazy::new(|| config::get_config().limit.short_url_table_name.clone());
+
+/// Table recording the schema version of each short urls table, one row per table name
+pub const SCHEMA_VERSION_TABLE: &str = "short_url_schema_version";
+
+/// Latest schema version of the short urls table, bump it along with a new migration step
+/// on every backend
+pub const SCHEMA_VERSION: i64 = 20;
+
+/// Append-only log of the changes made to the short urls, see `ShortUrl::replay_from_events`
+pub const EVENTS_TABLE: &str = "short_url_events";
+
+/// Expired short urls moved out of the short urls table, see `ShortUrl::archive_expired`
+pub const ARCHIVE_TABLE: &str = "short_url_archive";
+
+/// Columns copied to `ARCHIVE_TABLE`, the archive table has them all plus `archived_at`
+pub(crate) const ARCHIVE_COLUMNS: &str = "org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, deleted_at, resource_type, resource_id, tags, pinned, expiry_notify_at, notified, namespace, acl, pipeline, checksum";
+
+/// One row per redirect, see `ShortUrl::get_access_log`
+pub const ACCESS_LOG_TABLE: &str = "short_url_access_log";
+
+/// Each request with an `Idempotency-Key`, see `ShortUrl::reserve_idempotency_key`
+pub const IDEMPOTENCY_KEYS_TABLE: &str = "short_url_idempotency_keys";
+
+/// How long a retried request with the same `Idempotency-Key` gets the same short url back
+pub const IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
 
 #[async_trait]
 pub trait ShortUrl: Sync + Send + 'static {
     async fn create_table(&self) -> Result<()>;
     async fn create_table_index(&self) -> Result<()>;
-    async fn add(&self, short_id: &str, original_url: &str) -> Result<()>;
-    async fn remove(&self, short_id: &str) -> Result<()>;
-    async fn get(&self, short_id: &str) -> Result<ShortUrlRecord>;
-    async fn list(&self, limit: Option<i64>) -> Result<Vec<ShortUrlRecord>>;
-    async fn contains(&self, short_id: &str) -> Result<bool>;
+    /// Apply the migration steps newer than the schema version recorded for the table, every
+    /// step is idempotent so concurrent nodes may run it
+    async fn migrate(&self) -> Result<()>;
+    /// Insert a record, returns it as stored with its `created_ts`. Fails with
+    /// `ShortUrlError::Conflict` if the short_id is taken in the org
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord>;
+    /// Add a record unless its short_id is taken, returns `false` for the no-op
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool>;
+    /// Add records keeping their `created_ts` and `click_count`, a zero `created_ts` means now
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult>;
+    /// Soft delete a short url, it is hidden from every read until it is `restore`d
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()>;
+    /// Retarget a short_id, fails with `ShortUrlError::NotFound` if the short_id does not exist
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()>;
+    /// Move a short url to `new_short_id` in one statement, keeping its url and clicks. Fails
+    /// with `ShortUrlError::NotFound` if `old_short_id` does not exist and
+    /// `ShortUrlError::Conflict` if `new_short_id` is taken
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()>;
+    /// Pinned short urls are never returned by `get_expired`, fails with
+    /// `ShortUrlError::NotFound` if the short_id does not exist
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()>;
+    /// Fails with `ShortUrlError::NotFound` if the short_id does not exist
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord>;
+    /// Get the short urls of `org_id` in one query keyed by short_id, short_ids that do not
+    /// exist are left out of the map
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>>;
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()>;
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>>;
+    /// List short urls sorted by `sort_by`, `None` for org_id lists every org and `None` for
+    /// created_by every user
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>>;
+    /// Stream every short url newest first without loading them all in memory
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>>;
+    /// Stream the short urls of the org newest first without loading them all in memory
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>>;
+    /// List a page of the org's short urls sorted by `sort_by`, returns the page and the org's
+    /// total
+    /// The db reads every skipped row so a large `offset` gets slow, page through big orgs with
+    /// the `after_ts` cursor of `list` instead
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)>;
+    /// List the short urls linking to a resource in any org, newest first
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>>;
+    /// List the short urls in any org whose tag `key` is `value`, newest first
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>>;
+    /// Find short urls whose original_url contains `url_pattern`, newest first
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>>;
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool>;
     async fn len(&self) -> usize;
-    async fn clear(&self) -> Result<()>;
+    /// Delete every row, soft deleted ones included, returns the number deleted
+    async fn clear(&self) -> Result<u64>;
     async fn is_empty(&self) -> bool;
-    async fn get_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<Vec<String>>;
-    async fn batch_remove(&self, short_ids: Vec<String>) -> Result<()>;
+    /// Check the short url store is reachable
+    async fn ping(&self) -> Result<()>;
+    /// Version of the db server
+    async fn db_version(&self) -> Result<String>;
+    /// Get `(org_id, short_id)` of short urls created before `expired_before` or past their own
+    /// `expires_at`, only those of `org_id` when it is set, in the order of `strategy`
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>>;
+    /// Remove short urls by `(org_id, short_id)`, returns the number of rows deleted. Unlike
+    /// `remove` the rows are deleted for good, soft deleted or not
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64>;
+    /// Undo a `remove`, fails with `ShortUrlError::NotFound` if the short_id is not soft deleted
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()>;
+    /// Delete the short urls soft deleted before `older_than`, returns the number of rows deleted
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64>;
+    /// Move the short urls `get_expired` would return to `ARCHIVE_TABLE`, oldest first and at
+    /// most `limit` of them, in one transaction. Returns the number of rows moved
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64>;
+    /// Move the given short urls to `ARCHIVE_TABLE` in one transaction, pinned ones are kept.
+    /// Returns the number of rows moved
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64>;
+    /// Run `f` in one transaction, committed if `f` returns `Ok` and rolled back otherwise
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send;
+    /// Count short urls created in `[from_ts, to_ts)` per UTC time bucket, returns
+    /// `(bucket_ts, count)` pairs ordered by bucket, timestamps are in microseconds
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>>;
+    /// The org's short urls with the most clicks, most clicked first
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>>;
+    /// Number of short urls of the org and the sum of their clicks
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)>;
+    /// Number of short urls of the org, soft deleted ones are not counted
+    async fn count_by_org(&self, org_id: &str) -> Result<i64>;
+    /// Distinct namespaces of the org's short urls, sorted
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>>;
+    /// Short urls whose `expiry_notify_at` is at or before `now` and whose expiry notification
+    /// was not sent yet, pinned short urls never expire so they are left out
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>>;
+    /// Record that the expiry notification of a short url was sent so it is not sent again,
+    /// fails with `ShortUrlError::NotFound` if the short_id does not exist
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()>;
+    /// A random sample of at most `limit` short urls whose original_url is checked next by
+    /// the link rot detector
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>>;
+    /// Record the HTTP status the original_url of a short url answered with, a 4xx or 5xx sets
+    /// `link_rot_detected` and any other status clears it. Fails with
+    /// `ShortUrlError::NotFound` if the short_id does not exist
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()>;
+    /// The org's short urls whose last link check found their original_url broken, newest
+    /// first
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>>;
+    /// Apply the events logged after `from_ts` again in order, returns how many were applied.
+    /// Every add, remove, update and batch_remove logs one event per record in `EVENTS_TABLE`
+    /// along with the change, replaying does not log them again
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize>;
+    /// Append entries to `ACCESS_LOG_TABLE`, their `id` is assigned by the backend
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()>;
+    /// Accesses of a short url with `from_ts <= accessed_at < to_ts`, oldest first
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>>;
+    /// Delete the accesses before `older_than`, returns how many were deleted
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64>;
+    /// `idempotency_key` of the org, `None` unless it was reserved at or after `created_after`
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>>;
+    /// Reserve `idempotency_key` for the request with `body_hash` before it creates its short
+    /// url, on the unique index of the key so only one of concurrent requests gets it. Returns
+    /// false if a key reserved at or after `expired_before` holds it, an older one is taken over
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool>;
+    /// Record that the request holding `idempotency_key` created `short_id`
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()>;
+    /// Release `idempotency_key` if its request did not create a short url, so a retry can
+    /// reserve it again
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()>;
+    /// Delete the idempotency keys added before `older_than`, returns how many were deleted
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64>;
+    /// Activity of the org with `period_start <= ts < period_end`, see `ShortUrlDigestReport`
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport>;
+    /// Get the short_id already pointing to `record.original_url` in the org with the same
+    /// settings, see `ShortUrlRecord::reusable_for`, or insert `record` under a short_id
+    /// generated from the url, returns the short_id and whether it was inserted. Generated
+    /// short_ids taken by a record with other settings are skipped, conflicts are checked again
+    /// so concurrent calls for the same url and settings converge to the same short_id
+    async fn add_or_get(&self, record: &ShortUrlRecord) -> Result<(String, bool)> {
+        if let Some(existing) = self
+            .get_by_original_url(&record.org_id, &record.original_url)
+            .await?
+        {
+            if existing.reusable_for(record) {
+                return Ok((existing.short_id, false));
+            }
+        }
+        for attempt in 0..ADD_OR_GET_MAX_ATTEMPTS {
+            let short_id = generate_short_id(&record.original_url, attempt);
+            match self.get(&record.org_id, &short_id).await {
+                Ok(existing) if existing.reusable_for(record) => return Ok((short_id, false)),
+                Ok(_) => {
+                    collision::record(true);
+                    continue;
+                }
+                Err(ShortUrlError::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+            let mut record = record.clone();
+            record.short_id = short_id.clone();
+            let ret = self.add(&record).await;
+            collision::record(matches!(ret, Err(ShortUrlError::Conflict(_))));
+            match ret {
+                Ok(_) => return Ok((short_id, true)),
+                // lost the race, the winner may hold the same settings
+                Err(ShortUrlError::Conflict(_)) => {
+                    match self.get(&record.org_id, &short_id).await {
+                        Ok(existing) if existing.reusable_for(&record) => {
+                            return Ok((short_id, false));
+                        }
+                        Ok(_) | Err(ShortUrlError::NotFound(_)) => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ShortUrlError::Conflict(generate_short_id(
+            &record.original_url,
+            ADD_OR_GET_MAX_ATTEMPTS - 1,
+        )))
+    }
+
+    /// Add `alias` as another short_id for the url of `target` in the org. Aliases are one level
+    /// deep, `target` can not be an alias itself so aliases can never form a cycle
+    async fn add_alias(&self, org_id: &str, alias: &str, target: &str) -> Result<()> {
+        if alias == target {
+            return Err(ShortUrlError::InvalidShortId(format!(
+                "short url {alias} can not be an alias of itself"
+            )));
+        }
+        let canonical = self.get(org_id, target).await?;
+        if let Some(alias_of) = canonical.alias_of {
+            return Err(ShortUrlError::InvalidShortId(format!(
+                "short url {target} is already an alias of {alias_of}"
+            )));
+        }
+        let mut record = ShortUrlRecord::new(org_id, alias, &canonical.original_url);
+        record.alias_of = Some(target.to_string());
+        self.add(&record).await?;
+        Ok(())
+    }
+
+    /// Protect a short url from expiry
+    async fn pin(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.set_pinned(org_id, short_id, true).await
+    }
+
+    /// Let a pinned short url expire again
+    async fn unpin(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.set_pinned(org_id, short_id, false).await
+    }
+
+    /// Copy a short url of the org to `new_short_id`, or to a snowflake short_id, with a fresh
+    /// `created_ts` and no clicks, returns the new short_id. Fails with
+    /// `ShortUrlError::NotFound` if the source does not exist and `ShortUrlError::Conflict` if
+    /// `new_short_id` is taken
+    async fn clone_url(
+        &self,
+        org_id: &str,
+        source_short_id: &str,
+        new_short_id: Option<&str>,
+    ) -> Result<String> {
+        let source = self.get(org_id, source_short_id).await?;
+        let mut record = ShortUrlRecord {
+            created_ts: 0,
+            click_count: 0,
+            ..source
+        };
+        record.short_id = match new_short_id {
+            Some(new_short_id) => {
+                if !id::is_valid_custom_short_id(new_short_id)
+                    || id::is_reserved_short_id(new_short_id)
+                {
+                    return Err(ShortUrlError::InvalidShortId(new_short_id.to_string()));
+                }
+                new_short_id.to_string()
+            }
+            // a clone is a copy on purpose, an id from its url would mostly hit the source
+            None => id::next_snowflake_short_id(),
+        };
+        self.add(&record).await?;
+        Ok(record.short_id)
+    }
+
+    /// Add `record` and settle a conflict on its short_id with `ZO_SHORT_URL_MERGE_STRATEGY`,
+    /// for records created on another node holding the same short_id
+    async fn merge(&self, record: &ShortUrlRecord) -> Result<MergeResult> {
+        merge_with(self, record, MergeStrategy::from_config()).await
+    }
+
+    /// Time an `add`, `get` and `remove` of a sentinel short url, `ping` only tells the db
+    /// answers. The sentinel lives in its own org and is hard deleted afterwards
+    async fn health_report(&self) -> Result<ShortUrlHealthReport> {
+        let nanos = Utc::now().timestamp_subsec_nanos();
+        let record = ShortUrlRecord::new(
+            HEALTH_ORG_ID,
+            &format!("{HEALTH_SHORT_ID_PREFIX}{nanos:08x}"),
+            HEALTH_URL,
+        );
+        let ms = |start: Instant| start.elapsed().as_secs_f64() * 1000.0;
+
+        let start = Instant::now();
+        self.add(&record).await?;
+        let add_latency_ms = ms(start);
+        let start = Instant::now();
+        let ret = self.get(&record.org_id, &record.short_id).await;
+        let get_latency_ms = ms(start);
+        let start = Instant::now();
+        let removed = self.remove(&record.org_id, &record.short_id).await;
+        let remove_latency_ms = ms(start);
+        // `remove` keeps the row
+        self.batch_remove(vec![(record.org_id.clone(), record.short_id.clone())])
+            .await?;
+        ret?;
+        removed?;
+
+        Ok(ShortUrlHealthReport {
+            add_latency_ms,
+            get_latency_ms,
+            remove_latency_ms,
+            db_version: self.db_version().await?,
+        })
+    }
+
+    /// Recompute the checksum of every short url, `batch_size` records at a time, and report
+    /// those not matching the checksum stored on write
+    async fn verify_integrity(&self, batch_size: usize) -> Result<IntegrityReport> {
+        let mut batches = self.stream().await?.chunks(batch_size.max(1));
+        let mut report = IntegrityReport::default();
+        while let Some(batch) = batches.next().await {
+            for record in batch {
+                let record = record?;
+                report.scanned += 1;
+                match record.verify_checksum() {
+                    Some(true) => {}
+                    Some(false) => report.mismatches.push((record.org_id, record.short_id)),
+                    None => report.unchecked += 1,
+                }
+            }
+            // a full scan takes a while, let the other tasks run in between
+            tokio::task::yield_now().await;
+        }
+        Ok(report)
+    }
+}
+
+/// `ShortUrl::merge` with the given strategy
+pub async fn merge_with<S: ShortUrl + ?Sized>(
+    short_url: &S,
+    record: &ShortUrlRecord,
+    strategy: MergeStrategy,
+) -> Result<MergeResult> {
+    // unlike `add` this keeps the created_ts of the record, the merge is decided on it
+    if short_url
+        .batch_add(std::slice::from_ref(record))
+        .await?
+        .inserted
+        > 0
+    {
+        return Ok(MergeResult::Inserted);
+    }
+    if strategy == MergeStrategy::ErrorOnConflict {
+        return Err(ShortUrlError::Conflict(record.short_id.clone()));
+    }
+    // a deleted short url still holds its short_id, it is not merged
+    let local = match short_url.get(&record.org_id, &record.short_id).await {
+        Ok(local) => local,
+        Err(ShortUrlError::NotFound(_)) => {
+            return Err(ShortUrlError::Conflict(record.short_id.clone()));
+        }
+        Err(e) => return Err(e),
+    };
+    // every node has to pick the same winner, equal timestamps fall back to the url
+    if (record.created_ts, &record.original_url) <= (local.created_ts, &local.original_url) {
+        return Ok(MergeResult::LocalWon);
+    }
+    short_url
+        .batch_remove(vec![(record.org_id.clone(), record.short_id.clone())])
+        .await?;
+    if short_url
+        .batch_add(std::slice::from_ref(record))
+        .await?
+        .inserted
+        == 0
+    {
+        // added again in between
+        return Err(ShortUrlError::Conflict(record.short_id.clone()));
+    }
+    log::info!(
+        "[SHORT_URL] merged {}/{}, replaced the local record created at {} with the one created at {}",
+        record.org_id,
+        record.short_id,
+        local.created_ts,
+        record.created_ts
+    );
+    Ok(MergeResult::IncomingWon)
 }
 
 pub async fn init() -> Result<()> {
+    #[cfg(feature = "short_url_redis")]
+    CLIENT.inner().connect_from_config().await;
+    wait_connected().await?;
     CLIENT.create_table().await?;
     CLIENT.create_table_index().await?;
     Ok(())
 }
 
+/// Fail instead of blocking the startup when the db can not be reached within
+/// `ZO_SHORT_URL_DB_CONNECT_TIMEOUT_SECS`
+async fn wait_connected() -> Result<()> {
+    let secs = config::get_config().limit.short_url_db_connect_timeout_secs;
+    if secs == 0 {
+        return Ok(());
+    }
+    match tokio::time::timeout(std::time::Duration::from_secs(secs), CLIENT.ping()).await {
+        Ok(ret) => ret,
+        Err(_) => Err(ShortUrlError::Timeout(format!("connect after {secs}s"))),
+    }
+}
+
 pub async fn create_table() -> Result<()> {
     CLIENT.create_table().await
 }
@@ -64,28 +578,228 @@ pub async fn create_table_index() -> Result<()> {
 }
 
 #[inline]
-pub async fn add(short_id: &str, original_url: &str) -> Result<()> {
-    CLIENT.add(short_id, original_url).await
+pub async fn add(record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+    let ret = CLIENT.add(record).await;
+    match &ret {
+        Ok(_) => SHORT_URL_TOTAL.with_label_values(&[]).inc(),
+        Err(ShortUrlError::Conflict(_)) => SHORT_URL_ADD_CONFLICT.with_label_values(&[]).inc(),
+        Err(_) => {}
+    }
+    ret
+}
+
+#[inline]
+pub async fn add_if_absent(record: &ShortUrlRecord) -> Result<bool> {
+    let inserted = CLIENT.add_if_absent(record).await?;
+    if inserted {
+        SHORT_URL_TOTAL.with_label_values(&[]).inc();
+    }
+    Ok(inserted)
+}
+
+#[inline]
+pub async fn batch_add(records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+    let ret = CLIENT.batch_add(records).await?;
+    SHORT_URL_TOTAL
+        .with_label_values(&[])
+        .inc_by(ret.inserted as u64);
+    SHORT_URL_ADD_CONFLICT
+        .with_label_values(&[])
+        .inc_by(ret.skipped as u64);
+    Ok(ret)
+}
+
+#[inline]
+pub async fn add_or_get(record: &ShortUrlRecord) -> Result<(String, bool)> {
+    let ret = CLIENT.add_or_get(record).await?;
+    if ret.1 {
+        SHORT_URL_TOTAL.with_label_values(&[]).inc();
+    }
+    Ok(ret)
+}
+
+#[inline]
+pub async fn merge(record: &ShortUrlRecord) -> Result<MergeResult> {
+    CLIENT.merge(record).await
+}
+
+#[inline]
+pub async fn add_alias(org_id: &str, alias: &str, target: &str) -> Result<()> {
+    CLIENT.add_alias(org_id, alias, target).await?;
+    SHORT_URL_TOTAL.with_label_values(&[]).inc();
+    Ok(())
+}
+
+#[inline]
+pub async fn clone_url(
+    org_id: &str,
+    source_short_id: &str,
+    new_short_id: Option<&str>,
+) -> Result<String> {
+    let short_id = CLIENT
+        .clone_url(org_id, source_short_id, new_short_id)
+        .await?;
+    SHORT_URL_TOTAL.with_label_values(&[]).inc();
+    Ok(short_id)
+}
+
+#[inline]
+pub async fn remove(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.remove(org_id, short_id).await
 }
 
 #[inline]
-pub async fn remove(short_id: &str) -> Result<()> {
-    CLIENT.remove(short_id).await
+pub async fn restore(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.restore(org_id, short_id).await
 }
 
 #[inline]
-pub async fn get(short_id: &str) -> Result<ShortUrlRecord> {
-    CLIENT.get(short_id).await
+pub async fn hard_delete_expired_soft_deleted(older_than: i64) -> Result<u64> {
+    CLIENT.hard_delete_expired_soft_deleted(older_than).await
+}
+
+#[inline]
+pub async fn archive_expired(expired_before: i64, limit: Option<i64>) -> Result<u64> {
+    CLIENT.archive_expired(expired_before, limit).await
+}
+
+#[inline]
+pub async fn update(org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+    CLIENT.update(org_id, short_id, new_url).await
+}
+
+/// Rename a short url, `new_short_id` must pass the same checks as a custom short_id on creation
+#[inline]
+pub async fn rename(org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+    if !id::is_valid_custom_short_id(new_short_id) || id::is_reserved_short_id(new_short_id) {
+        return Err(ShortUrlError::InvalidShortId(new_short_id.to_string()));
+    }
+    CLIENT.rename(org_id, old_short_id, new_short_id).await
 }
 
 #[inline]
-pub async fn list(limit: Option<i64>) -> Result<Vec<ShortUrlRecord>> {
-    CLIENT.list(limit).await
+pub async fn pin(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.pin(org_id, short_id).await
 }
 
 #[inline]
-pub async fn contains(short_id: &str) -> Result<bool> {
-    CLIENT.contains(short_id).await
+pub async fn unpin(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.unpin(org_id, short_id).await
+}
+
+#[inline]
+pub async fn get(org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+    let mut record = CLIENT.get(org_id, short_id).await?;
+    // an alias keeps the url it was created with in case its canonical short url is removed
+    if let Some(alias_of) = record.alias_of.as_deref() {
+        if let Ok(canonical) = CLIENT.get(&record.org_id, alias_of).await {
+            record.original_url = canonical.original_url;
+        }
+    }
+    Ok(record)
+}
+
+#[inline]
+pub async fn get_many(org_id: &str, short_ids: &[&str]) -> Result<HashMap<String, ShortUrlRecord>> {
+    let mut records = CLIENT.get_many(org_id, short_ids).await?;
+    // resolve aliases like `get`, their canonical short urls are fetched in a second query
+    let canonical_ids = records
+        .values()
+        .filter_map(|r| r.alias_of.clone())
+        .collect::<Vec<_>>();
+    if canonical_ids.is_empty() {
+        return Ok(records);
+    }
+    let canonical_ids = canonical_ids.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+    let canonical = CLIENT.get_many(org_id, &canonical_ids).await?;
+    for record in records.values_mut() {
+        if let Some(target) = record.alias_of.as_deref().and_then(|id| canonical.get(id)) {
+            record.original_url = target.original_url.clone();
+        }
+    }
+    Ok(records)
+}
+
+#[inline]
+pub async fn increment_click_count(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.increment_click_count(org_id, short_id).await
+}
+
+#[inline]
+pub async fn get_by_original_url(
+    org_id: &str,
+    original_url: &str,
+) -> Result<Option<ShortUrlRecord>> {
+    CLIENT.get_by_original_url(org_id, original_url).await
+}
+
+#[inline]
+pub async fn list(
+    org_id: Option<&str>,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    after_ts: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT
+        .list(org_id, created_by, limit, after_ts, sort_by, sort_dir)
+        .await
+}
+
+#[inline]
+pub async fn stream() -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+    CLIENT.stream().await
+}
+
+#[inline]
+pub async fn stream_by_org(org_id: &str) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+    CLIENT.stream_by_org(org_id).await
+}
+
+#[inline]
+pub async fn list_with_count(
+    org_id: &str,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<(Vec<ShortUrlRecord>, i64)> {
+    CLIENT
+        .list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+        .await
+}
+
+#[inline]
+pub async fn list_by_resource(
+    resource_type: &str,
+    resource_id: &str,
+) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.list_by_resource(resource_type, resource_id).await
+}
+
+#[inline]
+pub async fn list_by_tag(
+    key: &str,
+    value: &str,
+    limit: Option<i64>,
+) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.list_by_tag(key, value, limit).await
+}
+
+#[inline]
+pub async fn search(
+    org_id: &str,
+    url_pattern: &str,
+    limit: Option<i64>,
+) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.search(org_id, url_pattern, limit).await
+}
+
+#[inline]
+pub async fn contains(org_id: &str, short_id: &str) -> Result<bool> {
+    CLIENT.contains(org_id, short_id).await
 }
 
 #[inline]
@@ -94,7 +808,7 @@ pub async fn len() -> usize {
 }
 
 #[inline]
-pub async fn clear() -> Result<()> {
+pub async fn clear() -> Result<u64> {
     CLIENT.clear().await
 }
 
@@ -104,26 +818,969 @@ pub async fn is_empty() -> bool {
 }
 
 #[inline]
-pub async fn get_expired(expired_before: i64, limit: Option<i64>) -> Result<Vec<String>> {
-    CLIENT.get_expired(expired_before, limit).await
+pub async fn ping() -> Result<()> {
+    CLIENT.ping().await
+}
+
+#[inline]
+pub async fn health_report() -> Result<ShortUrlHealthReport> {
+    CLIENT.health_report().await
+}
+
+#[inline]
+pub async fn verify_integrity(batch_size: usize) -> Result<IntegrityReport> {
+    CLIENT.verify_integrity(batch_size).await
+}
+
+#[inline]
+pub async fn get_expired(
+    org_id: Option<&str>,
+    expired_before: i64,
+    limit: Option<i64>,
+    strategy: EvictionStrategy,
+) -> Result<Vec<(String, String)>> {
+    CLIENT
+        .get_expired(org_id, expired_before, limit, strategy)
+        .await
+}
+
+#[inline]
+pub async fn top_by_clicks(org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.top_by_clicks(org_id, limit).await
+}
+
+#[inline]
+pub async fn click_totals(org_id: &str) -> Result<(i64, i64)> {
+    CLIENT.click_totals(org_id).await
+}
+
+#[inline]
+pub async fn count_by_org(org_id: &str) -> Result<i64> {
+    CLIENT.count_by_org(org_id).await
+}
+
+#[inline]
+pub async fn list_namespaces(org_id: &str) -> Result<Vec<String>> {
+    CLIENT.list_namespaces(org_id).await
+}
+
+#[inline]
+pub async fn get_due_for_notification(now: i64) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.get_due_for_notification(now).await
+}
+
+#[inline]
+pub async fn mark_notified(org_id: &str, short_id: &str) -> Result<()> {
+    CLIENT.mark_notified(org_id, short_id).await
+}
+
+#[inline]
+pub async fn sample_for_link_check(limit: i64) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.sample_for_link_check(limit).await
+}
+
+#[inline]
+pub async fn set_link_check_status(org_id: &str, short_id: &str, status: i16) -> Result<()> {
+    CLIENT.set_link_check_status(org_id, short_id, status).await
+}
+
+#[inline]
+pub async fn list_link_rot_candidates(org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+    CLIENT.list_link_rot_candidates(org_id).await
+}
+
+#[inline]
+pub async fn replay_from_events(from_ts: i64) -> Result<usize> {
+    CLIENT.replay_from_events(from_ts).await
+}
+
+#[inline]
+pub async fn add_access_log(entries: &[AccessLogEntry]) -> Result<()> {
+    CLIENT.add_access_log(entries).await
 }
 
 #[inline]
-pub async fn batch_remove(short_ids: Vec<String>) -> Result<()> {
+pub async fn get_access_log(
+    org_id: &str,
+    short_id: &str,
+    from_ts: i64,
+    to_ts: i64,
+) -> Result<Vec<AccessLogEntry>> {
+    CLIENT
+        .get_access_log(org_id, short_id, from_ts, to_ts)
+        .await
+}
+
+#[inline]
+pub async fn purge_access_log(older_than: i64) -> Result<u64> {
+    CLIENT.purge_access_log(older_than).await
+}
+
+#[inline]
+pub async fn get_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    created_after: i64,
+) -> Result<Option<IdempotencyKey>> {
+    CLIENT
+        .get_idempotency_key(org_id, idempotency_key, created_after)
+        .await
+}
+
+#[inline]
+pub async fn reserve_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    body_hash: &str,
+    expired_before: i64,
+) -> Result<bool> {
+    CLIENT
+        .reserve_idempotency_key(org_id, idempotency_key, body_hash, expired_before)
+        .await
+}
+
+#[inline]
+pub async fn complete_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    short_id: &str,
+) -> Result<()> {
+    CLIENT
+        .complete_idempotency_key(org_id, idempotency_key, short_id)
+        .await
+}
+
+#[inline]
+pub async fn release_idempotency_key(org_id: &str, idempotency_key: &str) -> Result<()> {
+    CLIENT
+        .release_idempotency_key(org_id, idempotency_key)
+        .await
+}
+
+#[inline]
+pub async fn purge_idempotency_keys(older_than: i64) -> Result<u64> {
+    CLIENT.purge_idempotency_keys(older_than).await
+}
+
+#[inline]
+pub async fn generate_digest(
+    org_id: &str,
+    period_start: i64,
+    period_end: i64,
+) -> Result<ShortUrlDigestReport> {
+    CLIENT
+        .generate_digest(org_id, period_start, period_end)
+        .await
+}
+
+#[inline]
+pub async fn batch_remove(short_ids: Vec<(String, String)>) -> Result<u64> {
     CLIENT.batch_remove(short_ids).await
 }
 
-#[derive(Debug, sqlx::FromRow)]
+/// Drop the cached record of a short_id changed by another node
+#[inline]
+pub fn invalidate_cache(org_id: &str, short_id: &str) {
+    CLIENT.invalidate(org_id, short_id)
+}
+
+#[inline]
+pub async fn with_transaction<F, T>(f: F) -> Result<T>
+where
+    F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+    T: Send,
+{
+    CLIENT.with_transaction(f).await
+}
+
+#[inline]
+pub async fn count_by_date_range(
+    org_id: &str,
+    from_ts: i64,
+    to_ts: i64,
+    granularity: Granularity,
+) -> Result<Vec<(i64, i64)>> {
+    CLIENT
+        .count_by_date_range(org_id, from_ts, to_ts, granularity)
+        .await
+}
+
+/// Serialized in camelCase, snake_case names are still accepted so older exports can be imported
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[serde(rename_all = "camelCase")]
 pub struct ShortUrlRecord {
+    #[sqlx(default)]
+    #[serde(default, alias = "org_id")]
+    pub org_id: String,
+    #[serde(alias = "short_id")]
     pub short_id: String,
+    #[serde(alias = "original_url")]
     pub original_url: String,
+    #[sqlx(default)]
+    #[serde(default, alias = "created_ts")]
+    pub created_ts: i64,
+    /// Expiry timestamp in microseconds, `None` falls back to the global retention
+    #[sqlx(default)]
+    #[serde(default, alias = "expires_at")]
+    pub expires_at: Option<i64>,
+    #[sqlx(default)]
+    #[serde(default, alias = "click_count")]
+    pub click_count: i64,
+    /// Redirect with 301 instead of 302 so clients can cache it
+    #[sqlx(default)]
+    #[serde(default)]
+    pub permanent: bool,
+    /// Email of the user who created the short url, `None` for system generated ones
+    #[sqlx(default)]
+    #[serde(default, alias = "created_by")]
+    pub created_by: Option<String>,
+    /// short_id of the canonical short url in the same org this one is an alias of
+    #[sqlx(default)]
+    #[serde(default, alias = "alias_of", skip_serializing_if = "Option::is_none")]
+    pub alias_of: Option<String>,
+    /// Kind of the resource the short url links to, e.g. `dashboard`
+    #[sqlx(default)]
+    #[serde(
+        default,
+        alias = "resource_type",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resource_type: Option<String>,
+    /// Id of the resource the short url links to, e.g. a dashboard_id
+    #[sqlx(default)]
+    #[serde(
+        default,
+        alias = "resource_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub resource_id: Option<String>,
+    /// Arbitrary key-value metadata, stored as a JSON object in the `tags` column
+    #[sqlx(default, try_from = "TagsColumn")]
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tags: HashMap<String, String>,
+    /// Pinned short urls never expire
+    #[sqlx(default)]
+    #[serde(default)]
+    pub pinned: bool,
+    /// When the owner is warned that the short url is about to expire, `None` for never
+    #[sqlx(default)]
+    #[serde(
+        default,
+        alias = "expiry_notify_at",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expiry_notify_at: Option<i64>,
+    /// Namespace of a vanity short url, its short_id is `{namespace}/{slug}`
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Orgs whose users may resolve the short url, `None` for everyone who can reach it. Only
+    /// members of `org_id` reach it, the acl narrows access within the org. Stored as a JSON
+    /// array in the `acl` column
+    #[sqlx(default, try_from = "AclColumn")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acl: Option<Vec<String>>,
+    /// Rewrites of `original_url` applied in order before redirecting, stored as a JSON array
+    /// in the `pipeline` column
+    #[sqlx(default, try_from = "PipelineColumn")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipeline: Vec<UrlTransform>,
+    /// `checksum` of the short_id and original_url computed on write, `None` for rows
+    /// written before the column existed
+    #[sqlx(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// HTTP status the original_url answered the last link check with, `None` if it was never
+    /// checked
+    #[sqlx(default)]
+    #[serde(
+        default,
+        alias = "last_check_status",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_check_status: Option<i16>,
+    /// The last link check got a 4xx or 5xx from the original_url
+    #[sqlx(default)]
+    #[serde(default, alias = "link_rot_detected")]
+    pub link_rot_detected: bool,
+}
+
+/// Raw `tags` column, NULL for short urls without tags
+#[derive(sqlx::Type)]
+#[sqlx(transparent)]
+pub(crate) struct TagsColumn(Option<String>);
+
+impl From<TagsColumn> for HashMap<String, String> {
+    fn from(column: TagsColumn) -> Self {
+        let Some(tags) = column.0 else {
+            return HashMap::new();
+        };
+        serde_json::from_str(&tags).unwrap_or_else(|e| {
+            log::warn!("[SHORT_URL] invalid tags {tags:?}: {e}");
+            HashMap::new()
+        })
+    }
+}
+
+/// Raw `acl` column, NULL for short urls everyone may resolve
+#[derive(sqlx::Type)]
+#[sqlx(transparent)]
+pub(crate) struct AclColumn(Option<String>);
+
+impl From<AclColumn> for Option<Vec<String>> {
+    fn from(column: AclColumn) -> Self {
+        let acl = column.0?;
+        match serde_json::from_str(&acl) {
+            Ok(acl) => Some(acl),
+            Err(e) => {
+                // fail closed, an unreadable acl lets no org in
+                log::warn!("[SHORT_URL] invalid acl {acl:?}: {e}");
+                Some(vec![])
+            }
+        }
+    }
+}
+
+/// Raw `pipeline` column, NULL for short urls that redirect to their original_url as is
+#[derive(sqlx::Type)]
+#[sqlx(transparent)]
+pub(crate) struct PipelineColumn(Option<String>);
+
+impl From<PipelineColumn> for Vec<UrlTransform> {
+    fn from(column: PipelineColumn) -> Self {
+        let Some(pipeline) = column.0 else {
+            return Vec::new();
+        };
+        serde_json::from_str(&pipeline).unwrap_or_else(|e| {
+            log::warn!("[SHORT_URL] invalid pipeline {pipeline:?}: {e}");
+            Vec::new()
+        })
+    }
 }
 
 impl ShortUrlRecord {
-    pub fn new(short_id: &str, original_url: &str) -> Self {
+    pub fn new(org_id: &str, short_id: &str, original_url: &str) -> Self {
         Self {
+            org_id: org_id.to_string(),
             short_id: short_id.to_string(),
             original_url: original_url.to_string(),
+            created_ts: 0,
+            expires_at: None,
+            click_count: 0,
+            permanent: false,
+            created_by: None,
+            alias_of: None,
+            resource_type: None,
+            resource_id: None,
+            tags: HashMap::new(),
+            pinned: false,
+            expiry_notify_at: None,
+            namespace: None,
+            acl: None,
+            pipeline: Vec::new(),
+            checksum: None,
+            last_check_status: None,
+            link_rot_detected: false,
+        }
+    }
+
+    /// Checksum of the record as it is stored
+    pub fn compute_checksum(&self) -> String {
+        checksum(&self.short_id, &self.original_url)
+    }
+
+    /// Whether the stored checksum matches the record, `None` when it has none
+    pub fn verify_checksum(&self) -> Option<bool> {
+        self.checksum
+            .as_ref()
+            .map(|checksum| *checksum == self.compute_checksum())
+    }
+
+    /// Value bound to the `tags` column, no tags are stored as NULL
+    pub(crate) fn tags_json(&self) -> Option<String> {
+        if self.tags.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&self.tags).ok()
+        }
+    }
+
+    pub(crate) fn acl_json(&self) -> Option<String> {
+        self.acl
+            .as_ref()
+            .and_then(|acl| serde_json::to_string(acl).ok())
+    }
+
+    pub(crate) fn pipeline_json(&self) -> Option<String> {
+        if self.pipeline.is_empty() {
+            None
+        } else {
+            serde_json::to_string(&self.pipeline).ok()
+        }
+    }
+
+    /// Whether a user of `org_id` may resolve the short url
+    pub fn acl_allows(&self, org_id: &str) -> bool {
+        self.acl
+            .as_ref()
+            .map_or(true, |acl| acl.iter().any(|allowed| allowed == org_id))
+    }
+
+    /// Whether `add_or_get` may hand out the record for `requested`, a request for the same
+    /// url that either sets none of acl, pipeline, expires_at, permanent and created_by or sets
+    /// all of them the same way
+    pub fn reusable_for(&self, requested: &ShortUrlRecord) -> bool {
+        if self.original_url != requested.original_url {
+            return false;
+        }
+        let plain = requested.acl.is_none()
+            && requested.pipeline.is_empty()
+            && requested.expires_at.is_none()
+            && !requested.permanent
+            && requested.created_by.is_none();
+        plain
+            || (self.acl == requested.acl
+                && self.pipeline == requested.pipeline
+                && self.expires_at == requested.expires_at
+                && self.permanent == requested.permanent
+                && self.created_by == requested.created_by)
+    }
+
+    pub fn is_expired(&self, now: i64) -> bool {
+        !self.pinned && self.expires_at.map_or(false, |expires_at| expires_at < now)
+    }
+}
+
+/// An `Idempotency-Key` of a shorten request as kept in `IDEMPOTENCY_KEYS_TABLE`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct IdempotencyKey {
+    /// `None` while the request holding the key is still creating its short url
+    pub short_id: Option<String>,
+    /// sha256 of the body of the request holding the key
+    pub body_hash: String,
+}
+
+/// A redirect of a short url as logged in `ACCESS_LOG_TABLE`, the client ip is not kept
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AccessLogEntry {
+    #[sqlx(default)]
+    pub id: i64,
+    pub org_id: String,
+    pub short_id: String,
+    /// Unix timestamp in microseconds
+    pub accessed_at: i64,
+    /// Hash of the user agent header
+    pub user_agent_hash: String,
+    /// ISO 3166 code of the country of the client ip, when it resolves
+    pub country_code: Option<String>,
+}
+
+/// A change to a short url as logged in `EVENTS_TABLE`. Clicks, expiry notifications and link
+/// checks are not logged, replaying an add keeps the counters of the stored record
+#[derive(Debug, Clone)]
+pub enum ShortUrlEvent {
+    /// The record as inserted, with its `created_ts`
+    Add(ShortUrlRecord),
+    Update {
+        org_id: String,
+        short_id: String,
+        original_url: String,
+    },
+    /// Soft delete
+    Remove { org_id: String, short_id: String },
+    /// Delete for good
+    BatchRemove { org_id: String, short_id: String },
+    Rename {
+        org_id: String,
+        short_id: String,
+        new_short_id: String,
+    },
+    SetPinned {
+        org_id: String,
+        short_id: String,
+        pinned: bool,
+    },
+    /// Undo a soft delete
+    Restore { org_id: String, short_id: String },
+}
+
+impl ShortUrlEvent {
+    /// The `health_report` sentinel comes and goes on every probe, logging it would only
+    /// grow the event table and the replays
+    pub fn is_logged(&self) -> bool {
+        self.org_id() != HEALTH_ORG_ID
+    }
+
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            Self::Add(_) => "add",
+            Self::Update { .. } => "update",
+            Self::Remove { .. } => "remove",
+            Self::BatchRemove { .. } => "batch_remove",
+            Self::Rename { .. } => "rename",
+            Self::SetPinned { .. } => "set_pinned",
+            Self::Restore { .. } => "restore",
+        }
+    }
+
+    pub fn org_id(&self) -> &str {
+        match self {
+            Self::Add(record) => &record.org_id,
+            Self::Update { org_id, .. }
+            | Self::Remove { org_id, .. }
+            | Self::BatchRemove { org_id, .. }
+            | Self::Rename { org_id, .. }
+            | Self::SetPinned { org_id, .. }
+            | Self::Restore { org_id, .. } => org_id,
+        }
+    }
+
+    pub fn short_id(&self) -> &str {
+        match self {
+            Self::Add(record) => &record.short_id,
+            Self::Update { short_id, .. }
+            | Self::Remove { short_id, .. }
+            | Self::BatchRemove { short_id, .. }
+            | Self::Rename { short_id, .. }
+            | Self::SetPinned { short_id, .. }
+            | Self::Restore { short_id, .. } => short_id,
+        }
+    }
+
+    /// JSON bound to the `payload` column, the record for an add, the new url for an update,
+    /// the new short_id for a rename and the flag for a pin
+    pub fn payload(&self) -> String {
+        match self {
+            Self::Add(record) => serde_json::to_string(record).unwrap_or_default(),
+            Self::Update { original_url, .. } => {
+                serde_json::json!({ "originalUrl": original_url }).to_string()
+            }
+            Self::Rename { new_short_id, .. } => {
+                serde_json::json!({ "newShortId": new_short_id }).to_string()
+            }
+            Self::SetPinned { pinned, .. } => serde_json::json!({ "pinned": pinned }).to_string(),
+            Self::Remove { .. } | Self::BatchRemove { .. } | Self::Restore { .. } => {
+                "{}".to_string()
+            }
+        }
+    }
+
+    /// Rebuild an event from the columns of its row in `EVENTS_TABLE`
+    pub fn from_row(
+        event_type: &str,
+        org_id: String,
+        short_id: String,
+        payload: &str,
+    ) -> Result<Self> {
+        let decode =
+            |e: serde_json::Error| ShortUrlError::DatabaseError(sqlx::Error::Decode(e.into()));
+        Ok(match event_type {
+            "add" => Self::Add(serde_json::from_str(payload).map_err(decode)?),
+            "update" => {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Payload {
+                    original_url: String,
+                }
+                let payload: Payload = serde_json::from_str(payload).map_err(decode)?;
+                Self::Update {
+                    org_id,
+                    short_id,
+                    original_url: payload.original_url,
+                }
+            }
+            "rename" => {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct Payload {
+                    new_short_id: String,
+                }
+                let payload: Payload = serde_json::from_str(payload).map_err(decode)?;
+                Self::Rename {
+                    org_id,
+                    short_id,
+                    new_short_id: payload.new_short_id,
+                }
+            }
+            "set_pinned" => {
+                #[derive(Deserialize)]
+                struct Payload {
+                    pinned: bool,
+                }
+                let payload: Payload = serde_json::from_str(payload).map_err(decode)?;
+                Self::SetPinned {
+                    org_id,
+                    short_id,
+                    pinned: payload.pinned,
+                }
+            }
+            "remove" => Self::Remove { org_id, short_id },
+            "batch_remove" => Self::BatchRemove { org_id, short_id },
+            "restore" => Self::Restore { org_id, short_id },
+            _ => {
+                return Err(ShortUrlError::Unsupported(format!(
+                    "short url event type {event_type}"
+                )));
+            }
+        })
+    }
+}
+
+/// Truncated SHA-256 of `short_id || original_url`, detects an original_url altered behind
+/// the back of the short url store
+pub fn checksum(short_id: &str, original_url: &str) -> String {
+    let mut digest = sha256::digest(format!("{short_id}{original_url}"));
+    digest.truncate(16);
+    digest
+}
+
+/// Short ids are derived from the url so the same url maps to the same short_id, a non zero
+/// `attempt` changes the input to move past collisions with other urls
+pub fn generate_short_id(original_url: &str, attempt: u32) -> String {
+    let cfg = config::get_config();
+    let input = if attempt == 0 {
+        original_url.to_string()
+    } else {
+        format!("{original_url}{attempt}")
+    };
+    md5::short_hash_with_charset(
+        &input,
+        &cfg.limit.short_url_id_charset,
+        cfg.limit.short_url_id_length,
+    )
+}
+
+// JSON path of the tag `key` for JSON_EXTRACT, quoted so any key is a single member
+fn tag_json_path(key: &str) -> String {
+    format!("$.{}", serde_json::Value::String(key.to_string()))
+}
+
+// LIKE pattern matching `pattern` anywhere, to be used with `ESCAPE '!'`
+fn like_contains_pattern(pattern: &str) -> String {
+    let mut ret = String::with_capacity(pattern.len() + 2);
+    ret.push('%');
+    for c in pattern.chars() {
+        if matches!(c, '!' | '%' | '_') {
+            ret.push('!');
+        }
+        ret.push(c);
+    }
+    ret.push('%');
+    ret
+}
+
+/// Outcome of a `batch_add`, records whose short_id already exists are skipped
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BatchAddResult {
+    pub inserted: usize,
+    pub skipped: usize,
+}
+
+/// Outcome of `verify_integrity`
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct IntegrityReport {
+    pub scanned: usize,
+    /// Records without a checksum, they can not be verified
+    pub unchecked: usize,
+    /// `(org_id, short_id)` of the records whose checksum does not match
+    pub mismatches: Vec<(String, String)>,
+}
+
+/// Number of short urls in `ShortUrlDigestReport::top_5_by_clicks`
+pub const DIGEST_TOP_URLS: i64 = 5;
+
+/// Outcome of `generate_digest`. Clicks are counted from the access log, they stay 0 unless
+/// `ZO_SHORT_URL_ACCESS_LOG_ENABLED` is set
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShortUrlDigestReport {
+    pub new_urls: u64,
+    pub total_clicks: u64,
+    /// Short urls deleted for good in the period, by the purge task or a batch remove
+    pub expired_removed: u64,
+    /// `(short_id, clicks)` of the most clicked short urls in the period, most clicks first
+    pub top_5_by_clicks: Vec<(String, u64)>,
+}
+
+/// Order in which `get_expired` picks expired short urls
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvictionStrategy {
+    /// Oldest `created_ts` first, walks the created_ts index from the start
+    #[default]
+    OldestFirst,
+    /// A random sample, a limited batch then does not depend on where the expired rows are
+    /// in the index, the db still has to sort every matching row
+    Random,
+}
+
+/// How `merge` settles a short_id that is already taken, from `ZO_SHORT_URL_MERGE_STRATEGY`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    /// The record with the higher `created_ts` is kept
+    LastWriteWins,
+    /// Fail with `ShortUrlError::Conflict` like `add`
+    #[default]
+    ErrorOnConflict,
+}
+
+impl MergeStrategy {
+    pub fn from_config() -> Self {
+        match config::get_config().limit.short_url_merge_strategy.as_str() {
+            "last_write_wins" => MergeStrategy::LastWriteWins,
+            _ => MergeStrategy::ErrorOnConflict,
         }
     }
 }
+
+/// Outcome of `merge`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeResult {
+    /// The short_id was free
+    Inserted,
+    /// The stored record is kept, the incoming one is dropped
+    LocalWon,
+    /// The incoming record replaced the stored one
+    IncomingWon,
+}
+
+/// Column `list` and `list_with_count` sort by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    CreatedTs,
+    ClickCount,
+    OriginalUrl,
+    ShortId,
+}
+
+impl SortBy {
+    pub fn column(&self) -> &'static str {
+        match self {
+            SortBy::CreatedTs => "created_ts",
+            SortBy::ClickCount => "click_count",
+            SortBy::OriginalUrl => "original_url",
+            SortBy::ShortId => "short_id",
+        }
+    }
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "created_ts" => Ok(SortBy::CreatedTs),
+            "click_count" => Ok(SortBy::ClickCount),
+            "original_url" => Ok(SortBy::OriginalUrl),
+            "short_id" => Ok(SortBy::ShortId),
+            _ => Err(format!(
+                "invalid sort_by {s:?}, it must be one of created_ts, click_count, original_url or short_id"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDir {
+    Asc,
+    #[default]
+    Desc,
+}
+
+impl SortDir {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SortDir::Asc => "ASC",
+            SortDir::Desc => "DESC",
+        }
+    }
+}
+
+impl FromStr for SortDir {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "asc" => Ok(SortDir::Asc),
+            "desc" => Ok(SortDir::Desc),
+            _ => Err(format!("invalid sort_dir {s:?}, it must be asc or desc")),
+        }
+    }
+}
+
+/// Appends the ORDER BY of a sorted list. Other columns than created_ts have many ties, e.g.
+/// unclicked short urls, they are broken by short_id so offset pages do not overlap
+pub(crate) fn push_order_by<DB: sqlx::Database>(
+    query_builder: &mut sqlx::QueryBuilder<'_, DB>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) {
+    query_builder
+        .push(" ORDER BY ")
+        .push(sort_by.column())
+        .push(" ")
+        .push(sort_dir.as_str());
+    if !matches!(sort_by, SortBy::CreatedTs | SortBy::ShortId) {
+        query_builder.push(", short_id ").push(sort_dir.as_str());
+    }
+}
+
+/// Bucket size for `count_by_date_range`, weeks start on Monday
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Granularity {
+    Hour,
+    Day,
+    Week,
+}
+
+impl Granularity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Granularity::Hour => "hour",
+            Granularity::Day => "day",
+            Granularity::Week => "week",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_url_record_serialize() {
+        let mut record =
+            ShortUrlRecord::new("default", "ddbffcea3ad44292", "https://example.com/a");
+        record.created_ts = 1704067200000000;
+        record.expires_at = Some(1704153600000000);
+        record.click_count = 3;
+        record.permanent = true;
+        record.created_by = Some("root@example.com".to_string());
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            serde_json::json!({
+                "orgId": "default",
+                "shortId": "ddbffcea3ad44292",
+                "originalUrl": "https://example.com/a",
+                "createdTs": 1704067200000000i64,
+                "expiresAt": 1704153600000000i64,
+                "clickCount": 3,
+                "permanent": true,
+                "createdBy": "root@example.com",
+                "pinned": false
+            })
+        );
+    }
+
+    #[test]
+    fn test_short_url_event_round_trip() {
+        let mut record = ShortUrlRecord::new("default", "abc", "https://example.com/a");
+        record.created_ts = 1704067200000000;
+        let add = ShortUrlEvent::Add(record);
+        let ShortUrlEvent::Add(got) = ShortUrlEvent::from_row(
+            add.event_type(),
+            "default".to_string(),
+            "abc".to_string(),
+            &add.payload(),
+        )
+        .unwrap() else {
+            panic!("expected an add event");
+        };
+        assert_eq!(got.original_url, "https://example.com/a");
+        assert_eq!(got.created_ts, 1704067200000000);
+
+        let update = ShortUrlEvent::Update {
+            org_id: "default".to_string(),
+            short_id: "abc".to_string(),
+            original_url: "https://example.com/b".to_string(),
+        };
+        assert!(matches!(
+            ShortUrlEvent::from_row("update", "default".to_string(), "abc".to_string(), &update.payload()).unwrap(),
+            ShortUrlEvent::Update { original_url, .. } if original_url == "https://example.com/b"
+        ));
+        assert!(matches!(
+            ShortUrlEvent::from_row("batch_remove", "default".to_string(), "abc".to_string(), "{}").unwrap(),
+            ShortUrlEvent::BatchRemove { short_id, .. } if short_id == "abc"
+        ));
+        assert!(
+            ShortUrlEvent::from_row("rename", "default".to_string(), "abc".to_string(), "{}")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_short_url_record_deserialize() {
+        let record: ShortUrlRecord = serde_json::from_str(
+            r#"{"shortId":"abc","originalUrl":"https://example.com","createdTs":1}"#,
+        )
+        .unwrap();
+        assert_eq!(record.short_id, "abc");
+        assert_eq!(record.created_ts, 1);
+        assert_eq!(record.expires_at, None);
+
+        // snake_case from older exports
+        let record: ShortUrlRecord = serde_json::from_str(
+            r#"{"short_id":"abc","original_url":"https://example.com","click_count":2}"#,
+        )
+        .unwrap();
+        assert_eq!(record.original_url, "https://example.com");
+        assert_eq!(record.click_count, 2);
+    }
+
+    #[test]
+    fn test_tag_json_path() {
+        assert_eq!(tag_json_path("campaign"), r#"$."campaign""#);
+        assert_eq!(tag_json_path(r#"a."b"#), r#"$."a.\"b""#);
+    }
+
+    #[test]
+    fn test_tags_column() {
+        let mut record = ShortUrlRecord::new("default", "tags", "https://example.com");
+        assert_eq!(record.tags_json(), None);
+        assert!(HashMap::from(TagsColumn(None)).is_empty());
+        record
+            .tags
+            .insert("team".to_string(), "marketing".to_string());
+        let tags = HashMap::from(TagsColumn(record.tags_json()));
+        assert_eq!(tags, record.tags);
+        assert!(HashMap::from(TagsColumn(Some("not json".to_string()))).is_empty());
+    }
+
+    #[test]
+    fn test_acl_column() {
+        let mut record = ShortUrlRecord::new("default", "acl", "https://example.com");
+        assert_eq!(record.acl_json(), None);
+        assert!(record.acl_allows("other"));
+        record.acl = Some(vec!["default".to_string(), "partner".to_string()]);
+        assert!(record.acl_allows("partner"));
+        assert!(!record.acl_allows("other"));
+        let acl: Option<Vec<String>> = AclColumn(record.acl_json()).into();
+        assert_eq!(acl, record.acl);
+        let acl: Option<Vec<String>> = AclColumn(Some("not json".to_string())).into();
+        assert_eq!(acl, Some(vec![]));
+    }
+
+    #[test]
+    fn test_like_contains_pattern() {
+        assert_eq!(like_contains_pattern("example.com"), "%example.com%");
+        assert_eq!(like_contains_pattern("a_b%c!d"), "%a!_b!%c!!d%");
+    }
+
+    #[test]
+    fn test_checksum() {
+        let sum = checksum("abc", "https://example.com/");
+        assert_eq!(sum.len(), 16);
+        assert_eq!(sum, checksum("abc", "https://example.com/"));
+        assert_ne!(sum, checksum("abd", "https://example.com/"));
+
+        let mut record = ShortUrlRecord::new("default", "abc", "https://example.com/");
+        assert_eq!(record.verify_checksum(), None);
+        record.checksum = Some(record.compute_checksum());
+        assert_eq!(record.verify_checksum(), Some(true));
+        record.original_url = "https://example.org/".to_string();
+        assert_eq!(record.verify_checksum(), Some(false));
+    }
+}
@@ -15,12 +15,23 @@
 
 use async_trait::async_trait;
 use chrono::Utc;
-use sqlx::Row;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt, TryStreamExt};
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+use sqlx::{Executor, PgConnection, Postgres, QueryBuilder, Row};
 
 use crate::{
-    db::postgres::{create_index, CLIENT},
-    errors::{DbError, Error, Result},
-    short_url::{ShortUrl, ShortUrlRecord},
+    db::postgres::{create_index, delete_index, CLIENT},
+    short_url::{
+        checksum,
+        error::{Result, ShortUrlError},
+        like_contains_pattern, push_order_by,
+        tx::ShortUrlTx,
+        AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+        ShortUrlDigestReport, ShortUrlEvent, ShortUrlRecord, SortBy, SortDir, ACCESS_LOG_TABLE,
+        ARCHIVE_COLUMNS, ARCHIVE_TABLE, DIGEST_TOP_URLS, EVENTS_TABLE, IDEMPOTENCY_KEYS_TABLE,
+        SCHEMA_VERSION, SCHEMA_VERSION_TABLE, TABLE_NAME,
+    },
 };
 
 pub struct PostgresShortUrl {}
@@ -41,109 +52,764 @@ impl Default for PostgresShortUrl {
 impl ShortUrl for PostgresShortUrl {
     /// Create table short_urls
     async fn create_table(&self) -> Result<()> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let query = r#"
-            CREATE TABLE IF NOT EXISTS short_urls (
+        let query = format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {table} (
                 id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
-                short_id VARCHAR(32) NOT NULL,
+                org_id VARCHAR(256) NOT NULL,
+                short_id VARCHAR(64) NOT NULL,
                 original_url TEXT NOT NULL,
-                created_ts BIGINT NOT NULL
+                created_ts BIGINT NOT NULL,
+                expires_at BIGINT,
+                click_count BIGINT NOT NULL DEFAULT 0,
+                permanent BOOLEAN NOT NULL DEFAULT false,
+                created_by VARCHAR(512),
+                alias_of VARCHAR(64),
+                deleted_at BIGINT,
+                resource_type VARCHAR(64),
+                resource_id VARCHAR(256),
+                tags TEXT,
+                pinned BOOLEAN NOT NULL DEFAULT false,
+                expiry_notify_at BIGINT,
+                notified BOOLEAN NOT NULL DEFAULT false,
+                namespace VARCHAR(32),
+                acl TEXT,
+                pipeline TEXT,
+                checksum VARCHAR(16),
+                last_check_status SMALLINT,
+                link_rot_detected BOOLEAN NOT NULL DEFAULT false
             );
-            "#;
-        sqlx::query(query).execute(&pool).await?;
+            "#
+        );
+        sqlx::query(&query).execute(&pool).await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {EVENTS_TABLE} (
+                id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                event_type VARCHAR(16) NOT NULL,
+                org_id VARCHAR(256) NOT NULL,
+                short_id VARCHAR(64) NOT NULL,
+                payload JSONB NOT NULL,
+                occurred_at BIGINT NOT NULL
+            );
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {ACCESS_LOG_TABLE} (
+                id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                org_id VARCHAR(256) NOT NULL,
+                short_id VARCHAR(64) NOT NULL,
+                accessed_at BIGINT NOT NULL,
+                user_agent_hash VARCHAR(16) NOT NULL,
+                country_code VARCHAR(2)
+            );
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {IDEMPOTENCY_KEYS_TABLE} (
+                id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                org_id VARCHAR(256) NOT NULL,
+                idempotency_key VARCHAR(128) NOT NULL,
+                short_id VARCHAR(64),
+                body_hash VARCHAR(64) NOT NULL,
+                created_at BIGINT NOT NULL
+            );
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {ARCHIVE_TABLE} (
+                id BIGINT GENERATED ALWAYS AS IDENTITY PRIMARY KEY,
+                org_id VARCHAR(256) NOT NULL,
+                short_id VARCHAR(64) NOT NULL,
+                original_url TEXT NOT NULL,
+                created_ts BIGINT NOT NULL,
+                expires_at BIGINT,
+                click_count BIGINT NOT NULL DEFAULT 0,
+                permanent BOOLEAN NOT NULL DEFAULT false,
+                created_by VARCHAR(512),
+                alias_of VARCHAR(64),
+                deleted_at BIGINT,
+                resource_type VARCHAR(64),
+                resource_id VARCHAR(256),
+                tags TEXT,
+                pinned BOOLEAN NOT NULL DEFAULT false,
+                expiry_notify_at BIGINT,
+                notified BOOLEAN NOT NULL DEFAULT false,
+                namespace VARCHAR(32),
+                acl TEXT,
+                pipeline TEXT,
+                checksum VARCHAR(16),
+                archived_at BIGINT NOT NULL
+            );
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+        self.migrate().await
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {SCHEMA_VERSION_TABLE} (
+                table_name VARCHAR(256) NOT NULL PRIMARY KEY,
+                version BIGINT NOT NULL
+            );
+            "#
+        ))
+        .execute(&pool)
+        .await?;
+        let current = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT version FROM {SCHEMA_VERSION_TABLE} WHERE table_name = $1;"
+        ))
+        .bind(table)
+        .fetch_optional(&pool)
+        .await?
+        .unwrap_or(0);
+        for version in current + 1..=SCHEMA_VERSION {
+            migrate_to(table, version).await?;
+            sqlx::query(&format!(
+                r#"
+                INSERT INTO {SCHEMA_VERSION_TABLE} (table_name, version) VALUES ($1, $2)
+                ON CONFLICT (table_name)
+                DO UPDATE SET version = GREATEST({SCHEMA_VERSION_TABLE}.version, EXCLUDED.version);
+                "#
+            ))
+            .bind(table)
+            .bind(version)
+            .execute(&pool)
+            .await?;
+            log::info!("[POSTGRES] short url table {table} migrated to schema version {version}");
+        }
         Ok(())
     }
 
-    /// Create index for short_urls at short_id and original_url
+    /// Create index for short_urls at org_id, short_id and original_url
     async fn create_table_index(&self) -> Result<()> {
-        create_index("short_urls_short_id_idx", "short_urls", true, &["short_id"]).await?;
+        let table = TABLE_NAME.as_str();
+        create_index(
+            &format!("{table}_org_short_id_idx"),
+            table,
+            true,
+            &["org_id", "short_id"],
+        )
+        .await?;
         create_index(
-            "short_urls_created_ts_idx",
-            "short_urls",
+            &format!("{table}_created_ts_idx"),
+            table,
             false,
             &["created_ts"],
         )
         .await?;
+        create_index(
+            &format!("{table}_org_created_idx"),
+            table,
+            false,
+            &["org_id", "created_ts"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_org_clicks_idx"),
+            table,
+            false,
+            &["org_id", "click_count DESC"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_expires_at_idx"),
+            table,
+            false,
+            &["expires_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_expiry_notify_idx"),
+            table,
+            false,
+            &["expiry_notify_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_namespace_idx"),
+            table,
+            false,
+            &["org_id", "namespace"],
+        )
+        .await?;
+        // index the hash instead of the url, long urls exceed the btree row size limit
+        create_index(
+            &format!("{table}_original_url_idx"),
+            table,
+            false,
+            &["md5(original_url)"],
+        )
+        .await?;
+
+        create_index(
+            &format!("{table}_created_by_idx"),
+            table,
+            false,
+            &["created_by"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_deleted_at_idx"),
+            table,
+            false,
+            &["deleted_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_resource_idx"),
+            table,
+            false,
+            &["resource_type", "resource_id"],
+        )
+        .await?;
+        create_index(
+            &format!("{EVENTS_TABLE}_occurred_at_idx"),
+            EVENTS_TABLE,
+            false,
+            &["occurred_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{ACCESS_LOG_TABLE}_org_id_short_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+            false,
+            &["org_id", "short_id", "accessed_at"],
+        )
+        .await?;
+        // generate_digest counts the accesses of an org in a period
+        create_index(
+            &format!("{ACCESS_LOG_TABLE}_org_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+            false,
+            &["org_id", "accessed_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{IDEMPOTENCY_KEYS_TABLE}_org_id_key_idx"),
+            IDEMPOTENCY_KEYS_TABLE,
+            true,
+            &["org_id", "idempotency_key"],
+        )
+        .await?;
+        create_index(
+            &format!("{ARCHIVE_TABLE}_org_id_short_id_idx"),
+            ARCHIVE_TABLE,
+            false,
+            &["org_id", "short_id"],
+        )
+        .await?;
+
+        // short_id is unique per org now
+        delete_index(&format!("{table}_short_id_idx"), table).await?;
+        // the access log is read per org, replaced by the org_id, short_id, accessed_at index
+        delete_index(
+            &format!("{ACCESS_LOG_TABLE}_short_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+        )
+        .await?;
         Ok(())
     }
 
     /// Add a new entry to the short_urls table
-    async fn add(&self, short_id: &str, original_url: &str) -> Result<()> {
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        let record = insert_record(&mut *tx, record).await?;
+        insert_event(&mut *tx, &ShortUrlEvent::Add(record.clone())).await?;
+        tx.commit().await?;
+        Ok(record)
+    }
+
+    /// Add a new entry to the short_urls table if its short_id is not taken
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
         let created_ts = Utc::now().timestamp_micros();
 
-        let query =
-            r#"INSERT INTO short_urls (short_id, original_url, created_ts) VALUES ($1, $2, $3);"#;
-        let result = sqlx::query(query)
-            .bind(short_id)
-            .bind(original_url)
+        let query = format!(
+            r#"INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17) ON CONFLICT DO NOTHING;"#
+        );
+        let ret = sqlx::query(&query)
+            .bind(&record.org_id)
+            .bind(&record.short_id)
+            .bind(&record.original_url)
             .bind(created_ts)
-            .execute(&pool)
-            .await;
+            .bind(record.expires_at)
+            .bind(record.permanent)
+            .bind(&record.created_by)
+            .bind(&record.alias_of)
+            .bind(&record.resource_type)
+            .bind(&record.resource_id)
+            .bind(record.tags_json())
+            .bind(record.pinned)
+            .bind(record.expiry_notify_at)
+            .bind(&record.namespace)
+            .bind(record.acl_json())
+            .bind(record.pipeline_json())
+            .bind(record.compute_checksum())
+            .execute(&mut *tx)
+            .await?;
+        let inserted = ret.rows_affected() > 0;
+        if inserted {
+            let record = ShortUrlRecord {
+                created_ts,
+                click_count: 0,
+                checksum: Some(record.compute_checksum()),
+                ..record.clone()
+            };
+            insert_event(&mut *tx, &ShortUrlEvent::Add(record)).await?;
+        }
+        tx.commit().await?;
+        Ok(inserted)
+    }
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
-                Err(Error::DbError(DbError::UniqueViolation))
+    /// Add multiple entries to the short_urls table, skipping existing short_ids
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        let table = TABLE_NAME.as_str();
+        if records.is_empty() {
+            return Ok(BatchAddResult::default());
+        }
+        let pool = CLIENT.clone();
+        let created_ts = Utc::now().timestamp_micros();
+        let mut inserted = 0;
+        for records in records.chunks(100) {
+            let mut tx = pool.begin().await?;
+            let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+                "INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum)"
+            ));
+            query_builder.push_values(records, |mut b, record| {
+                let created_ts = if record.created_ts > 0 {
+                    record.created_ts
+                } else {
+                    created_ts
+                };
+                b.push_bind(&record.org_id)
+                    .push_bind(&record.short_id)
+                    .push_bind(&record.original_url)
+                    .push_bind(created_ts)
+                    .push_bind(record.expires_at)
+                    .push_bind(record.click_count)
+                    .push_bind(record.permanent)
+                    .push_bind(&record.created_by)
+                    .push_bind(&record.alias_of)
+                    .push_bind(&record.resource_type)
+                    .push_bind(&record.resource_id)
+                    .push_bind(record.tags_json())
+                    .push_bind(record.pinned)
+                    .push_bind(record.expiry_notify_at)
+                    .push_bind(&record.namespace)
+                    .push_bind(record.acl_json())
+                    .push_bind(record.pipeline_json())
+                    .push_bind(record.compute_checksum());
+            });
+            // skipped rows are not returned, only the inserted ones are logged
+            query_builder.push(" ON CONFLICT DO NOTHING RETURNING org_id, short_id");
+            let added: Vec<(String, String)> =
+                match query_builder.build_query_as().fetch_all(&mut *tx).await {
+                    Ok(added) => added,
+                    Err(e) => {
+                        if let Err(e) = tx.rollback().await {
+                            log::error!("[POSTGRES] rollback short_urls batch add error: {}", e);
+                        }
+                        return Err(e.into());
+                    }
+                };
+            for (org_id, short_id) in &added {
+                let Some(record) = records
+                    .iter()
+                    .find(|record| record.org_id == *org_id && record.short_id == *short_id)
+                else {
+                    continue;
+                };
+                let record = ShortUrlRecord {
+                    created_ts: if record.created_ts > 0 {
+                        record.created_ts
+                    } else {
+                        created_ts
+                    },
+                    checksum: Some(record.compute_checksum()),
+                    ..record.clone()
+                };
+                insert_event(&mut *tx, &ShortUrlEvent::Add(record)).await?;
+            }
+            if let Err(e) = tx.commit().await {
+                log::error!("[POSTGRES] commit short_urls batch add error: {}", e);
+                return Err(e.into());
             }
-            Err(e) => Err(Error::SqlxError(e)),
+            inserted += added.len();
         }
+        Ok(BatchAddResult {
+            inserted,
+            skipped: records.len() - inserted,
+        })
     }
 
     /// Remove an entry from the short_urls table
-    async fn remove(&self, short_id: &str) -> Result<()> {
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        delete_record(&mut *tx, org_id, short_id).await?;
+        let event = ShortUrlEvent::Remove {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Update the original_url of an entry in the short_urls table
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        update_url(&mut *tx, org_id, short_id, new_url).await?;
+        let event = ShortUrlEvent::Update {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+            original_url: new_url.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        // a taken new_short_id fails the rename, dropping the transaction rolls it back
+        rename_record(&mut tx, org_id, old_short_id, new_short_id).await?;
+        let event = ShortUrlEvent::Rename {
+            org_id: org_id.to_string(),
+            short_id: old_short_id.to_string(),
+            new_short_id: new_short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let query = r#"DELETE FROM short_urls WHERE short_id = $1;"#;
-        sqlx::query(query).bind(short_id).execute(&pool).await?;
+        let query = format!(
+            r#"UPDATE {table} SET pinned = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+        );
+        let mut tx = pool.begin().await?;
+        let ret = sqlx::query(&query)
+            .bind(pinned)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        let event = ShortUrlEvent::SetPinned {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+            pinned,
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
         Ok(())
     }
 
     /// Get an entry from the short_urls table
-    async fn get(&self, short_id: &str) -> Result<ShortUrlRecord> {
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let query = r#"SELECT short_id, original_url FROM short_urls WHERE short_id = $1;"#;
-        let row = sqlx::query_as::<_, ShortUrlRecord>(query)
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL;"#
+        );
+        let row = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
             .bind(short_id)
-            .fetch_one(&pool)
+            .fetch_optional(&pool)
+            .await?;
+        row.ok_or_else(|| ShortUrlError::NotFound(short_id.to_string()))
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        if short_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND deleted_at IS NULL AND short_id = ANY($2::VARCHAR[]);"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .bind(short_ids)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.short_id.clone(), row))
+            .collect())
+    }
+
+    /// Increment the click_count of an entry in the short_urls table
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        increment_clicks(&pool, org_id, short_id).await
+    }
+
+    /// Get an entry from the short_urls table by its original_url
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND md5(original_url) = md5($2) AND original_url = $2 AND deleted_at IS NULL LIMIT 1;"#
+        );
+        let row = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .bind(original_url)
+            .fetch_optional(&pool)
             .await?;
         Ok(row)
     }
 
-    /// List all entries from the short_urls table
-    async fn list(&self, limit: Option<i64>) -> Result<Vec<ShortUrlRecord>> {
+    /// List all entries from the short_urls table, newest first
+    /// starting after the `after_ts` (created_ts) cursor
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let mut query =
-            r#"SELECT short_id, original_url FROM short_urls ORDER BY created_ts DESC"#.to_string();
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE deleted_at IS NULL"
+        ));
+        if let Some(org_id) = org_id {
+            query_builder.push(" AND org_id = ").push_bind(org_id);
+        }
+        if let Some(created_by) = created_by {
+            query_builder
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        if let Some(after_ts) = after_ts {
+            query_builder.push(" AND created_ts < ").push_bind(after_ts);
+        }
+        push_order_by(&mut query_builder, sort_by, sort_dir);
+        if let Some(limit) = limit {
+            query_builder.push(" LIMIT ").push_bind(limit);
+        }
 
-        if limit.is_some() {
-            query.push_str(" LIMIT $1");
+        let rows = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        // the query borrowed by the stream lives as long as the pool
+        static QUERY: Lazy<String> = Lazy::new(|| {
+            format!(
+                "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {} WHERE deleted_at IS NULL ORDER BY created_ts DESC",
+                TABLE_NAME.as_str()
+            )
+        });
+        Ok(sqlx::query_as::<_, ShortUrlRecord>(QUERY.as_str())
+            .fetch(&*CLIENT)
+            .map_err(ShortUrlError::from)
+            .boxed())
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        // the query borrowed by the stream lives as long as the pool
+        static QUERY: Lazy<String> = Lazy::new(|| {
+            format!(
+                "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {} WHERE org_id = $1 AND deleted_at IS NULL ORDER BY created_ts DESC",
+                TABLE_NAME.as_str()
+            )
+        });
+        Ok(sqlx::query_as::<_, ShortUrlRecord>(QUERY.as_str())
+            .bind(org_id.to_string())
+            .fetch(&*CLIENT)
+            .map_err(ShortUrlError::from)
+            .boxed())
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = "
+        ));
+        query_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL AND original_url LIKE ")
+            .push_bind(like_contains_pattern(url_pattern))
+            .push(" ESCAPE '!' ORDER BY created_ts DESC");
+        if let Some(limit) = limit {
+            query_builder.push(" LIMIT ").push_bind(limit);
         }
 
-        let query_builder = sqlx::query_as::<_, ShortUrlRecord>(&query);
-        let query_builder = if let Some(limit_value) = limit {
-            query_builder.bind(limit_value)
-        } else {
+        let rows = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    /// List a page of the org's short urls newest first with the total count, both read in one
+    /// transaction so the count matches the page
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = "
+        ));
+        query_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL");
+        if let Some(created_by) = created_by {
             query_builder
-        };
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        push_order_by(&mut query_builder, sort_by, sort_dir);
+        if let Some(limit) = limit {
+            query_builder.push(" LIMIT ").push_bind(limit);
+        }
+        if let Some(offset) = offset {
+            query_builder.push(" OFFSET ").push_bind(offset);
+        }
+        let records = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut count_builder: QueryBuilder<Postgres> =
+            QueryBuilder::new(format!("SELECT COUNT(*) FROM {table} WHERE org_id = "));
+        count_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL");
+        if let Some(created_by) = created_by {
+            count_builder
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        let total: i64 = count_builder
+            .build_query_scalar()
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok((records, total))
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE resource_type = $1 AND resource_id = $2 AND deleted_at IS NULL ORDER BY created_ts DESC;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(resource_type)
+            .bind(resource_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
 
-        let rows = query_builder.fetch_all(&pool).await?;
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE tags::jsonb ->> $1 = $2 AND deleted_at IS NULL ORDER BY created_ts DESC LIMIT $3;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(key)
+            .bind(value)
+            .bind(limit.unwrap_or(i64::MAX))
+            .fetch_all(&pool)
+            .await?;
         Ok(rows)
     }
 
     /// Check if an entry exists in the short_urls table
-    async fn contains(&self, short_id: &str) -> Result<bool> {
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let query = r#"SELECT 1 FROM short_urls WHERE short_id = $1"#;
-        let rows = sqlx::query(query).bind(short_id).fetch_all(&pool).await?;
+        let query = format!(
+            r#"SELECT 1 FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL"#
+        );
+        let rows = sqlx::query(&query)
+            .bind(org_id)
+            .bind(short_id)
+            .fetch_all(&pool)
+            .await?;
         Ok(!rows.is_empty())
     }
 
     /// Get the number of entries in the short_urls table
     async fn len(&self) -> usize {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let ret = match sqlx::query(r#"SELECT COUNT(*)::BIGINT AS num FROM short_urls;"#)
-            .fetch_one(&pool)
-            .await
+        let ret = match sqlx::query(&format!(
+            r#"SELECT COUNT(*)::BIGINT AS num FROM {table} WHERE deleted_at IS NULL;"#
+        ))
+        .fetch_one(&pool)
+        .await
         {
             Ok(r) => r,
             Err(e) => {
@@ -159,14 +825,20 @@ impl ShortUrl for PostgresShortUrl {
     }
 
     /// Clear all entries from the short_urls table
-    async fn clear(&self) -> Result<()> {
+    async fn clear(&self) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
-        let query = r#"DELETE FROM short_urls;"#;
-        match sqlx::query(query).execute(&pool).await {
-            Ok(_) => log::info!("[SHORT_URL] short_urls table cleared"),
-            Err(e) => log::error!("[POSTGRES] short_urls table clear error: {}", e),
+        let query = format!(r#"DELETE FROM {table} RETURNING org_id, short_id;"#);
+        match clear_logged(&pool, &query).await {
+            Ok(deleted) => {
+                log::info!("[SHORT_URL] short_urls table cleared, {deleted} rows deleted");
+                Ok(deleted)
+            }
+            Err(e) => {
+                log::error!("[POSTGRES] short_urls table clear error: {}", e);
+                Err(e)
+            }
         }
-        Ok(())
     }
 
     /// Check if the short_urls table is empty
@@ -174,45 +846,952 @@ impl ShortUrl for PostgresShortUrl {
         self.len().await == 0
     }
 
-    async fn get_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<Vec<String>> {
+    async fn ping(&self) -> Result<()> {
+        let pool = CLIENT.clone();
+        sqlx::query("SELECT 1").execute(&pool).await?;
+        Ok(())
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        let pool = CLIENT.clone();
+        let version: String = sqlx::query_scalar("SHOW server_version")
+            .fetch_one(&pool)
+            .await?;
+        Ok(version)
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        let table = TABLE_NAME.as_str();
         let pool = CLIENT.clone();
 
-        let mut query = r#"
-            SELECT short_id FROM short_urls
-            WHERE created_ts < $1
+        let order_by = match strategy {
+            EvictionStrategy::OldestFirst => "created_ts ASC",
+            EvictionStrategy::Random => "RANDOM()",
+        };
+        // scoped to one org this uses the (org_id, created_ts) index
+        let org_filter = if org_id.is_some() {
+            "org_id = $3 AND "
+        } else {
+            ""
+        };
+        let mut query = format!(
+            r#"
+            SELECT org_id, short_id FROM {table}
+            WHERE {org_filter}(created_ts < $1 OR expires_at < $2) AND pinned = FALSE
+            ORDER BY {order_by}
             "#
-        .to_string();
+        );
 
         if limit.is_some() {
-            query.push_str(" LIMIT $2");
+            query.push_str(if org_id.is_some() {
+                " LIMIT $4"
+            } else {
+                " LIMIT $3"
+            });
         }
 
-        let mut query = sqlx::query_as(&query).bind(expired_before);
+        let now = Utc::now().timestamp_micros();
+        let mut query = sqlx::query_as(&query).bind(expired_before).bind(now);
 
+        if let Some(org_id) = org_id {
+            query = query.bind(org_id);
+        }
         if let Some(limit_value) = limit {
             query = query.bind(limit_value);
         }
 
-        let expired_short_ids: Vec<(String,)> = query.fetch_all(&pool).await?;
-        let expired_short_ids: Vec<String> = expired_short_ids
-            .into_iter()
-            .map(|(short_id,)| short_id)
-            .collect();
+        let expired_short_ids: Vec<(String, String)> = query.fetch_all(&pool).await?;
         Ok(expired_short_ids)
     }
 
-    async fn batch_remove(&self, short_ids: Vec<String>) -> Result<()> {
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let bucket = format!(
+            "EXTRACT(EPOCH FROM date_trunc('{}', to_timestamp(created_ts / 1000000) AT TIME ZONE 'UTC'))::BIGINT * 1000000",
+            granularity.as_str()
+        );
+        let query = format!(
+            "SELECT {bucket} AS bucket, COUNT(*) AS num FROM {table} WHERE org_id = $1 AND deleted_at IS NULL AND created_ts >= $2 AND created_ts < $3 GROUP BY bucket ORDER BY bucket"
+        );
+        let ret: Vec<(i64, i64)> = sqlx::query_as(&query)
+            .bind(org_id)
+            .bind(from_ts)
+            .bind(to_ts)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND deleted_at IS NULL ORDER BY click_count DESC LIMIT $2;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .bind(limit as i64)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT COUNT(*), COALESCE(SUM(click_count), 0)::BIGINT FROM {table} WHERE org_id = $1 AND deleted_at IS NULL;"#
+        );
+        let ret: (i64, i64) = sqlx::query_as(&query).bind(org_id).fetch_one(&pool).await?;
+        Ok(ret)
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query =
+            format!(r#"SELECT COUNT(*) FROM {table} WHERE org_id = $1 AND deleted_at IS NULL;"#);
+        let ret: i64 = sqlx::query_scalar(&query)
+            .bind(org_id)
+            .fetch_one(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT DISTINCT namespace FROM {table} WHERE org_id = $1 AND namespace IS NOT NULL AND deleted_at IS NULL ORDER BY namespace;"#
+        );
+        let ret: Vec<String> = sqlx::query_scalar(&query)
+            .bind(org_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE expiry_notify_at IS NOT NULL AND expiry_notify_at <= $1 AND notified = FALSE AND pinned = FALSE AND deleted_at IS NULL ORDER BY expiry_notify_at;"#
+        );
+        let ret = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(now)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let query =
+            format!(r#"UPDATE {table} SET notified = TRUE WHERE org_id = $1 AND short_id = $2;"#);
+        let pool = CLIENT.clone();
+        let ret = sqlx::query(&query)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&pool)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE deleted_at IS NULL ORDER BY RANDOM() LIMIT $1;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(limit)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let query = format!(
+            r#"UPDATE {table} SET last_check_status = $1, link_rot_detected = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+        );
+        let pool = CLIENT.clone();
+        let ret = sqlx::query(&query)
+            .bind(status)
+            .bind(status >= 400)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&pool)
+            .await?;
+
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND link_rot_detected = TRUE AND deleted_at IS NULL ORDER BY created_ts DESC;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
+        if short_ids.is_empty() {
+            return Ok(0);
+        }
+        let pool = CLIENT.clone();
+
+        let query = format!(
+            r#"
+            DELETE FROM {table}
+            WHERE (org_id, short_id) IN (SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[]))
+        "#
+        );
+
+        let mut tx = pool.begin().await?;
+        let (org_ids, short_ids): (Vec<String>, Vec<String>) = short_ids.into_iter().unzip();
+        let ret = sqlx::query(&query)
+            .bind(&org_ids)
+            .bind(&short_ids)
+            .execute(&mut *tx)
+            .await?;
+        for (org_id, short_id) in org_ids.into_iter().zip(short_ids) {
+            let event = ShortUrlEvent::BatchRemove { org_id, short_id };
+            insert_event(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
+        Ok(ret.rows_affected())
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        if !restore_record(&mut tx, org_id, short_id).await? {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        let event = ShortUrlEvent::Restore {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        let query = format!(
+            r#"DELETE FROM {table} WHERE deleted_at IS NOT NULL AND deleted_at < $1 RETURNING org_id, short_id;"#
+        );
+        let deleted: Vec<(String, String)> = sqlx::query_as(&query)
+            .bind(older_than)
+            .fetch_all(&mut *tx)
+            .await?;
+        for (org_id, short_id) in &deleted {
+            let event = ShortUrlEvent::BatchRemove {
+                org_id: org_id.clone(),
+                short_id: short_id.clone(),
+            };
+            insert_event(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
+        Ok(deleted.len() as u64)
+    }
+
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        let now = Utc::now().timestamp_micros();
+        // the rows are picked once and locked so the copy and the delete see the same ones
+        let mut select: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "SELECT id, org_id, short_id FROM {table} WHERE (created_ts < "
+        ));
+        select
+            .push_bind(expired_before)
+            .push(" OR expires_at < ")
+            .push_bind(now)
+            .push(") AND pinned = FALSE ORDER BY created_ts ASC");
+        if let Some(limit) = limit {
+            select.push(" LIMIT ").push_bind(limit);
+        }
+        select.push(" FOR UPDATE");
+        let rows: Vec<(i64, String, String)> = select.build_query_as().fetch_all(&mut *tx).await?;
+        let archived = move_to_archive(&mut tx, &rows, now).await?;
+        tx.commit().await?;
+        Ok(archived)
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
         if short_ids.is_empty() {
+            return Ok(0);
+        }
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let mut tx = pool.begin().await?;
+        let now = Utc::now().timestamp_micros();
+        let mut archived = 0;
+        for short_ids in short_ids.chunks(500) {
+            let mut select: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+                "SELECT id, org_id, short_id FROM {table} WHERE pinned = FALSE AND (org_id, short_id) IN ("
+            ));
+            let mut keys = select.separated(", ");
+            for (org_id, short_id) in short_ids {
+                keys.push("(")
+                    .push_bind_unseparated(org_id)
+                    .push_unseparated(", ")
+                    .push_bind_unseparated(short_id)
+                    .push_unseparated(")");
+            }
+            select.push(") FOR UPDATE");
+            let rows: Vec<(i64, String, String)> =
+                select.build_query_as().fetch_all(&mut *tx).await?;
+            archived += move_to_archive(&mut tx, &rows, now).await?;
+        }
+        tx.commit().await?;
+        Ok(archived)
+    }
+
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        let pool = CLIENT.clone();
+        let mut tx = ShortUrlTx::Postgres(pool.begin().await?);
+        let ret = f(&mut tx).await;
+        tx.finish(ret).await
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        let pool = CLIENT.clone();
+        let rows = sqlx::query_as::<_, (String, String, String, String)>(&format!(
+            r#"SELECT event_type, org_id, short_id, payload::TEXT FROM {EVENTS_TABLE} WHERE occurred_at > $1 ORDER BY id;"#
+        ))
+        .bind(from_ts)
+        .fetch_all(&pool)
+        .await?;
+        let replayed = rows.len();
+        let mut tx = pool.begin().await?;
+        for (event_type, org_id, short_id, payload) in rows {
+            let event = ShortUrlEvent::from_row(&event_type, org_id, short_id, &payload)?;
+            apply_event(&mut tx, &event).await?;
+        }
+        tx.commit().await?;
+        Ok(replayed)
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        if entries.is_empty() {
             return Ok(());
         }
         let pool = CLIENT.clone();
+        for entries in entries.chunks(100) {
+            let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+                "INSERT INTO {ACCESS_LOG_TABLE} (org_id, short_id, accessed_at, user_agent_hash, country_code)"
+            ));
+            query_builder.push_values(entries, |mut b, entry| {
+                b.push_bind(&entry.org_id)
+                    .push_bind(&entry.short_id)
+                    .push_bind(entry.accessed_at)
+                    .push_bind(&entry.user_agent_hash)
+                    .push_bind(&entry.country_code);
+            });
+            query_builder.build().execute(&pool).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        let pool = CLIENT.clone();
+        let entries = sqlx::query_as::<_, AccessLogEntry>(&format!(
+            r#"SELECT id, org_id, short_id, accessed_at, user_agent_hash, country_code FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND short_id = $2 AND accessed_at >= $3 AND accessed_at < $4 ORDER BY accessed_at, id;"#
+        ))
+        .bind(org_id)
+        .bind(short_id)
+        .bind(from_ts)
+        .bind(to_ts)
+        .fetch_all(&pool)
+        .await?;
+        Ok(entries)
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        let pool = CLIENT.clone();
+        let ret = sqlx::query(&format!(
+            r#"DELETE FROM {ACCESS_LOG_TABLE} WHERE accessed_at < $1;"#
+        ))
+        .bind(older_than)
+        .execute(&pool)
+        .await?;
+        Ok(ret.rows_affected())
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        let pool = CLIENT.clone();
+        let idempotency_key: Option<IdempotencyKey> = sqlx::query_as(&format!(
+            r#"SELECT short_id, body_hash FROM {IDEMPOTENCY_KEYS_TABLE} WHERE org_id = $1 AND idempotency_key = $2 AND created_at >= $3;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .bind(created_after)
+        .fetch_optional(&pool)
+        .await?;
+        Ok(idempotency_key)
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        let pool = CLIENT.clone();
+        let ret = sqlx::query(&format!(
+            r#"INSERT INTO {IDEMPOTENCY_KEYS_TABLE} (org_id, idempotency_key, body_hash, created_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (org_id, idempotency_key) DO UPDATE SET short_id = NULL, body_hash = excluded.body_hash, created_at = excluded.created_at
+                WHERE {IDEMPOTENCY_KEYS_TABLE}.created_at < $5;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .bind(body_hash)
+        .bind(Utc::now().timestamp_micros())
+        .bind(expired_before)
+        .execute(&pool)
+        .await?;
+        Ok(ret.rows_affected() == 1)
+    }
 
-        let query = r#"
-            DELETE FROM short_urls
-            WHERE short_id = ANY($1)
-        "#;
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        let pool = CLIENT.clone();
+        sqlx::query(&format!(
+            r#"UPDATE {IDEMPOTENCY_KEYS_TABLE} SET short_id = $1 WHERE org_id = $2 AND idempotency_key = $3;"#
+        ))
+        .bind(short_id)
+        .bind(org_id)
+        .bind(idempotency_key)
+        .execute(&pool)
+        .await?;
+        Ok(())
+    }
 
-        sqlx::query(query).bind(&short_ids).execute(&pool).await?;
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        let pool = CLIENT.clone();
+        sqlx::query(&format!(
+            r#"DELETE FROM {IDEMPOTENCY_KEYS_TABLE} WHERE org_id = $1 AND idempotency_key = $2 AND short_id IS NULL;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .execute(&pool)
+        .await?;
         Ok(())
     }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        let pool = CLIENT.clone();
+        let ret = sqlx::query(&format!(
+            r#"DELETE FROM {IDEMPOTENCY_KEYS_TABLE} WHERE created_at < $1;"#
+        ))
+        .bind(older_than)
+        .execute(&pool)
+        .await?;
+        Ok(ret.rows_affected())
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT.clone();
+        let new_urls: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {table} WHERE org_id = $1 AND created_ts >= $2 AND created_ts < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let total_clicks: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND accessed_at >= $2 AND accessed_at < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let expired_removed: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {EVENTS_TABLE} WHERE org_id = $1 AND event_type = 'batch_remove' AND occurred_at >= $2 AND occurred_at < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let top: Vec<(String, i64)> = sqlx::query_as(&format!(
+            r#"SELECT short_id, COUNT(*) AS clicks FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND accessed_at >= $2 AND accessed_at < $3 GROUP BY short_id ORDER BY clicks DESC, short_id LIMIT $4;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .bind(DIGEST_TOP_URLS)
+        .fetch_all(&pool)
+        .await?;
+        Ok(ShortUrlDigestReport {
+            new_urls: new_urls as u64,
+            total_clicks: total_clicks as u64,
+            expired_removed: expired_removed as u64,
+            top_5_by_clicks: top
+                .into_iter()
+                .map(|(short_id, clicks)| (short_id, clicks as u64))
+                .collect(),
+        })
+    }
+}
+
+async fn clear_logged(pool: &sqlx::Pool<Postgres>, query: &str) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let deleted: Vec<(String, String)> = sqlx::query_as(query).fetch_all(&mut *tx).await?;
+    for (org_id, short_id) in &deleted {
+        let event = ShortUrlEvent::BatchRemove {
+            org_id: org_id.clone(),
+            short_id: short_id.clone(),
+        };
+        insert_event(&mut *tx, &event).await?;
+    }
+    tx.commit().await?;
+    Ok(deleted.len() as u64)
+}
+
+// copies `rows`, `(id, org_id, short_id)` picked in the open transaction, to
+// `ARCHIVE_TABLE` and deletes them, returns how many were moved
+async fn move_to_archive(
+    conn: &mut PgConnection,
+    rows: &[(i64, String, String)],
+    now: i64,
+) -> Result<u64> {
+    let table = TABLE_NAME.as_str();
+    let mut archived = 0;
+    for rows in rows.chunks(500) {
+        let mut insert: QueryBuilder<Postgres> = QueryBuilder::new(format!(
+            "INSERT INTO {ARCHIVE_TABLE} ({ARCHIVE_COLUMNS}, archived_at) SELECT {ARCHIVE_COLUMNS}, "
+        ));
+        insert
+            .push_bind(now)
+            .push(format!(" FROM {table} WHERE id IN ("));
+        let mut ids = insert.separated(", ");
+        for (id, ..) in rows {
+            ids.push_bind(*id);
+        }
+        ids.push_unseparated(")");
+        insert.build().execute(&mut *conn).await?;
+
+        let mut delete: QueryBuilder<Postgres> =
+            QueryBuilder::new(format!("DELETE FROM {table} WHERE id IN ("));
+        let mut ids = delete.separated(", ");
+        for (id, ..) in rows {
+            ids.push_bind(*id);
+        }
+        ids.push_unseparated(")");
+        archived += delete.build().execute(&mut *conn).await?.rows_affected();
+
+        for (_, org_id, short_id) in rows {
+            let event = ShortUrlEvent::BatchRemove {
+                org_id: org_id.clone(),
+                short_id: short_id.clone(),
+            };
+            insert_event(&mut *conn, &event).await?;
+        }
+    }
+    Ok(archived)
+}
+
+// re-applies a logged change without logging it again, changes to records deleted for good
+// since then do nothing. An add over a stored record only overwrites its settings, the clicks
+// are never logged so the stored counters are kept
+async fn apply_event(conn: &mut PgConnection, event: &ShortUrlEvent) -> Result<()> {
+    let table = TABLE_NAME.as_str();
+    match event {
+        ShortUrlEvent::Add(record) => {
+            let query = format!(
+                r#"INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+                ON CONFLICT (org_id, short_id) DO UPDATE SET original_url = EXCLUDED.original_url, created_ts = EXCLUDED.created_ts, expires_at = EXCLUDED.expires_at, permanent = EXCLUDED.permanent, created_by = EXCLUDED.created_by, alias_of = EXCLUDED.alias_of, resource_type = EXCLUDED.resource_type, resource_id = EXCLUDED.resource_id, tags = EXCLUDED.tags, pinned = EXCLUDED.pinned, expiry_notify_at = EXCLUDED.expiry_notify_at, namespace = EXCLUDED.namespace, acl = EXCLUDED.acl, pipeline = EXCLUDED.pipeline, checksum = EXCLUDED.checksum, deleted_at = NULL;"#
+            );
+            sqlx::query(&query)
+                .bind(&record.org_id)
+                .bind(&record.short_id)
+                .bind(&record.original_url)
+                .bind(record.created_ts)
+                .bind(record.expires_at)
+                .bind(record.click_count)
+                .bind(record.permanent)
+                .bind(&record.created_by)
+                .bind(&record.alias_of)
+                .bind(&record.resource_type)
+                .bind(&record.resource_id)
+                .bind(record.tags_json())
+                .bind(record.pinned)
+                .bind(record.expiry_notify_at)
+                .bind(&record.namespace)
+                .bind(record.acl_json())
+                .bind(record.pipeline_json())
+                .bind(record.compute_checksum())
+                .execute(&mut *conn)
+                .await?;
+        }
+        ShortUrlEvent::Update {
+            org_id,
+            short_id,
+            original_url,
+        } => match update_url(&mut *conn, org_id, short_id, original_url).await {
+            Ok(()) | Err(ShortUrlError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        },
+        ShortUrlEvent::Rename {
+            org_id,
+            short_id,
+            new_short_id,
+        } => {
+            // checked up front, a unique violation would abort the whole replay transaction
+            let renamed: Option<i64> = sqlx::query_scalar(&format!(
+                r#"SELECT id FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+            ))
+            .bind(org_id)
+            .bind(new_short_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+            if renamed.is_some() {
+                // replayed over a store that holds the rename already, the record the replayed
+                // add put back under the old short_id is dropped
+                sqlx::query(&format!(
+                    r#"DELETE FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+                ))
+                .bind(org_id)
+                .bind(short_id)
+                .execute(&mut *conn)
+                .await?;
+            } else {
+                match rename_record(&mut *conn, org_id, short_id, new_short_id).await {
+                    Ok(()) | Err(ShortUrlError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        ShortUrlEvent::SetPinned {
+            org_id,
+            short_id,
+            pinned,
+        } => {
+            sqlx::query(&format!(
+                r#"UPDATE {table} SET pinned = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+            ))
+            .bind(pinned)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        ShortUrlEvent::Remove { org_id, short_id } => {
+            delete_record(&mut *conn, org_id, short_id).await?
+        }
+        ShortUrlEvent::BatchRemove { org_id, short_id } => {
+            sqlx::query(&format!(
+                r#"DELETE FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+            ))
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        ShortUrlEvent::Restore { org_id, short_id } => {
+            restore_record(&mut *conn, org_id, short_id).await?;
+        }
+    }
+    Ok(())
+}
+
+// the checksum covers the short_id, it is recomputed from the stored original_url which is
+// locked so a concurrent update can not change it in between
+async fn rename_record(
+    conn: &mut PgConnection,
+    org_id: &str,
+    old_short_id: &str,
+    new_short_id: &str,
+) -> Result<()> {
+    let table = TABLE_NAME.as_str();
+    let original_url: Option<String> = sqlx::query_scalar(&format!(
+        r#"SELECT original_url FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL FOR UPDATE;"#
+    ))
+    .bind(org_id)
+    .bind(old_short_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(original_url) = original_url else {
+        return Err(ShortUrlError::NotFound(old_short_id.to_string()));
+    };
+    let query = format!(
+        r#"UPDATE {table} SET short_id = $1, checksum = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+    );
+    let ret = sqlx::query(&query)
+        .bind(new_short_id)
+        .bind(checksum(new_short_id, &original_url))
+        .bind(org_id)
+        .bind(old_short_id)
+        .execute(&mut *conn)
+        .await;
+    // the unique (org_id, short_id) index rejects a taken new_short_id
+    match ret {
+        Ok(r) if r.rows_affected() == 0 => Err(ShortUrlError::NotFound(old_short_id.to_string())),
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(ShortUrlError::Conflict(new_short_id.to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// returns whether a soft deleted record was restored
+async fn restore_record(conn: &mut PgConnection, org_id: &str, short_id: &str) -> Result<bool> {
+    let table = TABLE_NAME.as_str();
+    let ret = sqlx::query(&format!(
+        r#"UPDATE {table} SET deleted_at = NULL WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NOT NULL;"#
+    ))
+    .bind(org_id)
+    .bind(short_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(ret.rows_affected() > 0)
+}
+
+// the write queries below are shared by `ShortUrl` and `ShortUrlTx`, `executor` is either a
+// pool or an open transaction
+
+// payload is bound as text, postgres does not cast it to JSONB implicitly
+pub(super) async fn insert_event<'c, E>(executor: E, event: &ShortUrlEvent) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    if !event.is_logged() {
+        return Ok(());
+    }
+    let query = format!(
+        r#"INSERT INTO {EVENTS_TABLE} (event_type, org_id, short_id, payload, occurred_at) VALUES ($1, $2, $3, $4::JSONB, $5);"#
+    );
+    sqlx::query(&query)
+        .bind(event.event_type())
+        .bind(event.org_id())
+        .bind(event.short_id())
+        .bind(event.payload())
+        .bind(Utc::now().timestamp_micros())
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub(super) async fn insert_record<'c, E>(
+    executor: E,
+    record: &ShortUrlRecord,
+) -> Result<ShortUrlRecord>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let table = TABLE_NAME.as_str();
+    let created_ts = Utc::now().timestamp_micros();
+    let query = format!(
+        r#"INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17) ON CONFLICT DO NOTHING;"#
+    );
+    let result = sqlx::query(&query)
+        .bind(&record.org_id)
+        .bind(&record.short_id)
+        .bind(&record.original_url)
+        .bind(created_ts)
+        .bind(record.expires_at)
+        .bind(record.permanent)
+        .bind(&record.created_by)
+        .bind(&record.alias_of)
+        .bind(&record.resource_type)
+        .bind(&record.resource_id)
+        .bind(record.tags_json())
+        .bind(record.pinned)
+        .bind(record.expiry_notify_at)
+        .bind(&record.namespace)
+        .bind(record.acl_json())
+        .bind(record.pipeline_json())
+        .bind(record.compute_checksum())
+        .execute(executor)
+        .await;
+    // a conflicting short_id is skipped by `ON CONFLICT DO NOTHING`, so no
+    // row affected means the short_id is already taken
+    match result {
+        Ok(r) if r.rows_affected() == 0 => Err(ShortUrlError::Conflict(record.short_id.clone())),
+        Ok(_) => Ok(ShortUrlRecord {
+            created_ts,
+            click_count: 0,
+            checksum: Some(record.compute_checksum()),
+            ..record.clone()
+        }),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(ShortUrlError::Conflict(record.short_id.clone()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) async fn delete_record<'c, E>(executor: E, org_id: &str, short_id: &str) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET deleted_at = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+    );
+    sqlx::query(&query)
+        .bind(Utc::now().timestamp_micros())
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub(super) async fn update_url<'c, E>(
+    executor: E,
+    org_id: &str,
+    short_id: &str,
+    new_url: &str,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET original_url = $1, checksum = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+    );
+    let ret = sqlx::query(&query)
+        .bind(new_url)
+        .bind(checksum(short_id, new_url))
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    if ret.rows_affected() == 0 {
+        return Err(ShortUrlError::NotFound(short_id.to_string()));
+    }
+    Ok(())
+}
+
+pub(super) async fn increment_clicks<'c, E>(executor: E, org_id: &str, short_id: &str) -> Result<()>
+where
+    E: Executor<'c, Database = Postgres>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET click_count = click_count + 1 WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL;"#
+    );
+    sqlx::query(&query)
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// tables created before the version tracking start at version 0 and run every step
+async fn migrate_to(table: &str, version: i64) -> Result<()> {
+    match version {
+        // short_id was VARCHAR(32) for old version <= 0.12.0
+        1 => {
+            sqlx::query(&format!(
+                r#"ALTER TABLE {table} ALTER COLUMN short_id TYPE VARCHAR(64);"#
+            ))
+            .execute(&CLIENT.clone())
+            .await?;
+        }
+        // create column expires_at for old version <= 0.12.0
+        2 => add_column(table, "expires_at", "BIGINT").await?,
+        3 => add_column(table, "click_count", "BIGINT NOT NULL DEFAULT 0").await?,
+        4 => add_column(table, "permanent", "BOOLEAN NOT NULL DEFAULT false").await?,
+        // create column org_id for old version <= 0.12.0, existing rows get an empty org_id
+        5 => add_column(table, "org_id", "VARCHAR(256) NOT NULL DEFAULT ''").await?,
+        6 => add_column(table, "created_by", "VARCHAR(512)").await?,
+        7 => add_column(table, "alias_of", "VARCHAR(64)").await?,
+        8 => add_column(table, "deleted_at", "BIGINT").await?,
+        9 => add_column(table, "resource_type", "VARCHAR(64)").await?,
+        10 => add_column(table, "resource_id", "VARCHAR(256)").await?,
+        11 => add_column(table, "tags", "TEXT").await?,
+        12 => add_column(table, "pinned", "BOOLEAN NOT NULL DEFAULT false").await?,
+        13 => add_column(table, "expiry_notify_at", "BIGINT").await?,
+        14 => add_column(table, "notified", "BOOLEAN NOT NULL DEFAULT false").await?,
+        15 => add_column(table, "namespace", "VARCHAR(32)").await?,
+        16 => add_column(table, "acl", "TEXT").await?,
+        17 => add_column(table, "pipeline", "TEXT").await?,
+        18 => add_column(table, "checksum", "VARCHAR(16)").await?,
+        19 => add_column(table, "last_check_status", "SMALLINT").await?,
+        20 => add_column(table, "link_rot_detected", "BOOLEAN NOT NULL DEFAULT false").await?,
+        _ => {
+            return Err(sqlx::Error::Configuration(
+                format!("unknown short url schema version {version}").into(),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+async fn add_column(table: &str, column: &str, data_type: &str) -> Result<()> {
+    let pool = CLIENT.clone();
+    let alter_sql = format!("ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {column} {data_type};");
+    if let Err(e) = sqlx::query(&alter_sql).execute(&pool).await {
+        log::error!("[POSTGRES] Error in adding column {column}: {}", e);
+        return Err(e.into());
+    }
+    Ok(())
 }
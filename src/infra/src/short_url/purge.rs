@@ -0,0 +1,244 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{future::Future, time::Duration};
+
+use chrono::Utc;
+use config::{
+    get_config,
+    metrics::{SHORT_URL_EXPIRED_REMOVED, SHORT_URL_PURGE_DRY_RUN},
+};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::short_url::{self, error::Result, EvictionStrategy, ShortUrlRecord};
+
+/// Background task removing expired short urls from the db in batches
+pub struct ShortUrlPurgeTask {
+    interval: Duration,
+    batch_size: i64,
+    retention: chrono::Duration,
+    /// Only log the first batch of expired short urls, nothing is removed
+    dry_run: bool,
+    /// Order in which each batch of expired short urls is picked
+    strategy: EvictionStrategy,
+    /// Send the expiry notifications that are due before each purge
+    notify_expiry: bool,
+    /// How long the access log is kept, `None` keeps it forever
+    access_log_retention: Option<chrono::Duration>,
+    /// Move the expired short urls to the archive table instead of deleting them
+    archive: bool,
+    token: CancellationToken,
+}
+
+/// Number of short_ids logged by a dry run
+const DRY_RUN_SAMPLE_SIZE: usize = 10;
+
+impl ShortUrlPurgeTask {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        interval: Duration,
+        batch_size: i64,
+        retention: chrono::Duration,
+        dry_run: bool,
+        strategy: EvictionStrategy,
+        notify_expiry: bool,
+        access_log_retention: Option<chrono::Duration>,
+        archive: bool,
+        token: CancellationToken,
+    ) -> Self {
+        Self {
+            interval,
+            batch_size: batch_size.max(1),
+            retention,
+            dry_run,
+            strategy,
+            notify_expiry,
+            access_log_retention,
+            archive,
+            token,
+        }
+    }
+
+    /// Build the task from `ZO_SHORT_URL_PURGE_*`, `ZO_SHORT_URL_RETENTION_DAYS`,
+    /// `ZO_SHORT_URL_EXPIRY_NOTIFY_DAYS`, `ZO_SHORT_URL_ACCESS_LOG_RETENTION_DAYS` and
+    /// `ZO_SHORT_URL_ARCHIVE_EXPIRED`
+    pub fn from_config(token: CancellationToken) -> Self {
+        let cfg = get_config();
+        let strategy = if cfg.limit.short_url_purge_random_sample {
+            EvictionStrategy::Random
+        } else {
+            EvictionStrategy::OldestFirst
+        };
+        Self::new(
+            Duration::from_secs(cfg.limit.short_url_purge_interval.max(1)),
+            cfg.limit.short_url_purge_batch_size,
+            chrono::Duration::days(cfg.limit.short_url_retention_days),
+            cfg.limit.short_url_purge_dry_run,
+            strategy,
+            cfg.limit.short_url_expiry_notify_days > 0,
+            (cfg.limit.short_url_access_log_retention_days > 0)
+                .then(|| chrono::Duration::days(cfg.limit.short_url_access_log_retention_days)),
+            cfg.limit.short_url_archive_expired,
+            token,
+        )
+    }
+
+    /// Run the purge loop until the token is cancelled, `on_removed` is called with each
+    /// batch of removed `(org_id, short_id)` so callers can evict their caches and
+    /// `on_expiry_due` with each short url whose expiry notification is due
+    pub fn spawn<F, Fut, N, NFut>(self, on_removed: F, on_expiry_due: N) -> JoinHandle<()>
+    where
+        F: Fn(Vec<(String, String)>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send,
+        N: Fn(ShortUrlRecord) -> NFut + Send + Sync + 'static,
+        NFut: Future<Output = bool> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                tokio::select! {
+                    _ = self.token.cancelled() => break,
+                    _ = interval.tick() => {}
+                }
+                if self.notify_expiry {
+                    match self.notify_once(&on_expiry_due).await {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("[SHORT_URL] sent {n} short url expiry notifications"),
+                        Err(e) => log::error!("[SHORT_URL] expiry notification error: {}", e),
+                    }
+                }
+                match self.purge_once(&on_removed).await {
+                    Ok(0) => {}
+                    Ok(n) => log::info!("[SHORT_URL] purged {n} expired short urls"),
+                    Err(e) => log::error!("[SHORT_URL] purge expired short urls error: {}", e),
+                }
+                if let Some(retention) = self.access_log_retention.filter(|_| !self.dry_run) {
+                    let older_than = (Utc::now() - retention).timestamp_micros();
+                    match short_url::purge_access_log(older_than).await {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("[SHORT_URL] purged {n} short url access log entries"),
+                        Err(e) => log::error!("[SHORT_URL] purge access log error: {}", e),
+                    }
+                }
+                if !self.dry_run {
+                    let older_than = (Utc::now()
+                        - chrono::Duration::hours(short_url::IDEMPOTENCY_KEY_TTL_HOURS))
+                    .timestamp_micros();
+                    match short_url::purge_idempotency_keys(older_than).await {
+                        Ok(0) => {}
+                        Ok(n) => log::info!("[SHORT_URL] purged {n} expired idempotency keys"),
+                        Err(e) => log::error!("[SHORT_URL] purge idempotency keys error: {}", e),
+                    }
+                }
+            }
+            log::info!("[SHORT_URL] purge task stopped");
+        })
+    }
+
+    /// Remove all currently expired short urls and those soft deleted before the retention
+    /// period, returns the number removed, a dry run removes nothing and returns 0. With
+    /// `archive` the expired short urls are moved to the archive table instead
+    pub async fn purge_once<F, Fut>(&self, on_removed: &F) -> Result<usize>
+    where
+        F: Fn(Vec<(String, String)>) -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        let expired_before = (Utc::now() - self.retention).timestamp_micros();
+        if self.dry_run {
+            self.dry_run_once(expired_before).await?;
+            return Ok(0);
+        }
+        // `archive_expired` moves the oldest first, the batch looked up here is then the one
+        // archived and its short_ids are evicted from the caches
+        let strategy = if self.archive {
+            EvictionStrategy::OldestFirst
+        } else {
+            self.strategy
+        };
+        let mut removed = 0;
+        while !self.token.is_cancelled() {
+            let short_ids =
+                short_url::get_expired(None, expired_before, Some(self.batch_size), strategy)
+                    .await?;
+            if short_ids.is_empty() {
+                break;
+            }
+            let num = short_ids.len();
+            let deleted = if self.archive {
+                short_url::archive_expired(expired_before, Some(self.batch_size)).await?
+            } else {
+                short_url::batch_remove(short_ids.clone()).await?
+            };
+            SHORT_URL_EXPIRED_REMOVED
+                .with_label_values(&[])
+                .inc_by(deleted);
+            on_removed(short_ids).await;
+            removed += deleted as usize;
+            if (num as i64) < self.batch_size {
+                break;
+            }
+        }
+        // soft deleted short urls are kept for the retention period as well
+        if !self.token.is_cancelled() {
+            removed += short_url::hard_delete_expired_soft_deleted(expired_before).await? as usize;
+        }
+        Ok(removed)
+    }
+
+    /// Call `on_expiry_due` with each short url whose expiry notification is due, those it
+    /// returns `true` for are marked notified and the others are retried on the next run.
+    /// Returns the number marked notified
+    pub async fn notify_once<N, NFut>(&self, on_expiry_due: &N) -> Result<usize>
+    where
+        N: Fn(ShortUrlRecord) -> NFut,
+        NFut: Future<Output = bool>,
+    {
+        let due = short_url::get_due_for_notification(Utc::now().timestamp_micros()).await?;
+        let mut notified = 0;
+        for record in due {
+            if self.token.is_cancelled() {
+                break;
+            }
+            let (org_id, short_id) = (record.org_id.clone(), record.short_id.clone());
+            if on_expiry_due(record).await {
+                short_url::mark_notified(&org_id, &short_id).await?;
+                notified += 1;
+            }
+        }
+        Ok(notified)
+    }
+
+    // nothing is removed so only one batch is looked at, the count is capped by the batch size
+    async fn dry_run_once(&self, expired_before: i64) -> Result<()> {
+        let short_ids =
+            short_url::get_expired(None, expired_before, Some(self.batch_size), self.strategy)
+                .await?;
+        SHORT_URL_PURGE_DRY_RUN
+            .with_label_values(&[])
+            .inc_by(short_ids.len() as u64);
+        let sample = short_ids
+            .iter()
+            .take(DRY_RUN_SAMPLE_SIZE)
+            .map(|(org_id, short_id)| format!("{org_id}/{short_id}"))
+            .collect::<Vec<_>>();
+        log::info!(
+            "[SHORT_URL_PURGE] dry_run: would remove {} records, sample: {:?}",
+            short_ids.len(),
+            sample
+        );
+        Ok(())
+    }
+}
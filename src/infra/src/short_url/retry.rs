@@ -0,0 +1,154 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use sqlx::mysql::MySqlDatabaseError;
+
+use crate::short_url::error::{Result, ShortUrlError};
+
+// ER_LOCK_WAIT_TIMEOUT and ER_LOCK_DEADLOCK, the statement was rolled back
+const MYSQL_TRANSIENT_ERRORS: [u16; 2] = [1205, 1213];
+// serialization_failure and deadlock_detected
+const TRANSIENT_SQLSTATES: [&str; 2] = ["40001", "40P01"];
+
+/// Deadlocks, lock wait timeouts and an exhausted pool go away on their own, retrying the
+/// statement is safe since the failed attempt changed nothing
+pub fn is_transient(e: &ShortUrlError) -> bool {
+    match e {
+        ShortUrlError::DatabaseError(sqlx::Error::PoolTimedOut) => true,
+        ShortUrlError::DatabaseError(sqlx::Error::Database(err)) => {
+            if let Some(err) = err.try_downcast_ref::<MySqlDatabaseError>() {
+                if MYSQL_TRANSIENT_ERRORS.contains(&err.number()) {
+                    return true;
+                }
+            }
+            err.code()
+                .is_some_and(|code| TRANSIENT_SQLSTATES.contains(&code.as_ref()))
+        }
+        _ => false,
+    }
+}
+
+/// Run `f` up to `max_attempts` times while it fails with a transient error, waiting
+/// `base_delay` doubled on every attempt plus up to as much again of jitter in between
+pub async fn with_retry<F, Fut, T>(mut f: F, max_attempts: u32, base_delay: Duration) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Err(e) if attempt < max_attempts && is_transient(&e) => {
+                let delay = backoff(base_delay, attempt);
+                log::warn!(
+                    "[SHORT_URL] transient db error on attempt {attempt}/{max_attempts}, retrying in {delay:?}: {e}"
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            ret => return ret,
+        }
+    }
+}
+
+fn backoff(base_delay: Duration, attempt: u32) -> Duration {
+    let delay = base_delay.saturating_mul(1 << (attempt - 1).min(16));
+    // concurrent callers that hit the same deadlock should not retry in lockstep
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    delay + delay.mul_f64(nanos as f64 / 1_000_000_000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&ShortUrlError::DatabaseError(
+            sqlx::Error::PoolTimedOut
+        )));
+        assert!(!is_transient(&ShortUrlError::DatabaseError(
+            sqlx::Error::RowNotFound
+        )));
+        assert!(!is_transient(&ShortUrlError::NotFound("abc".to_string())));
+    }
+
+    #[test]
+    fn test_backoff() {
+        let base = Duration::from_millis(10);
+        for attempt in 1..=3 {
+            let delay = backoff(base, attempt);
+            let min = base * (1 << (attempt - 1));
+            assert!(delay >= min && delay < min * 2, "{attempt}: {delay:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_with_retry() {
+        let calls = AtomicU32::new(0);
+        let ret = with_retry(
+            || async {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(ShortUrlError::DatabaseError(sqlx::Error::PoolTimedOut))
+                } else {
+                    Ok(7)
+                }
+            },
+            3,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert_eq!(ret.unwrap(), 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // gives up after max_attempts
+        calls.store(0, Ordering::SeqCst);
+        let ret: Result<()> = with_retry(
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(ShortUrlError::DatabaseError(sqlx::Error::PoolTimedOut))
+            },
+            2,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(ret.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // other errors are returned right away
+        calls.store(0, Ordering::SeqCst);
+        let ret: Result<()> = with_retry(
+            || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                Err(ShortUrlError::NotFound("abc".to_string()))
+            },
+            3,
+            Duration::from_millis(1),
+        )
+        .await;
+        assert!(matches!(ret, Err(ShortUrlError::NotFound(_))));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,829 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{future::Future, sync::Arc};
+
+use async_trait::async_trait;
+use config::meta::short_url::ShortUrlHealthReport;
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt};
+use hashbrown::HashMap;
+use tokio::sync::mpsc;
+
+use crate::short_url::{
+    backend::ShortUrlBackend,
+    error::{Result, ShortUrlError},
+    tx::ShortUrlTx,
+    AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+    ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir,
+};
+
+/// Sends every write to `shadow` as well, to check a new db against the one in use before
+/// migrating to it.
+///
+/// Everything is answered by `primary`. Successful writes are repeated on `shadow` in the
+/// background and `get`, `get_by_original_url` and `contains` read `shadow` too, a result that
+/// differs from the primary one is logged. Shadow errors are only logged and never slow down or
+/// fail a call. Writes done in `with_transaction` and by `replay_from_events` are not repeated.
+///
+/// The writes are queued and applied one at a time in the order the primary took them, a write
+/// that does not fit in the queue is dropped and logged.
+pub struct ShadowShortUrl<P: ShortUrl, S: ShortUrl> {
+    primary: P,
+    shadow: Option<Arc<S>>,
+    writes: Option<mpsc::Sender<MirroredWrite>>,
+}
+
+// a write to repeat on the shadow, named for the log
+type MirroredWrite = (&'static str, BoxFuture<'static, Result<()>>);
+
+const MIRROR_QUEUE_SIZE: usize = 10000;
+
+impl<P: ShortUrl, S: ShortUrl> ShadowShortUrl<P, S> {
+    /// Spawns the task applying the writes to `shadow`, must be called within the runtime
+    pub fn new(primary: P, shadow: S) -> Self {
+        Self {
+            primary,
+            shadow: Some(Arc::new(shadow)),
+            writes: Some(Self::handle_mirror_queue()),
+        }
+    }
+
+    /// Only use `primary`
+    pub fn disabled(primary: P) -> Self {
+        Self {
+            primary,
+            shadow: None,
+            writes: None,
+        }
+    }
+
+    fn handle_mirror_queue() -> mpsc::Sender<MirroredWrite> {
+        let (tx, mut rx) = mpsc::channel::<MirroredWrite>(MIRROR_QUEUE_SIZE);
+        tokio::task::spawn(async move {
+            while let Some((operation, write)) = rx.recv().await {
+                if let Err(e) = write.await {
+                    log::warn!("[SHORT_URL] shadow {operation} failed: {e}");
+                }
+            }
+            log::info!("[SHORT_URL] shadow write queue closed");
+        });
+        tx
+    }
+
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    // queue `f` for the shadow without waiting for it
+    fn mirror<F, Fut, T>(&self, operation: &'static str, f: F)
+    where
+        F: FnOnce(Arc<S>) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (Some(shadow), Some(writes)) = (self.shadow.clone(), self.writes.as_ref()) else {
+            return;
+        };
+        let write = f(shadow).map(|ret| ret.map(|_| ())).boxed();
+        if let Err(e) = writes.try_send((operation, write)) {
+            log::warn!("[SHORT_URL] shadow {operation} dropped: {e}");
+        }
+    }
+
+    // run the shadow read `f` without waiting for it, it returns the fields that differ from
+    // the primary result
+    fn compare<F, Fut>(&self, operation: &'static str, key: String, f: F)
+    where
+        F: FnOnce(Arc<S>) -> Fut,
+        Fut: Future<Output = Result<Vec<&'static str>>> + Send + 'static,
+    {
+        let Some(shadow) = self.shadow.clone() else {
+            return;
+        };
+        let fut = f(shadow);
+        tokio::spawn(async move {
+            match fut.await {
+                Ok(diff) if diff.is_empty() => {}
+                Ok(diff) => log::warn!(
+                    "[SHORT_URL] shadow {operation} {key} differs from primary: {}",
+                    diff.join(", ")
+                ),
+                Err(e) => log::warn!("[SHORT_URL] shadow {operation} {key} failed: {e}"),
+            }
+        });
+    }
+}
+
+impl ShadowShortUrl<ShortUrlBackend, ShortUrlBackend> {
+    /// Shadow `primary` with the db in `ZO_SHORT_URL_SHADOW_BACKEND`, if set
+    pub fn from_config(primary: ShortUrlBackend) -> Self {
+        let shadow = &config::get_config().limit.short_url_shadow_backend;
+        if shadow.is_empty() {
+            Self::disabled(primary)
+        } else {
+            Self::new(primary, ShortUrlBackend::from_name(shadow))
+        }
+    }
+}
+
+// a `get` result, not found is a result to compare too
+fn found(ret: Result<ShortUrlRecord>) -> Result<Option<ShortUrlRecord>> {
+    match ret {
+        Ok(record) => Ok(Some(record)),
+        Err(ShortUrlError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The fields `shadow` has different from `primary`. The click count lags behind as clicks
+/// are mirrored in the background and the shadow sets its own `created_ts` on insert, neither
+/// is compared.
+fn diff(primary: Option<&ShortUrlRecord>, shadow: Option<&ShortUrlRecord>) -> Vec<&'static str> {
+    let (primary, shadow) = match (primary, shadow) {
+        (None, None) => return vec![],
+        (Some(_), None) => return vec!["missing in shadow"],
+        (None, Some(_)) => return vec!["missing in primary"],
+        (Some(primary), Some(shadow)) => (primary, shadow),
+    };
+    let mut fields = vec![];
+    if primary.original_url != shadow.original_url {
+        fields.push("original_url");
+    }
+    if primary.expires_at != shadow.expires_at {
+        fields.push("expires_at");
+    }
+    if primary.permanent != shadow.permanent {
+        fields.push("permanent");
+    }
+    if primary.created_by != shadow.created_by {
+        fields.push("created_by");
+    }
+    if primary.alias_of != shadow.alias_of {
+        fields.push("alias_of");
+    }
+    if primary.resource_type != shadow.resource_type || primary.resource_id != shadow.resource_id {
+        fields.push("resource");
+    }
+    if primary.tags != shadow.tags {
+        fields.push("tags");
+    }
+    if primary.pinned != shadow.pinned {
+        fields.push("pinned");
+    }
+    if primary.expiry_notify_at != shadow.expiry_notify_at {
+        fields.push("expiry_notify_at");
+    }
+    if primary.namespace != shadow.namespace {
+        fields.push("namespace");
+    }
+    if primary.acl != shadow.acl {
+        fields.push("acl");
+    }
+    if primary.pipeline != shadow.pipeline {
+        fields.push("pipeline");
+    }
+    fields
+}
+
+#[async_trait]
+impl<P: ShortUrl, S: ShortUrl> ShortUrl for ShadowShortUrl<P, S> {
+    async fn create_table(&self) -> Result<()> {
+        self.primary.create_table().await?;
+        if let Some(shadow) = self.shadow.as_ref() {
+            if let Err(e) = shadow.create_table().await {
+                log::error!("[SHORT_URL] shadow create_table failed: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_table_index(&self) -> Result<()> {
+        self.primary.create_table_index().await?;
+        if let Some(shadow) = self.shadow.as_ref() {
+            if let Err(e) = shadow.create_table_index().await {
+                log::error!("[SHORT_URL] shadow create_table_index failed: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        self.primary.migrate().await?;
+        if let Some(shadow) = self.shadow.as_ref() {
+            if let Err(e) = shadow.migrate().await {
+                log::error!("[SHORT_URL] shadow migrate failed: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        let added = self.primary.add(record).await?;
+        let record = added.clone();
+        self.mirror(
+            "add",
+            move |shadow| async move { shadow.add(&record).await },
+        );
+        Ok(added)
+    }
+
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        let inserted = self.primary.add_if_absent(record).await?;
+        if inserted {
+            let record = record.clone();
+            self.mirror("add_if_absent", move |shadow| async move {
+                shadow.add_if_absent(&record).await
+            });
+        }
+        Ok(inserted)
+    }
+
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        let ret = self.primary.batch_add(records).await?;
+        let records = records.to_vec();
+        self.mirror("batch_add", move |shadow| async move {
+            shadow.batch_add(&records).await
+        });
+        Ok(ret)
+    }
+
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.remove(org_id, short_id).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("remove", move |shadow| async move {
+            shadow.remove(&org_id, &short_id).await
+        });
+        Ok(())
+    }
+
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        self.primary.update(org_id, short_id, new_url).await?;
+        let (org_id, short_id, new_url) = (
+            org_id.to_string(),
+            short_id.to_string(),
+            new_url.to_string(),
+        );
+        self.mirror("update", move |shadow| async move {
+            shadow.update(&org_id, &short_id, &new_url).await
+        });
+        Ok(())
+    }
+
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
+        self.primary
+            .rename(org_id, old_short_id, new_short_id)
+            .await?;
+        let (org_id, old_short_id, new_short_id) = (
+            org_id.to_string(),
+            old_short_id.to_string(),
+            new_short_id.to_string(),
+        );
+        self.mirror("rename", move |shadow| async move {
+            shadow.rename(&org_id, &old_short_id, &new_short_id).await
+        });
+        Ok(())
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        self.primary.set_pinned(org_id, short_id, pinned).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("set_pinned", move |shadow| async move {
+            shadow.set_pinned(&org_id, &short_id, pinned).await
+        });
+        Ok(())
+    }
+
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        let ret = self.primary.get(org_id, short_id).await;
+        let expected = match &ret {
+            Ok(record) => Some(record.clone()),
+            Err(ShortUrlError::NotFound(_)) => None,
+            // nothing to compare with
+            Err(_) => return ret,
+        };
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.compare(
+            "get",
+            format!("{org_id}/{short_id}"),
+            move |shadow| async move {
+                let record = found(shadow.get(&org_id, &short_id).await)?;
+                Ok(diff(expected.as_ref(), record.as_ref()))
+            },
+        );
+        ret
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        self.primary.get_many(org_id, short_ids).await
+    }
+
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.increment_click_count(org_id, short_id).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("increment_click_count", move |shadow| async move {
+            shadow.increment_click_count(&org_id, &short_id).await
+        });
+        Ok(())
+    }
+
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        let expected = self
+            .primary
+            .get_by_original_url(org_id, original_url)
+            .await?;
+        let ret = expected.clone();
+        let (org_id, original_url) = (org_id.to_string(), original_url.to_string());
+        self.compare(
+            "get_by_original_url",
+            format!("{org_id}/{original_url}"),
+            move |shadow| async move {
+                let record = shadow.get_by_original_url(&org_id, &original_url).await?;
+                Ok(diff(expected.as_ref(), record.as_ref()))
+            },
+        );
+        Ok(ret)
+    }
+
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary
+            .list(org_id, created_by, limit, after_ts, sort_by, sort_dir)
+            .await
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.primary.stream().await
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        self.primary.stream_by_org(org_id).await
+    }
+
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        self.primary
+            .list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+            .await
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary
+            .list_by_resource(resource_type, resource_id)
+            .await
+    }
+
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.list_by_tag(key, value, limit).await
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.search(org_id, url_pattern, limit).await
+    }
+
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        let expected = self.primary.contains(org_id, short_id).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.compare(
+            "contains",
+            format!("{org_id}/{short_id}"),
+            move |shadow| async move {
+                let found = shadow.contains(&org_id, &short_id).await?;
+                Ok(match (expected, found) {
+                    (true, false) => vec!["missing in shadow"],
+                    (false, true) => vec!["missing in primary"],
+                    _ => vec![],
+                })
+            },
+        );
+        Ok(expected)
+    }
+
+    async fn len(&self) -> usize {
+        self.primary.len().await
+    }
+
+    async fn clear(&self) -> Result<u64> {
+        let deleted = self.primary.clear().await?;
+        self.mirror("clear", |shadow| async move { shadow.clear().await });
+        Ok(deleted)
+    }
+
+    async fn is_empty(&self) -> bool {
+        self.primary.is_empty().await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.primary.ping().await
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        self.primary.db_version().await
+    }
+
+    async fn health_report(&self) -> Result<ShortUrlHealthReport> {
+        self.primary.health_report().await
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        self.primary
+            .get_expired(org_id, expired_before, limit, strategy)
+            .await
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let ret = self.primary.batch_remove(short_ids.clone()).await?;
+        self.mirror("batch_remove", move |shadow| async move {
+            shadow.batch_remove(short_ids).await
+        });
+        Ok(ret)
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.restore(org_id, short_id).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("restore", move |shadow| async move {
+            shadow.restore(&org_id, &short_id).await
+        });
+        Ok(())
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        let deleted = self
+            .primary
+            .hard_delete_expired_soft_deleted(older_than)
+            .await?;
+        self.mirror(
+            "hard_delete_expired_soft_deleted",
+            move |shadow| async move { shadow.hard_delete_expired_soft_deleted(older_than).await },
+        );
+        Ok(deleted)
+    }
+
+    /// With a shadow the rows are picked on the primary and the same ones are archived on both,
+    /// each db would pick its own oldest rows otherwise
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        if self.shadow.is_none() {
+            return self.primary.archive_expired(expired_before, limit).await;
+        }
+        let short_ids = self
+            .primary
+            .get_expired(None, expired_before, limit, EvictionStrategy::OldestFirst)
+            .await?;
+        self.archive(&short_ids).await
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
+        let archived = self.primary.archive(short_ids).await?;
+        let short_ids = short_ids.to_vec();
+        self.mirror("archive", move |shadow| async move {
+            shadow.archive(&short_ids).await
+        });
+        Ok(archived)
+    }
+
+    async fn with_transaction<Func, T>(&self, f: Func) -> Result<T>
+    where
+        Self: Sized,
+        Func: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        self.primary.with_transaction(f).await
+    }
+
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        self.primary
+            .count_by_date_range(org_id, from_ts, to_ts, granularity)
+            .await
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.top_by_clicks(org_id, limit).await
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        self.primary.click_totals(org_id).await
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        self.primary.count_by_org(org_id).await
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        self.primary.list_namespaces(org_id).await
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.get_due_for_notification(now).await
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        self.primary.mark_notified(org_id, short_id).await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("mark_notified", move |shadow| async move {
+            shadow.mark_notified(&org_id, &short_id).await
+        });
+        Ok(())
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.sample_for_link_check(limit).await
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        self.primary
+            .set_link_check_status(org_id, short_id, status)
+            .await?;
+        let (org_id, short_id) = (org_id.to_string(), short_id.to_string());
+        self.mirror("set_link_check_status", move |shadow| async move {
+            shadow
+                .set_link_check_status(&org_id, &short_id, status)
+                .await
+        });
+        Ok(())
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        self.primary.list_link_rot_candidates(org_id).await
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        self.primary.replay_from_events(from_ts).await
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        self.primary.add_access_log(entries).await?;
+        let entries = entries.to_vec();
+        self.mirror("add_access_log", move |shadow| async move {
+            shadow.add_access_log(&entries).await
+        });
+        Ok(())
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        self.primary
+            .get_access_log(org_id, short_id, from_ts, to_ts)
+            .await
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        let purged = self.primary.purge_access_log(older_than).await?;
+        self.mirror("purge_access_log", move |shadow| async move {
+            shadow.purge_access_log(older_than).await
+        });
+        Ok(purged)
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        self.primary
+            .get_idempotency_key(org_id, idempotency_key, created_after)
+            .await
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        let reserved = self
+            .primary
+            .reserve_idempotency_key(org_id, idempotency_key, body_hash, expired_before)
+            .await?;
+        if reserved {
+            let (org_id, idempotency_key, body_hash) = (
+                org_id.to_string(),
+                idempotency_key.to_string(),
+                body_hash.to_string(),
+            );
+            self.mirror("reserve_idempotency_key", move |shadow| async move {
+                shadow
+                    .reserve_idempotency_key(&org_id, &idempotency_key, &body_hash, expired_before)
+                    .await
+            });
+        }
+        Ok(reserved)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        self.primary
+            .complete_idempotency_key(org_id, idempotency_key, short_id)
+            .await?;
+        let (org_id, idempotency_key, short_id) = (
+            org_id.to_string(),
+            idempotency_key.to_string(),
+            short_id.to_string(),
+        );
+        self.mirror("complete_idempotency_key", move |shadow| async move {
+            shadow
+                .complete_idempotency_key(&org_id, &idempotency_key, &short_id)
+                .await
+        });
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        self.primary
+            .release_idempotency_key(org_id, idempotency_key)
+            .await?;
+        let (org_id, idempotency_key) = (org_id.to_string(), idempotency_key.to_string());
+        self.mirror("release_idempotency_key", move |shadow| async move {
+            shadow
+                .release_idempotency_key(&org_id, &idempotency_key)
+                .await
+        });
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        let purged = self.primary.purge_idempotency_keys(older_than).await?;
+        self.mirror("purge_idempotency_keys", move |shadow| async move {
+            shadow.purge_idempotency_keys(older_than).await
+        });
+        Ok(purged)
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        self.primary
+            .generate_digest(org_id, period_start, period_end)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::short_url::{memory::MemoryShortUrl, sqlite::SqliteShortUrl};
+
+    #[test]
+    fn test_diff() {
+        let primary = ShortUrlRecord::new("default", "shadow", "https://example.com/shadow");
+        let mut shadow = primary.clone();
+        shadow.created_ts += 1;
+        shadow.click_count += 1;
+        assert!(diff(Some(&primary), Some(&shadow)).is_empty());
+        shadow.original_url = "https://example.com/other".to_string();
+        shadow.pinned = true;
+        assert_eq!(
+            diff(Some(&primary), Some(&shadow)),
+            vec!["original_url", "pinned"]
+        );
+        assert_eq!(diff(Some(&primary), None), vec!["missing in shadow"]);
+        assert!(diff(None, None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_writes_reach_shadow() {
+        let short_url = ShadowShortUrl::new(SqliteShortUrl::new(), MemoryShortUrl::new());
+        short_url.create_table().await.unwrap();
+        short_url
+            .primary()
+            .batch_remove(vec![("default".to_string(), "shadow".to_string())])
+            .await
+            .unwrap();
+        let record = ShortUrlRecord::new("default", "shadow", "https://example.com/shadow");
+        short_url.add(&record).await.unwrap();
+
+        let shadow = short_url.shadow.clone().unwrap();
+        let mut mirrored = false;
+        for _ in 0..100 {
+            if shadow.contains("default", "shadow").await.unwrap() {
+                mirrored = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(mirrored);
+        // answered by the primary
+        assert_eq!(
+            short_url
+                .get("default", "shadow")
+                .await
+                .unwrap()
+                .original_url,
+            record.original_url
+        );
+
+        short_url
+            .batch_remove(vec![("default".to_string(), "shadow".to_string())])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_writes_reach_shadow_in_order() {
+        let short_url = ShadowShortUrl::new(MemoryShortUrl::new(), MemoryShortUrl::new());
+        let record = ShortUrlRecord::new("default", "ordered", "https://example.com/0");
+        short_url.add(&record).await.unwrap();
+        for i in 1..=20 {
+            short_url
+                .update("default", "ordered", &format!("https://example.com/{i}"))
+                .await
+                .unwrap();
+        }
+        let marker = ShortUrlRecord::new("default", "ordered_done", "https://example.com/done");
+        short_url.add(&marker).await.unwrap();
+
+        // the marker is the last write, once it is there every earlier one was applied
+        let shadow = short_url.shadow.clone().unwrap();
+        let mut mirrored = false;
+        for _ in 0..100 {
+            if shadow.contains("default", "ordered_done").await.unwrap() {
+                mirrored = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(mirrored);
+        assert_eq!(
+            shadow.get("default", "ordered").await.unwrap().original_url,
+            "https://example.com/20"
+        );
+    }
+}
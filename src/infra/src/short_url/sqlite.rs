@@ -15,12 +15,23 @@
 
 use async_trait::async_trait;
 use chrono::Utc;
-use sqlx::Row;
+use futures::{future::BoxFuture, stream::BoxStream, StreamExt, TryStreamExt};
+use hashbrown::HashMap;
+use once_cell::sync::Lazy;
+use sqlx::{Executor, Pool, QueryBuilder, Row, Sqlite, SqliteConnection};
 
 use crate::{
-    db::sqlite::{create_index, CLIENT_RO, CLIENT_RW},
-    errors::{DbError, Error, Result},
-    short_url::{ShortUrl, ShortUrlRecord},
+    db::sqlite::{create_index, delete_index, CLIENT_RO, CLIENT_RW},
+    short_url::{
+        checksum,
+        error::{Result, ShortUrlError},
+        like_contains_pattern, push_order_by, tag_json_path,
+        tx::ShortUrlTx,
+        AccessLogEntry, BatchAddResult, EvictionStrategy, Granularity, IdempotencyKey, ShortUrl,
+        ShortUrlDigestReport, ShortUrlEvent, ShortUrlRecord, SortBy, SortDir, ACCESS_LOG_TABLE,
+        ARCHIVE_COLUMNS, ARCHIVE_TABLE, DIGEST_TOP_URLS, EVENTS_TABLE, IDEMPOTENCY_KEYS_TABLE,
+        SCHEMA_VERSION, SCHEMA_VERSION_TABLE, TABLE_NAME,
+    },
 };
 
 pub struct SqliteShortUrl {}
@@ -41,129 +52,807 @@ impl Default for SqliteShortUrl {
 impl ShortUrl for SqliteShortUrl {
     /// Creates the short_urls table if it does not exist
     async fn create_table(&self) -> Result<()> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RW.clone();
         let client = client.lock().await;
-
-        sqlx::query(
+        sqlx::query(&format!(
             r#"
-                CREATE TABLE IF NOT EXISTS short_urls
+                CREATE TABLE IF NOT EXISTS {table}
                 (
                     id           INTEGER PRIMARY KEY AUTOINCREMENT,
-                    short_id     VARCHAR(32) NOT NULL,
+                    org_id       VARCHAR(256) NOT NULL,
+                    short_id     VARCHAR(64) NOT NULL,
                     original_url TEXT NOT NULL,
-                    created_ts   BIGINT NOT NULL
+                    created_ts   BIGINT NOT NULL,
+                    expires_at   BIGINT,
+                    click_count  BIGINT NOT NULL DEFAULT 0,
+                    permanent    BOOLEAN NOT NULL DEFAULT false,
+                    created_by   VARCHAR(512),
+                    alias_of     VARCHAR(64),
+                    deleted_at   BIGINT,
+                    resource_type VARCHAR(64),
+                    resource_id  VARCHAR(256),
+                    tags         TEXT,
+                    pinned       BOOLEAN NOT NULL DEFAULT false,
+                    expiry_notify_at BIGINT,
+                    notified     BOOLEAN NOT NULL DEFAULT false,
+                    namespace    VARCHAR(32),
+                    acl          TEXT,
+                    pipeline     TEXT,
+                    checksum     VARCHAR(16),
+                    last_check_status SMALLINT,
+                    link_rot_detected BOOLEAN NOT NULL DEFAULT false
                 );
-                "#,
-        )
+                "#
+        ))
+        .execute(&*client)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {EVENTS_TABLE}
+                (
+                    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                    event_type  VARCHAR(16) NOT NULL,
+                    org_id      VARCHAR(256) NOT NULL,
+                    short_id    VARCHAR(64) NOT NULL,
+                    payload     TEXT NOT NULL,
+                    occurred_at BIGINT NOT NULL
+                );
+                "#
+        ))
+        .execute(&*client)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {ACCESS_LOG_TABLE}
+                (
+                    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                    org_id          VARCHAR(256) NOT NULL,
+                    short_id        VARCHAR(64) NOT NULL,
+                    accessed_at     BIGINT NOT NULL,
+                    user_agent_hash VARCHAR(16) NOT NULL,
+                    country_code    VARCHAR(2)
+                );
+                "#
+        ))
+        .execute(&*client)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {IDEMPOTENCY_KEYS_TABLE}
+                (
+                    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                    org_id          VARCHAR(256) NOT NULL,
+                    idempotency_key VARCHAR(128) NOT NULL,
+                    short_id        VARCHAR(64),
+                    body_hash       VARCHAR(64) NOT NULL,
+                    created_at      BIGINT NOT NULL
+                );
+                "#
+        ))
+        .execute(&*client)
+        .await?;
+        sqlx::query(&format!(
+            r#"
+                CREATE TABLE IF NOT EXISTS {ARCHIVE_TABLE}
+                (
+                    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+                    org_id           VARCHAR(256) NOT NULL,
+                    short_id         VARCHAR(64) NOT NULL,
+                    original_url     TEXT NOT NULL,
+                    created_ts       BIGINT NOT NULL,
+                    expires_at       BIGINT,
+                    click_count      BIGINT NOT NULL DEFAULT 0,
+                    permanent        BOOLEAN NOT NULL DEFAULT false,
+                    created_by       VARCHAR(512),
+                    alias_of         VARCHAR(64),
+                    deleted_at       BIGINT,
+                    resource_type    VARCHAR(64),
+                    resource_id      VARCHAR(256),
+                    tags             TEXT,
+                    pinned           BOOLEAN NOT NULL DEFAULT false,
+                    expiry_notify_at BIGINT,
+                    notified         BOOLEAN NOT NULL DEFAULT false,
+                    namespace        VARCHAR(32),
+                    acl              TEXT,
+                    pipeline         TEXT,
+                    checksum         VARCHAR(16),
+                    archived_at      BIGINT NOT NULL
+                );
+                "#
+        ))
         .execute(&*client)
         .await?;
+        // migrate takes the lock itself
+        drop(client);
+        self.migrate().await
+    }
 
+    async fn migrate(&self) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        sqlx::query(&format!(
+            r#"
+            CREATE TABLE IF NOT EXISTS {SCHEMA_VERSION_TABLE}
+            (
+                table_name VARCHAR(256) NOT NULL PRIMARY KEY,
+                version    BIGINT NOT NULL
+            );
+            "#
+        ))
+        .execute(&*client)
+        .await?;
+        let current = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT version FROM {SCHEMA_VERSION_TABLE} WHERE table_name = $1;"
+        ))
+        .bind(table)
+        .fetch_optional(&*client)
+        .await?
+        .unwrap_or(0);
+        for version in current + 1..=SCHEMA_VERSION {
+            migrate_to(&client, table, version).await?;
+            sqlx::query(&format!(
+                r#"
+                INSERT INTO {SCHEMA_VERSION_TABLE} (table_name, version) VALUES ($1, $2)
+                ON CONFLICT (table_name) DO UPDATE SET version = MAX(version, excluded.version);
+                "#
+            ))
+            .bind(table)
+            .bind(version)
+            .execute(&*client)
+            .await?;
+            log::info!("[SQLITE] short url table {table} migrated to schema version {version}");
+        }
         Ok(())
     }
 
     /// Creates indexes on the short_urls table
     async fn create_table_index(&self) -> Result<()> {
-        create_index("short_urls_short_id_idx", "short_urls", true, &["short_id"]).await?;
+        let table = TABLE_NAME.as_str();
+        create_index(
+            &format!("{table}_org_short_id_idx"),
+            table,
+            true,
+            &["org_id", "short_id"],
+        )
+        .await?;
         create_index(
-            "short_urls_created_ts_idx",
-            "short_urls",
+            &format!("{table}_created_ts_idx"),
+            table,
             false,
             &["created_ts"],
         )
         .await?;
+        create_index(
+            &format!("{table}_org_created_idx"),
+            table,
+            false,
+            &["org_id", "created_ts"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_org_clicks_idx"),
+            table,
+            false,
+            &["org_id", "click_count DESC"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_expires_at_idx"),
+            table,
+            false,
+            &["expires_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_expiry_notify_idx"),
+            table,
+            false,
+            &["expiry_notify_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_namespace_idx"),
+            table,
+            false,
+            &["org_id", "namespace"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_original_url_idx"),
+            table,
+            false,
+            &["original_url"],
+        )
+        .await?;
+
+        create_index(
+            &format!("{table}_created_by_idx"),
+            table,
+            false,
+            &["created_by"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_deleted_at_idx"),
+            table,
+            false,
+            &["deleted_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{table}_resource_idx"),
+            table,
+            false,
+            &["resource_type", "resource_id"],
+        )
+        .await?;
+
+        create_index(
+            &format!("{EVENTS_TABLE}_occurred_at_idx"),
+            EVENTS_TABLE,
+            false,
+            &["occurred_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{ACCESS_LOG_TABLE}_org_id_short_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+            false,
+            &["org_id", "short_id", "accessed_at"],
+        )
+        .await?;
+        // generate_digest counts the accesses of an org in a period
+        create_index(
+            &format!("{ACCESS_LOG_TABLE}_org_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+            false,
+            &["org_id", "accessed_at"],
+        )
+        .await?;
+        create_index(
+            &format!("{IDEMPOTENCY_KEYS_TABLE}_org_id_key_idx"),
+            IDEMPOTENCY_KEYS_TABLE,
+            true,
+            &["org_id", "idempotency_key"],
+        )
+        .await?;
+        create_index(
+            &format!("{ARCHIVE_TABLE}_org_id_short_id_idx"),
+            ARCHIVE_TABLE,
+            false,
+            &["org_id", "short_id"],
+        )
+        .await?;
+
+        // short_id is unique per org now
+        delete_index(&format!("{table}_short_id_idx"), table).await?;
+        // the access log is read per org, replaced by the org_id, short_id, accessed_at index
+        delete_index(
+            &format!("{ACCESS_LOG_TABLE}_short_id_accessed_at_idx"),
+            ACCESS_LOG_TABLE,
+        )
+        .await?;
         Ok(())
     }
 
     /// Adds a new short URL entry
-    async fn add(&self, short_id: &str, original_url: &str) -> Result<()> {
+    async fn add(&self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
         let client = CLIENT_RW.clone();
         let client = client.lock().await;
-        let created_ts = Utc::now().timestamp_micros();
+        let mut tx = client.begin().await?;
+        let result = insert_record(&mut *tx, record).await;
+        if let Ok(record) = &result {
+            insert_event(&mut *tx, &ShortUrlEvent::Add(record.clone())).await?;
+            tx.commit().await?;
+        }
+
+        // release lock
+        drop(client);
+
+        result
+    }
 
+    /// Adds a new short URL entry if its short_id is not taken
+    async fn add_if_absent(&self, record: &ShortUrlRecord) -> Result<bool> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
         let mut tx = client.begin().await?;
-        let query =
-            r#"INSERT INTO short_urls (short_id, original_url, created_ts) VALUES ($1, $2, $3);"#;
-        let result = sqlx::query(query)
-            .bind(short_id)
-            .bind(original_url)
+        let created_ts = Utc::now().timestamp_micros();
+
+        let query = format!(
+            r#"INSERT OR IGNORE INTO {table} (org_id, short_id, original_url, created_ts, expires_at, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17);"#
+        );
+        let ret = sqlx::query(&query)
+            .bind(&record.org_id)
+            .bind(&record.short_id)
+            .bind(&record.original_url)
             .bind(created_ts)
+            .bind(record.expires_at)
+            .bind(record.permanent)
+            .bind(&record.created_by)
+            .bind(&record.alias_of)
+            .bind(&record.resource_type)
+            .bind(&record.resource_id)
+            .bind(record.tags_json())
+            .bind(record.pinned)
+            .bind(record.expiry_notify_at)
+            .bind(&record.namespace)
+            .bind(record.acl_json())
+            .bind(record.pipeline_json())
+            .bind(record.compute_checksum())
             .execute(&mut *tx)
-            .await;
-
-        if result.is_ok() {
-            tx.commit().await?;
+            .await?;
+        let inserted = ret.rows_affected() > 0;
+        if inserted {
+            let record = ShortUrlRecord {
+                created_ts,
+                click_count: 0,
+                checksum: Some(record.compute_checksum()),
+                ..record.clone()
+            };
+            insert_event(&mut *tx, &ShortUrlEvent::Add(record)).await?;
         }
+        tx.commit().await?;
 
         // release lock
         drop(client);
 
-        match result {
-            Ok(_) => Ok(()),
-            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
-                Err(Error::DbError(DbError::UniqueViolation))
+        Ok(inserted)
+    }
+
+    /// Adds multiple short URL entries, skipping existing short_ids
+    async fn batch_add(&self, records: &[ShortUrlRecord]) -> Result<BatchAddResult> {
+        let table = TABLE_NAME.as_str();
+        if records.is_empty() {
+            return Ok(BatchAddResult::default());
+        }
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let created_ts = Utc::now().timestamp_micros();
+        let mut inserted = 0;
+        for records in records.chunks(100) {
+            let mut tx = client.begin().await?;
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+                "INSERT OR IGNORE INTO {table} (org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum)"
+            ));
+            query_builder.push_values(records, |mut b, record| {
+                let created_ts = if record.created_ts > 0 {
+                    record.created_ts
+                } else {
+                    created_ts
+                };
+                b.push_bind(&record.org_id)
+                    .push_bind(&record.short_id)
+                    .push_bind(&record.original_url)
+                    .push_bind(created_ts)
+                    .push_bind(record.expires_at)
+                    .push_bind(record.click_count)
+                    .push_bind(record.permanent)
+                    .push_bind(&record.created_by)
+                    .push_bind(&record.alias_of)
+                    .push_bind(&record.resource_type)
+                    .push_bind(&record.resource_id)
+                    .push_bind(record.tags_json())
+                    .push_bind(record.pinned)
+                    .push_bind(record.expiry_notify_at)
+                    .push_bind(&record.namespace)
+                    .push_bind(record.acl_json())
+                    .push_bind(record.pipeline_json())
+                    .push_bind(record.compute_checksum());
+            });
+            // ignored rows are not returned, only the inserted ones are logged
+            query_builder.push(" RETURNING org_id, short_id");
+            let added: Vec<(String, String)> =
+                match query_builder.build_query_as().fetch_all(&mut *tx).await {
+                    Ok(added) => added,
+                    Err(e) => {
+                        if let Err(e) = tx.rollback().await {
+                            log::error!("[SQLITE] rollback short_urls batch add error: {}", e);
+                        }
+                        return Err(e.into());
+                    }
+                };
+            for (org_id, short_id) in &added {
+                let Some(record) = records
+                    .iter()
+                    .find(|record| record.org_id == *org_id && record.short_id == *short_id)
+                else {
+                    continue;
+                };
+                let record = ShortUrlRecord {
+                    created_ts: if record.created_ts > 0 {
+                        record.created_ts
+                    } else {
+                        created_ts
+                    },
+                    checksum: Some(record.compute_checksum()),
+                    ..record.clone()
+                };
+                insert_event(&mut *tx, &ShortUrlEvent::Add(record)).await?;
+            }
+            if let Err(e) = tx.commit().await {
+                log::error!("[SQLITE] commit short_urls batch add error: {}", e);
+                return Err(e.into());
             }
-            Err(e) => Err(Error::SqlxError(e)),
+            inserted += added.len();
         }
+        Ok(BatchAddResult {
+            inserted,
+            skipped: records.len() - inserted,
+        })
+    }
+
+    /// Removes a short URL entry by org_id and short_id
+    async fn remove(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        delete_record(&mut *tx, org_id, short_id).await?;
+        let event = ShortUrlEvent::Remove {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        drop(client);
+
+        Ok(())
+    }
+
+    /// Updates the original_url of a short URL entry
+    async fn update(&self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        update_url(&mut *tx, org_id, short_id, new_url).await?;
+        let event = ShortUrlEvent::Update {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+            original_url: new_url.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        drop(client);
+
+        Ok(())
     }
 
-    /// Removes a short URL entry by short_id
-    async fn remove(&self, short_id: &str) -> Result<()> {
+    async fn rename(&self, org_id: &str, old_short_id: &str, new_short_id: &str) -> Result<()> {
         let client = CLIENT_RW.clone();
         let client = client.lock().await;
-        let query = r#"DELETE FROM short_urls WHERE short_id = $1;"#;
-        sqlx::query(query).bind(short_id).execute(&*client).await?;
+        let mut tx = client.begin().await?;
+        rename_record(&mut tx, org_id, old_short_id, new_short_id).await?;
+        let event = ShortUrlEvent::Rename {
+            org_id: org_id.to_string(),
+            short_id: old_short_id.to_string(),
+            new_short_id: new_short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        drop(client);
+
+        Ok(())
+    }
+
+    async fn set_pinned(&self, org_id: &str, short_id: &str, pinned: bool) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let query = format!(
+            r#"UPDATE {table} SET pinned = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+        );
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        let ret = sqlx::query(&query)
+            .bind(pinned)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *tx)
+            .await?;
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        let event = ShortUrlEvent::SetPinned {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+            pinned,
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
         drop(client);
 
         Ok(())
     }
 
-    /// Retrieves a short URL entry by short_id
-    async fn get(&self, short_id: &str) -> Result<ShortUrlRecord> {
+    /// Retrieves a short URL entry by org_id and short_id
+    async fn get(&self, org_id: &str, short_id: &str) -> Result<ShortUrlRecord> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RO.clone();
-        let query = r#"SELECT short_id, original_url FROM short_urls WHERE short_id = $1;"#;
-        let row = sqlx::query_as::<_, ShortUrlRecord>(query)
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL;"#
+        );
+        let row = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
             .bind(short_id)
-            .fetch_one(&client)
+            .fetch_optional(&client)
+            .await?;
+        row.ok_or_else(|| ShortUrlError::NotFound(short_id.to_string()))
+    }
+
+    async fn get_many(
+        &self,
+        org_id: &str,
+        short_ids: &[&str],
+    ) -> Result<HashMap<String, ShortUrlRecord>> {
+        if short_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RO.clone();
+        let query = format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = ? AND deleted_at IS NULL AND short_id IN ({})",
+            short_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+        );
+        let mut sql_query = sqlx::query_as::<_, ShortUrlRecord>(&query).bind(org_id);
+        for short_id in short_ids {
+            sql_query = sql_query.bind(short_id);
+        }
+        let rows = sql_query.fetch_all(&client).await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.short_id.clone(), row))
+            .collect())
+    }
+
+    /// Increments the click_count of a short URL entry
+    async fn increment_click_count(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = increment_clicks(&*client, org_id, short_id).await;
+        drop(client);
+
+        ret
+    }
+
+    /// Retrieves a short URL entry by original_url
+    async fn get_by_original_url(
+        &self,
+        org_id: &str,
+        original_url: &str,
+    ) -> Result<Option<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND original_url = $2 AND deleted_at IS NULL LIMIT 1;"#
+        );
+        let row = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .bind(original_url)
+            .fetch_optional(&client)
             .await?;
         Ok(row)
     }
 
-    /// Lists all short URL entries
-    async fn list(&self, limit: Option<i64>) -> Result<Vec<ShortUrlRecord>> {
+    /// Lists all short URL entries, newest first
+    /// starting after the `after_ts` (created_ts) cursor
+    async fn list(
+        &self,
+        org_id: Option<&str>,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        after_ts: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RO.clone();
-        let mut query =
-            r#"SELECT short_id, original_url FROM short_urls ORDER BY created_ts DESC"#.to_string();
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE deleted_at IS NULL"
+        ));
+        if let Some(org_id) = org_id {
+            query_builder.push(" AND org_id = ").push_bind(org_id);
+        }
+        if let Some(created_by) = created_by {
+            query_builder
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        if let Some(after_ts) = after_ts {
+            query_builder.push(" AND created_ts < ").push_bind(after_ts);
+        }
+        push_order_by(&mut query_builder, sort_by, sort_dir);
+        if let Some(limit) = limit {
+            query_builder.push(" LIMIT ").push_bind(limit);
+        }
 
-        if limit.is_some() {
-            query.push_str(" LIMIT $1");
+        let rows = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&client)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn stream(&self) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        // the query borrowed by the stream lives as long as the pool
+        static QUERY: Lazy<String> = Lazy::new(|| {
+            format!(
+                "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {} WHERE deleted_at IS NULL ORDER BY created_ts DESC",
+                TABLE_NAME.as_str()
+            )
+        });
+        Ok(sqlx::query_as::<_, ShortUrlRecord>(QUERY.as_str())
+            .fetch(&*CLIENT_RO)
+            .map_err(ShortUrlError::from)
+            .boxed())
+    }
+
+    async fn stream_by_org(
+        &self,
+        org_id: &str,
+    ) -> Result<BoxStream<'static, Result<ShortUrlRecord>>> {
+        // the query borrowed by the stream lives as long as the pool
+        static QUERY: Lazy<String> = Lazy::new(|| {
+            format!(
+                "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {} WHERE org_id = $1 AND deleted_at IS NULL ORDER BY created_ts DESC",
+                TABLE_NAME.as_str()
+            )
+        });
+        Ok(sqlx::query_as::<_, ShortUrlRecord>(QUERY.as_str())
+            .bind(org_id.to_string())
+            .fetch(&*CLIENT_RO)
+            .map_err(ShortUrlError::from)
+            .boxed())
+    }
+
+    async fn search(
+        &self,
+        org_id: &str,
+        url_pattern: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RO.clone();
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = "
+        ));
+        query_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL AND original_url LIKE ")
+            .push_bind(like_contains_pattern(url_pattern))
+            .push(" ESCAPE '!' ORDER BY created_ts DESC");
+        if let Some(limit) = limit {
+            query_builder.push(" LIMIT ").push_bind(limit);
         }
 
-        let query_builder = sqlx::query_as::<_, ShortUrlRecord>(&query);
-        let query_builder = if let Some(limit_value) = limit {
-            query_builder.bind(limit_value)
-        } else {
+        let rows = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&client)
+            .await?;
+        Ok(rows)
+    }
+
+    /// List a page of the org's short urls newest first with the total count, both read in one
+    /// transaction so the count matches the page
+    async fn list_with_count(
+        &self,
+        org_id: &str,
+        created_by: Option<&str>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+        sort_by: SortBy,
+        sort_dir: SortDir,
+    ) -> Result<(Vec<ShortUrlRecord>, i64)> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RO.clone();
+        let mut tx = client.begin().await?;
+        let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = "
+        ));
+        query_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL");
+        if let Some(created_by) = created_by {
             query_builder
-        };
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        push_order_by(&mut query_builder, sort_by, sort_dir);
+        // OFFSET is only valid after a LIMIT
+        if limit.is_some() || offset.is_some() {
+            query_builder
+                .push(" LIMIT ")
+                .push_bind(limit.unwrap_or(i64::MAX));
+        }
+        if let Some(offset) = offset {
+            query_builder.push(" OFFSET ").push_bind(offset);
+        }
+        let records = query_builder
+            .build_query_as::<ShortUrlRecord>()
+            .fetch_all(&mut *tx)
+            .await?;
+        let mut count_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT COUNT(*) FROM {table} WHERE org_id = "));
+        count_builder
+            .push_bind(org_id)
+            .push(" AND deleted_at IS NULL");
+        if let Some(created_by) = created_by {
+            count_builder
+                .push(" AND created_by = ")
+                .push_bind(created_by);
+        }
+        let total: i64 = count_builder
+            .build_query_scalar()
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok((records, total))
+    }
+
+    async fn list_by_resource(
+        &self,
+        resource_type: &str,
+        resource_id: &str,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE resource_type = $1 AND resource_id = $2 AND deleted_at IS NULL ORDER BY created_ts DESC;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(resource_type)
+            .bind(resource_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
 
-        let rows = query_builder.fetch_all(&client).await?;
+    async fn list_by_tag(
+        &self,
+        key: &str,
+        value: &str,
+        limit: Option<i64>,
+    ) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE json_extract(tags, $1) = $2 AND deleted_at IS NULL ORDER BY created_ts DESC LIMIT $3;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(tag_json_path(key))
+            .bind(value)
+            .bind(limit.unwrap_or(i64::MAX))
+            .fetch_all(&pool)
+            .await?;
         Ok(rows)
     }
 
     /// Checks if a short_id exists in the database
-    async fn contains(&self, short_id: &str) -> Result<bool> {
+    async fn contains(&self, org_id: &str, short_id: &str) -> Result<bool> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RO.clone();
-        let query = r#"SELECT 1 FROM short_urls WHERE short_id = $1"#;
-        let rows = sqlx::query(query).bind(short_id).fetch_all(&client).await?;
-        Ok(rows.is_empty())
+        let query = format!(
+            r#"SELECT 1 FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL"#
+        );
+        let rows = sqlx::query(&query)
+            .bind(org_id)
+            .bind(short_id)
+            .fetch_all(&client)
+            .await?;
+        Ok(!rows.is_empty())
     }
 
     /// Returns the number of entries in the short_urls table
     async fn len(&self) -> usize {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RO.clone();
 
-        let result = match sqlx::query(r#"SELECT COUNT(*) as num FROM short_urls;"#)
-            .fetch_one(&client)
-            .await
+        let result = match sqlx::query(&format!(
+            r#"SELECT COUNT(*) as num FROM {table} WHERE deleted_at IS NULL;"#
+        ))
+        .fetch_one(&client)
+        .await
         {
             Ok(row) => row,
             Err(e) => {
@@ -179,17 +868,29 @@ impl ShortUrl for SqliteShortUrl {
     }
 
     /// Clears all entries from the short_urls table
-    async fn clear(&self) -> Result<()> {
+    async fn clear(&self) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RW.clone();
         let client = client.lock().await;
+        let mut tx = client.begin().await?;
 
-        sqlx::query(r#"DELETE FROM short_urls;"#)
-            .execute(&*client)
-            .await?;
+        let deleted: Vec<(String, String)> = sqlx::query_as(&format!(
+            r#"DELETE FROM {table} RETURNING org_id, short_id;"#
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+        for (org_id, short_id) in &deleted {
+            let event = ShortUrlEvent::BatchRemove {
+                org_id: org_id.clone(),
+                short_id: short_id.clone(),
+            };
+            insert_event(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
 
         drop(client);
 
-        Ok(())
+        Ok(deleted.len() as u64)
     }
 
     /// Checks if the short_urls table is empty
@@ -197,36 +898,228 @@ impl ShortUrl for SqliteShortUrl {
         self.len().await == 0
     }
 
-    async fn get_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<Vec<String>> {
+    async fn ping(&self) -> Result<()> {
+        let client = CLIENT_RO.clone();
+        sqlx::query("SELECT 1").execute(&client).await?;
+        Ok(())
+    }
+
+    async fn db_version(&self) -> Result<String> {
+        let client = CLIENT_RO.clone();
+        let version: String = sqlx::query_scalar("SELECT sqlite_version()")
+            .fetch_one(&client)
+            .await?;
+        Ok(version)
+    }
+
+    async fn get_expired(
+        &self,
+        org_id: Option<&str>,
+        expired_before: i64,
+        limit: Option<i64>,
+        strategy: EvictionStrategy,
+    ) -> Result<Vec<(String, String)>> {
+        let table = TABLE_NAME.as_str();
         let client = CLIENT_RO.clone();
 
-        let mut query = r#"
-            SELECT short_id FROM short_urls
-            WHERE created_ts < $1
+        let order_by = match strategy {
+            EvictionStrategy::OldestFirst => "created_ts ASC",
+            EvictionStrategy::Random => "RANDOM()",
+        };
+        // scoped to one org this uses the (org_id, created_ts) index
+        let org_filter = if org_id.is_some() {
+            "org_id = $3 AND "
+        } else {
+            ""
+        };
+        let mut query = format!(
+            r#"
+            SELECT org_id, short_id FROM {table}
+            WHERE {org_filter}(created_ts < $1 OR expires_at < $2) AND pinned = 0
+            ORDER BY {order_by}
             "#
-        .to_string();
+        );
 
         if limit.is_some() {
-            query.push_str(" LIMIT $2");
+            query.push_str(if org_id.is_some() {
+                " LIMIT $4"
+            } else {
+                " LIMIT $3"
+            });
         }
 
-        let mut query = sqlx::query_as(&query).bind(expired_before);
+        let now = Utc::now().timestamp_micros();
+        let mut query = sqlx::query_as(&query).bind(expired_before).bind(now);
 
+        if let Some(org_id) = org_id {
+            query = query.bind(org_id);
+        }
         if let Some(limit_value) = limit {
             query = query.bind(limit_value);
         }
 
-        let expired_short_ids: Vec<(String,)> = query.fetch_all(&client).await?;
-        let expired_short_ids: Vec<String> = expired_short_ids
-            .into_iter()
-            .map(|(short_id,)| short_id)
-            .collect();
+        let expired_short_ids: Vec<(String, String)> = query.fetch_all(&client).await?;
         Ok(expired_short_ids)
     }
 
-    async fn batch_remove(&self, short_ids: Vec<String>) -> Result<()> {
+    async fn count_by_date_range(
+        &self,
+        org_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+        granularity: Granularity,
+    ) -> Result<Vec<(i64, i64)>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let bucket = format!(
+            "CAST(strftime('%s', {}) AS INTEGER) * 1000000",
+            date_bucket(granularity)
+        );
+        let query = format!(
+            "SELECT {bucket} AS bucket, COUNT(*) AS num FROM {table} WHERE org_id = $1 AND deleted_at IS NULL AND created_ts >= $2 AND created_ts < $3 GROUP BY bucket ORDER BY bucket"
+        );
+        let ret: Vec<(i64, i64)> = sqlx::query_as(&query)
+            .bind(org_id)
+            .bind(from_ts)
+            .bind(to_ts)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn top_by_clicks(&self, org_id: &str, limit: usize) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND deleted_at IS NULL ORDER BY click_count DESC LIMIT $2;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .bind(limit as i64)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn click_totals(&self, org_id: &str) -> Result<(i64, i64)> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT COUNT(*), COALESCE(SUM(click_count), 0) FROM {table} WHERE org_id = $1 AND deleted_at IS NULL;"#
+        );
+        let ret: (i64, i64) = sqlx::query_as(&query).bind(org_id).fetch_one(&pool).await?;
+        Ok(ret)
+    }
+
+    async fn count_by_org(&self, org_id: &str) -> Result<i64> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query =
+            format!(r#"SELECT COUNT(*) FROM {table} WHERE org_id = $1 AND deleted_at IS NULL;"#);
+        let ret: i64 = sqlx::query_scalar(&query)
+            .bind(org_id)
+            .fetch_one(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn list_namespaces(&self, org_id: &str) -> Result<Vec<String>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT DISTINCT namespace FROM {table} WHERE org_id = $1 AND namespace IS NOT NULL AND deleted_at IS NULL ORDER BY namespace;"#
+        );
+        let ret: Vec<String> = sqlx::query_scalar(&query)
+            .bind(org_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn get_due_for_notification(&self, now: i64) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE expiry_notify_at IS NOT NULL AND expiry_notify_at <= $1 AND notified = 0 AND pinned = 0 AND deleted_at IS NULL ORDER BY expiry_notify_at;"#
+        );
+        let ret = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(now)
+            .fetch_all(&pool)
+            .await?;
+        Ok(ret)
+    }
+
+    async fn mark_notified(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let query =
+            format!(r#"UPDATE {table} SET notified = 1 WHERE org_id = $1 AND short_id = $2;"#);
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = sqlx::query(&query)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&*client)
+            .await?;
+        drop(client);
+
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn sample_for_link_check(&self, limit: i64) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE deleted_at IS NULL ORDER BY RANDOM() LIMIT $1;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(limit)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn set_link_check_status(&self, org_id: &str, short_id: &str, status: i16) -> Result<()> {
+        let table = TABLE_NAME.as_str();
+        let query = format!(
+            r#"UPDATE {table} SET last_check_status = $1, link_rot_detected = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+        );
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = sqlx::query(&query)
+            .bind(status)
+            .bind(status >= 400)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&*client)
+            .await?;
+        drop(client);
+
+        if ret.rows_affected() == 0 {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn list_link_rot_candidates(&self, org_id: &str) -> Result<Vec<ShortUrlRecord>> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let query = format!(
+            r#"SELECT org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum, last_check_status, link_rot_detected FROM {table} WHERE org_id = $1 AND link_rot_detected = 1 AND deleted_at IS NULL ORDER BY created_ts DESC;"#
+        );
+        let rows = sqlx::query_as::<_, ShortUrlRecord>(&query)
+            .bind(org_id)
+            .fetch_all(&pool)
+            .await?;
+        Ok(rows)
+    }
+
+    async fn batch_remove(&self, short_ids: Vec<(String, String)>) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
         if short_ids.is_empty() {
-            return Ok(());
+            return Ok(0);
         }
         let client = CLIENT_RW.clone();
         let client = client.lock().await;
@@ -234,26 +1127,2520 @@ impl ShortUrl for SqliteShortUrl {
 
         let query = format!(
             "
-            DELETE FROM short_urls
-            WHERE short_id IN ({})
+            DELETE FROM {table}
+            WHERE (org_id, short_id) IN ({})
         ",
-            short_ids.iter().map(|_| "?").collect::<Vec<_>>().join(", ")
+            short_ids
+                .iter()
+                .map(|_| "(?, ?)")
+                .collect::<Vec<_>>()
+                .join(", ")
         );
 
         let mut sql_query = sqlx::query(&query);
-        for short_id in &short_ids {
-            sql_query = sql_query.bind(short_id);
+        for (org_id, short_id) in &short_ids {
+            sql_query = sql_query.bind(org_id).bind(short_id);
         }
 
         let result = sql_query.execute(&mut *tx).await;
 
         if result.is_ok() {
+            for (org_id, short_id) in short_ids {
+                let event = ShortUrlEvent::BatchRemove { org_id, short_id };
+                insert_event(&mut *tx, &event).await?;
+            }
             tx.commit().await?;
         }
 
         // release lock
         drop(client);
 
+        Ok(result?.rows_affected())
+    }
+
+    async fn restore(&self, org_id: &str, short_id: &str) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        if !restore_record(&mut tx, org_id, short_id).await? {
+            return Err(ShortUrlError::NotFound(short_id.to_string()));
+        }
+        let event = ShortUrlEvent::Restore {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+        };
+        insert_event(&mut *tx, &event).await?;
+        tx.commit().await?;
+        drop(client);
+
+        Ok(())
+    }
+
+    async fn hard_delete_expired_soft_deleted(&self, older_than: i64) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        let query = format!(
+            r#"DELETE FROM {table} WHERE deleted_at IS NOT NULL AND deleted_at < $1 RETURNING org_id, short_id;"#
+        );
+        let deleted: Vec<(String, String)> = sqlx::query_as(&query)
+            .bind(older_than)
+            .fetch_all(&mut *tx)
+            .await?;
+        for (org_id, short_id) in &deleted {
+            let event = ShortUrlEvent::BatchRemove {
+                org_id: org_id.clone(),
+                short_id: short_id.clone(),
+            };
+            insert_event(&mut *tx, &event).await?;
+        }
+        tx.commit().await?;
+        drop(client);
+
+        Ok(deleted.len() as u64)
+    }
+
+    async fn archive_expired(&self, expired_before: i64, limit: Option<i64>) -> Result<u64> {
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        let now = Utc::now().timestamp_micros();
+        // the rows are picked once so the copy and the delete see the same ones
+        let mut select: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "SELECT id, org_id, short_id FROM {table} WHERE (created_ts < "
+        ));
+        select
+            .push_bind(expired_before)
+            .push(" OR expires_at < ")
+            .push_bind(now)
+            .push(") AND pinned = 0 ORDER BY created_ts ASC");
+        if let Some(limit) = limit {
+            select.push(" LIMIT ").push_bind(limit);
+        }
+        let rows: Vec<(i64, String, String)> = select.build_query_as().fetch_all(&mut *tx).await?;
+        let archived = move_to_archive(&mut tx, &rows, now).await?;
+        tx.commit().await?;
+
+        // release lock
+        drop(client);
+
+        Ok(archived)
+    }
+
+    async fn archive(&self, short_ids: &[(String, String)]) -> Result<u64> {
+        if short_ids.is_empty() {
+            return Ok(0);
+        }
+        let table = TABLE_NAME.as_str();
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = client.begin().await?;
+        let now = Utc::now().timestamp_micros();
+        let mut archived = 0;
+        for short_ids in short_ids.chunks(500) {
+            let mut select: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+                "SELECT id, org_id, short_id FROM {table} WHERE pinned = 0 AND (org_id, short_id) IN ("
+            ));
+            let mut keys = select.separated(", ");
+            for (org_id, short_id) in short_ids {
+                keys.push("(")
+                    .push_bind_unseparated(org_id)
+                    .push_unseparated(", ")
+                    .push_bind_unseparated(short_id)
+                    .push_unseparated(")");
+            }
+            select.push(")");
+            let rows: Vec<(i64, String, String)> =
+                select.build_query_as().fetch_all(&mut *tx).await?;
+            archived += move_to_archive(&mut tx, &rows, now).await?;
+        }
+        tx.commit().await?;
+
+        // release lock
+        drop(client);
+
+        Ok(archived)
+    }
+
+    async fn with_transaction<F, T>(&self, f: F) -> Result<T>
+    where
+        Self: Sized,
+        F: for<'t> FnOnce(&'t mut ShortUrlTx) -> BoxFuture<'t, Result<T>> + Send,
+        T: Send,
+    {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let mut tx = ShortUrlTx::Sqlite(client.begin().await?);
+        let ret = f(&mut tx).await;
+        let ret = tx.finish(ret).await;
+
+        // release lock
+        drop(client);
+
+        ret
+    }
+
+    async fn replay_from_events(&self, from_ts: i64) -> Result<usize> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let rows = sqlx::query_as::<_, (String, String, String, String)>(&format!(
+            r#"SELECT event_type, org_id, short_id, payload FROM {EVENTS_TABLE} WHERE occurred_at > $1 ORDER BY id;"#
+        ))
+        .bind(from_ts)
+        .fetch_all(&*client)
+        .await?;
+        let replayed = rows.len();
+        let mut tx = client.begin().await?;
+        for (event_type, org_id, short_id, payload) in rows {
+            let event = ShortUrlEvent::from_row(&event_type, org_id, short_id, &payload)?;
+            apply_event(&mut tx, &event).await?;
+        }
+        tx.commit().await?;
+
+        // release lock
+        drop(client);
+
+        Ok(replayed)
+    }
+
+    async fn add_access_log(&self, entries: &[AccessLogEntry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        for entries in entries.chunks(100) {
+            let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+                "INSERT INTO {ACCESS_LOG_TABLE} (org_id, short_id, accessed_at, user_agent_hash, country_code)"
+            ));
+            query_builder.push_values(entries, |mut b, entry| {
+                b.push_bind(&entry.org_id)
+                    .push_bind(&entry.short_id)
+                    .push_bind(entry.accessed_at)
+                    .push_bind(&entry.user_agent_hash)
+                    .push_bind(&entry.country_code);
+            });
+            query_builder.build().execute(&*client).await?;
+        }
+
+        // release lock
+        drop(client);
+
+        Ok(())
+    }
+
+    async fn get_access_log(
+        &self,
+        org_id: &str,
+        short_id: &str,
+        from_ts: i64,
+        to_ts: i64,
+    ) -> Result<Vec<AccessLogEntry>> {
+        let pool = CLIENT_RO.clone();
+        let entries = sqlx::query_as::<_, AccessLogEntry>(&format!(
+            r#"SELECT id, org_id, short_id, accessed_at, user_agent_hash, country_code FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND short_id = $2 AND accessed_at >= $3 AND accessed_at < $4 ORDER BY accessed_at, id;"#
+        ))
+        .bind(org_id)
+        .bind(short_id)
+        .bind(from_ts)
+        .bind(to_ts)
+        .fetch_all(&pool)
+        .await?;
+        Ok(entries)
+    }
+
+    async fn purge_access_log(&self, older_than: i64) -> Result<u64> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = sqlx::query(&format!(
+            r#"DELETE FROM {ACCESS_LOG_TABLE} WHERE accessed_at < $1;"#
+        ))
+        .bind(older_than)
+        .execute(&*client)
+        .await?;
+
+        // release lock
+        drop(client);
+
+        Ok(ret.rows_affected())
+    }
+
+    async fn get_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        created_after: i64,
+    ) -> Result<Option<IdempotencyKey>> {
+        let pool = CLIENT_RO.clone();
+        let idempotency_key: Option<IdempotencyKey> = sqlx::query_as(&format!(
+            r#"SELECT short_id, body_hash FROM {IDEMPOTENCY_KEYS_TABLE} WHERE org_id = $1 AND idempotency_key = $2 AND created_at >= $3;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .bind(created_after)
+        .fetch_optional(&pool)
+        .await?;
+        Ok(idempotency_key)
+    }
+
+    async fn reserve_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        body_hash: &str,
+        expired_before: i64,
+    ) -> Result<bool> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = sqlx::query(&format!(
+            r#"INSERT INTO {IDEMPOTENCY_KEYS_TABLE} (org_id, idempotency_key, body_hash, created_at) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (org_id, idempotency_key) DO UPDATE SET short_id = NULL, body_hash = excluded.body_hash, created_at = excluded.created_at
+                WHERE {IDEMPOTENCY_KEYS_TABLE}.created_at < $5;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .bind(body_hash)
+        .bind(Utc::now().timestamp_micros())
+        .bind(expired_before)
+        .execute(&*client)
+        .await?;
+
+        // release lock
+        drop(client);
+
+        Ok(ret.rows_affected() == 1)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        org_id: &str,
+        idempotency_key: &str,
+        short_id: &str,
+    ) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        sqlx::query(&format!(
+            r#"UPDATE {IDEMPOTENCY_KEYS_TABLE} SET short_id = $1 WHERE org_id = $2 AND idempotency_key = $3;"#
+        ))
+        .bind(short_id)
+        .bind(org_id)
+        .bind(idempotency_key)
+        .execute(&*client)
+        .await?;
+
+        // release lock
+        drop(client);
+
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, org_id: &str, idempotency_key: &str) -> Result<()> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        sqlx::query(&format!(
+            r#"DELETE FROM {IDEMPOTENCY_KEYS_TABLE} WHERE org_id = $1 AND idempotency_key = $2 AND short_id IS NULL;"#
+        ))
+        .bind(org_id)
+        .bind(idempotency_key)
+        .execute(&*client)
+        .await?;
+
+        // release lock
+        drop(client);
+
         Ok(())
     }
+
+    async fn purge_idempotency_keys(&self, older_than: i64) -> Result<u64> {
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        let ret = sqlx::query(&format!(
+            r#"DELETE FROM {IDEMPOTENCY_KEYS_TABLE} WHERE created_at < $1;"#
+        ))
+        .bind(older_than)
+        .execute(&*client)
+        .await?;
+
+        // release lock
+        drop(client);
+
+        Ok(ret.rows_affected())
+    }
+
+    async fn generate_digest(
+        &self,
+        org_id: &str,
+        period_start: i64,
+        period_end: i64,
+    ) -> Result<ShortUrlDigestReport> {
+        let table = TABLE_NAME.as_str();
+        let pool = CLIENT_RO.clone();
+        let new_urls: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {table} WHERE org_id = $1 AND created_ts >= $2 AND created_ts < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let total_clicks: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND accessed_at >= $2 AND accessed_at < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let expired_removed: i64 = sqlx::query_scalar(&format!(
+            r#"SELECT COUNT(*) FROM {EVENTS_TABLE} WHERE org_id = $1 AND event_type = 'batch_remove' AND occurred_at >= $2 AND occurred_at < $3;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .fetch_one(&pool)
+        .await?;
+        let top: Vec<(String, i64)> = sqlx::query_as(&format!(
+            r#"SELECT short_id, COUNT(*) AS clicks FROM {ACCESS_LOG_TABLE} WHERE org_id = $1 AND accessed_at >= $2 AND accessed_at < $3 GROUP BY short_id ORDER BY clicks DESC, short_id LIMIT $4;"#
+        ))
+        .bind(org_id)
+        .bind(period_start)
+        .bind(period_end)
+        .bind(DIGEST_TOP_URLS)
+        .fetch_all(&pool)
+        .await?;
+        Ok(ShortUrlDigestReport {
+            new_urls: new_urls as u64,
+            total_clicks: total_clicks as u64,
+            expired_removed: expired_removed as u64,
+            top_5_by_clicks: top
+                .into_iter()
+                .map(|(short_id, clicks)| (short_id, clicks as u64))
+                .collect(),
+        })
+    }
+}
+
+// the write queries below are shared by `ShortUrl` and `ShortUrlTx`, `executor` is either a
+// pool or an open transaction
+
+pub(super) async fn insert_record<'c, E>(
+    executor: E,
+    record: &ShortUrlRecord,
+) -> Result<ShortUrlRecord>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let table = TABLE_NAME.as_str();
+    let created_ts = Utc::now().timestamp_micros();
+    let query = format!(
+        r#"INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17);"#
+    );
+    let result = sqlx::query(&query)
+        .bind(&record.org_id)
+        .bind(&record.short_id)
+        .bind(&record.original_url)
+        .bind(created_ts)
+        .bind(record.expires_at)
+        .bind(record.permanent)
+        .bind(&record.created_by)
+        .bind(&record.alias_of)
+        .bind(&record.resource_type)
+        .bind(&record.resource_id)
+        .bind(record.tags_json())
+        .bind(record.pinned)
+        .bind(record.expiry_notify_at)
+        .bind(&record.namespace)
+        .bind(record.acl_json())
+        .bind(record.pipeline_json())
+        .bind(record.compute_checksum())
+        .execute(executor)
+        .await;
+    match result {
+        Ok(_) => Ok(ShortUrlRecord {
+            created_ts,
+            click_count: 0,
+            checksum: Some(record.compute_checksum()),
+            ..record.clone()
+        }),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(ShortUrlError::Conflict(record.short_id.clone()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub(super) async fn delete_record<'c, E>(executor: E, org_id: &str, short_id: &str) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET deleted_at = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+    );
+    sqlx::query(&query)
+        .bind(Utc::now().timestamp_micros())
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub(super) async fn update_url<'c, E>(
+    executor: E,
+    org_id: &str,
+    short_id: &str,
+    new_url: &str,
+) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET original_url = $1, checksum = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+    );
+    let ret = sqlx::query(&query)
+        .bind(new_url)
+        .bind(checksum(short_id, new_url))
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    if ret.rows_affected() == 0 {
+        return Err(ShortUrlError::NotFound(short_id.to_string()));
+    }
+    Ok(())
+}
+
+pub(super) async fn increment_clicks<'c, E>(executor: E, org_id: &str, short_id: &str) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    let table = TABLE_NAME.as_str();
+    let query = format!(
+        r#"UPDATE {table} SET click_count = click_count + 1 WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL;"#
+    );
+    sqlx::query(&query)
+        .bind(org_id)
+        .bind(short_id)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+pub(super) async fn insert_event<'c, E>(executor: E, event: &ShortUrlEvent) -> Result<()>
+where
+    E: Executor<'c, Database = Sqlite>,
+{
+    if !event.is_logged() {
+        return Ok(());
+    }
+    let query = format!(
+        r#"INSERT INTO {EVENTS_TABLE} (event_type, org_id, short_id, payload, occurred_at) VALUES ($1, $2, $3, $4, $5);"#
+    );
+    sqlx::query(&query)
+        .bind(event.event_type())
+        .bind(event.org_id())
+        .bind(event.short_id())
+        .bind(event.payload())
+        .bind(Utc::now().timestamp_micros())
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+// copies `rows`, `(id, org_id, short_id)` picked in the open transaction, to
+// `ARCHIVE_TABLE` and deletes them, returns how many were moved
+async fn move_to_archive(
+    conn: &mut SqliteConnection,
+    rows: &[(i64, String, String)],
+    now: i64,
+) -> Result<u64> {
+    let table = TABLE_NAME.as_str();
+    let mut archived = 0;
+    for rows in rows.chunks(500) {
+        let mut insert: QueryBuilder<Sqlite> = QueryBuilder::new(format!(
+            "INSERT INTO {ARCHIVE_TABLE} ({ARCHIVE_COLUMNS}, archived_at) SELECT {ARCHIVE_COLUMNS}, "
+        ));
+        insert
+            .push_bind(now)
+            .push(format!(" FROM {table} WHERE id IN ("));
+        let mut ids = insert.separated(", ");
+        for (id, ..) in rows {
+            ids.push_bind(*id);
+        }
+        ids.push_unseparated(")");
+        insert.build().execute(&mut *conn).await?;
+
+        let mut delete: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("DELETE FROM {table} WHERE id IN ("));
+        let mut ids = delete.separated(", ");
+        for (id, ..) in rows {
+            ids.push_bind(*id);
+        }
+        ids.push_unseparated(")");
+        archived += delete.build().execute(&mut *conn).await?.rows_affected();
+
+        for (_, org_id, short_id) in rows {
+            let event = ShortUrlEvent::BatchRemove {
+                org_id: org_id.clone(),
+                short_id: short_id.clone(),
+            };
+            insert_event(&mut *conn, &event).await?;
+        }
+    }
+    Ok(archived)
+}
+
+// re-applies a logged change without logging it again, changes to records deleted for good
+// since then do nothing. An add over a stored record only overwrites its settings, the clicks
+// are never logged so the stored counters are kept
+async fn apply_event(conn: &mut SqliteConnection, event: &ShortUrlEvent) -> Result<()> {
+    let table = TABLE_NAME.as_str();
+    match event {
+        ShortUrlEvent::Add(record) => {
+            let query = format!(
+                r#"INSERT INTO {table} (org_id, short_id, original_url, created_ts, expires_at, click_count, permanent, created_by, alias_of, resource_type, resource_id, tags, pinned, expiry_notify_at, namespace, acl, pipeline, checksum) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+                ON CONFLICT (org_id, short_id) DO UPDATE SET original_url = excluded.original_url, created_ts = excluded.created_ts, expires_at = excluded.expires_at, permanent = excluded.permanent, created_by = excluded.created_by, alias_of = excluded.alias_of, resource_type = excluded.resource_type, resource_id = excluded.resource_id, tags = excluded.tags, pinned = excluded.pinned, expiry_notify_at = excluded.expiry_notify_at, namespace = excluded.namespace, acl = excluded.acl, pipeline = excluded.pipeline, checksum = excluded.checksum, deleted_at = NULL;"#
+            );
+            sqlx::query(&query)
+                .bind(&record.org_id)
+                .bind(&record.short_id)
+                .bind(&record.original_url)
+                .bind(record.created_ts)
+                .bind(record.expires_at)
+                .bind(record.click_count)
+                .bind(record.permanent)
+                .bind(&record.created_by)
+                .bind(&record.alias_of)
+                .bind(&record.resource_type)
+                .bind(&record.resource_id)
+                .bind(record.tags_json())
+                .bind(record.pinned)
+                .bind(record.expiry_notify_at)
+                .bind(&record.namespace)
+                .bind(record.acl_json())
+                .bind(record.pipeline_json())
+                .bind(record.compute_checksum())
+                .execute(&mut *conn)
+                .await?;
+        }
+        ShortUrlEvent::Update {
+            org_id,
+            short_id,
+            original_url,
+        } => match update_url(&mut *conn, org_id, short_id, original_url).await {
+            Ok(()) | Err(ShortUrlError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        },
+        ShortUrlEvent::Rename {
+            org_id,
+            short_id,
+            new_short_id,
+        } => {
+            let renamed: Option<i64> = sqlx::query_scalar(&format!(
+                r#"SELECT id FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+            ))
+            .bind(org_id)
+            .bind(new_short_id)
+            .fetch_optional(&mut *conn)
+            .await?;
+            if renamed.is_some() {
+                // replayed over a store that holds the rename already, the record the replayed
+                // add put back under the old short_id is dropped
+                sqlx::query(&format!(
+                    r#"DELETE FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+                ))
+                .bind(org_id)
+                .bind(short_id)
+                .execute(&mut *conn)
+                .await?;
+            } else {
+                match rename_record(&mut *conn, org_id, short_id, new_short_id).await {
+                    Ok(()) | Err(ShortUrlError::NotFound(_)) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        ShortUrlEvent::SetPinned {
+            org_id,
+            short_id,
+            pinned,
+        } => {
+            sqlx::query(&format!(
+                r#"UPDATE {table} SET pinned = $1 WHERE org_id = $2 AND short_id = $3 AND deleted_at IS NULL;"#
+            ))
+            .bind(pinned)
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        ShortUrlEvent::Remove { org_id, short_id } => {
+            delete_record(&mut *conn, org_id, short_id).await?
+        }
+        ShortUrlEvent::BatchRemove { org_id, short_id } => {
+            sqlx::query(&format!(
+                r#"DELETE FROM {table} WHERE org_id = $1 AND short_id = $2;"#
+            ))
+            .bind(org_id)
+            .bind(short_id)
+            .execute(&mut *conn)
+            .await?;
+        }
+        ShortUrlEvent::Restore { org_id, short_id } => {
+            restore_record(&mut *conn, org_id, short_id).await?;
+        }
+    }
+    Ok(())
+}
+
+// the checksum covers the short_id, it is recomputed from the stored original_url
+async fn rename_record(
+    conn: &mut SqliteConnection,
+    org_id: &str,
+    old_short_id: &str,
+    new_short_id: &str,
+) -> Result<()> {
+    let table = TABLE_NAME.as_str();
+    let original_url: Option<String> = sqlx::query_scalar(&format!(
+        r#"SELECT original_url FROM {table} WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NULL;"#
+    ))
+    .bind(org_id)
+    .bind(old_short_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+    let Some(original_url) = original_url else {
+        return Err(ShortUrlError::NotFound(old_short_id.to_string()));
+    };
+    let query = format!(
+        r#"UPDATE {table} SET short_id = $1, checksum = $2 WHERE org_id = $3 AND short_id = $4 AND deleted_at IS NULL;"#
+    );
+    let ret = sqlx::query(&query)
+        .bind(new_short_id)
+        .bind(checksum(new_short_id, &original_url))
+        .bind(org_id)
+        .bind(old_short_id)
+        .execute(&mut *conn)
+        .await;
+
+    // the unique (org_id, short_id) index rejects a taken new_short_id
+    match ret {
+        Ok(r) if r.rows_affected() == 0 => Err(ShortUrlError::NotFound(old_short_id.to_string())),
+        Ok(_) => Ok(()),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+            Err(ShortUrlError::Conflict(new_short_id.to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// returns whether a soft deleted record was restored
+async fn restore_record(conn: &mut SqliteConnection, org_id: &str, short_id: &str) -> Result<bool> {
+    let table = TABLE_NAME.as_str();
+    let ret = sqlx::query(&format!(
+        r#"UPDATE {table} SET deleted_at = NULL WHERE org_id = $1 AND short_id = $2 AND deleted_at IS NOT NULL;"#
+    ))
+    .bind(org_id)
+    .bind(short_id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(ret.rows_affected() > 0)
+}
+
+// strftime expression truncating created_ts to the start of its bucket
+fn date_bucket(granularity: Granularity) -> &'static str {
+    match granularity {
+        Granularity::Hour => "strftime('%Y-%m-%d %H:00:00', created_ts / 1000000, 'unixepoch')",
+        Granularity::Day => "strftime('%Y-%m-%d', created_ts / 1000000, 'unixepoch')",
+        Granularity::Week => {
+            "strftime('%Y-%m-%d', created_ts / 1000000, 'unixepoch', '-6 days', 'weekday 1')"
+        }
+    }
+}
+
+// tables created before the version tracking start at version 0 and run every step, sqlite
+// does not enforce the VARCHAR length so short_id needs no change
+async fn migrate_to(client: &Pool<Sqlite>, table: &str, version: i64) -> Result<()> {
+    match version {
+        1 => {}
+        // create column expires_at for old version <= 0.12.0
+        2 => add_column(client, table, "expires_at", "BIGINT").await?,
+        3 => add_column(client, table, "click_count", "BIGINT NOT NULL DEFAULT 0").await?,
+        4 => add_column(client, table, "permanent", "BOOLEAN NOT NULL DEFAULT false").await?,
+        // create column org_id for old version <= 0.12.0, existing rows get an empty org_id
+        5 => add_column(client, table, "org_id", "VARCHAR(256) NOT NULL DEFAULT ''").await?,
+        6 => add_column(client, table, "created_by", "VARCHAR(512)").await?,
+        7 => add_column(client, table, "alias_of", "VARCHAR(64)").await?,
+        8 => add_column(client, table, "deleted_at", "BIGINT").await?,
+        9 => add_column(client, table, "resource_type", "VARCHAR(64)").await?,
+        10 => add_column(client, table, "resource_id", "VARCHAR(256)").await?,
+        11 => add_column(client, table, "tags", "TEXT").await?,
+        12 => add_column(client, table, "pinned", "BOOLEAN NOT NULL DEFAULT false").await?,
+        13 => add_column(client, table, "expiry_notify_at", "BIGINT").await?,
+        14 => add_column(client, table, "notified", "BOOLEAN NOT NULL DEFAULT false").await?,
+        15 => add_column(client, table, "namespace", "VARCHAR(32)").await?,
+        16 => add_column(client, table, "acl", "TEXT").await?,
+        17 => add_column(client, table, "pipeline", "TEXT").await?,
+        18 => add_column(client, table, "checksum", "VARCHAR(16)").await?,
+        19 => add_column(client, table, "last_check_status", "SMALLINT").await?,
+        20 => {
+            add_column(
+                client,
+                table,
+                "link_rot_detected",
+                "BOOLEAN NOT NULL DEFAULT false",
+            )
+            .await?
+        }
+        _ => {
+            return Err(sqlx::Error::Configuration(
+                format!("unknown short url schema version {version}").into(),
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+async fn add_column(
+    client: &Pool<Sqlite>,
+    table: &str,
+    column: &str,
+    data_type: &str,
+) -> Result<()> {
+    // Attempt to add the column, ignoring the error if the column already exists
+    let alter_sql = format!("ALTER TABLE {table} ADD COLUMN {column} {data_type};");
+    if let Err(e) = sqlx::query(&alter_sql).execute(client).await {
+        if !e.to_string().contains("duplicate column name") {
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use config::meta::short_url::UrlTransform;
+
+    use super::*;
+    use crate::short_url::{
+        generate_short_id, id, merge_with, MergeResult, MergeStrategy, ADD_OR_GET_MAX_ATTEMPTS,
+    };
+
+    /// Delete for good, `remove` only soft deletes and the short_id could not be added again
+    async fn purge(short_url: &SqliteShortUrl, org_id: &str, short_id: &str) {
+        short_url
+            .batch_remove(vec![(org_id.to_string(), short_id.to_string())])
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_add() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let short_ids: Vec<(String, String)> = (0..50)
+            .map(|i| ("default".to_string(), format!("concurrent_add_{i}")))
+            .collect();
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+
+        let tasks = (0..50).map(|i| {
+            tokio::spawn(async move {
+                let record = ShortUrlRecord::new(
+                    "default",
+                    &format!("concurrent_add_{i}"),
+                    &format!("https://example.com/concurrent/{i}"),
+                );
+                SqliteShortUrl::new().add(&record).await
+            })
+        });
+        for ret in futures::future::join_all(tasks).await {
+            assert!(ret.unwrap().is_ok());
+        }
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_expired_order() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let short_ids: Vec<(String, String)> = (1..=3)
+            .map(|i| ("default".to_string(), format!("expired_order_{i}")))
+            .collect();
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+
+        let records: Vec<ShortUrlRecord> = [3, 1, 2]
+            .into_iter()
+            .map(|i| {
+                let mut record = ShortUrlRecord::new(
+                    "default",
+                    &format!("expired_order_{i}"),
+                    &format!("https://example.com/expired/{i}"),
+                );
+                record.created_ts = i;
+                record
+            })
+            .collect();
+        short_url.batch_add(&records).await.unwrap();
+
+        let expired = short_url
+            .get_expired(None, 4, Some(3), EvictionStrategy::OldestFirst)
+            .await
+            .unwrap();
+        assert_eq!(expired, short_ids);
+        let expired = short_url
+            .get_expired(Some("default"), 4, Some(3), EvictionStrategy::OldestFirst)
+            .await
+            .unwrap();
+        assert_eq!(expired, short_ids);
+        let mut expired = short_url
+            .get_expired(None, 4, Some(3), EvictionStrategy::Random)
+            .await
+            .unwrap();
+        expired.sort();
+        assert_eq!(expired, short_ids);
+        let expired = short_url
+            .get_expired(Some("other"), 4, None, EvictionStrategy::OldestFirst)
+            .await
+            .unwrap();
+        assert!(expired.is_empty());
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_count_by_date_range() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        // 2024-01-01 00:10, 00:20, 01:05 and 2024-01-09 12:00 UTC
+        let created: [i64; 4] = [1704067800, 1704068400, 1704071100, 1704801600];
+        let records: Vec<ShortUrlRecord> = created
+            .iter()
+            .enumerate()
+            .map(|(i, ts)| {
+                let mut record = ShortUrlRecord::new(
+                    "default",
+                    &format!("count_range_{i}"),
+                    &format!("https://example.com/count/{i}"),
+                );
+                record.created_ts = ts * 1_000_000;
+                record
+            })
+            .collect();
+        let short_ids: Vec<(String, String)> = records
+            .iter()
+            .map(|r| (r.org_id.clone(), r.short_id.clone()))
+            .collect();
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+        short_url.batch_add(&records).await.unwrap();
+
+        let from_ts = 1704067200 * 1_000_000;
+        let to_ts = 1704844800 * 1_000_000;
+        let hours = short_url
+            .count_by_date_range("default", from_ts, to_ts, Granularity::Hour)
+            .await
+            .unwrap();
+        assert_eq!(
+            hours,
+            vec![
+                (1704067200 * 1_000_000, 2),
+                (1704070800 * 1_000_000, 1),
+                (1704801600 * 1_000_000, 1)
+            ]
+        );
+        let days = short_url
+            .count_by_date_range("default", from_ts, to_ts, Granularity::Day)
+            .await
+            .unwrap();
+        assert_eq!(
+            days,
+            vec![(1704067200 * 1_000_000, 3), (1704758400 * 1_000_000, 1)]
+        );
+        let weeks = short_url
+            .count_by_date_range("default", from_ts, to_ts, Granularity::Week)
+            .await
+            .unwrap();
+        assert_eq!(
+            weeks,
+            vec![(1704067200 * 1_000_000, 3), (1704672000 * 1_000_000, 1)]
+        );
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_org_scoped_short_id() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let short_ids = vec![
+            ("org_a".to_string(), "org_scoped".to_string()),
+            ("org_b".to_string(), "org_scoped".to_string()),
+        ];
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+
+        for (org_id, short_id) in &short_ids {
+            let record =
+                ShortUrlRecord::new(org_id, short_id, &format!("https://example.com/{org_id}"));
+            short_url.add(&record).await.unwrap();
+        }
+        let record = short_url.get("org_a", "org_scoped").await.unwrap();
+        assert_eq!(record.original_url, "https://example.com/org_a");
+        let record = short_url.get("org_b", "org_scoped").await.unwrap();
+        assert_eq!(record.original_url, "https://example.com/org_b");
+
+        short_url.remove("org_a", "org_scoped").await.unwrap();
+        assert!(matches!(
+            short_url.get("org_a", "org_scoped").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        assert!(short_url.get("org_b", "org_scoped").await.is_ok());
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_get_created_ts() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "default", "created_ts").await;
+
+        let before = Utc::now().timestamp_micros();
+        let record = ShortUrlRecord::new("default", "created_ts", "https://example.com/created");
+        let added = short_url.add(&record).await.unwrap();
+        let after = Utc::now().timestamp_micros();
+
+        let record = short_url.get("default", "created_ts").await.unwrap();
+        assert!((before..=after).contains(&record.created_ts));
+        assert_eq!(added.created_ts, record.created_ts);
+        let by_url = short_url
+            .get_by_original_url("default", "https://example.com/created")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_url.created_ts, record.created_ts);
+
+        purge(&short_url, "default", "created_ts").await;
+    }
+
+    #[tokio::test]
+    async fn test_rename() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["rename_old", "rename_new", "rename_taken"] {
+            purge(&short_url, "default", short_id).await;
+        }
+        for short_id in ["rename_old", "rename_taken"] {
+            let record = ShortUrlRecord::new(
+                "default",
+                short_id,
+                &format!("https://example.com/{short_id}"),
+            );
+            short_url.add(&record).await.unwrap();
+        }
+
+        short_url
+            .rename("default", "rename_old", "rename_new")
+            .await
+            .unwrap();
+        assert!(!short_url.contains("default", "rename_old").await.unwrap());
+        assert_eq!(
+            short_url
+                .get("default", "rename_new")
+                .await
+                .unwrap()
+                .original_url,
+            "https://example.com/rename_old"
+        );
+        assert!(matches!(
+            short_url
+                .rename("default", "rename_old", "rename_other")
+                .await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        assert!(matches!(
+            short_url
+                .rename("default", "rename_new", "rename_taken")
+                .await,
+            Err(ShortUrlError::Conflict(_))
+        ));
+
+        for short_id in ["rename_new", "rename_taken"] {
+            purge(&short_url, "default", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["stream_1", "stream_2"] {
+            purge(&short_url, "default", short_id).await;
+            let record = ShortUrlRecord::new(
+                "default",
+                short_id,
+                &format!("https://example.com/{short_id}"),
+            );
+            short_url.add(&record).await.unwrap();
+        }
+        short_url.remove("default", "stream_2").await.unwrap();
+
+        // other tests share the table, only look at the records added here
+        let short_ids = short_url
+            .stream()
+            .await
+            .unwrap()
+            .try_filter_map(|r| async move {
+                Ok(r.short_id.starts_with("stream_").then_some(r.short_id))
+            })
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(short_ids, vec!["stream_1".to_string()]);
+
+        for short_id in ["stream_1", "stream_2"] {
+            purge(&short_url, "default", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_by_org() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for (org_id, short_id) in [("org_stream_a", "stream_a"), ("org_stream_b", "stream_b")] {
+            purge(&short_url, org_id, short_id).await;
+            let record =
+                ShortUrlRecord::new(org_id, short_id, &format!("https://example.com/{short_id}"));
+            short_url.add(&record).await.unwrap();
+        }
+
+        let short_ids = short_url
+            .stream_by_org("org_stream_a")
+            .await
+            .unwrap()
+            .map_ok(|r| r.short_id)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(short_ids, vec!["stream_a".to_string()]);
+
+        purge(&short_url, "org_stream_a", "stream_a").await;
+        purge(&short_url, "org_stream_b", "stream_b").await;
+    }
+
+    #[tokio::test]
+    async fn test_get_many() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["get_many_1", "get_many_2"] {
+            purge(&short_url, "default", short_id).await;
+            let record = ShortUrlRecord::new(
+                "default",
+                short_id,
+                &format!("https://example.com/{short_id}"),
+            );
+            short_url.add(&record).await.unwrap();
+        }
+
+        let records = short_url
+            .get_many("default", &["get_many_1", "get_many_2", "get_many_missing"])
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records["get_many_2"].original_url,
+            "https://example.com/get_many_2"
+        );
+        assert!(!records.contains_key("get_many_missing"));
+        assert!(
+            short_url
+                .get_many("other", &["get_many_1"])
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert!(short_url.get_many("default", &[]).await.unwrap().is_empty());
+
+        for short_id in ["get_many_1", "get_many_2"] {
+            purge(&short_url, "default", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_soft_delete() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "org_soft", "soft_delete").await;
+
+        let record = ShortUrlRecord::new("org_soft", "soft_delete", "https://example.com/soft");
+        short_url.add(&record).await.unwrap();
+        short_url.remove("org_soft", "soft_delete").await.unwrap();
+        assert!(matches!(
+            short_url.get("org_soft", "soft_delete").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        assert!(!short_url.contains("org_soft", "soft_delete").await.unwrap());
+        assert!(
+            short_url
+                .list(
+                    Some("org_soft"),
+                    None,
+                    None,
+                    None,
+                    SortBy::default(),
+                    SortDir::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        // the row is still there and keeps its short_id
+        assert!(matches!(
+            short_url.add(&record).await,
+            Err(ShortUrlError::Conflict(_))
+        ));
+
+        short_url.restore("org_soft", "soft_delete").await.unwrap();
+        assert!(short_url.contains("org_soft", "soft_delete").await.unwrap());
+        assert!(matches!(
+            short_url.restore("org_soft", "soft_delete").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+
+        short_url.remove("org_soft", "soft_delete").await.unwrap();
+        short_url
+            .hard_delete_expired_soft_deleted(Utc::now().timestamp_micros() + 1)
+            .await
+            .unwrap();
+        assert!(matches!(
+            short_url.restore("org_soft", "soft_delete").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        short_url.add(&record).await.unwrap();
+
+        purge(&short_url, "org_soft", "soft_delete").await;
+    }
+
+    #[tokio::test]
+    async fn test_list_by_resource() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for (org_id, short_id) in [
+            ("org_a", "resource_a"),
+            ("org_b", "resource_b"),
+            ("org_a", "resource_c"),
+        ] {
+            purge(&short_url, org_id, short_id).await;
+        }
+
+        let mut linked_a = ShortUrlRecord::new("org_a", "resource_a", "https://example.com/d/1");
+        linked_a.resource_type = Some("dashboard".to_string());
+        linked_a.resource_id = Some("dashboard_1".to_string());
+        short_url.add(&linked_a).await.unwrap();
+        let mut linked_b = linked_a.clone();
+        linked_b.org_id = "org_b".to_string();
+        linked_b.short_id = "resource_b".to_string();
+        short_url.add(&linked_b).await.unwrap();
+        let mut other = ShortUrlRecord::new("org_a", "resource_c", "https://example.com/d/2");
+        other.resource_type = Some("dashboard".to_string());
+        other.resource_id = Some("dashboard_2".to_string());
+        short_url.add(&other).await.unwrap();
+
+        let mut found = short_url
+            .list_by_resource("dashboard", "dashboard_1")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| {
+                assert_eq!(r.resource_id.as_deref(), Some("dashboard_1"));
+                (r.org_id, r.short_id)
+            })
+            .collect::<Vec<_>>();
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                ("org_a".to_string(), "resource_a".to_string()),
+                ("org_b".to_string(), "resource_b".to_string()),
+            ]
+        );
+        assert!(
+            short_url
+                .list_by_resource("alert", "dashboard_1")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        short_url.remove("org_b", "resource_b").await.unwrap();
+        assert_eq!(
+            short_url
+                .list_by_resource("dashboard", "dashboard_1")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        for (org_id, short_id) in [
+            ("org_a", "resource_a"),
+            ("org_b", "resource_b"),
+            ("org_a", "resource_c"),
+        ] {
+            purge(&short_url, org_id, short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_by_tag() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for (org_id, short_id) in [("org_a", "tag_a"), ("org_b", "tag_b"), ("org_a", "tag_c")] {
+            purge(&short_url, org_id, short_id).await;
+        }
+
+        let mut tagged_a = ShortUrlRecord::new("org_a", "tag_a", "https://example.com/t/1");
+        tagged_a
+            .tags
+            .insert("campaign".to_string(), "q4-2024".to_string());
+        tagged_a
+            .tags
+            .insert("team".to_string(), "marketing".to_string());
+        short_url.add(&tagged_a).await.unwrap();
+        let mut tagged_b = tagged_a.clone();
+        tagged_b.org_id = "org_b".to_string();
+        tagged_b.short_id = "tag_b".to_string();
+        short_url.add(&tagged_b).await.unwrap();
+        short_url
+            .add(&ShortUrlRecord::new(
+                "org_a",
+                "tag_c",
+                "https://example.com/t/2",
+            ))
+            .await
+            .unwrap();
+
+        let found = short_url
+            .list_by_tag("campaign", "q4-2024", None)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found.iter().all(|r| r.tags == tagged_a.tags));
+        assert_eq!(
+            short_url
+                .list_by_tag("campaign", "q4-2024", Some(1))
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(
+            short_url
+                .list_by_tag("campaign", "q1-2025", None)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        let untagged = short_url.get("org_a", "tag_c").await.unwrap();
+        assert!(untagged.tags.is_empty());
+
+        for (org_id, short_id) in [("org_a", "tag_a"), ("org_b", "tag_b"), ("org_a", "tag_c")] {
+            purge(&short_url, org_id, short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_if_absent() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "default", "if_absent").await;
+
+        let record = ShortUrlRecord::new("default", "if_absent", "https://example.com/first");
+        assert!(short_url.add_if_absent(&record).await.unwrap());
+        let record = ShortUrlRecord::new("default", "if_absent", "https://example.com/second");
+        assert!(!short_url.add_if_absent(&record).await.unwrap());
+        let record = short_url.get("default", "if_absent").await.unwrap();
+        assert_eq!(record.original_url, "https://example.com/first");
+
+        purge(&short_url, "default", "if_absent").await;
+    }
+
+    #[tokio::test]
+    async fn test_with_transaction() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["tx_old", "tx_new", "tx_rollback"] {
+            purge(&short_url, "default", short_id).await;
+        }
+        let record = ShortUrlRecord::new("default", "tx_old", "https://example.com/tx");
+        short_url.add(&record).await.unwrap();
+
+        // replace tx_old by tx_new atomically
+        short_url
+            .with_transaction(|tx| {
+                Box::pin(async move {
+                    tx.remove("default", "tx_old").await?;
+                    let record = ShortUrlRecord::new("default", "tx_new", "https://example.com/tx");
+                    tx.add(&record).await
+                })
+            })
+            .await
+            .unwrap();
+        assert!(short_url.get("default", "tx_old").await.is_err());
+        assert!(short_url.get("default", "tx_new").await.is_ok());
+
+        // the add is rolled back with the failing update
+        let ret = short_url
+            .with_transaction(|tx| {
+                Box::pin(async move {
+                    let record =
+                        ShortUrlRecord::new("default", "tx_rollback", "https://example.com/tx");
+                    tx.add(&record).await?;
+                    tx.update("default", "tx_missing", "https://example.com/tx")
+                        .await
+                })
+            })
+            .await;
+        assert!(matches!(ret, Err(ShortUrlError::NotFound(_))));
+        assert!(short_url.get("default", "tx_rollback").await.is_err());
+
+        purge(&short_url, "default", "tx_new").await;
+    }
+
+    #[tokio::test]
+    async fn test_batch_remove_count() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["remove_a", "remove_b"] {
+            purge(&short_url, "default", short_id).await;
+            let record = ShortUrlRecord::new("default", short_id, "https://example.com/remove");
+            short_url.add(&record).await.unwrap();
+        }
+
+        let short_ids = ["remove_a", "remove_b", "remove_missing", "remove_gone"]
+            .iter()
+            .map(|short_id| ("default".to_string(), short_id.to_string()))
+            .collect();
+        assert_eq!(short_url.batch_remove(short_ids).await.unwrap(), 2);
+        assert_eq!(short_url.batch_remove(vec![]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_with_count() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let short_ids: Vec<(String, String)> = (0..5)
+            .map(|i| ("org_page".to_string(), format!("page_{i}")))
+            .collect();
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+
+        let records: Vec<ShortUrlRecord> = short_ids
+            .iter()
+            .enumerate()
+            .map(|(i, (org_id, short_id))| {
+                let mut record =
+                    ShortUrlRecord::new(org_id, short_id, &format!("https://example.com/{i}"));
+                record.created_ts = i as i64 + 1;
+                if i % 2 == 1 {
+                    record.created_by = Some("page_user@example.com".to_string());
+                }
+                record
+            })
+            .collect();
+        short_url.batch_add(&records).await.unwrap();
+
+        let (page, total) = short_url
+            .list_with_count(
+                "org_page",
+                None,
+                Some(2),
+                Some(2),
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total, 5);
+        let page: Vec<_> = page.into_iter().map(|r| r.short_id).collect();
+        assert_eq!(page, vec!["page_2", "page_1"]);
+
+        let (page, total) = short_url
+            .list_with_count(
+                "org_page",
+                None,
+                None,
+                Some(4),
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(page.len(), 1);
+
+        let (page, total) = short_url
+            .list_with_count(
+                "org_page",
+                Some("page_user@example.com"),
+                None,
+                None,
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        let page: Vec<_> = page.into_iter().map(|r| r.short_id).collect();
+        assert_eq!(page, vec!["page_3", "page_1"]);
+
+        let page = short_url
+            .list(
+                Some("org_page"),
+                Some("page_user@example.com"),
+                None,
+                Some(4),
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        let page: Vec<_> = page.into_iter().map(|r| r.short_id).collect();
+        assert_eq!(page, vec!["page_1"]);
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_health_report() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let report = short_url.health_report().await.unwrap();
+        assert!(report.add_latency_ms >= 0.0);
+        assert!(!report.db_version.is_empty());
+        // the sentinel is gone
+        assert!(
+            short_url
+                .list(
+                    Some("__health__"),
+                    None,
+                    None,
+                    None,
+                    SortBy::default(),
+                    SortDir::default()
+                )
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        // and it left nothing to replay
+        let events = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT COUNT(*) FROM {EVENTS_TABLE} WHERE org_id = $1;"
+        ))
+        .bind("__health__")
+        .fetch_one(&CLIENT_RO.clone())
+        .await
+        .unwrap();
+        assert_eq!(events, 0);
+    }
+
+    #[tokio::test]
+    async fn test_list_namespaces() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for (namespace, short_id) in [("eng", "setup"), ("eng", "oncall"), ("sales", "setup")] {
+            let mut record = ShortUrlRecord::new(
+                "org_namespaces",
+                &id::namespaced_short_id(namespace, short_id),
+                "https://example.com/",
+            );
+            record.namespace = Some(namespace.to_string());
+            short_url.add(&record).await.unwrap();
+        }
+        short_url
+            .add(&ShortUrlRecord::new(
+                "org_namespaces",
+                "setup",
+                "https://example.com/",
+            ))
+            .await
+            .unwrap();
+        // the same slug in another namespace is another short url
+        let got = short_url
+            .get("org_namespaces", "sales/setup")
+            .await
+            .unwrap();
+        assert_eq!(got.namespace.as_deref(), Some("sales"));
+        assert_eq!(
+            short_url.list_namespaces("org_namespaces").await.unwrap(),
+            vec!["eng", "sales"]
+        );
+        assert!(short_url.list_namespaces("other").await.unwrap().is_empty());
+        for short_id in ["eng/setup", "eng/oncall", "sales/setup", "setup"] {
+            purge(&short_url, "org_namespaces", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "org_merge", "merge").await;
+
+        let mut local = ShortUrlRecord::new("org_merge", "merge", "https://example.com/local");
+        local.created_ts = 10;
+        assert_eq!(
+            merge_with(&short_url, &local, MergeStrategy::LastWriteWins)
+                .await
+                .unwrap(),
+            MergeResult::Inserted
+        );
+
+        let mut incoming =
+            ShortUrlRecord::new("org_merge", "merge", "https://example.com/incoming");
+        incoming.created_ts = 5;
+        assert!(matches!(
+            merge_with(&short_url, &incoming, MergeStrategy::ErrorOnConflict).await,
+            Err(ShortUrlError::Conflict(_))
+        ));
+        // older than the local record
+        assert_eq!(
+            merge_with(&short_url, &incoming, MergeStrategy::LastWriteWins)
+                .await
+                .unwrap(),
+            MergeResult::LocalWon
+        );
+        assert_eq!(
+            short_url
+                .get("org_merge", "merge")
+                .await
+                .unwrap()
+                .original_url,
+            local.original_url
+        );
+
+        incoming.created_ts = 20;
+        assert_eq!(
+            merge_with(&short_url, &incoming, MergeStrategy::LastWriteWins)
+                .await
+                .unwrap(),
+            MergeResult::IncomingWon
+        );
+        let stored = short_url.get("org_merge", "merge").await.unwrap();
+        assert_eq!(stored.original_url, incoming.original_url);
+        assert_eq!(stored.created_ts, 20);
+
+        purge(&short_url, "org_merge", "merge").await;
+    }
+
+    #[tokio::test]
+    async fn test_list_sorted() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let short_ids: Vec<(String, String)> = ["sort_a", "sort_b", "sort_c"]
+            .iter()
+            .map(|short_id| ("org_sort".to_string(), short_id.to_string()))
+            .collect();
+        short_url.batch_remove(short_ids.clone()).await.unwrap();
+
+        // (short_id, original_url, click_count)
+        let records: Vec<ShortUrlRecord> =
+            [("sort_a", "c", 5), ("sort_b", "a", 0), ("sort_c", "b", 0)]
+                .iter()
+                .enumerate()
+                .map(|(i, (short_id, path, clicks))| {
+                    let mut record = ShortUrlRecord::new(
+                        "org_sort",
+                        short_id,
+                        &format!("https://example.com/{path}"),
+                    );
+                    record.created_ts = i as i64 + 1;
+                    record.click_count = *clicks;
+                    record
+                })
+                .collect();
+        short_url.batch_add(&records).await.unwrap();
+
+        for (sort_by, sort_dir, expected) in [
+            (
+                SortBy::CreatedTs,
+                SortDir::Desc,
+                ["sort_c", "sort_b", "sort_a"],
+            ),
+            (
+                SortBy::ClickCount,
+                SortDir::Desc,
+                ["sort_a", "sort_c", "sort_b"],
+            ),
+            (
+                SortBy::ClickCount,
+                SortDir::Asc,
+                ["sort_b", "sort_c", "sort_a"],
+            ),
+            (
+                SortBy::OriginalUrl,
+                SortDir::Asc,
+                ["sort_b", "sort_c", "sort_a"],
+            ),
+            (
+                SortBy::ShortId,
+                SortDir::Asc,
+                ["sort_a", "sort_b", "sort_c"],
+            ),
+        ] {
+            let list = short_url
+                .list(Some("org_sort"), None, None, None, sort_by, sort_dir)
+                .await
+                .unwrap();
+            let list: Vec<_> = list.into_iter().map(|r| r.short_id).collect();
+            assert_eq!(list, expected, "{sort_by:?} {sort_dir:?}");
+        }
+
+        let (page, total) = short_url
+            .list_with_count(
+                "org_sort",
+                None,
+                Some(1),
+                Some(1),
+                SortBy::ClickCount,
+                SortDir::Desc,
+            )
+            .await
+            .unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(page[0].short_id, "sort_c");
+
+        short_url.batch_remove(short_ids).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_add_alias() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["alias_docs", "alias_rtfm", "alias_manual"] {
+            purge(&short_url, "default", short_id).await;
+        }
+
+        let record = ShortUrlRecord::new("default", "alias_docs", "https://example.com/docs");
+        short_url.add(&record).await.unwrap();
+        short_url
+            .add_alias("default", "alias_rtfm", "alias_docs")
+            .await
+            .unwrap();
+        let alias = short_url.get("default", "alias_rtfm").await.unwrap();
+        assert_eq!(alias.alias_of.as_deref(), Some("alias_docs"));
+        assert_eq!(alias.original_url, "https://example.com/docs");
+
+        // no alias of an alias, no alias of itself, no alias of a missing short url
+        assert!(
+            short_url
+                .add_alias("default", "alias_manual", "alias_rtfm")
+                .await
+                .is_err()
+        );
+        assert!(
+            short_url
+                .add_alias("default", "alias_manual", "alias_manual")
+                .await
+                .is_err()
+        );
+        assert!(matches!(
+            short_url
+                .add_alias("default", "alias_manual", "alias_missing")
+                .await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+
+        for short_id in ["alias_docs", "alias_rtfm"] {
+            purge(&short_url, "default", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_top_by_clicks() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in ["stats_1", "stats_2", "stats_3"] {
+            purge(&short_url, "org_stats", short_id).await;
+        }
+        assert_eq!(short_url.click_totals("org_stats").await.unwrap(), (0, 0));
+
+        let records = [("stats_1", 5), ("stats_2", 20), ("stats_3", 1)]
+            .into_iter()
+            .map(|(short_id, clicks)| {
+                let mut record =
+                    ShortUrlRecord::new("org_stats", short_id, "https://example.com/stats");
+                record.click_count = clicks;
+                record
+            })
+            .collect::<Vec<_>>();
+        short_url.batch_add(&records).await.unwrap();
+
+        let top = short_url.top_by_clicks("org_stats", 2).await.unwrap();
+        assert_eq!(
+            top.iter().map(|r| r.short_id.as_str()).collect::<Vec<_>>(),
+            vec!["stats_2", "stats_1"]
+        );
+        assert_eq!(short_url.click_totals("org_stats").await.unwrap(), (3, 26));
+        assert_eq!(short_url.count_by_org("org_stats").await.unwrap(), 3);
+        // soft deleted short urls are left out
+        short_url.remove("org_stats", "stats_2").await.unwrap();
+        assert_eq!(short_url.click_totals("org_stats").await.unwrap(), (2, 6));
+        assert_eq!(short_url.count_by_org("org_stats").await.unwrap(), 2);
+
+        for short_id in ["stats_1", "stats_2", "stats_3"] {
+            purge(&short_url, "org_stats", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_due_for_notification() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        for short_id in [
+            "notify_due",
+            "notify_later",
+            "notify_never",
+            "notify_pinned",
+        ] {
+            purge(&short_url, "org_notify", short_id).await;
+        }
+
+        let records = [
+            ("notify_due", Some(1)),
+            ("notify_later", Some(i64::MAX)),
+            ("notify_never", None),
+            ("notify_pinned", Some(1)),
+        ]
+        .into_iter()
+        .map(|(short_id, notify_at)| {
+            let mut record = ShortUrlRecord::new("org_notify", short_id, "https://example.com");
+            record.expiry_notify_at = notify_at;
+            record.pinned = short_id == "notify_pinned";
+            record
+        })
+        .collect::<Vec<_>>();
+        short_url.batch_add(&records).await.unwrap();
+
+        let due = |now| {
+            let short_url = &short_url;
+            async move {
+                short_url
+                    .get_due_for_notification(now)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|r| r.org_id == "org_notify")
+                    .map(|r| r.short_id)
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(due(0).await, Vec::<String>::new());
+        assert_eq!(due(1).await, vec!["notify_due"]);
+        short_url
+            .mark_notified("org_notify", "notify_due")
+            .await
+            .unwrap();
+        assert_eq!(due(1).await, Vec::<String>::new());
+        assert!(
+            short_url
+                .mark_notified("org_notify", "missing")
+                .await
+                .is_err()
+        );
+
+        for short_id in [
+            "notify_due",
+            "notify_later",
+            "notify_never",
+            "notify_pinned",
+        ] {
+            purge(&short_url, "org_notify", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pin() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "org_pin", "pinned").await;
+
+        let mut record = ShortUrlRecord::new("org_pin", "pinned", "https://example.com/home");
+        record.created_ts = 1;
+        short_url.batch_add(&[record]).await.unwrap();
+        let expired =
+            || short_url.get_expired(Some("org_pin"), 2, None, EvictionStrategy::OldestFirst);
+        assert_eq!(expired().await.unwrap().len(), 1);
+
+        short_url.pin("org_pin", "pinned").await.unwrap();
+        assert!(short_url.get("org_pin", "pinned").await.unwrap().pinned);
+        assert!(expired().await.unwrap().is_empty());
+        // pinning twice is not an error
+        short_url.pin("org_pin", "pinned").await.unwrap();
+
+        short_url.unpin("org_pin", "pinned").await.unwrap();
+        assert!(!short_url.get("org_pin", "pinned").await.unwrap().pinned);
+        assert_eq!(expired().await.unwrap().len(), 1);
+
+        assert!(matches!(
+            short_url.pin("org_pin", "missing").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        purge(&short_url, "org_pin", "pinned").await;
+    }
+
+    #[tokio::test]
+    async fn test_clone_url() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let original_url = "https://example.com/clone";
+        for short_id in ["clone_src", "clone_tracked"] {
+            purge(&short_url, "default", short_id).await;
+        }
+
+        let mut source = ShortUrlRecord::new("default", "clone_src", original_url);
+        source.permanent = true;
+        short_url.add(&source).await.unwrap();
+        short_url
+            .increment_click_count("default", "clone_src")
+            .await
+            .unwrap();
+
+        let cloned = short_url
+            .clone_url("default", "clone_src", Some("clone_tracked"))
+            .await
+            .unwrap();
+        assert_eq!(cloned, "clone_tracked");
+        let record = short_url.get("default", "clone_tracked").await.unwrap();
+        assert_eq!(record.original_url, original_url);
+        assert!(record.permanent);
+        assert_eq!(record.click_count, 0);
+        // both stay active
+        assert!(short_url.contains("default", "clone_src").await.unwrap());
+
+        assert!(matches!(
+            short_url
+                .clone_url("default", "clone_src", Some("clone_tracked"))
+                .await,
+            Err(ShortUrlError::Conflict(_))
+        ));
+        assert!(matches!(
+            short_url.clone_url("default", "clone_missing", None).await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+        assert!(matches!(
+            short_url.clone_url("default", "clone_src", Some("x")).await,
+            Err(ShortUrlError::InvalidShortId(_))
+        ));
+
+        // without a new_short_id the clone gets a snowflake short_id
+        let cloned = short_url
+            .clone_url("default", "clone_src", None)
+            .await
+            .unwrap();
+        assert_ne!(cloned, "clone_src");
+        assert_ne!(cloned, generate_short_id(original_url, 0));
+        assert!(cloned.chars().all(|c| c.is_ascii_alphanumeric()));
+        let record = short_url.get("default", &cloned).await.unwrap();
+        assert_eq!(record.original_url, original_url);
+
+        for short_id in ["clone_src", "clone_tracked", cloned.as_str()] {
+            purge(&short_url, "default", short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_or_get_converges() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let original_url = "https://example.com/add_or_get";
+        if let Some(existing) = short_url
+            .get_by_original_url("default", original_url)
+            .await
+            .unwrap()
+        {
+            purge(&short_url, "default", &existing.short_id).await;
+        }
+
+        let tasks = (0..10).map(|_| {
+            tokio::spawn(async move {
+                let record = ShortUrlRecord::new("default", "", original_url);
+                SqliteShortUrl::new().add_or_get(&record).await
+            })
+        });
+        let rets: Vec<(String, bool)> = futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .map(|ret| ret.unwrap().unwrap())
+            .collect();
+        assert_eq!(rets.iter().filter(|(_, inserted)| *inserted).count(), 1);
+        assert!(rets.iter().all(|(short_id, _)| *short_id == rets[0].0));
+
+        purge(&short_url, "default", &rets[0].0).await;
+    }
+
+    #[tokio::test]
+    async fn test_add_or_get_keeps_settings_apart() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let original_url = "https://example.com/add_or_get/acl";
+        for attempt in 0..ADD_OR_GET_MAX_ATTEMPTS {
+            purge(
+                &short_url,
+                "default",
+                &generate_short_id(original_url, attempt),
+            )
+            .await;
+        }
+
+        let public = ShortUrlRecord::new("default", "", original_url);
+        let (public_id, inserted) = short_url.add_or_get(&public).await.unwrap();
+        assert!(inserted);
+        let mut restricted = ShortUrlRecord::new("default", "", original_url);
+        restricted.acl = Some(vec!["other_org".to_string()]);
+        let (restricted_id, inserted) = short_url.add_or_get(&restricted).await.unwrap();
+        assert!(inserted);
+        assert_ne!(public_id, restricted_id);
+        assert_eq!(
+            short_url.get("default", &restricted_id).await.unwrap().acl,
+            restricted.acl
+        );
+        assert_eq!(
+            short_url.add_or_get(&restricted).await.unwrap(),
+            (restricted_id.clone(), false)
+        );
+        assert!(!short_url.add_or_get(&public).await.unwrap().1);
+        let mut rewritten = ShortUrlRecord::new("default", "", original_url);
+        rewritten.pipeline = vec![UrlTransform::AppendQueryParam {
+            key: "utm_source".to_string(),
+            value: "short".to_string(),
+        }];
+        let (rewritten_id, inserted) = short_url.add_or_get(&rewritten).await.unwrap();
+        assert!(inserted);
+        assert_ne!(rewritten_id, public_id);
+        assert_ne!(rewritten_id, restricted_id);
+
+        purge(&short_url, "default", &public_id).await;
+        purge(&short_url, "default", &restricted_id).await;
+        purge(&short_url, "default", &rewritten_id).await;
+    }
+
+    #[tokio::test]
+    async fn test_events_logged() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "org_events", "events").await;
+        let from_ts = Utc::now().timestamp_micros();
+
+        let record = ShortUrlRecord::new("org_events", "events", "https://example.com/events");
+        short_url.add(&record).await.unwrap();
+        short_url
+            .update("org_events", "events", "https://example.com/events/new")
+            .await
+            .unwrap();
+        short_url.remove("org_events", "events").await.unwrap();
+        purge(&short_url, "org_events", "events").await;
+
+        let events = sqlx::query_as::<_, (String, String)>(&format!(
+            "SELECT event_type, payload FROM {EVENTS_TABLE} WHERE org_id = $1 AND occurred_at > $2 ORDER BY id;"
+        ))
+        .bind("org_events")
+        .bind(from_ts)
+        .fetch_all(&CLIENT_RO.clone())
+        .await
+        .unwrap();
+        assert_eq!(
+            events.iter().map(|(t, _)| t.as_str()).collect::<Vec<_>>(),
+            vec!["add", "update", "remove", "batch_remove"]
+        );
+        let ShortUrlEvent::Add(added) = ShortUrlEvent::from_row(
+            "add",
+            "org_events".to_string(),
+            "events".to_string(),
+            &events[0].1,
+        )
+        .unwrap() else {
+            panic!("expected an add event");
+        };
+        assert_eq!(added.original_url, "https://example.com/events");
+        assert!(added.created_ts > 0);
+    }
+
+    #[tokio::test]
+    async fn test_replay_add_rename_pin() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        purge(&short_url, "org_replay", "replay_old").await;
+        purge(&short_url, "org_replay", "replay_new").await;
+        let from_ts = Utc::now().timestamp_micros();
+
+        let record = ShortUrlRecord::new("org_replay", "replay_old", "https://example.com/replay");
+        short_url.add(&record).await.unwrap();
+        short_url
+            .increment_click_count("org_replay", "replay_old")
+            .await
+            .unwrap();
+        short_url
+            .rename("org_replay", "replay_old", "replay_new")
+            .await
+            .unwrap();
+        short_url.pin("org_replay", "replay_new").await.unwrap();
+
+        // replayed over a store that holds the changes already, the clicks are kept
+        short_url.replay_from_events(from_ts).await.unwrap();
+        let replayed = short_url.get("org_replay", "replay_new").await.unwrap();
+        assert!(replayed.pinned);
+        assert_eq!(replayed.click_count, 1);
+        assert!(matches!(
+            short_url.get("org_replay", "replay_old").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+
+        // replayed onto a store that lost the record, untouched by the event log
+        sqlx::query(&format!(
+            "DELETE FROM {} WHERE org_id = $1;",
+            TABLE_NAME.as_str()
+        ))
+        .bind("org_replay")
+        .execute(&*CLIENT_RW.clone().lock().await)
+        .await
+        .unwrap();
+        short_url.replay_from_events(from_ts).await.unwrap();
+        let rebuilt = short_url.get("org_replay", "replay_new").await.unwrap();
+        assert_eq!(rebuilt.original_url, "https://example.com/replay");
+        assert!(rebuilt.pinned);
+        assert_eq!(
+            rebuilt.checksum,
+            Some(checksum("replay_new", &rebuilt.original_url))
+        );
+        assert!(matches!(
+            short_url.get("org_replay", "replay_old").await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+
+        purge(&short_url, "org_replay", "replay_new").await;
+    }
+
+    #[tokio::test]
+    async fn test_migrate() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        // the steps are idempotent, a rerun from scratch leaves the table usable
+        sqlx::query(&format!(
+            "DELETE FROM {SCHEMA_VERSION_TABLE} WHERE table_name = $1;"
+        ))
+        .bind(TABLE_NAME.as_str())
+        .execute(&*CLIENT_RW.clone().lock().await)
+        .await
+        .unwrap();
+        short_url.migrate().await.unwrap();
+        short_url.migrate().await.unwrap();
+
+        let version = sqlx::query_scalar::<_, i64>(&format!(
+            "SELECT version FROM {SCHEMA_VERSION_TABLE} WHERE table_name = $1;"
+        ))
+        .bind(TABLE_NAME.as_str())
+        .fetch_one(&CLIENT_RO.clone())
+        .await
+        .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_access_log() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let entries: Vec<_> = [(1_000, Some("DE")), (3_000, None), (2_000, Some("US"))]
+            .into_iter()
+            .map(|(accessed_at, country_code)| AccessLogEntry {
+                org_id: "org_access_log".to_string(),
+                short_id: "abc".to_string(),
+                accessed_at,
+                user_agent_hash: "cbf29ce484222325".to_string(),
+                country_code: country_code.map(str::to_string),
+                ..Default::default()
+            })
+            .collect();
+        short_url.add_access_log(&entries).await.unwrap();
+
+        let got = short_url
+            .get_access_log("org_access_log", "abc", 1_000, 3_000)
+            .await
+            .unwrap();
+        let got: Vec<_> = got
+            .iter()
+            .map(|e| (e.accessed_at, e.country_code.as_deref()))
+            .collect();
+        assert_eq!(got, vec![(1_000, Some("DE")), (2_000, Some("US"))]);
+        assert!(
+            short_url
+                .get_access_log("org_access_log", "xyz", 0, i64::MAX)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        assert_eq!(short_url.purge_access_log(2_500).await.unwrap(), 2);
+        let got = short_url
+            .get_access_log("org_access_log", "abc", 0, i64::MAX)
+            .await
+            .unwrap();
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].accessed_at, 3_000);
+        short_url.purge_access_log(3_001).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_verify_integrity() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let org = "org_integrity";
+        for short_id in ["intact", "corrupt", "renamed"] {
+            short_url
+                .add(&ShortUrlRecord::new(org, short_id, "https://example.com/"))
+                .await
+                .unwrap();
+        }
+        short_url
+            .update(org, "intact", "https://example.com/updated")
+            .await
+            .unwrap();
+        short_url
+            .rename(org, "renamed", "renamed_new")
+            .await
+            .unwrap();
+        let got = short_url.get(org, "renamed_new").await.unwrap();
+        assert_eq!(got.verify_checksum(), Some(true));
+
+        // change the url behind the back of the store
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        sqlx::query(&format!(
+            "UPDATE {} SET original_url = $1 WHERE org_id = $2 AND short_id = $3;",
+            TABLE_NAME.as_str()
+        ))
+        .bind("https://evil.example.com/")
+        .bind(org)
+        .bind("corrupt")
+        .execute(&*client)
+        .await
+        .unwrap();
+        drop(client);
+
+        let report = short_url.verify_integrity(2).await.unwrap();
+        let key = |short_id: &str| (org.to_string(), short_id.to_string());
+        assert!(report.mismatches.contains(&key("corrupt")));
+        assert!(!report.mismatches.contains(&key("intact")));
+        assert!(!report.mismatches.contains(&key("renamed_new")));
+        assert!(report.scanned >= 3);
+
+        for short_id in ["intact", "corrupt", "renamed_new"] {
+            purge(&short_url, org, short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archive_expired() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let org = "org_archive";
+        for short_id in ["old", "expired", "pinned", "fresh"] {
+            purge(&short_url, org, short_id).await;
+        }
+
+        // created_ts below 0 keeps the rows of the other tests out of the way
+        let mut old = ShortUrlRecord::new(org, "old", "https://example.com/old");
+        old.created_ts = -2;
+        let mut expired = ShortUrlRecord::new(org, "expired", "https://example.com/expired");
+        expired.expires_at = Some(1);
+        let mut pinned = ShortUrlRecord::new(org, "pinned", "https://example.com/pinned");
+        pinned.created_ts = -1;
+        let fresh = ShortUrlRecord::new(org, "fresh", "https://example.com/fresh");
+        short_url
+            .batch_add(&[old, expired, pinned, fresh])
+            .await
+            .unwrap();
+        short_url.pin(org, "pinned").await.unwrap();
+
+        assert!(short_url.archive_expired(0, None).await.unwrap() >= 2);
+        for short_id in ["old", "expired"] {
+            assert!(short_url.get(org, short_id).await.is_err());
+        }
+        short_url.get(org, "pinned").await.unwrap();
+        short_url.get(org, "fresh").await.unwrap();
+
+        let client = CLIENT_RO.clone();
+        let archived: Vec<(String, String)> = sqlx::query_as(&format!(
+            "SELECT short_id, original_url FROM {ARCHIVE_TABLE} WHERE org_id = $1 ORDER BY short_id;"
+        ))
+        .bind(org)
+        .fetch_all(&client)
+        .await
+        .unwrap();
+        assert_eq!(
+            archived,
+            vec![
+                (
+                    "expired".to_string(),
+                    "https://example.com/expired".to_string()
+                ),
+                ("old".to_string(), "https://example.com/old".to_string()),
+            ]
+        );
+
+        let client = CLIENT_RW.clone();
+        let client = client.lock().await;
+        sqlx::query(&format!("DELETE FROM {ARCHIVE_TABLE} WHERE org_id = $1;"))
+            .bind(org)
+            .execute(&*client)
+            .await
+            .unwrap();
+        drop(client);
+        for short_id in ["pinned", "fresh"] {
+            purge(&short_url, org, short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_link_rot_candidates() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let org = "org_link_rot";
+        for short_id in ["ok", "gone", "down"] {
+            purge(&short_url, org, short_id).await;
+            short_url
+                .add(&ShortUrlRecord::new(org, short_id, "https://example.com/"))
+                .await
+                .unwrap();
+        }
+        assert!(!short_url.sample_for_link_check(2).await.unwrap().is_empty());
+        assert!(short_url.sample_for_link_check(2).await.unwrap().len() <= 2);
+
+        for (short_id, status) in [("ok", 200), ("gone", 404), ("down", 503)] {
+            short_url
+                .set_link_check_status(org, short_id, status)
+                .await
+                .unwrap();
+        }
+        let got = short_url.get(org, "ok").await.unwrap();
+        assert_eq!(got.last_check_status, Some(200));
+        assert!(!got.link_rot_detected);
+        let mut candidates = short_url
+            .list_link_rot_candidates(org)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.short_id, r.last_check_status))
+            .collect::<Vec<_>>();
+        candidates.sort();
+        assert_eq!(
+            candidates,
+            vec![
+                ("down".to_string(), Some(503)),
+                ("gone".to_string(), Some(404))
+            ]
+        );
+
+        // a later check clears the flag
+        short_url
+            .set_link_check_status(org, "down", 200)
+            .await
+            .unwrap();
+        let candidates = short_url.list_link_rot_candidates(org).await.unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert!(matches!(
+            short_url.set_link_check_status(org, "missing", 200).await,
+            Err(ShortUrlError::NotFound(_))
+        ));
+
+        for short_id in ["ok", "gone", "down"] {
+            purge(&short_url, org, short_id).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_keys() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let org = "org_idempotency";
+        short_url.purge_idempotency_keys(i64::MAX).await.unwrap();
+        let pending = |body_hash: &str| IdempotencyKey {
+            short_id: None,
+            body_hash: body_hash.to_string(),
+        };
+
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            None
+        );
+        assert!(
+            short_url
+                .reserve_idempotency_key(org, "k1", "h1", 0)
+                .await
+                .unwrap()
+        );
+        // a concurrent request with the key loses the reservation
+        assert!(
+            !short_url
+                .reserve_idempotency_key(org, "k1", "h2", 0)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            Some(pending("h1"))
+        );
+        short_url
+            .complete_idempotency_key(org, "k1", "first")
+            .await
+            .unwrap();
+        // a completed key is not released
+        short_url.release_idempotency_key(org, "k1").await.unwrap();
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            Some(IdempotencyKey {
+                short_id: Some("first".to_string()),
+                body_hash: "h1".to_string(),
+            })
+        );
+        assert_eq!(
+            short_url
+                .get_idempotency_key("org_other", "k1", 0)
+                .await
+                .unwrap(),
+            None
+        );
+        // an expired key is not returned and is taken over
+        assert_eq!(
+            short_url
+                .get_idempotency_key(org, "k1", i64::MAX)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            short_url
+                .reserve_idempotency_key(org, "k1", "h2", i64::MAX)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            Some(pending("h2"))
+        );
+
+        // a released key can be reserved again
+        short_url.release_idempotency_key(org, "k1").await.unwrap();
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            None
+        );
+        assert!(
+            short_url
+                .reserve_idempotency_key(org, "k1", "h3", 0)
+                .await
+                .unwrap()
+        );
+
+        assert_eq!(short_url.purge_idempotency_keys(0).await.unwrap(), 0);
+        assert_eq!(short_url.purge_idempotency_keys(i64::MAX).await.unwrap(), 1);
+        assert_eq!(
+            short_url.get_idempotency_key(org, "k1", 0).await.unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_generate_digest() {
+        let short_url = SqliteShortUrl::new();
+        short_url.create_table().await.unwrap();
+        short_url.create_table_index().await.unwrap();
+        let org = "org_digest";
+        for short_id in ["popular", "quiet", "gone"] {
+            purge(&short_url, org, short_id).await;
+        }
+        // keep the removes above out of the period
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        let period_start = Utc::now().timestamp_micros();
+
+        for short_id in ["popular", "quiet", "gone"] {
+            short_url
+                .add(&ShortUrlRecord::new(org, short_id, "https://example.com/"))
+                .await
+                .unwrap();
+        }
+        let entries = [("popular", 3), ("quiet", 1)]
+            .into_iter()
+            .flat_map(|(short_id, clicks)| (0..clicks).map(move |_| short_id))
+            .map(|short_id| AccessLogEntry {
+                org_id: org.to_string(),
+                short_id: short_id.to_string(),
+                accessed_at: period_start + 1,
+                user_agent_hash: "cbf29ce484222325".to_string(),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        short_url.add_access_log(&entries).await.unwrap();
+        purge(&short_url, org, "gone").await;
+        let period_end = Utc::now().timestamp_micros() + 1;
+
+        let report = short_url
+            .generate_digest(org, period_start, period_end)
+            .await
+            .unwrap();
+        assert_eq!(
+            report,
+            ShortUrlDigestReport {
+                new_urls: 2,
+                total_clicks: 4,
+                expired_removed: 1,
+                top_5_by_clicks: vec![("popular".to_string(), 3), ("quiet".to_string(), 1)],
+            }
+        );
+        let report = short_url
+            .generate_digest(org, period_end, period_end + 1)
+            .await
+            .unwrap();
+        assert_eq!(report, ShortUrlDigestReport::default());
+
+        for short_id in ["popular", "quiet"] {
+            purge(&short_url, org, short_id).await;
+        }
+    }
 }
@@ -0,0 +1,140 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Rewrites of the original_url of a short url applied in order before redirecting, e.g. to
+//! add UTM parameters
+
+use config::{meta::short_url::UrlTransform, utils::base64};
+use url::Url;
+
+use crate::short_url::error::{Result, ShortUrlError};
+
+/// Most transforms a short url can have
+pub const MAX_PIPELINE_LEN: usize = 16;
+
+fn apply_one(transform: &UrlTransform, url: &mut Url) -> Result<()> {
+    match transform {
+        UrlTransform::AppendQueryParam { key, value } => {
+            url.query_pairs_mut().append_pair(key, value);
+        }
+        UrlTransform::ReplaceHost { new_host } => url
+            .set_host(Some(new_host))
+            .map_err(|e| invalid(format!("replace_host {new_host:?}: {e}")))?,
+        UrlTransform::Base64DecodePath => {
+            let path = base64::decode_url(url.path().trim_start_matches('/'))
+                .map_err(|e| invalid(format!("base64_decode_path: {e}")))?;
+            url.set_path(&path);
+        }
+    }
+    Ok(())
+}
+
+fn invalid(msg: String) -> ShortUrlError {
+    ShortUrlError::InvalidUrl(msg)
+}
+
+/// Run `pipeline` over `original_url` in order, returns the url to redirect to
+pub fn apply(pipeline: &[UrlTransform], original_url: &str) -> Result<String> {
+    if pipeline.is_empty() {
+        return Ok(original_url.to_string());
+    }
+    let mut url = Url::parse(original_url).map_err(|e| invalid(e.to_string()))?;
+    for transform in pipeline {
+        apply_one(transform, &mut url)?;
+    }
+    Ok(url.to_string())
+}
+
+/// Check a pipeline when the short url is created: the transforms are well formed and turn
+/// `original_url` into an http(s) url
+pub fn validate(pipeline: &[UrlTransform], original_url: &str) -> Result<()> {
+    if pipeline.len() > MAX_PIPELINE_LEN {
+        return Err(invalid(format!(
+            "a pipeline has at most {MAX_PIPELINE_LEN} transforms"
+        )));
+    }
+    for transform in pipeline {
+        match transform {
+            UrlTransform::AppendQueryParam { key, .. } if key.is_empty() => {
+                return Err(invalid("append_query_param needs a key".to_string()));
+            }
+            UrlTransform::ReplaceHost { new_host } if new_host.is_empty() => {
+                return Err(invalid("replace_host needs a new_host".to_string()));
+            }
+            _ => {}
+        }
+    }
+    let url = apply(pipeline, original_url)?;
+    match Url::parse(&url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(()),
+        _ => Err(invalid(format!("the pipeline turns the url into {url:?}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let pipeline = vec![
+            UrlTransform::AppendQueryParam {
+                key: "utm_source".to_string(),
+                value: "short url".to_string(),
+            },
+            UrlTransform::ReplaceHost {
+                new_host: "cdn.example.com".to_string(),
+            },
+        ];
+        assert_eq!(
+            apply(&pipeline, "https://example.com/a?b=1").unwrap(),
+            "https://cdn.example.com/a?b=1&utm_source=short+url"
+        );
+        assert_eq!(apply(&[], "not a url").unwrap(), "not a url");
+
+        let encoded = base64::encode_url("/docs/setup");
+        let url = format!("https://example.com/{encoded}");
+        assert_eq!(
+            apply(&[UrlTransform::Base64DecodePath], &url).unwrap(),
+            "https://example.com/docs/setup"
+        );
+        assert!(apply(&[UrlTransform::Base64DecodePath], "https://example.com/-").is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&[], "https://example.com/").is_ok());
+        let empty_key = UrlTransform::AppendQueryParam {
+            key: String::new(),
+            value: "v".to_string(),
+        };
+        assert!(validate(&[empty_key], "https://example.com/").is_err());
+        let bad_host = UrlTransform::ReplaceHost {
+            new_host: "exa mple.com".to_string(),
+        };
+        assert!(validate(&[bad_host], "https://example.com/").is_err());
+        let too_long = vec![UrlTransform::Base64DecodePath; MAX_PIPELINE_LEN + 1];
+        assert!(validate(&too_long, "https://example.com/").is_err());
+
+        let transform: UrlTransform =
+            serde_json::from_str(r#"{"type":"replace_host","new_host":"example.org"}"#).unwrap();
+        assert_eq!(
+            transform,
+            UrlTransform::ReplaceHost {
+                new_host: "example.org".to_string()
+            }
+        );
+    }
+}
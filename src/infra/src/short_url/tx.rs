@@ -0,0 +1,107 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use sqlx::{MySql, Postgres, Sqlite, Transaction};
+
+use crate::short_url::{error::Result, mysql, postgres, sqlite, ShortUrlEvent, ShortUrlRecord};
+
+/// An open transaction of the short url backend, see `ShortUrl::with_transaction`
+pub enum ShortUrlTx {
+    Mysql(Transaction<'static, MySql>),
+    Postgres(Transaction<'static, Postgres>),
+    Sqlite(Transaction<'static, Sqlite>),
+}
+
+impl ShortUrlTx {
+    pub async fn add(&mut self, record: &ShortUrlRecord) -> Result<ShortUrlRecord> {
+        let record = match self {
+            Self::Mysql(tx) => mysql::insert_record(&mut **tx, record).await?,
+            Self::Postgres(tx) => postgres::insert_record(&mut **tx, record).await?,
+            Self::Sqlite(tx) => sqlite::insert_record(&mut **tx, record).await?,
+        };
+        self.log_event(&ShortUrlEvent::Add(record.clone())).await?;
+        Ok(record)
+    }
+
+    pub async fn remove(&mut self, org_id: &str, short_id: &str) -> Result<()> {
+        match self {
+            Self::Mysql(tx) => mysql::delete_record(&mut **tx, org_id, short_id).await?,
+            Self::Postgres(tx) => postgres::delete_record(&mut **tx, org_id, short_id).await?,
+            Self::Sqlite(tx) => sqlite::delete_record(&mut **tx, org_id, short_id).await?,
+        }
+        self.log_event(&ShortUrlEvent::Remove {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+        })
+        .await
+    }
+
+    pub async fn update(&mut self, org_id: &str, short_id: &str, new_url: &str) -> Result<()> {
+        match self {
+            Self::Mysql(tx) => mysql::update_url(&mut **tx, org_id, short_id, new_url).await?,
+            Self::Postgres(tx) => {
+                postgres::update_url(&mut **tx, org_id, short_id, new_url).await?
+            }
+            Self::Sqlite(tx) => sqlite::update_url(&mut **tx, org_id, short_id, new_url).await?,
+        }
+        self.log_event(&ShortUrlEvent::Update {
+            org_id: org_id.to_string(),
+            short_id: short_id.to_string(),
+            original_url: new_url.to_string(),
+        })
+        .await
+    }
+
+    // changes made in the transaction are logged in the same transaction
+    async fn log_event(&mut self, event: &ShortUrlEvent) -> Result<()> {
+        match self {
+            Self::Mysql(tx) => mysql::insert_event(&mut **tx, event).await,
+            Self::Postgres(tx) => postgres::insert_event(&mut **tx, event).await,
+            Self::Sqlite(tx) => sqlite::insert_event(&mut **tx, event).await,
+        }
+    }
+
+    pub async fn increment_click_count(&mut self, org_id: &str, short_id: &str) -> Result<()> {
+        match self {
+            Self::Mysql(tx) => mysql::increment_clicks(&mut **tx, org_id, short_id).await,
+            Self::Postgres(tx) => postgres::increment_clicks(&mut **tx, org_id, short_id).await,
+            Self::Sqlite(tx) => sqlite::increment_clicks(&mut **tx, org_id, short_id).await,
+        }
+    }
+
+    /// Commit if `ret` is `Ok`, roll back otherwise
+    pub(crate) async fn finish<T>(self, ret: Result<T>) -> Result<T> {
+        let e = match ret {
+            Ok(v) => {
+                match self {
+                    Self::Mysql(tx) => tx.commit().await?,
+                    Self::Postgres(tx) => tx.commit().await?,
+                    Self::Sqlite(tx) => tx.commit().await?,
+                }
+                return Ok(v);
+            }
+            Err(e) => e,
+        };
+        let rollback = match self {
+            Self::Mysql(tx) => tx.rollback().await,
+            Self::Postgres(tx) => tx.rollback().await,
+            Self::Sqlite(tx) => tx.rollback().await,
+        };
+        if let Err(re) = rollback {
+            log::error!("[SHORT_URL] rollback transaction error: {}", re);
+        }
+        Err(e)
+    }
+}
@@ -0,0 +1,358 @@
+// Copyright 2024 OpenObserve Inc.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Integration tests of the MySQL short_url backend against a MySQL started with
+//! testcontainers, they need a docker daemon: `cargo test -p infra -- --ignored`
+//!
+//! The mysql pool is a process wide static built from `ZO_META_MYSQL_DSN` the first time it
+//! is used, so all tests share one container and one runtime, run one at a time and start
+//! from an empty table.
+
+use std::{future::Future, sync::Mutex, time::Duration};
+
+use infra::short_url::{
+    mysql::MysqlShortUrl, EvictionStrategy, ShortUrl, ShortUrlRecord, SortBy, SortDir,
+};
+use once_cell::sync::Lazy;
+use testcontainers_modules::{
+    mysql::Mysql,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+use tokio::{runtime::Runtime, sync::OnceCell};
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("tokio runtime create failed")
+});
+static MYSQL: OnceCell<ContainerAsync<Mysql>> = OnceCell::const_new();
+static SERIAL: Mutex<()> = Mutex::new(());
+
+async fn start_mysql() -> ContainerAsync<Mysql> {
+    let container = Mysql::default()
+        .start()
+        .await
+        .expect("mysql container start failed");
+    let host = container.get_host().await.unwrap();
+    let port = container.get_host_port_ipv4(3306).await.unwrap();
+    std::env::set_var(
+        "ZO_META_MYSQL_DSN",
+        format!("mysql://root@{host}:{port}/test"),
+    );
+    config::refresh_config().unwrap();
+
+    let short_url = MysqlShortUrl::new();
+    short_url.create_table().await.unwrap();
+    short_url.create_table_index().await.unwrap();
+    container
+}
+
+/// Run a test on the shared runtime against an empty short_url table
+fn run<F, Fut>(test: F)
+where
+    F: FnOnce(MysqlShortUrl) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let _guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    RUNTIME.block_on(async {
+        MYSQL.get_or_init(start_mysql).await;
+        let short_url = MysqlShortUrl::new();
+        short_url.clear().await.unwrap();
+        test(short_url).await;
+    });
+}
+
+fn record(org_id: &str, short_id: &str) -> ShortUrlRecord {
+    ShortUrlRecord::new(
+        org_id,
+        short_id,
+        &format!("https://example.com/{org_id}/{short_id}"),
+    )
+}
+
+#[test]
+#[ignore]
+fn test_add_and_get() {
+    run(|short_url| async move {
+        short_url.add(&record("default", "add_get")).await.unwrap();
+        let got = short_url.get("default", "add_get").await.unwrap();
+        assert_eq!(got.org_id, "default");
+        assert_eq!(got.original_url, "https://example.com/default/add_get");
+        assert!(got.created_ts > 0);
+        assert!(short_url.get("other", "add_get").await.is_err());
+        assert!(short_url.get("default", "missing").await.is_err());
+    });
+}
+
+#[test]
+#[ignore]
+fn test_contains() {
+    run(|short_url| async move {
+        short_url.add(&record("default", "contains")).await.unwrap();
+        assert!(short_url.contains("default", "contains").await.unwrap());
+        assert!(!short_url.contains("default", "missing").await.unwrap());
+        assert!(!short_url.contains("other", "contains").await.unwrap());
+    });
+}
+
+#[test]
+#[ignore]
+fn test_list() {
+    run(|short_url| async move {
+        for short_id in ["list_1", "list_2", "list_3"] {
+            short_url.add(&record("default", short_id)).await.unwrap();
+            // created_ts is set on insert, keep them apart so the order is stable
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        short_url.add(&record("other", "list_4")).await.unwrap();
+
+        let all = short_url
+            .list(
+                Some("default"),
+                None,
+                None,
+                None,
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            all.iter().map(|r| r.short_id.as_str()).collect::<Vec<_>>(),
+            vec!["list_3", "list_2", "list_1"]
+        );
+
+        let limited = short_url
+            .list(
+                Some("default"),
+                None,
+                Some(2),
+                None,
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(limited.len(), 2);
+
+        let after = short_url
+            .list(
+                Some("default"),
+                None,
+                None,
+                Some(all[0].created_ts),
+                SortBy::default(),
+                SortDir::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            after
+                .iter()
+                .map(|r| r.short_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["list_2", "list_1"]
+        );
+
+        assert_eq!(
+            short_url
+                .list(
+                    None,
+                    None,
+                    None,
+                    None,
+                    SortBy::default(),
+                    SortDir::default()
+                )
+                .await
+                .unwrap()
+                .len(),
+            4
+        );
+    });
+}
+
+#[test]
+#[ignore]
+fn test_remove() {
+    run(|short_url| async move {
+        short_url.add(&record("default", "remove")).await.unwrap();
+        short_url.add(&record("other", "remove")).await.unwrap();
+        short_url.remove("default", "remove").await.unwrap();
+        assert!(!short_url.contains("default", "remove").await.unwrap());
+        assert!(short_url.contains("other", "remove").await.unwrap());
+        // removing a missing short_id is not an error
+        short_url.remove("default", "remove").await.unwrap();
+
+        // remove only soft deletes
+        short_url.restore("default", "remove").await.unwrap();
+        assert!(short_url.contains("default", "remove").await.unwrap());
+        short_url.remove("default", "remove").await.unwrap();
+        assert_eq!(
+            short_url
+                .hard_delete_expired_soft_deleted(i64::MAX)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(short_url.restore("default", "remove").await.is_err());
+    });
+}
+
+#[test]
+#[ignore]
+fn test_batch_remove() {
+    run(|short_url| async move {
+        for short_id in ["batch_1", "batch_2", "batch_3"] {
+            short_url.add(&record("default", short_id)).await.unwrap();
+        }
+        let removed = short_url
+            .batch_remove(vec![
+                ("default".to_string(), "batch_1".to_string()),
+                ("default".to_string(), "batch_2".to_string()),
+                ("default".to_string(), "missing".to_string()),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(removed, 2);
+        assert_eq!(short_url.len().await, 1);
+        assert!(short_url.contains("default", "batch_3").await.unwrap());
+        assert_eq!(short_url.batch_remove(vec![]).await.unwrap(), 0);
+    });
+}
+
+#[test]
+#[ignore]
+fn test_get_expired() {
+    run(|short_url| async move {
+        short_url
+            .add(&record("default", "expired_old"))
+            .await
+            .unwrap();
+        let old = short_url.get("default", "expired_old").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+
+        let mut due = record("default", "expired_due");
+        due.expires_at = Some(1);
+        short_url.add(&due).await.unwrap();
+        let mut fresh = record("default", "expired_fresh");
+        fresh.expires_at = Some(i64::MAX);
+        short_url.add(&fresh).await.unwrap();
+
+        // created before the cutoff or past their own expires_at
+        let mut expired = short_url
+            .get_expired(
+                None,
+                old.created_ts + 1,
+                None,
+                EvictionStrategy::OldestFirst,
+            )
+            .await
+            .unwrap();
+        expired.sort();
+        assert_eq!(
+            expired,
+            vec![
+                ("default".to_string(), "expired_due".to_string()),
+                ("default".to_string(), "expired_old".to_string()),
+            ]
+        );
+        assert_eq!(
+            short_url
+                .get_expired(
+                    None,
+                    old.created_ts + 1,
+                    Some(1),
+                    EvictionStrategy::OldestFirst
+                )
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(
+            short_url
+                .get_expired(None, 0, None, EvictionStrategy::OldestFirst)
+                .await
+                .unwrap(),
+            vec![("default".to_string(), "expired_due".to_string())]
+        );
+        assert_eq!(
+            short_url
+                .get_expired(Some("default"), 0, None, EvictionStrategy::OldestFirst)
+                .await
+                .unwrap(),
+            vec![("default".to_string(), "expired_due".to_string())]
+        );
+        assert!(
+            short_url
+                .get_expired(Some("other"), i64::MAX, None, EvictionStrategy::OldestFirst)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    });
+}
+
+#[test]
+#[ignore]
+fn test_replay_from_events() {
+    run(|short_url| async move {
+        let from_ts = chrono::Utc::now().timestamp_micros();
+        short_url.add(&record("default", "replay_1")).await.unwrap();
+        short_url.add(&record("default", "replay_2")).await.unwrap();
+        short_url
+            .update("default", "replay_1", "https://example.com/updated")
+            .await
+            .unwrap();
+        short_url.remove("default", "replay_2").await.unwrap();
+        let created_ts = short_url
+            .get("default", "replay_1")
+            .await
+            .unwrap()
+            .created_ts;
+
+        // the events outlive the table
+        short_url.clear().await.unwrap();
+        assert_eq!(short_url.replay_from_events(from_ts).await.unwrap(), 4);
+        let got = short_url.get("default", "replay_1").await.unwrap();
+        assert_eq!(got.original_url, "https://example.com/updated");
+        assert_eq!(got.created_ts, created_ts);
+        assert!(!short_url.contains("default", "replay_2").await.unwrap());
+
+        // replaying twice ends in the same state
+        assert_eq!(short_url.replay_from_events(from_ts).await.unwrap(), 4);
+        assert!(short_url.contains("default", "replay_1").await.unwrap());
+        short_url.restore("default", "replay_2").await.unwrap();
+        assert!(short_url.contains("default", "replay_2").await.unwrap());
+    });
+}
+
+#[test]
+#[ignore]
+fn test_len_is_empty_and_clear() {
+    run(|short_url| async move {
+        assert!(short_url.is_empty().await);
+        assert_eq!(short_url.len().await, 0);
+        short_url.add(&record("default", "len_1")).await.unwrap();
+        short_url.add(&record("other", "len_2")).await.unwrap();
+        assert!(!short_url.is_empty().await);
+        assert_eq!(short_url.len().await, 2);
+        assert_eq!(short_url.clear().await.unwrap(), 2);
+        assert!(short_url.is_empty().await);
+        assert_eq!(short_url.len().await, 0);
+    });
+}
@@ -24,7 +24,7 @@ use crate::{
         infra::config::SYSLOG_ENABLED,
         meta::{organization::DEFAULT_ORG, user::UserRequest},
     },
-    service::{compact::stats::update_stats_from_file_list, db, usage, users},
+    service::{compact::stats::update_stats_from_file_list, db, short_url, usage, users},
 };
 
 mod alert_manager;
@@ -106,6 +106,12 @@ pub async fn init() -> Result<(), anyhow::Error> {
     db::short_url::cache()
         .await
         .expect("short url cache failed");
+    db::short_url::start_purge_task(short_url::notify_expiry);
+    if LOCAL_NODE.is_compactor() {
+        tokio::task::spawn(async move { short_url::run_integrity_check().await });
+        db::short_url::start_link_rot_detector(short_url::check_link);
+        tokio::task::spawn(async move { short_url::run_digest().await });
+    }
 
     // initialize metadata watcher
     tokio::task::spawn(async move { db::schema::watch().await });
@@ -241,6 +241,8 @@ async fn main() -> Result<(), anyhow::Error> {
 
             job_init_tx.send(true).ok();
             job_shutdown_rx.await.ok();
+            // stop background purge of short urls
+            db::short_url::stop_purge_task();
             job_stopped_tx.send(()).ok();
 
             // shutdown meter provider
@@ -22,7 +22,7 @@ use actix_web::http;
 use chrono::{Duration, Local, TimeZone, Timelike, Utc};
 use config::{
     get_config,
-    meta::stream::StreamType,
+    meta::{short_url::ShortenUrlRequest, stream::StreamType},
     utils::{
         base64,
         json::{Map, Value},
@@ -804,13 +804,14 @@ async fn process_dest_template(
     };
 
     // Shorten the alert url
-    let alert_url = match short_url::shorten(&alert.org_id, &alert_url).await {
-        Ok(short_url) => short_url,
-        Err(e) => {
-            log::error!("Error shortening alert url: {e}");
-            alert_url
-        }
-    };
+    let alert_url =
+        match short_url::shorten(&alert.org_id, &ShortenUrlRequest::new(&alert_url)).await {
+            Ok(short_url) => short_url,
+            Err(e) => {
+                log::error!("Error shortening alert url: {e}");
+                alert_url
+            }
+        };
 
     let mut resp = tpl
         .replace("{org_name}", &alert.org_id)
@@ -18,7 +18,9 @@ use std::{str::FromStr, time::Duration};
 use actix_web::http;
 use chromiumoxide::{browser::Browser, cdp::browser_protocol::page::PrintToPdfParams, Page};
 use chrono::Timelike;
-use config::{get_chrome_launch_options, get_config, SMTP_CLIENT};
+use config::{
+    get_chrome_launch_options, get_config, meta::short_url::ShortenUrlRequest, SMTP_CLIENT,
+};
 use cron::Schedule;
 use futures::{future::try_join_all, StreamExt};
 use lettre::{
@@ -618,13 +620,14 @@ async fn generate_report(
     log::debug!("done with headless browser");
 
     // convert to short_url
-    let email_dashb_url = match short_url::shorten(org_id, &email_dashb_url).await {
-        Ok(short_url) => short_url,
-        Err(e) => {
-            log::error!("Error shortening email dashboard url: {e}");
-            email_dashb_url
-        }
-    };
+    let email_dashb_url =
+        match short_url::shorten(org_id, &ShortenUrlRequest::new(&email_dashb_url)).await {
+            Ok(short_url) => short_url,
+            Err(e) => {
+                log::error!("Error shortening email dashboard url: {e}");
+                email_dashb_url
+            }
+        };
     Ok((pdf_data, email_dashb_url))
 }
 
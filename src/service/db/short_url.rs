@@ -18,42 +18,304 @@ use std::sync::Arc;
 use anyhow::{anyhow, Context};
 use bytes::Bytes;
 use chrono::Utc;
-use config::get_config;
 use infra::{
     db::{Event, NEED_WATCH},
-    short_url,
-    short_url::ShortUrlRecord,
+    dist_lock, short_url,
+    short_url::{
+        link_rot::LinkRotDetector, purge::ShortUrlPurgeTask, IdempotencyKey, ShortUrlRecord,
+        SortBy, SortDir, IDEMPOTENCY_KEY_TTL_HOURS,
+    },
 };
+use once_cell::sync::Lazy;
+use tokio_util::sync::CancellationToken;
 
 use crate::{common::infra::config::SHORT_URLS, service::db};
 
 // DBKey to set short URL's
 pub const SHORT_URL_KEY: &str = "/short_urls/";
-// GC interval for `SHORT_URLS` cache in days
-const SHORT_URL_GC_INTERVAL: i64 = 1; // days
 const SHORT_URL_CACHE_LIMIT: i64 = 10_000; // records
 
-pub async fn get(short_id: &str) -> Result<String, anyhow::Error> {
-    if let Some(v) = SHORT_URLS.get(short_id) {
-        return Ok(v.original_url.to_string());
+static PURGE_TOKEN: Lazy<CancellationToken> = Lazy::new(CancellationToken::new);
+
+pub async fn get(org_id: &str, short_id: &str) -> Result<ShortUrlRecord, anyhow::Error> {
+    let now = Utc::now().timestamp_micros();
+    let key = cache_key(org_id, short_id);
+    if let Some(v) = SHORT_URLS.get(&key) {
+        if v.is_expired(now) {
+            return Err(anyhow!("Short URL expired"));
+        }
+        return Ok(v.clone());
+    }
+
+    let val = match short_url::get(org_id, short_id).await {
+        Ok(val) => {
+            SHORT_URLS.insert(key, val.clone());
+            val
+        }
+        // rows created before short URLs were scoped by org have an empty org_id
+        Err(_) => short_url::get("", short_id)
+            .await
+            .map_err(|_| anyhow!("Short URL not found in db"))?,
+    };
+    if val.is_expired(now) {
+        return Err(anyhow!("Short URL expired"));
     }
+    Ok(val)
+}
 
-    let val = short_url::get(short_id)
+pub async fn list(
+    org_id: &str,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    after_ts: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<Vec<ShortUrlRecord>, anyhow::Error> {
+    short_url::list(Some(org_id), created_by, limit, after_ts, sort_by, sort_dir)
         .await
-        .map_err(|_| anyhow!("Short URL not found in db"))?;
-    let original_url = val.original_url.clone();
-    SHORT_URLS.insert(short_id.to_string(), val);
-    Ok(original_url)
+        .context("Failed to list short URLs from DB")
 }
 
-pub async fn set(short_id: &str, entry: ShortUrlRecord) -> Result<(), anyhow::Error> {
-    if let Err(e) = short_url::add(short_id, &entry.original_url).await {
-        return Err(e).context("Failed to add short URL to DB");
-    }
+pub async fn list_with_count(
+    org_id: &str,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<(Vec<ShortUrlRecord>, i64), anyhow::Error> {
+    short_url::list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+        .await
+        .context("Failed to list short URLs with count from DB")
+}
+
+pub async fn search(
+    org_id: &str,
+    url_pattern: &str,
+    limit: Option<i64>,
+) -> Result<Vec<ShortUrlRecord>, anyhow::Error> {
+    short_url::search(org_id, url_pattern, limit)
+        .await
+        .context("Failed to search short URLs in DB")
+}
+
+pub async fn list_link_rot_candidates(org_id: &str) -> Result<Vec<ShortUrlRecord>, anyhow::Error> {
+    short_url::list_link_rot_candidates(org_id)
+        .await
+        .context("Failed to list the short URLs with link rot from DB")
+}
+
+/// `idempotency_key` of the org if it was reserved in the last `IDEMPOTENCY_KEY_TTL_HOURS`
+pub async fn get_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+) -> Result<Option<IdempotencyKey>, anyhow::Error> {
+    short_url::get_idempotency_key(org_id, idempotency_key, idempotency_key_expired_before())
+        .await
+        .context("Failed to get the short URL idempotency key from DB")
+}
+
+pub async fn reserve_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    body_hash: &str,
+) -> Result<bool, anyhow::Error> {
+    short_url::reserve_idempotency_key(
+        org_id,
+        idempotency_key,
+        body_hash,
+        idempotency_key_expired_before(),
+    )
+    .await
+    .context("Failed to reserve the short URL idempotency key in DB")
+}
+
+pub async fn complete_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    short_id: &str,
+) -> Result<(), anyhow::Error> {
+    short_url::complete_idempotency_key(org_id, idempotency_key, short_id)
+        .await
+        .context("Failed to complete the short URL idempotency key in DB")
+}
+
+pub async fn release_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+) -> Result<(), anyhow::Error> {
+    short_url::release_idempotency_key(org_id, idempotency_key)
+        .await
+        .context("Failed to release the short URL idempotency key in DB")
+}
+
+// period_end of the last digest sent by any node, see `claim_digest`
+const DIGEST_LAST_SENT_KEY: &str = "/short_url_digest/last_sent";
+
+/// Claim the digest that is due for this node, returns the period `(start, end)` it covers or
+/// `None` if this or another node sent one less than `period` ago. The period starts where the
+/// previous digest ended, the first one covers the last `period`
+pub async fn claim_digest(period: i64) -> Result<Option<(i64, i64)>, anyhow::Error> {
+    let locker = dist_lock::lock(DIGEST_LAST_SENT_KEY, 0, None).await?;
+    let last_sent = match db::get(DIGEST_LAST_SENT_KEY).await {
+        Ok(ret) => String::from_utf8_lossy(&ret).parse().unwrap_or_default(),
+        Err(_) => 0,
+    };
+    let now = Utc::now().timestamp_micros();
+    let ret = if now - last_sent < period {
+        Ok(None)
+    } else {
+        let period_start = if last_sent > 0 {
+            last_sent
+        } else {
+            now - period
+        };
+        db::put(
+            DIGEST_LAST_SENT_KEY,
+            now.to_string().into(),
+            db::NO_NEED_WATCH,
+            None,
+        )
+        .await
+        .map(|_| Some((period_start, now)))
+    };
+    dist_lock::unlock(&locker).await?;
+    ret
+}
+
+// keys added before this are expired even if the purge task did not delete them yet
+fn idempotency_key_expired_before() -> i64 {
+    (Utc::now() - chrono::Duration::hours(IDEMPOTENCY_KEY_TTL_HOURS)).timestamp_micros()
+}
+
+pub async fn count_by_org(org_id: &str) -> Result<i64, anyhow::Error> {
+    short_url::count_by_org(org_id)
+        .await
+        .context("Failed to count short URLs in DB")
+}
+
+/// Number of short URLs of the org, their total clicks and the `limit` most clicked ones
+pub async fn stats(
+    org_id: &str,
+    limit: usize,
+) -> Result<(i64, i64, Vec<ShortUrlRecord>), anyhow::Error> {
+    let (total_urls, total_clicks) = short_url::click_totals(org_id)
+        .await
+        .context("Failed to count short URL clicks in DB")?;
+    let top = short_url::top_by_clicks(org_id, limit)
+        .await
+        .context("Failed to get the most clicked short URLs from DB")?;
+    Ok((total_urls, total_clicks, top))
+}
+
+pub async fn increment_click_count(org_id: &str, short_id: &str) -> Result<(), anyhow::Error> {
+    short_url::increment_click_count(org_id, short_id)
+        .await
+        .context("Failed to increment short URL click count in DB")
+}
+
+/// Insert the short URL with the short_id it carries, fails with a `ShortUrlError::Conflict`
+/// when the short_id is already taken in the org
+pub async fn add(entry: ShortUrlRecord) -> Result<(), anyhow::Error> {
+    short_url::add(&entry)
+        .await
+        .context("Failed to add short URL to DB")?;
 
     // trigger watch event
     db::put(
-        &format!("{SHORT_URL_KEY}{short_id}"),
+        &format!(
+            "{SHORT_URL_KEY}{}",
+            cache_key(&entry.org_id, &entry.short_id)
+        ),
+        Bytes::new(),
+        NEED_WATCH,
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Insert the short URL unless its original URL is already shortened in the org, returns the
+/// short_id in use
+pub async fn add_or_get(entry: ShortUrlRecord) -> Result<String, anyhow::Error> {
+    let (short_id, inserted) = short_url::add_or_get(&entry)
+        .await
+        .context("Failed to add short URL to DB")?;
+
+    if inserted {
+        // trigger watch event
+        db::put(
+            &format!("{SHORT_URL_KEY}{}", cache_key(&entry.org_id, &short_id)),
+            Bytes::new(),
+            NEED_WATCH,
+            None,
+        )
+        .await?;
+    }
+
+    Ok(short_id)
+}
+
+pub async fn update(org_id: &str, short_id: &str, new_url: &str) -> Result<(), anyhow::Error> {
+    if let Err(e) = short_url::update(org_id, short_id, new_url).await {
+        return Err(e).context("Failed to update short URL in DB");
+    }
+
+    // trigger watch event to refresh the cached record
+    db::put(
+        &format!("{SHORT_URL_KEY}{}", cache_key(org_id, short_id)),
+        Bytes::new(),
+        NEED_WATCH,
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Pin or unpin a short URL, pinned short URLs are never purged
+pub async fn set_pinned(org_id: &str, short_id: &str, pinned: bool) -> Result<(), anyhow::Error> {
+    let ret = if pinned {
+        short_url::pin(org_id, short_id).await
+    } else {
+        short_url::unpin(org_id, short_id).await
+    };
+    ret.context("Failed to pin short URL in DB")?;
+
+    // trigger watch event to refresh the cached record
+    db::put(
+        &format!("{SHORT_URL_KEY}{}", cache_key(org_id, short_id)),
+        Bytes::new(),
+        NEED_WATCH,
+        None,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Move a short URL to a new short_id, fails with a `ShortUrlError::Conflict` when the new
+/// short_id is already taken in the org
+pub async fn rename(
+    org_id: &str,
+    old_short_id: &str,
+    new_short_id: &str,
+) -> Result<(), anyhow::Error> {
+    short_url::rename(org_id, old_short_id, new_short_id)
+        .await
+        .context("Failed to rename short URL in DB")?;
+
+    // trigger watch events to drop the old short_id and cache the new one
+    db::delete(
+        &format!("{SHORT_URL_KEY}{}", cache_key(org_id, old_short_id)),
+        false,
+        NEED_WATCH,
+        None,
+    )
+    .await?;
+    db::put(
+        &format!("{SHORT_URL_KEY}{}", cache_key(org_id, new_short_id)),
         Bytes::new(),
         NEED_WATCH,
         None,
@@ -70,13 +332,6 @@ pub async fn watch() -> Result<(), anyhow::Error> {
     let events = Arc::get_mut(&mut events).unwrap();
     log::info!("Start watching short URLs");
 
-    // Spawn a background task for garbage collection
-    let config = get_config();
-    tokio::spawn(run_gc_task(
-        days_to_minutes(SHORT_URL_GC_INTERVAL),
-        days_to_minutes(config.limit.short_url_retention_days),
-    ));
-
     loop {
         let ev = match events.recv().await {
             Some(ev) => ev,
@@ -89,7 +344,13 @@ pub async fn watch() -> Result<(), anyhow::Error> {
         match ev {
             Event::Put(ev) => {
                 let item_key = ev.key.strip_prefix(key).unwrap();
-                let item_value = match short_url::get(item_key).await {
+                let Some((org_id, short_id)) = item_key.split_once('/') else {
+                    log::error!("watch_short_url: invalid key {}", item_key);
+                    continue;
+                };
+                // the change may come from another node, skip the db read cache
+                short_url::invalidate_cache(org_id, short_id);
+                let item_value = match short_url::get(org_id, short_id).await {
                     Ok(val) => val,
                     Err(e) => {
                         log::error!("Error getting value: {}", e);
@@ -100,6 +361,9 @@ pub async fn watch() -> Result<(), anyhow::Error> {
             }
             Event::Delete(ev) => {
                 let item_key = ev.key.strip_prefix(key).unwrap();
+                if let Some((org_id, short_id)) = item_key.split_once('/') {
+                    short_url::invalidate_cache(org_id, short_id);
+                }
                 SHORT_URLS.remove(item_key);
             }
             Event::Empty => {}
@@ -109,70 +373,69 @@ pub async fn watch() -> Result<(), anyhow::Error> {
 
 /// Preload all short URLs from the database into the cache at startup.
 pub async fn cache() -> Result<(), anyhow::Error> {
-    let ret = short_url::list(Some(SHORT_URL_CACHE_LIMIT)).await?;
+    let ret = short_url::list(
+        None,
+        None,
+        Some(SHORT_URL_CACHE_LIMIT),
+        None,
+        SortBy::default(),
+        SortDir::default(),
+    )
+    .await?;
     for row in ret.into_iter() {
-        SHORT_URLS.insert(row.short_id.to_owned(), row);
+        SHORT_URLS.insert(cache_key(&row.org_id, &row.short_id), row);
     }
     log::info!("[SHORT_URLS] Cached with len: {}", SHORT_URLS.len());
     Ok(())
 }
 
-// Background task to run GC at a regular interval
-async fn run_gc_task(gc_interval_minutes: i64, retention_period_minutes: i64) {
-    let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(
-        gc_interval_minutes as u64 * 60,
-    ));
-
-    loop {
-        interval.tick().await;
-
-        if let Err(e) = gc_cache(retention_period_minutes).await {
-            log::error!("Error during garbage collection: {}", e);
-        }
-    }
-}
-
-// Garbage Collection to clean up expired entries from cache and db
-pub async fn gc_cache(retention_period_minutes: i64) -> Result<(), anyhow::Error> {
-    log::info!(
-        "[SHORT_URLS] Garbage collection start with cache size: {}",
-        SHORT_URLS.len(),
-    );
-
-    let retention_period = chrono::Duration::minutes(retention_period_minutes);
-    let expired_before = Utc::now() - retention_period;
-
-    // get expired ids
-    if let Ok(expired_short_ids) = short_url::get_expired(
-        expired_before.timestamp_micros(),
-        Some(SHORT_URL_CACHE_LIMIT),
-    )
-    .await
-    {
-        if !expired_short_ids.is_empty() {
-            // delete from db
-            short_url::batch_remove(expired_short_ids.clone()).await?;
-
-            // delete from cache
-            for short_id in expired_short_ids {
-                db::delete(
-                    &format!("{SHORT_URL_KEY}{short_id}"),
+/// Spawn the background task purging expired short URLs from db and cache, `on_expiry_due`
+/// sends the expiry notification of a short URL and returns whether it was delivered
+pub fn start_purge_task<N, Fut>(on_expiry_due: N)
+where
+    N: Fn(ShortUrlRecord) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = bool> + Send,
+{
+    ShortUrlPurgeTask::from_config(PURGE_TOKEN.clone()).spawn(
+        |short_ids| async move {
+            for (org_id, short_id) in short_ids {
+                if let Err(e) = db::delete(
+                    &format!("{SHORT_URL_KEY}{}", cache_key(&org_id, &short_id)),
                     false,
                     db::NEED_WATCH,
                     None,
                 )
-                .await?;
+                .await
+                {
+                    log::error!(
+                        "[SHORT_URLS] Error removing purged short url from cache: {}",
+                        e
+                    );
+                }
             }
-        }
+        },
+        on_expiry_due,
+    );
+}
+
+/// Spawn the link rot detector unless it is disabled, `check` requests an original URL and
+/// returns the HTTP status it answered with. It is stopped along with the purge task
+pub fn start_link_rot_detector<C, Fut>(check: C)
+where
+    C: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: std::future::Future<Output = Option<u16>> + Send,
+{
+    if let Some(detector) = LinkRotDetector::from_config(PURGE_TOKEN.clone()) {
+        detector.spawn(check);
     }
+}
 
-    log::info!(
-        "[SHORT_URLS] Garbage collection end with cache size: {}",
-        SHORT_URLS.len(),
-    );
-    Ok(())
+/// Stop the purge task and the link rot detector, called on shutdown
+pub fn stop_purge_task() {
+    PURGE_TOKEN.cancel();
 }
 
-fn days_to_minutes(days: i64) -> i64 {
-    days * 24 * 60
+// `SHORT_URLS` and watch keys are `{org_id}/{short_id}`
+fn cache_key(org_id: &str, short_id: &str) -> String {
+    format!("{org_id}/{short_id}")
 }
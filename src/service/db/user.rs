@@ -20,7 +20,7 @@ use config::utils::json;
 
 use crate::{
     common::{
-        infra::config::{ROOT_USER, USERS, USERS_RUM_TOKEN},
+        infra::config::{ROOT_USER, USERS, USERS_RUM_TOKEN, USERS_SHORT_URL_API_KEY},
         meta::user::{DBUser, User, UserOrg, UserRole},
     },
     service::db,
@@ -86,6 +86,38 @@ pub async fn get_by_token(
     Ok(users[0].get_user(org_id.to_string()))
 }
 
+/// Retrieve the email of the user whose short url api key hashes to `key_hash` from the
+/// cache, the key of the root user is valid in every org
+pub async fn get_by_short_url_api_key(
+    org_id: &str,
+    key_hash: &str,
+) -> Result<Option<String>, anyhow::Error> {
+    if let Some(email) = USERS_SHORT_URL_API_KEY.get(&format!("{org_id}/{key_hash}")) {
+        return Ok(Some(email.clone()));
+    }
+    let Some(root) = ROOT_USER.get("root") else {
+        return Ok(None);
+    };
+    Ok(USERS_SHORT_URL_API_KEY
+        .get(&format!("{}/{key_hash}", root.org))
+        .filter(|email| email.value() == &root.email)
+        .map(|email| email.clone()))
+}
+
+// replace the cached short url api keys of the user with those of `user`
+fn cache_short_url_api_keys(user: &DBUser) {
+    uncache_short_url_api_keys(&user.email);
+    for org in user.organizations.iter() {
+        if let Some(key_hash) = &org.short_url_api_key {
+            USERS_SHORT_URL_API_KEY.insert(format!("{}/{key_hash}", org.name), user.email.clone());
+        }
+    }
+}
+
+fn uncache_short_url_api_keys(email: &str) {
+    USERS_SHORT_URL_API_KEY.retain(|_, v| v != email);
+}
+
 pub async fn get_db_user(name: &str) -> Result<DBUser, anyhow::Error> {
     let key = format!("/user/{name}");
     let val = db::get(&key).await?;
@@ -103,6 +135,7 @@ pub async fn set(user: &DBUser) -> Result<(), anyhow::Error> {
     .await?;
 
     // cache user
+    cache_short_url_api_keys(user);
     for org in &user.organizations {
         let user = User {
             email: user.email.clone(),
@@ -179,6 +212,7 @@ pub async fn watch() -> Result<(), anyhow::Error> {
                     json::from_slice(&ev.value.unwrap()).unwrap()
                 };
 
+                cache_short_url_api_keys(&item_value);
                 let users = item_value.get_all_users();
                 // Invalidate the entire RUM-TOKEN-CACHE
                 for (_, user) in USERS.clone() {
@@ -221,6 +255,7 @@ pub async fn watch() -> Result<(), anyhow::Error> {
             }
             db::Event::Delete(ev) => {
                 let item_key = ev.key.strip_prefix(key).unwrap();
+                uncache_short_url_api_keys(item_key);
                 for (_, user) in USERS.clone() {
                     if user.email.eq(item_key) {
                         USERS.remove(&format!("{}/{}", user.org, user.email));
@@ -245,6 +280,7 @@ pub async fn cache() -> Result<(), anyhow::Error> {
     for (_, item_value) in ret {
         // let item_key = item_key.strip_prefix(key).unwrap();
         let json_val: DBUser = json::from_slice(&item_value).unwrap();
+        cache_short_url_api_keys(&json_val);
         let users = json_val.get_all_users();
         #[cfg(not(feature = "enterprise"))]
         for mut user in users {
@@ -337,6 +373,7 @@ mod tests {
                 name: org_id.clone(),
                 token: "Abcd".to_string(),
                 rum_token: Some("rumAbcd".to_string()),
+                short_url_api_key: None,
             }],
             password_ext: Some("pass".to_string()),
         })
@@ -13,17 +13,256 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use chrono::Utc;
-use config::{get_config, utils::md5};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use config::{
+    get_config,
+    meta::short_url::{
+        ListShortUrlResponse, ShortUrlBatchRowError, ShortUrlItem, ShortUrlPreviewResponse,
+        ShortUrlStatsResponse, ShortenUrlRequest, UrlTransform,
+    },
+    metrics::{
+        SHORT_URL_ACCESS_LOG_DROPPED, SHORT_URL_CLICK_WEBHOOK_DROPPED, SHORT_URL_INTEGRITY_FAILURE,
+    },
+    utils::hash::{fnv, Sum64},
+    SMTP_CLIENT,
+};
+use dashmap::DashMap;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{stream, Stream, StreamExt};
+use image::{GrayImage, ImageFormat, Luma};
 use infra::{
-    errors::{DbError, Error},
-    short_url::ShortUrlRecord,
+    short_url::{
+        error::ShortUrlError,
+        migration::{ImportReport, ShortUrlMigration},
+        transform, AccessLogEntry, ShortUrlDigestReport, ShortUrlRecord, SortBy, SortDir,
+    },
+    storage,
 };
+use lettre::{message::SinglePart, AsyncTransport, Message};
+use once_cell::sync::Lazy;
+use qrcode::{Color, QrCode};
+use regex::Regex;
+use serde::Serialize;
+use tokio::{io::AsyncWrite, sync::mpsc};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
-use crate::service::db;
+use crate::{
+    common::{
+        infra::config::{MAXMIND_DB_CLIENT, USERS},
+        meta::user::UserRole,
+        short_url::rbac::ShortUrlRole,
+        utils::{auth::is_root_user, http::parse_ip_addr},
+    },
+    service::db,
+};
 
 const SHORT_URL_WEB_PATH: &str = "/short/";
 
+/// Parses a caller provided original URL, only absolute http(s) URLs with a host are accepted.
+/// Returns the URL normalized by the parser: lowercase scheme and host, no default port
+pub fn normalize_url(original_url: &str) -> Result<String, String> {
+    let url = url::Url::parse(original_url.trim())
+        .map_err(|e| format!("invalid original_url {original_url:?}: {e}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "invalid original_url {original_url:?}, only http and https are allowed"
+        ));
+    }
+    if url.host_str().map_or(true, |host| host.is_empty()) {
+        return Err(format!(
+            "invalid original_url {original_url:?}, it has no host"
+        ));
+    }
+    Ok(url.to_string())
+}
+
+static SHORT_URL_ALLOWED_DOMAINS: Lazy<Vec<Regex>> = Lazy::new(|| {
+    get_config()
+        .limit
+        .short_url_allowed_domains
+        .split(',')
+        .filter_map(|s| {
+            let s = s.trim();
+            if s.is_empty() {
+                None
+            } else {
+                Some(domain_pattern(s))
+            }
+        })
+        .collect()
+});
+
+/// Turns a glob such as `*.corp.example.com` into a regex matching a whole host
+fn domain_pattern(glob: &str) -> Regex {
+    let pattern = regex::escape(glob).replace(r"\*", ".*").replace(r"\?", ".");
+    Regex::new(&format!("(?i)^{pattern}$")).unwrap()
+}
+
+/// Checks the host of `original_url` against `ZO_SHORT_URL_ALLOWED_DOMAINS`
+pub fn is_domain_allowed(original_url: &str) -> bool {
+    domain_allowed(&SHORT_URL_ALLOWED_DOMAINS, original_url)
+}
+
+fn domain_allowed(patterns: &[Regex], original_url: &str) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    let Ok(url) = url::Url::parse(original_url) else {
+        return false;
+    };
+    url.host_str()
+        .is_some_and(|host| patterns.iter().any(|p| p.is_match(host)))
+}
+
+/// Checks the pipeline of a new short URL against its original URL, the URL it turns into
+/// must pass the same domain check. Returns the reason it is rejected
+pub fn validate_pipeline(pipeline: &[UrlTransform], original_url: &str) -> Result<(), String> {
+    if pipeline.is_empty() {
+        return Ok(());
+    }
+    transform::validate(pipeline, original_url).map_err(|e| e.to_string())?;
+    let target = transform::apply(pipeline, original_url).map_err(|e| e.to_string())?;
+    if !is_domain_allowed(&target) {
+        return Err(format!(
+            "the pipeline redirects to {target}, its domain is not allowed"
+        ));
+    }
+    Ok(())
+}
+
+/// The URL a short URL redirects to, its original URL rewritten by its pipeline
+pub fn redirect_target(record: &ShortUrlRecord) -> Result<String, ShortUrlError> {
+    transform::apply(&record.pipeline, &record.original_url)
+}
+
+/// Checks a caller provided short ID, returns the reason it is rejected
+pub fn validate_short_id(short_id: &str) -> Result<(), String> {
+    if infra::short_url::id::is_valid_custom_short_id(short_id) {
+        Ok(())
+    } else {
+        Err(format!(
+            "invalid short_id {short_id:?}, it must be 3 to 32 characters of letters, digits, '_' or '-'"
+        ))
+    }
+}
+
+/// Whether `short_id` is one of the reserved words callers may not choose, ignoring case
+pub fn is_reserved_short_id(short_id: &str) -> bool {
+    infra::short_url::id::is_reserved_short_id(short_id)
+}
+
+// the last path segment of the preview and qr routes, `/{namespace}/preview` would hit them
+const RESERVED_NAMESPACED_SHORT_IDS: [&str; 2] = ["preview", "qr"];
+
+/// Checks a caller provided namespace and the short ID it goes with, returns the reason they
+/// are rejected
+pub fn validate_namespace(namespace: &str, short_id: Option<&str>) -> Result<(), String> {
+    if !infra::short_url::id::is_valid_namespace(namespace) {
+        return Err(format!(
+            "invalid namespace {namespace:?}, it must be 1 to 31 characters of letters, digits, '_' or '-'"
+        ));
+    }
+    match short_id {
+        None => Err("a namespaced short URL needs a short_id".to_string()),
+        Some(short_id) if RESERVED_NAMESPACED_SHORT_IDS.contains(&short_id) => {
+            Err(format!("short_id {short_id:?} is reserved in a namespace"))
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 128;
+
+/// Checks an `Idempotency-Key` header value, returns the reason it is rejected
+pub fn validate_idempotency_key(idempotency_key: &str) -> Result<(), String> {
+    if idempotency_key.is_empty() || idempotency_key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(format!(
+            "Idempotency-Key must be 1 to {MAX_IDEMPOTENCY_KEY_LEN} characters"
+        ));
+    }
+    if !idempotency_key.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err("Idempotency-Key must be printable ASCII without spaces".to_string());
+    }
+    Ok(())
+}
+
+// how long a request waits for a concurrent one with the same key to create its short URL
+const IDEMPOTENCY_KEY_WAIT: Duration = Duration::from_secs(5);
+const IDEMPOTENCY_KEY_POLL: Duration = Duration::from_millis(100);
+
+/// What a shorten request with an `Idempotency-Key` does, see `reserve_idempotency_key`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IdempotencyKeyState {
+    /// The key is held by this request, it creates the short URL and then completes or
+    /// releases the key
+    Reserved,
+    /// The short URL an earlier request with the key and the same body created
+    Created(String),
+    /// An earlier request with the key is still creating its short URL
+    InProgress,
+    /// The key was used by a request with a different body
+    BodyMismatch,
+}
+
+/// Reserve `idempotency_key` for the request with `body` before it creates a short URL.
+/// A request losing the reservation to a concurrent one waits up to `IDEMPOTENCY_KEY_WAIT`
+/// for the short URL of the winner
+pub async fn reserve_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    body: &[u8],
+) -> Result<IdempotencyKeyState, anyhow::Error> {
+    let body_hash = sha256::digest(body);
+    let started = Instant::now();
+    loop {
+        if db::short_url::reserve_idempotency_key(org_id, idempotency_key, &body_hash).await? {
+            return Ok(IdempotencyKeyState::Reserved);
+        }
+        // `None` when the key was released or expired since, it is reserved again then
+        if let Some(key) = db::short_url::get_idempotency_key(org_id, idempotency_key).await? {
+            if key.body_hash != body_hash {
+                return Ok(IdempotencyKeyState::BodyMismatch);
+            }
+            if let Some(short_id) = key.short_id {
+                return Ok(IdempotencyKeyState::Created(construct_short_url(
+                    org_id, &short_id,
+                )));
+            }
+        }
+        if started.elapsed() >= IDEMPOTENCY_KEY_WAIT {
+            return Ok(IdempotencyKeyState::InProgress);
+        }
+        tokio::time::sleep(IDEMPOTENCY_KEY_POLL).await;
+    }
+}
+
+/// Record that the request holding `idempotency_key` created `short_id`, so retries of it get
+/// the same short URL back
+pub async fn complete_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+    short_id: &str,
+) -> Result<(), anyhow::Error> {
+    db::short_url::complete_idempotency_key(org_id, idempotency_key, short_id).await
+}
+
+/// Give up `idempotency_key` after its request failed, so a retry can create the short URL
+pub async fn release_idempotency_key(
+    org_id: &str,
+    idempotency_key: &str,
+) -> Result<(), anyhow::Error> {
+    db::short_url::release_idempotency_key(org_id, idempotency_key).await
+}
+
 pub fn get_base_url() -> String {
     let config = get_config();
     format!("{}{}", config.common.web_url, config.common.base_uri)
@@ -40,65 +279,1301 @@ fn construct_short_url(org_id: &str, short_id: &str) -> String {
     )
 }
 
-async fn store_short_url(
+/// Shortens the given original URL and stores it in the database, a URL that was already
+/// shortened in the org gets its existing short URL back unless a custom short ID is requested
+pub async fn shorten(org_id: &str, req: &ShortenUrlRequest) -> Result<String, anyhow::Error> {
+    let mut entry = ShortUrlRecord::new(org_id, "", &req.original_url);
+    entry.expires_at = req.expires_at;
+    entry.permanent = req.permanent;
+    entry.created_by = req.created_by.clone();
+    entry.resource_type = req.resource_type.clone();
+    entry.resource_id = req.resource_id.clone();
+    entry.tags = req.tags.clone().into_iter().collect();
+    entry.acl = req.acl.clone();
+    entry.pipeline = req.pipeline.clone();
+    let cfg = get_config();
+    entry.expiry_notify_at = expiry_notify_at(
+        req.expires_at,
+        chrono::Utc::now().timestamp_micros(),
+        cfg.limit.short_url_expiry_notify_days,
+        cfg.limit.short_url_retention_days,
+    );
+    let short_id = match req.short_id.as_deref() {
+        Some(short_id) => {
+            validate_short_id(short_id).map_err(anyhow::Error::msg)?;
+            if is_reserved_short_id(short_id) {
+                return Err(
+                    ShortUrlError::InvalidShortId(format!("{short_id} is reserved")).into(),
+                );
+            }
+            entry.short_id = match req.namespace.as_deref() {
+                Some(namespace) => {
+                    validate_namespace(namespace, Some(short_id)).map_err(anyhow::Error::msg)?;
+                    entry.namespace = Some(namespace.to_string());
+                    infra::short_url::id::namespaced_short_id(namespace, short_id)
+                }
+                None => short_id.to_string(),
+            };
+            let short_id = entry.short_id.clone();
+            db::short_url::add(entry).await?;
+            short_id
+        }
+        None if req.namespace.is_some() => {
+            anyhow::bail!("a namespaced short URL needs a short_id")
+        }
+        None => db::short_url::add_or_get(entry).await?,
+    };
+    Ok(construct_short_url(org_id, &short_id))
+}
+
+const API_KEY_BYTES: usize = 32;
+
+/// The at rest form of a short url api key
+pub fn api_key_hash(api_key: &str) -> String {
+    sha256::digest(api_key)
+}
+
+/// Generate a new short url api key for the user in the org, the previous key stops working.
+/// Only its hash is stored so the returned key can not be shown again
+pub async fn rotate_api_key(org_id: &str, user_id: &str) -> Result<String, anyhow::Error> {
+    let mut db_user = db::user::get_db_user(user_id).await?;
+    let Some(org) = db_user
+        .organizations
+        .iter_mut()
+        .find(|org| org.name == org_id)
+    else {
+        anyhow::bail!("User not found");
+    };
+    let mut buf = [0u8; API_KEY_BYTES];
+    getrandom::getrandom(&mut buf)
+        .map_err(|e| anyhow::anyhow!("failed to generate api key: {e}"))?;
+    let api_key = hex::encode(buf);
+    org.short_url_api_key = Some(api_key_hash(&api_key));
+    db::user::set(&db_user).await?;
+    Ok(api_key)
+}
+
+/// The user that owns `api_key` in the org
+pub async fn user_by_api_key(org_id: &str, api_key: &str) -> Option<String> {
+    match db::user::get_by_short_url_api_key(org_id, &api_key_hash(api_key)).await {
+        Ok(user) => user,
+        Err(e) => {
+            log::error!("[SHORT_URL] api key lookup failed: {e}");
+            None
+        }
+    }
+}
+
+const QUOTA_COUNT_TTL: Duration = Duration::from_secs(60);
+
+// org_id -> (counted at, number of short URLs), read by `quota_exceeded`
+static ORG_URL_COUNTS: Lazy<DashMap<String, (Instant, i64)>> = Lazy::new(DashMap::new);
+
+/// Returns `(limit, current)` when the org reached `ZO_SHORT_URL_QUOTA_PER_ORG`. The count is
+/// cached for a minute so an org may go slightly over its quota within that window
+pub async fn quota_exceeded(org_id: &str) -> Result<Option<(i64, i64)>, anyhow::Error> {
+    quota_exceeded_by(org_id, 1).await
+}
+
+/// Like `quota_exceeded` for adding `adding` short URLs at once
+pub async fn quota_exceeded_by(
+    org_id: &str,
+    adding: i64,
+) -> Result<Option<(i64, i64)>, anyhow::Error> {
+    let limit = get_config().limit.short_url_quota_per_org;
+    if limit <= 0 {
+        return Ok(None);
+    }
+    let cached = ORG_URL_COUNTS
+        .get(org_id)
+        .filter(|entry| entry.0.elapsed() < QUOTA_COUNT_TTL)
+        .map(|entry| entry.1);
+    let current = match cached {
+        Some(current) => current,
+        None => {
+            let current = db::short_url::count_by_org(org_id).await?;
+            ORG_URL_COUNTS.insert(org_id.to_string(), (Instant::now(), current));
+            current
+        }
+    };
+    Ok((current + adding > limit).then_some((limit, current)))
+}
+
+/// The caller is in none of the orgs of the `acl` of a short URL. The short URL routes only
+/// serve members of the org owning the short URL, so an acl narrows who in that org may
+/// resolve it, it can not open the short URL to other orgs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclDenied;
+
+/// Retrieves the short URL record corresponding to the given org and short ID for `user_id`,
+/// the click is only counted when the user may resolve it
+pub async fn retrieve(
     org_id: &str,
     short_id: &str,
-    original_url: &str,
-) -> Result<String, anyhow::Error> {
-    let entry = ShortUrlRecord::new(short_id, original_url);
-    db::short_url::set(short_id, entry).await?;
-    Ok(construct_short_url(org_id, short_id))
+    user_id: Option<&str>,
+) -> Result<Option<ShortUrlRecord>, AclDenied> {
+    let Ok(record) = db::short_url::get(org_id, short_id).await else {
+        return Ok(None);
+    };
+    check_acl(&record, user_id).await?;
+    if let Err(e) = db::short_url::increment_click_count(&record.org_id, short_id).await {
+        log::error!("Failed to increment click count for {short_id}: {e}");
+    }
+    Ok(Some(record))
+}
+
+// a request without a user can not be checked against the acl
+async fn check_acl(record: &ShortUrlRecord, user_id: Option<&str>) -> Result<(), AclDenied> {
+    if record.acl.is_none() {
+        return Ok(());
+    }
+    match user_id {
+        Some(user_id) if acl_allows_user(record, user_id).await => Ok(()),
+        _ => Err(AclDenied),
+    }
+}
+
+/// Root users and the users of an org in the acl may resolve the short URL
+async fn acl_allows_user(record: &ShortUrlRecord, user_id: &str) -> bool {
+    if is_root_user(user_id) {
+        return true;
+    }
+    match db::user::get_db_user(user_id).await {
+        Ok(user) => user
+            .organizations
+            .iter()
+            .any(|org| org.role.eq(&UserRole::Root) || record.acl_allows(&org.name)),
+        Err(e) => {
+            log::warn!("[SHORT_URL] acl lookup of user {user_id} failed: {e}");
+            false
+        }
+    }
+}
+
+/// Body of the POST sent to `ZO_SHORT_URL_CLICK_WEBHOOK_URL`
+#[derive(Debug, Serialize)]
+struct ClickEvent {
+    short_id: String,
+    original_url: String,
+    clicked_at: i64,
+    user_agent: String,
+    ip: String,
+    /// W3C trace context of the click, sent as `traceparent` / `tracestate` headers
+    #[serde(skip)]
+    trace_headers: HashMap<String, String>,
+}
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// shared by the click and expiry webhooks, `None` when it could not be built
+static WEBHOOK_CLIENT: Lazy<Option<reqwest::Client>> = Lazy::new(|| {
+    let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build();
+    match client {
+        Ok(client) => Some(client),
+        Err(e) => {
+            log::error!("[SHORT_URL] webhook client build error: {e}");
+            None
+        }
+    }
+});
+
+// started on the first click, `None` when no webhook is configured
+static CLICK_WEBHOOK: Lazy<Option<mpsc::Sender<ClickEvent>>> = Lazy::new(|| {
+    let cfg = get_config();
+    let url = cfg.limit.short_url_click_webhook_url.trim().to_string();
+    if url.is_empty() {
+        return None;
+    }
+    let (tx, rx) = mpsc::channel(cfg.limit.short_url_click_webhook_queue_size.max(1));
+    tokio::spawn(send_click_events(url, rx));
+    Some(tx)
+});
+
+/// Weak ETag of the redirect of a short URL, changes when the short URL is recreated or
+/// retargeted
+pub fn redirect_etag(record: &ShortUrlRecord) -> String {
+    let url_hash = fnv::new().sum64(&record.original_url);
+    format!("W/\"{:x}-{:x}\"", record.created_ts, url_hash)
+}
+
+/// ETag of the redirect of the given org and short ID without counting a click, the record
+/// comes from the cache when it holds the short URL
+pub async fn current_etag(org_id: &str, short_id: &str) -> Option<String> {
+    let record = db::short_url::get(org_id, short_id).await.ok()?;
+    // a 304 would skip the acl check
+    if record.acl.is_some() {
+        return None;
+    }
+    Some(redirect_etag(&record))
+}
+
+/// Queue a click for the webhook, never waits: the event is dropped when the queue is full
+pub fn notify_click(record: &ShortUrlRecord, user_agent: &str, ip: &str) {
+    let Some(tx) = CLICK_WEBHOOK.as_ref() else {
+        return;
+    };
+    let event = ClickEvent {
+        short_id: record.short_id.clone(),
+        original_url: record.original_url.clone(),
+        clicked_at: chrono::Utc::now().timestamp_micros(),
+        user_agent: user_agent.to_string(),
+        ip: ip.to_string(),
+        // the event is sent from another task, take the context of the click along
+        trace_headers: trace_headers(&tracing::Span::current().context()),
+    };
+    if tx.try_send(event).is_err() {
+        SHORT_URL_CLICK_WEBHOOK_DROPPED.with_label_values(&[]).inc();
+    }
+}
+
+async fn send_click_events(url: String, mut rx: mpsc::Receiver<ClickEvent>) {
+    let Some(client) = WEBHOOK_CLIENT.as_ref() else {
+        return;
+    };
+    while let Some(event) = rx.recv().await {
+        let body = match serde_json::to_vec(&event) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("[SHORT_URL] click webhook serialize error: {e}");
+                continue;
+            }
+        };
+        let request = match click_request(client, &url, &event, body) {
+            Ok(request) => request,
+            Err(e) => {
+                log::error!("[SHORT_URL] click webhook request error: {e}");
+                continue;
+            }
+        };
+        match client.execute(request).await {
+            Ok(resp) if !resp.status().is_success() => log::warn!(
+                "[SHORT_URL] click webhook for {} returned {}",
+                event.short_id,
+                resp.status()
+            ),
+            Ok(_) => {}
+            Err(e) => log::warn!(
+                "[SHORT_URL] click webhook for {} error: {e}",
+                event.short_id
+            ),
+        }
+    }
+}
+
+/// Headers carrying the trace context `cx` through the global propagator, empty when there is
+/// no trace
+fn trace_headers(cx: &opentelemetry::Context) -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut headers)
+    });
+    headers
+}
+
+fn click_request(
+    client: &reqwest::Client,
+    url: &str,
+    event: &ClickEvent,
+    body: Vec<u8>,
+) -> reqwest::Result<reqwest::Request> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    for (key, value) in event.trace_headers.iter() {
+        request = request.header(key, value);
+    }
+    request.body(body).build()
+}
+
+// the ip is only kept until its country is looked up
+struct PendingAccess {
+    entry: AccessLogEntry,
+    ip: String,
+}
+
+/// Most access log entries written in one insert
+const ACCESS_LOG_BATCH_SIZE: usize = 500;
+
+// started on the first redirect, `None` when the access log is disabled
+static ACCESS_LOG: Lazy<Option<mpsc::Sender<PendingAccess>>> = Lazy::new(|| {
+    let cfg = get_config();
+    if !cfg.limit.short_url_access_log_enabled {
+        return None;
+    }
+    let (tx, rx) = mpsc::channel(cfg.limit.short_url_access_log_queue_size.max(1));
+    tokio::spawn(write_access_log(rx));
+    Some(tx)
+});
+
+/// Queue a redirect for the access log, never waits: the entry is dropped when the queue is
+/// full
+pub fn log_access(record: &ShortUrlRecord, user_agent: &str, ip: &str) {
+    let Some(tx) = ACCESS_LOG.as_ref() else {
+        return;
+    };
+    let access = PendingAccess {
+        entry: AccessLogEntry {
+            org_id: record.org_id.clone(),
+            short_id: record.short_id.clone(),
+            accessed_at: chrono::Utc::now().timestamp_micros(),
+            user_agent_hash: format!("{:x}", fnv::new().sum64(user_agent)),
+            ..Default::default()
+        },
+        ip: ip.to_string(),
+    };
+    if tx.try_send(access).is_err() {
+        SHORT_URL_ACCESS_LOG_DROPPED.with_label_values(&[]).inc();
+    }
+}
+
+// writes whatever is queued in one insert, so the writes keep up with bursts of redirects
+async fn write_access_log(mut rx: mpsc::Receiver<PendingAccess>) {
+    while let Some(access) = rx.recv().await {
+        let mut batch = vec![access];
+        while batch.len() < ACCESS_LOG_BATCH_SIZE {
+            match rx.try_recv() {
+                Ok(access) => batch.push(access),
+                Err(_) => break,
+            }
+        }
+        let entries = {
+            let maxminddb_client = MAXMIND_DB_CLIENT.read().await;
+            batch
+                .into_iter()
+                .map(|PendingAccess { mut entry, ip }| {
+                    entry.country_code = maxminddb_client.as_ref().and_then(|client| {
+                        let (ip, _) = parse_ip_addr(&ip).ok()?;
+                        let city = client
+                            .city_reader
+                            .lookup::<maxminddb::geoip2::City>(ip)
+                            .ok()?;
+                        city.country?.iso_code.map(str::to_string)
+                    });
+                    entry
+                })
+                .collect::<Vec<_>>()
+        };
+        if let Err(e) = infra::short_url::add_access_log(&entries).await {
+            log::error!(
+                "[SHORT_URL] write {} access log entries error: {e}",
+                entries.len()
+            );
+        }
+    }
+}
+
+/// When to warn the owner of a short URL expiring at `expires_at`, or after the global
+/// retention counted from `now` when it has no expiry of its own. `None` when expiry
+/// notifications are disabled
+fn expiry_notify_at(
+    expires_at: Option<i64>,
+    now: i64,
+    notify_days: i64,
+    retention_days: i64,
+) -> Option<i64> {
+    if notify_days <= 0 {
+        return None;
+    }
+    let day = chrono::Duration::days(1).num_microseconds().unwrap();
+    let expires_at = expires_at.unwrap_or_else(|| now.saturating_add(retention_days * day));
+    Some(expires_at.saturating_sub(notify_days * day))
+}
+
+/// Body of the POST sent to `ZO_SHORT_URL_EXPIRY_WEBHOOK_URL`
+#[derive(Debug, Serialize)]
+struct ExpiryEvent {
+    org_id: String,
+    short_id: String,
+    short_url: String,
+    original_url: String,
+    /// `None` when the short URL expires after the global retention
+    expires_at: Option<i64>,
+    created_by: Option<String>,
+}
+
+/// Verify the checksums of all short URLs every `ZO_SHORT_URL_INTEGRITY_CHECK_INTERVAL`
+/// seconds, each mismatch is logged and counted in `short_url_integrity_failure_total`
+pub async fn run_integrity_check() {
+    let cfg = get_config();
+    if cfg.limit.short_url_integrity_check_interval == 0 {
+        return;
+    }
+    let mut interval = tokio::time::interval(Duration::from_secs(
+        cfg.limit.short_url_integrity_check_interval,
+    ));
+    interval.tick().await; // trigger the first run
+    loop {
+        interval.tick().await;
+        let report = match infra::short_url::verify_integrity(
+            cfg.limit.short_url_integrity_check_batch_size,
+        )
+        .await
+        {
+            Ok(report) => report,
+            Err(e) => {
+                log::error!("[SHORT_URL] integrity check error: {e}");
+                continue;
+            }
+        };
+        for (org_id, short_id) in report.mismatches.iter() {
+            log::error!("[SHORT_URL] checksum mismatch of short url {org_id}/{short_id}");
+        }
+        SHORT_URL_INTEGRITY_FAILURE
+            .with_label_values(&[])
+            .inc_by(report.mismatches.len() as u64);
+        log::info!(
+            "[SHORT_URL] integrity check scanned {} short urls, {} mismatches, {} without checksum",
+            report.scanned,
+            report.mismatches.len(),
+            report.unchecked
+        );
+    }
+}
+
+const DIGEST_PERIOD: Duration = Duration::from_secs(24 * 3600);
+// how often the nodes check whether the next digest is due
+const DIGEST_CHECK_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Email the admins of each org the `ShortUrlDigestReport` since the previous digest, once a
+/// day when `ZO_SHORT_URL_DIGEST_ENABLED` and `ZO_SMTP_ENABLED` are set. Every compactor
+/// checks, the one claiming the digest in the meta db sends it
+pub async fn run_digest() {
+    if !get_config().limit.short_url_digest_enabled {
+        return;
+    }
+    if SMTP_CLIENT.is_none() {
+        log::warn!("[SHORT_URL] digest is enabled but SMTP is not, no digest is sent");
+        return;
+    }
+    let mut interval = tokio::time::interval(DIGEST_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let (period_start, period_end) =
+            match db::short_url::claim_digest(DIGEST_PERIOD.as_micros() as i64).await {
+                Ok(Some(period)) => period,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::error!("[SHORT_URL] claiming the digest failed: {e}");
+                    continue;
+                }
+            };
+        for (org_id, recipients) in digest_recipients() {
+            if let Err(e) = send_digest(&org_id, &recipients, period_start, period_end).await {
+                log::error!("[SHORT_URL] sending the digest of org {org_id} failed: {e}");
+            }
+        }
+    }
+}
+
+// the emails of the short url admins of each org
+fn digest_recipients() -> HashMap<String, Vec<String>> {
+    let mut recipients: HashMap<String, Vec<String>> = HashMap::new();
+    for user in USERS.iter() {
+        let Some((org_id, _)) = user.key().split_once('/') else {
+            continue;
+        };
+        if ShortUrlRole::from_user_role(&user.role) == Some(ShortUrlRole::Admin) {
+            recipients
+                .entry(org_id.to_string())
+                .or_default()
+                .push(user.email.clone());
+        }
+    }
+    recipients
 }
 
-fn generate_short_id(original_url: &str, timestamp: Option<i64>) -> String {
-    match timestamp {
-        Some(ts) => {
-            let input = format!("{}{}", original_url, ts);
-            md5::short_hash(&input)
+/// Email the digest of the org for `period_start <= ts < period_end` to `recipients`, nothing
+/// is sent for a period without activity
+pub async fn send_digest(
+    org_id: &str,
+    recipients: &[String],
+    period_start: i64,
+    period_end: i64,
+) -> Result<(), anyhow::Error> {
+    let Some(client) = SMTP_CLIENT.as_ref() else {
+        anyhow::bail!("SMTP is not enabled");
+    };
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    let report = infra::short_url::generate_digest(org_id, period_start, period_end).await?;
+    if report == ShortUrlDigestReport::default() {
+        return Ok(());
+    }
+    let cfg = get_config();
+    let mut email = Message::builder()
+        .from(cfg.smtp.smtp_from_email.parse()?)
+        .subject(format!("Openobserve Short URL digest - {org_id}"));
+    for recipient in recipients {
+        email = email.to(recipient.parse()?);
+    }
+    if !cfg.smtp.smtp_reply_to.is_empty() {
+        email = email.reply_to(cfg.smtp.smtp_reply_to.parse()?);
+    }
+    let email = email.singlepart(SinglePart::html(digest_html(org_id, &report)))?;
+    client
+        .send(email)
+        .await
+        .map_err(|e| anyhow::anyhow!("Error sending email: {e}"))?;
+    Ok(())
+}
+
+// short ids and org ids are letters, digits, '_', '-' and '/', they need no escaping
+fn digest_html(org_id: &str, report: &ShortUrlDigestReport) -> String {
+    let mut html = format!(
+        "<h3>Short URL activity of {}</h3>\
+        <ul><li>{} created</li><li>{} clicks</li><li>{} expired and removed</li></ul>",
+        escape_html(org_id),
+        report.new_urls,
+        report.total_clicks,
+        report.expired_removed
+    );
+    if !report.top_5_by_clicks.is_empty() {
+        html.push_str("<h4>Most clicked</h4><ol>");
+        for (short_id, clicks) in report.top_5_by_clicks.iter() {
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a>: {clicks} clicks</li>",
+                escape_html(&construct_short_url(org_id, short_id)),
+                escape_html(short_id)
+            ));
         }
-        None => md5::short_hash(original_url),
+        html.push_str("</ol>");
     }
+    html
 }
 
-/// Shortens the given original URL and stores it in the database
-pub async fn shorten(org_id: &str, original_url: &str) -> Result<String, anyhow::Error> {
-    let mut short_id = generate_short_id(original_url, None);
+// org ids and short ids are caller provided, they must not add markup to the digest
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
 
-    if let Ok(existing_url) = db::short_url::get(&short_id).await {
-        if existing_url == original_url {
-            return Ok(construct_short_url(org_id, &short_id));
+/// Warns the owner that a short URL is about to expire, called by the purge task once the
+/// `expiry_notify_at` of the record is due. Returns whether the notification was delivered,
+/// the failed ones are retried on the next purge run
+pub async fn notify_expiry(record: ShortUrlRecord) -> bool {
+    let url = get_config()
+        .limit
+        .short_url_expiry_webhook_url
+        .trim()
+        .to_string();
+    if url.is_empty() {
+        log::info!(
+            "[SHORT_URL] short url {}/{} of {} is about to expire",
+            record.org_id,
+            record.short_id,
+            record.created_by.as_deref().unwrap_or("system")
+        );
+        return true;
+    }
+    let event = ExpiryEvent {
+        short_url: construct_short_url(&record.org_id, &record.short_id),
+        org_id: record.org_id,
+        short_id: record.short_id,
+        original_url: record.original_url,
+        expires_at: record.expires_at,
+        created_by: record.created_by,
+    };
+    let body = match serde_json::to_vec(&event) {
+        Ok(body) => body,
+        Err(e) => {
+            log::error!("[SHORT_URL] expiry webhook serialize error: {e}");
+            return false;
+        }
+    };
+    let Some(client) = WEBHOOK_CLIENT.as_ref() else {
+        return false;
+    };
+    match client
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+    {
+        Ok(resp) if resp.status().is_success() => true,
+        Ok(resp) => {
+            log::warn!(
+                "[SHORT_URL] expiry webhook for {} returned {}",
+                event.short_id,
+                resp.status()
+            );
+            false
+        }
+        Err(e) => {
+            log::warn!(
+                "[SHORT_URL] expiry webhook for {} error: {e}",
+                event.short_id
+            );
+            false
         }
     }
+}
+
+const LINK_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
 
-    let result = store_short_url(org_id, &short_id, original_url).await;
-    match result {
-        Ok(url) => Ok(url),
+// redirects are not followed, a redirect could lead to an internal host
+static LINK_CHECK_CLIENT: Lazy<Option<reqwest::Client>> = Lazy::new(|| {
+    match reqwest::Client::builder()
+        .timeout(LINK_CHECK_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(std::sync::Arc::new(PublicOnlyResolver))
+        .build()
+    {
+        Ok(client) => Some(client),
         Err(e) => {
-            if let Some(infra_error) = e.downcast_ref::<Error>() {
-                match infra_error {
-                    Error::DbError(DbError::UniqueViolation) => {
-                        let timestamp = Utc::now().timestamp();
-                        short_id = generate_short_id(original_url, Some(timestamp));
-                        store_short_url(org_id, &short_id, original_url).await
-                    }
-                    _ => Err(e),
+            log::error!("[SHORT_URL] link check client build error: {e}");
+            None
+        }
+    }
+});
+
+/// Whether the link checker may connect to `ip`. Loopback, link local (which holds the cloud
+/// metadata service at 169.254.169.254), private and other non global addresses are refused
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                // shared address space 100.64.0.0/10
+                || (a == 100 && (b & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local fc00::/7 and link local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+// resolves like the system resolver but drops the addresses `is_public_ip` refuses, the
+// connection then goes to the checked address even if the name resolves differently later
+struct PublicOnlyResolver;
+
+impl reqwest::dns::Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// Requests the original URL of a short URL for the link rot detector, returns the HTTP status
+/// it answered with or `None` if no answer came within 5 seconds. Servers refusing HEAD are
+/// asked again with GET. Hosts that are not public addresses are never requested and
+/// redirects are reported as their 3xx status
+pub async fn check_link(original_url: String) -> Option<u16> {
+    let client = LINK_CHECK_CLIENT.as_ref()?;
+    // names are checked by `PublicOnlyResolver`, addresses in the url skip the resolver
+    let ip = match url::Url::parse(&original_url).ok()?.host()? {
+        url::Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+        url::Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+        url::Host::Domain(_) => None,
+    };
+    if ip.is_some_and(|ip| !is_public_ip(ip)) {
+        log::debug!("[SHORT_URL] link check of {original_url} skipped, not a public address");
+        return None;
+    }
+    let status = match client.head(&original_url).send().await {
+        Ok(resp) => resp.status(),
+        Err(e) => {
+            log::debug!("[SHORT_URL] link check of {original_url} error: {e}");
+            return None;
+        }
+    };
+    if status != reqwest::StatusCode::METHOD_NOT_ALLOWED {
+        return Some(status.as_u16());
+    }
+    match client.get(&original_url).send().await {
+        Ok(resp) => Some(resp.status().as_u16()),
+        Err(e) => {
+            log::debug!("[SHORT_URL] link check of {original_url} error: {e}");
+            None
+        }
+    }
+}
+
+/// Whether `user_id` may change the short URL with its `role`, see `ShortUrlRole::may_modify`.
+/// A short URL that does not exist is allowed so the caller answers it is not found
+pub async fn may_modify(org_id: &str, short_id: &str, user_id: &str, role: ShortUrlRole) -> bool {
+    if role == ShortUrlRole::Admin {
+        return true;
+    }
+    match db::short_url::get(org_id, short_id).await {
+        Ok(record) => role.may_modify(user_id, record.created_by.as_deref()),
+        Err(_) => true,
+    }
+}
+
+/// Pins or unpins a short URL, fails with a `ShortUrlError::NotFound` when it does not exist
+pub async fn set_pinned(org_id: &str, short_id: &str, pinned: bool) -> Result<(), anyhow::Error> {
+    db::short_url::set_pinned(org_id, short_id, pinned).await
+}
+
+/// Returns the metadata of the given org and short ID without counting a click, checked
+/// against the acl like a redirect
+pub async fn preview(
+    org_id: &str,
+    short_id: &str,
+    user_id: Option<&str>,
+) -> Result<Option<ShortUrlPreviewResponse>, AclDenied> {
+    let Ok(record) = db::short_url::get(org_id, short_id).await else {
+        return Ok(None);
+    };
+    check_acl(&record, user_id).await?;
+    Ok(Some(ShortUrlPreviewResponse {
+        short_id: record.short_id,
+        original_url: record.original_url,
+        created_ts: record.created_ts,
+    }))
+}
+
+/// Lists the short URLs of the given organization sorted by `sort_by`, starting after the
+/// `after_ts` cursor, only those created by `created_by` when it is set
+pub async fn list(
+    org_id: &str,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    after_ts: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<ListShortUrlResponse, anyhow::Error> {
+    let records =
+        db::short_url::list(org_id, created_by, limit, after_ts, sort_by, sort_dir).await?;
+    Ok(to_list_response(org_id, records))
+}
+
+/// Lists a page of the short URLs of the given organization sorted by `sort_by`, along with
+/// the total number of short URLs matching, only those created by `created_by` when it is set
+pub async fn list_page(
+    org_id: &str,
+    created_by: Option<&str>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+    sort_by: SortBy,
+    sort_dir: SortDir,
+) -> Result<ListShortUrlResponse, anyhow::Error> {
+    let (records, total) =
+        db::short_url::list_with_count(org_id, created_by, limit, offset, sort_by, sort_dir)
+            .await?;
+    let mut response = to_list_response(org_id, records);
+    response.total = Some(total);
+    Ok(response)
+}
+
+/// Search short URLs whose original URL contains `query`
+pub async fn search(
+    org_id: &str,
+    query: &str,
+    limit: Option<i64>,
+) -> Result<ListShortUrlResponse, anyhow::Error> {
+    let records = db::short_url::search(org_id, query, limit).await?;
+    Ok(to_list_response(org_id, records))
+}
+
+const STATS_TOP_URLS: usize = 10;
+
+/// Click summary of the org with its ten most clicked short URLs
+pub async fn stats(org_id: &str) -> Result<ShortUrlStatsResponse, anyhow::Error> {
+    let (total_urls, total_clicks, top) = db::short_url::stats(org_id, STATS_TOP_URLS).await?;
+    Ok(ShortUrlStatsResponse {
+        total_urls,
+        total_clicks,
+        top_urls: to_list_response(org_id, top).list,
+    })
+}
+
+/// Short URLs of the org whose original URL answered the last link check with a 4xx or 5xx
+pub async fn link_rot(org_id: &str) -> Result<ListShortUrlResponse, anyhow::Error> {
+    let records = db::short_url::list_link_rot_candidates(org_id).await?;
+    Ok(to_list_response(org_id, records))
+}
+
+/// Renders the short URL of the given short ID as a PNG QR code, `scale` is the size of a module
+/// in pixels and `margin` the width of the quiet zone in modules, `None` if the short ID does
+/// not exist
+pub async fn qr_code(
+    org_id: &str,
+    short_id: &str,
+    scale: u32,
+    margin: u32,
+) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    if db::short_url::get(org_id, short_id).await.is_err() {
+        return Ok(None);
+    }
+    // encode the short URL rather than the original one so scans are counted as clicks
+    render_qr_png(&construct_short_url(org_id, short_id), scale, margin).map(Some)
+}
+
+fn render_qr_png(data: &str, scale: u32, margin: u32) -> Result<Vec<u8>, anyhow::Error> {
+    let code = QrCode::new(data)?;
+    let width = code.width() as u32;
+    let pixels = (width + 2 * margin) * scale;
+    let mut image = GrayImage::from_pixel(pixels, pixels, Luma([255]));
+    for y in 0..width {
+        for x in 0..width {
+            if code[(x as usize, y as usize)] != Color::Dark {
+                continue;
+            }
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    image.put_pixel(
+                        (x + margin) * scale + dx,
+                        (y + margin) * scale + dy,
+                        Luma([0]),
+                    );
                 }
-            } else {
-                Err(e)
             }
         }
     }
+    let mut png = Vec::new();
+    image.write_to(&mut std::io::Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+fn to_list_response(org_id: &str, records: Vec<ShortUrlRecord>) -> ListShortUrlResponse {
+    let list = records
+        .into_iter()
+        .map(|record| ShortUrlItem {
+            short_url: construct_short_url(org_id, &record.short_id),
+            short_id: record.short_id,
+            original_url: record.original_url,
+            created_ts: record.created_ts,
+            click_count: record.click_count,
+            created_by: record.created_by,
+        })
+        .collect();
+    ListShortUrlResponse { list, total: None }
+}
+
+const CSV_HEADER: [&str; 7] = [
+    "short_id",
+    "original_url",
+    "created_ts",
+    "created_by",
+    "org_id",
+    "click_count",
+    "pinned",
+];
+
+/// Streams the short URLs of the org as CSV, one chunk per row after the header. Records are
+/// read one at a time from the store so memory stays bounded
+pub async fn export_csv(
+    org_id: &str,
+) -> Result<impl Stream<Item = Result<Bytes, anyhow::Error>>, anyhow::Error> {
+    let header = csv_row(&CSV_HEADER);
+    let rows = infra::short_url::stream_by_org(org_id)
+        .await?
+        .map(|record| {
+            let record = record?;
+            csv_row(&[
+                record.short_id.as_str(),
+                record.original_url.as_str(),
+                &record.created_ts.to_string(),
+                record.created_by.as_deref().unwrap_or_default(),
+                record.org_id.as_str(),
+                &record.click_count.to_string(),
+                &record.pinned.to_string(),
+            ])
+        });
+    Ok(stream::once(std::future::ready(header)).chain(rows))
 }
 
-/// Retrieves the original URL corresponding to the given short ID
-pub async fn retrieve(short_id: &str) -> Option<String> {
-    db::short_url::get(short_id).await.ok()
+fn csv_row(fields: &[&str]) -> Result<Bytes, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner()?.into())
+}
+
+/// Largest CSV file accepted by the batch create
+pub const BATCH_MAX_FILE_SIZE: usize = 10 * 1024 * 1024;
+/// Most rows accepted by the batch create
+pub const BATCH_MAX_ROWS: usize = 10_000;
+// short_ids checked and inserted per db call
+const BATCH_CHUNK_SIZE: usize = 500;
+
+// positions of the batch create CSV columns
+struct BatchColumns {
+    short_id: usize,
+    original_url: usize,
+    expires_at: Option<usize>,
+    pinned: Option<usize>,
+    tags: Option<usize>,
+}
+
+/// Reads the short URLs of a batch create CSV with the columns `short_id`, `original_url`,
+/// `expires_at`, `pinned` and `tags` in any order, only the first two are required. `expires_at`
+/// is in microseconds and `tags` is a JSON object. Invalid rows are returned with the reason,
+/// the whole file is rejected when it has no header or more than `BATCH_MAX_ROWS` rows
+pub fn parse_batch_csv(
+    org_id: &str,
+    created_by: Option<&str>,
+    data: &[u8],
+) -> Result<(Vec<ShortUrlRecord>, Vec<ShortUrlBatchRowError>), String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+    let headers = reader
+        .headers()
+        .map_err(|e| format!("invalid CSV header: {e}"))?
+        .iter()
+        .map(|h| h.trim().to_lowercase())
+        .collect::<Vec<_>>();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let (Some(short_id), Some(original_url)) = (column("short_id"), column("original_url")) else {
+        return Err("the CSV must have the columns short_id and original_url".to_string());
+    };
+    let columns = BatchColumns {
+        short_id,
+        original_url,
+        expires_at: column("expires_at"),
+        pinned: column("pinned"),
+        tags: column("tags"),
+    };
+
+    let created_ts = chrono::Utc::now().timestamp_micros();
+    let mut records = Vec::new();
+    let mut errors = Vec::new();
+    // short_id -> line it was first seen on
+    let mut seen = HashMap::new();
+    for (i, row) in reader.records().enumerate() {
+        if i >= BATCH_MAX_ROWS {
+            return Err(format!("the CSV has more than {BATCH_MAX_ROWS} rows"));
+        }
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                errors.push(ShortUrlBatchRowError {
+                    line: e.position().map_or(0, |p| p.line()),
+                    error: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = row.position().map_or(0, |p| p.line());
+        match batch_row(org_id, created_by, &columns, &row, created_ts) {
+            Ok(record) => match seen.get(&record.short_id) {
+                Some(first) => errors.push(ShortUrlBatchRowError {
+                    line,
+                    error: format!("short_id {} is already on line {first}", record.short_id),
+                }),
+                None => {
+                    seen.insert(record.short_id.clone(), line);
+                    records.push(record);
+                }
+            },
+            Err(error) => errors.push(ShortUrlBatchRowError { line, error }),
+        }
+    }
+    Ok((records, errors))
+}
+
+// the short URL of a batch create row, checked like the ones created by `shorten`
+fn batch_row(
+    org_id: &str,
+    created_by: Option<&str>,
+    columns: &BatchColumns,
+    row: &csv::StringRecord,
+    created_ts: i64,
+) -> Result<ShortUrlRecord, String> {
+    let field = |column: Option<usize>| {
+        column
+            .and_then(|column| row.get(column))
+            .map(str::trim)
+            .unwrap_or_default()
+    };
+    let short_id = field(Some(columns.short_id));
+    validate_short_id(short_id)?;
+    if is_reserved_short_id(short_id) {
+        return Err("short_id is reserved".to_string());
+    }
+    let original_url = field(Some(columns.original_url));
+    let cfg = get_config();
+    let max_url_length = cfg.limit.short_url_max_url_length;
+    if original_url.len() > max_url_length {
+        return Err(format!(
+            "original_url is longer than {max_url_length} bytes"
+        ));
+    }
+    let original_url = normalize_url(original_url)?;
+    if !is_domain_allowed(&original_url) {
+        return Err("domain not allowed".to_string());
+    }
+
+    let mut record = ShortUrlRecord::new(org_id, short_id, &original_url);
+    record.created_ts = created_ts;
+    record.created_by = created_by.map(|v| v.to_string());
+    record.expires_at = match field(columns.expires_at) {
+        "" => None,
+        v => Some(v.parse::<i64>().map_err(|_| {
+            format!("invalid expires_at {v:?}, it must be a timestamp in microseconds")
+        })?),
+    };
+    record.pinned = match field(columns.pinned) {
+        "" => false,
+        v => v
+            .to_lowercase()
+            .parse::<bool>()
+            .map_err(|_| format!("invalid pinned {v:?}, it must be true or false"))?,
+    };
+    record.tags = match field(columns.tags) {
+        "" => Default::default(),
+        v => serde_json::from_str::<HashMap<String, String>>(v)
+            .map_err(|e| format!("invalid tags, they must be a JSON object of strings: {e}"))?
+            .into_iter()
+            .collect(),
+    };
+    record.expiry_notify_at = expiry_notify_at(
+        record.expires_at,
+        created_ts,
+        cfg.limit.short_url_expiry_notify_days,
+        cfg.limit.short_url_retention_days,
+    );
+    Ok(record)
+}
+
+/// Adds the short URLs read by `parse_batch_csv`, those whose short_id is already in use in the
+/// org are skipped. Returns the inserted and the skipped short_ids
+pub async fn batch_create(
+    org_id: &str,
+    records: Vec<ShortUrlRecord>,
+) -> Result<(Vec<String>, Vec<String>), anyhow::Error> {
+    let mut inserted = Vec::with_capacity(records.len());
+    let mut duplicates = Vec::new();
+    for chunk in records.chunks(BATCH_CHUNK_SIZE) {
+        let short_ids = chunk
+            .iter()
+            .map(|r| r.short_id.as_str())
+            .collect::<Vec<_>>();
+        let existing = infra::short_url::get_many(org_id, &short_ids).await?;
+        let (taken, new): (Vec<_>, Vec<_>) = chunk
+            .iter()
+            .cloned()
+            .partition(|r| existing.contains_key(&r.short_id));
+        duplicates.extend(taken.into_iter().map(|r| r.short_id));
+        if new.is_empty() {
+            continue;
+        }
+        let ret = infra::short_url::batch_add(&new).await?;
+        if ret.skipped == 0 {
+            inserted.extend(new.into_iter().map(|r| r.short_id));
+            continue;
+        }
+        // taken since they were checked, or by a deleted short URL, the rows we wrote are the
+        // ones that look like ours
+        let short_ids = new.iter().map(|r| r.short_id.as_str()).collect::<Vec<_>>();
+        let stored = infra::short_url::get_many(org_id, &short_ids).await?;
+        for record in new {
+            match stored.get(&record.short_id) {
+                Some(s)
+                    if s.created_ts == record.created_ts
+                        && s.original_url == record.original_url =>
+                {
+                    inserted.push(record.short_id)
+                }
+                _ => duplicates.push(record.short_id),
+            }
+        }
+    }
+    ORG_URL_COUNTS.remove(org_id);
+    Ok((inserted, duplicates))
+}
+
+/// Object store prefix of the short URL backups, one `{timestamp}.ndjson.gz` per backup
+const BACKUP_PREFIX: &str = "short_url/backup/";
+
+/// Compresses what is written to it into memory as it comes
+struct GzWriter(GzEncoder<Vec<u8>>);
+
+impl AsyncWrite for GzWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Poll::Ready(self.get_mut().0.write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(self.get_mut().0.flush())
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Uploads every short URL to the object store as gzipped NDJSON, returns the object key
+/// and the number of short URLs backed up
+pub async fn backup() -> Result<(String, usize), anyhow::Error> {
+    let mut writer = GzWriter(GzEncoder::new(Vec::new(), Compression::default()));
+    let count = ShortUrlMigration::default()
+        .export_ndjson(&mut writer)
+        .await?;
+    let data = writer.0.finish()?;
+    let key = format!(
+        "{BACKUP_PREFIX}{}.ndjson.gz",
+        chrono::Utc::now().timestamp_micros()
+    );
+    storage::put(&key, data.into()).await?;
+    Ok((key, count))
+}
+
+/// Adds the short URLs of the latest backup, those whose short_id exists are skipped.
+/// Returns the object key restored from
+pub async fn restore() -> Result<(String, ImportReport), anyhow::Error> {
+    // keys are named by their timestamp so the latest sorts last
+    let key = storage::list(BACKUP_PREFIX)
+        .await?
+        .into_iter()
+        .filter(|key| key.ends_with(".ndjson.gz"))
+        .max()
+        .ok_or_else(|| anyhow::anyhow!("no short url backup found under {BACKUP_PREFIX}"))?;
+    let data = storage::get(&key).await?;
+    let mut ndjson = Vec::new();
+    GzDecoder::new(data.as_ref()).read_to_end(&mut ndjson)?;
+    let report = ShortUrlMigration::default()
+        .import_ndjson(&mut ndjson.as_slice())
+        .await?;
+    Ok((key, report))
 }
 
 #[cfg(test)]
 mod tests {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
     use super::*;
 
+    #[test]
+    fn test_click_request_traceparent() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let span_context = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let cx = opentelemetry::Context::new().with_remote_span_context(span_context);
+        let event = ClickEvent {
+            short_id: "abc123".to_string(),
+            original_url: "https://example.com/".to_string(),
+            clicked_at: 0,
+            user_agent: String::new(),
+            ip: String::new(),
+            trace_headers: trace_headers(&cx),
+        };
+        let client = reqwest::Client::new();
+        let request = click_request(&client, "http://localhost/hook", &event, vec![]).unwrap();
+        assert_eq!(
+            request.headers().get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+        // json body, no trace context fields
+        assert!(!serde_json::to_string(&event).unwrap().contains("trace"));
+
+        // no trace, no header
+        let event = ClickEvent {
+            trace_headers: trace_headers(&opentelemetry::Context::new()),
+            ..event
+        };
+        let request = click_request(&client, "http://localhost/hook", &event, vec![]).unwrap();
+        assert!(request.headers().get("traceparent").is_none());
+    }
+
+    #[test]
+    fn test_api_key_hash() {
+        let hash = api_key_hash("abc");
+        assert_eq!(
+            hash,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(hash, api_key_hash("abd"));
+    }
+
+    #[test]
+    fn test_csv_row() {
+        assert_eq!(
+            csv_row(&["abc", "https://example.com/?a=1,b=\"2\"", ""]).unwrap(),
+            Bytes::from("abc,\"https://example.com/?a=1,b=\"\"2\"\"\",\n")
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_csv() {
+        let data = "short_id,original_url,expires_at,pinned,tags
+spring-sale,https://example.com/spring,,true,\"{\"\"campaign\"\": \"\"spring\"\"}\"
+x,https://example.com/x,,,
+spring-sale,https://example.com/again,,,
+summer-sale,https://example.com/summer,tomorrow,,
+";
+        let (records, errors) =
+            parse_batch_csv("default", Some("root@example.com"), data.as_bytes()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].short_id, "spring-sale");
+        assert!(records[0].pinned);
+        assert_eq!(records[0].tags.get("campaign").unwrap(), "spring");
+        assert_eq!(records[0].created_by.as_deref(), Some("root@example.com"));
+        assert_eq!(
+            errors.iter().map(|e| e.line).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+
+        assert!(parse_batch_csv("default", None, b"id,url\nabc,https://example.com").is_err());
+    }
+
+    #[test]
+    fn test_redirect_etag() {
+        let mut record = ShortUrlRecord::new("default", "abc", "https://example.com/a");
+        record.created_ts = 0x18d1;
+        let etag = redirect_etag(&record);
+        assert!(etag.starts_with(r#"W/"18d1-"#), "{etag}");
+        assert_eq!(redirect_etag(&record), etag);
+        record.original_url = "https://example.com/b".to_string();
+        assert_ne!(redirect_etag(&record), etag);
+    }
+
+    #[test]
+    fn test_expiry_notify_at() {
+        let day = 86_400_000_000;
+        assert_eq!(expiry_notify_at(Some(10 * day), 0, 0, 30), None);
+        assert_eq!(expiry_notify_at(Some(10 * day), 0, 3, 30), Some(7 * day));
+        // no expiry of its own, expires after the global retention
+        assert_eq!(expiry_notify_at(None, day, 3, 30), Some(28 * day));
+    }
+
     /// Extracts the short ID from the shortened URL
     fn get_short_id_from_url(org_id: &str, short_url: &str) -> Option<String> {
         let prefix = format!("{}api/{}{}", get_base_url(), org_id, SHORT_URL_WEB_PATH);
@@ -109,21 +1584,165 @@ mod tests {
     #[ignore]
     async fn test_shorten_and_retrieve() {
         let original_url = "https://www.example.com/some/long/url";
-        let short_url = shorten("default", original_url).await.unwrap();
+        let short_url = shorten("default", &ShortenUrlRequest::new(original_url))
+            .await
+            .unwrap();
         let short_id = get_short_id_from_url("default", &short_url).unwrap();
 
-        let retrieved_url = retrieve(&short_id).await.expect("Failed to retrieve URL");
-        assert_eq!(retrieved_url, original_url);
+        let retrieved = retrieve("default", &short_id, None)
+            .await
+            .unwrap()
+            .expect("Failed to retrieve URL");
+        assert_eq!(retrieved.original_url, original_url);
 
         let short_id = get_short_id_from_url("default", &short_url).unwrap();
-        assert_eq!(short_id.len(), 16);
+        assert_eq!(short_id.len(), get_config().limit.short_url_id_length);
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert!(normalize_url("javascript:alert(1)").is_err());
+        assert!(normalize_url("//evil.com").is_err());
+        assert!(normalize_url("/relative/path").is_err());
+        assert!(normalize_url("ftp://example.com/file").is_err());
+        assert!(normalize_url("http://").is_err());
+        assert_eq!(
+            normalize_url("http://valid.example.com/path?q=1").unwrap(),
+            "http://valid.example.com/path?q=1"
+        );
+        assert_eq!(
+            normalize_url("HTTPS://Valid.Example.COM:443/Path").unwrap(),
+            "https://valid.example.com/Path"
+        );
+        assert_eq!(
+            normalize_url("http://valid.example.com:80").unwrap(),
+            "http://valid.example.com/"
+        );
+    }
+
+    #[test]
+    fn test_domain_allowed() {
+        assert!(domain_allowed(&[], "https://example.com/a"));
+        let patterns = vec![
+            domain_pattern("*.corp.example.com"),
+            domain_pattern("example.org"),
+        ];
+        assert!(domain_allowed(
+            &patterns,
+            "https://logs.corp.example.com/web/logs"
+        ));
+        assert!(domain_allowed(
+            &patterns,
+            "http://a.b.CORP.example.com:5080/"
+        ));
+        assert!(domain_allowed(&patterns, "https://example.org"));
+        assert!(!domain_allowed(&patterns, "https://corp.example.com"));
+        assert!(!domain_allowed(
+            &patterns,
+            "https://example.com.attacker.io"
+        ));
+        assert!(!domain_allowed(&patterns, "https://sub.example.org"));
+        assert!(!domain_allowed(&patterns, "not a url"));
+    }
+
+    #[test]
+    fn test_render_qr_png() {
+        let png = render_qr_png("http://localhost:5080/api/default/short/abc", 2, 4).unwrap();
+        let image = image::load_from_memory_with_format(&png, ImageFormat::Png)
+            .unwrap()
+            .to_luma8();
+        // 2 pixels per module, a quiet zone of 4 on each side and a 21 + 4k module symbol
+        assert_eq!(image.width() % 2, 0);
+        assert_eq!((image.width() / 2 - 8 - 21) % 4, 0);
+        assert_eq!(image.height(), image.width());
+        // the quiet zone is white and the finder pattern starts dark
+        assert_eq!(image.get_pixel(0, 0), &Luma([255]));
+        assert_eq!(image.get_pixel(8, 8), &Luma([0]));
+    }
+
+    #[test]
+    fn test_validate_short_id() {
+        for short_id in ["abc", "status", "go_status-2", &"a".repeat(32)] {
+            assert!(validate_short_id(short_id).is_ok(), "{short_id}");
+        }
+        for short_id in ["ab", "", "go/status", "with space", "café", &"a".repeat(33)] {
+            assert!(validate_short_id(short_id).is_err(), "{short_id}");
+        }
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        for ip in [
+            "93.184.216.34",
+            "2606:2800:220:1:248:1893:25c8:1946",
+            "8.8.8.8",
+        ] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in [
+            "127.0.0.1",
+            "169.254.169.254",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_digest_html() {
+        let report = ShortUrlDigestReport {
+            new_urls: 2,
+            total_clicks: 7,
+            expired_removed: 1,
+            top_5_by_clicks: vec![("abc".to_string(), 5), ("def".to_string(), 2)],
+        };
+        let html = digest_html("default", &report);
+        assert!(html.contains("<li>2 created</li><li>7 clicks</li><li>1 expired and removed</li>"));
+        assert!(html.contains(&format!(
+            "<li><a href=\"{}\">abc</a>: 5 clicks</li>",
+            construct_short_url("default", "abc")
+        )));
+        let html = digest_html("default", &ShortUrlDigestReport::default());
+        assert!(!html.contains("Most clicked"));
+
+        let report = ShortUrlDigestReport {
+            top_5_by_clicks: vec![("<b>x</b>".to_string(), 1)],
+            ..Default::default()
+        };
+        let html = digest_html("<script>", &report);
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("<b>"));
+        assert!(html.contains("&lt;script&gt;"));
+        assert!(html.contains("&lt;b&gt;x&lt;/b&gt;</a>"));
+    }
+
+    #[test]
+    fn test_validate_idempotency_key() {
+        for key in [
+            "a",
+            "3f1c9b2e-7d4a-4c1e-9f0b-2a6d8e5c1b7f",
+            &"k".repeat(128),
+        ] {
+            assert!(validate_idempotency_key(key).is_ok(), "{key}");
+        }
+        for key in ["", "with space", "tab\t", "café", &"k".repeat(129)] {
+            assert!(validate_idempotency_key(key).is_err(), "{key}");
+        }
     }
 
     #[tokio::test]
     #[ignore]
     async fn test_retrieve_nonexistent_short_id() {
-        let retrieved_url = retrieve("nonexistent_id").await;
-        assert!(retrieved_url.is_none());
+        let retrieved_url = retrieve("default", "nonexistent_id", None).await;
+        assert_eq!(retrieved_url.map(|r| r.is_none()), Ok(true));
     }
 
     #[tokio::test]
@@ -131,10 +1750,34 @@ mod tests {
     async fn test_unique_original_urls() {
         let original_url = "https://www.example.com/some/long/url";
 
-        let short_url1 = shorten("default", original_url).await.unwrap();
-        let short_url2 = shorten("default", original_url).await.unwrap();
+        let short_url1 = shorten("default", &ShortenUrlRequest::new(original_url))
+            .await
+            .unwrap();
+        let short_url2 = shorten("default", &ShortenUrlRequest::new(original_url))
+            .await
+            .unwrap();
 
         // Should return the same short_id
         assert_eq!(short_url1, short_url2);
     }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_shorten_acl_not_deduped_with_public() {
+        let original_url = "https://www.example.com/some/acl/url";
+
+        let public = shorten("default", &ShortenUrlRequest::new(original_url))
+            .await
+            .unwrap();
+        let mut req = ShortenUrlRequest::new(original_url);
+        req.acl = Some(vec!["other_org".to_string()]);
+        let restricted = shorten("default", &req).await.unwrap();
+        assert_ne!(public, restricted);
+
+        let short_id = get_short_id_from_url("default", &restricted).unwrap();
+        let record = db::short_url::get("default", &short_id).await.unwrap();
+        assert_eq!(record.acl, req.acl);
+        // the same request again reuses the restricted record
+        assert_eq!(shorten("default", &req).await.unwrap(), restricted);
+    }
 }
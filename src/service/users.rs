@@ -268,12 +268,18 @@ pub async fn update_user(
                             db_user.last_name = new_user.last_name;
                             if is_org_updated {
                                 let mut orgs = db_user.clone().organizations;
+                                // the short url api key survives the org entry being replaced
+                                let short_url_api_key = orgs
+                                    .iter()
+                                    .find(|org| org.name.eq(org_id))
+                                    .and_then(|org| org.short_url_api_key.clone());
                                 let new_orgs = if orgs.is_empty() {
                                     vec![UserOrg {
                                         name: org_id.to_string(),
                                         token: new_user.token,
                                         rum_token: new_user.rum_token,
                                         role: new_user.role,
+                                        short_url_api_key,
                                     }]
                                 } else {
                                     orgs.retain(|org| !org.name.eq(org_id));
@@ -282,6 +288,7 @@ pub async fn update_user(
                                         token: new_user.token,
                                         rum_token: new_user.rum_token,
                                         role: new_user.role,
+                                        short_url_api_key,
                                     });
                                     orgs
                                 };
@@ -385,12 +392,18 @@ pub async fn add_user_to_org(
             let token = generate_random_string(16);
             let rum_token = format!("rum{}", generate_random_string(16));
             let mut orgs = db_user.clone().organizations;
+            // the short url api key survives the org entry being replaced
+            let short_url_api_key = orgs
+                .iter()
+                .find(|org| org.name.eq(&local_org))
+                .and_then(|org| org.short_url_api_key.clone());
             let new_orgs = if orgs.is_empty() {
                 vec![UserOrg {
                     name: local_org.to_string(),
                     token,
                     rum_token: Some(rum_token),
                     role: role.clone(),
+                    short_url_api_key,
                 }]
             } else {
                 if db_user.is_external {
@@ -410,6 +423,7 @@ pub async fn add_user_to_org(
                     token,
                     rum_token: Some(rum_token),
                     role: role.clone(),
+                    short_url_api_key,
                 });
                 orgs
             };
@@ -827,4 +841,54 @@ mod tests {
 
         assert!(resp.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_update_user_keeps_short_url_api_key() {
+        infra_db::create_table().await.unwrap();
+        set_up().await;
+
+        let email = "short_url_key@zo.dev";
+        let resp = post_user(
+            "dummy",
+            UserRequest {
+                email: email.to_string(),
+                password: "pass#123".to_string(),
+                role: UserRole::Member,
+                first_name: "user".to_owned(),
+                last_name: "".to_owned(),
+                is_external: false,
+            },
+            "admin@zo.dev",
+        )
+        .await;
+        assert!(resp.is_ok());
+        let api_key = crate::service::short_url::rotate_api_key("dummy", email)
+            .await
+            .unwrap();
+
+        let resp = update_user(
+            "dummy",
+            email,
+            false,
+            "admin@zo.dev",
+            UpdateUser {
+                first_name: Some("renamed".to_string()),
+                role: Some(UserRole::Admin),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(resp.is_ok());
+        assert_eq!(
+            crate::service::short_url::user_by_api_key("dummy", &api_key).await,
+            Some(email.to_string())
+        );
+
+        let resp = add_user_to_org("dummy", email, UserRole::Member, "admin@zo.dev").await;
+        assert!(resp.is_ok());
+        assert_eq!(
+            crate::service::short_url::user_by_api_key("dummy", &api_key).await,
+            Some(email.to_string())
+        );
+    }
 }